@@ -0,0 +1,91 @@
+//! Unified diffs between input files and their formatted output
+
+use diffy::{create_patch, Line};
+use rxq_core::format::ColorScheme;
+use std::io::{self, Write};
+
+/// Write a unified diff from `original` to `formatted` in the `diff-*` colors
+/// of a theme; nothing if they are equal
+pub fn write_diff(
+    output: &mut dyn Write,
+    path: &str,
+    original: &str,
+    formatted: &str,
+    colors: &ColorScheme,
+) -> io::Result<()> {
+    if original == formatted {
+        return Ok(());
+    }
+    let end = |code: &str| {
+        if code.is_empty() {
+            ""
+        } else {
+            colors.reset.as_str()
+        }
+    };
+    let header = &colors.diff_header;
+
+    let patch = create_patch(original, formatted);
+    writeln!(output, "{}--- a/{}{}", header, path, end(header))?;
+    writeln!(output, "{}+++ b/{}{}", header, path, end(header))?;
+    for hunk in patch.hunks() {
+        writeln!(
+            output,
+            "{}@@ -{} +{} @@{}",
+            colors.diff_hunk,
+            hunk.old_range(),
+            hunk.new_range(),
+            end(&colors.diff_hunk)
+        )?;
+        for line in hunk.lines() {
+            let (prefix, code, text) = match line {
+                Line::Context(text) => (' ', "", *text),
+                Line::Delete(text) => ('-', colors.diff_delete.as_str(), *text),
+                Line::Insert(text) => ('+', colors.diff_insert.as_str(), *text),
+            };
+            let end = end(code);
+            match text.strip_suffix('\n') {
+                Some(text) => writeln!(output, "{}{}{}{}", code, prefix, text, end)?,
+                None => {
+                    writeln!(output, "{}{}{}{}", code, prefix, text, end)?;
+                    writeln!(output, "\\ No newline at end of file")?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_diff() {
+        let mut output = Vec::new();
+        write_diff(
+            &mut output,
+            "a.xml",
+            "<a><b/></a>",
+            "<a>\n  <b/>\n</a>\n",
+            &ColorScheme::none(),
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "--- a/a.xml\n+++ b/a.xml\n@@ -1 +1,3 @@\n-<a><b/></a>\n\\ No newline at end of file\n\
+             +<a>\n+  <b/>\n+</a>\n"
+        );
+
+        let mut output = Vec::new();
+        write_diff(
+            &mut output,
+            "a.xml",
+            "<a/>\n",
+            "<a/>\n",
+            &ColorScheme::default(),
+        )
+        .unwrap();
+        assert!(output.is_empty());
+    }
+}
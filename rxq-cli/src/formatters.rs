@@ -1,12 +1,19 @@
 //! Formatters for different document types
 
+use rxq_core::format::{Canonicalizer, ColorScheme, HtmlFormatter, HtmlHighlighter, Minifier};
 use rxq_core::{Document, DocumentType, FormatError, FormatOptions, Formatter};
-use std::io::Write;
+use serde::Serialize;
+use serde_json::ser::{CompactFormatter, PrettyFormatter};
+use std::io::{self, Write};
 
 /// Enum wrapper for formatters to avoid object safety issues
 pub enum DocFormatter {
     Xml(XmlHtmlFormatter),
+    Html(HtmlFormatter),
     Json(JsonFormatter),
+    Canonical(Canonicalizer),
+    Minified(Minifier),
+    Highlighted(Box<HtmlHighlighter<DocFormatter>>),
 }
 
 impl Formatter for DocFormatter {
@@ -18,7 +25,11 @@ impl Formatter for DocFormatter {
     ) -> Result<(), FormatError> {
         match self {
             DocFormatter::Xml(f) => f.format(doc, writer, options),
+            DocFormatter::Html(f) => f.format(doc, writer, options),
             DocFormatter::Json(f) => f.format(doc, writer, options),
+            DocFormatter::Canonical(f) => f.format(doc, writer, options),
+            DocFormatter::Minified(f) => f.format(doc, writer, options),
+            DocFormatter::Highlighted(f) => f.format(doc, writer, options),
         }
     }
 }
@@ -26,12 +37,22 @@ impl Formatter for DocFormatter {
 /// Get the appropriate formatter for a document type
 pub fn get_formatter(doc_type: DocumentType) -> DocFormatter {
     match doc_type {
-        DocumentType::Xml | DocumentType::Html => DocFormatter::Xml(XmlHtmlFormatter),
+        DocumentType::Xml => DocFormatter::Xml(XmlHtmlFormatter),
+        DocumentType::Html => DocFormatter::Html(HtmlFormatter),
         DocumentType::Json => DocFormatter::Json(JsonFormatter),
     }
 }
 
-/// XML/HTML formatter implementation
+/// Keep write failures as I/O errors, unlike the data errors of serde
+fn serialize_error(err: serde_json::Error) -> FormatError {
+    if err.is_io() {
+        FormatError::IoError(err.into())
+    } else {
+        FormatError::FormatFailed(err.to_string())
+    }
+}
+
+/// XML formatter implementation
 pub struct XmlHtmlFormatter;
 
 impl Formatter for XmlHtmlFormatter {
@@ -74,15 +95,18 @@ impl Formatter for JsonFormatter {
             }
             DocumentType::Json => {
                 let source = doc.source();
-                serde_json::from_str(source).map_err(|e| {
-                    FormatError::IoError(std::io::Error::new(std::io::ErrorKind::InvalidData, e))
-                })?
+                serde_json::from_str(source)
+                    .map_err(|e| FormatError::FormatFailed(format!("invalid JSON: {}", e)))?
             }
         };
 
+        let colors = options.colors();
         if options.compact {
-            serde_json::to_writer(&mut *writer, &value)
-                .map_err(|e| FormatError::IoError(e.into()))?;
+            let formatter = ColorFormatter::new(CompactFormatter, &colors);
+            let mut serializer = serde_json::Serializer::with_formatter(&mut *writer, formatter);
+            value
+                .serialize(&mut serializer)
+                .map_err(serialize_error)?;
         } else {
             // Configure indentation
             let indent_str = if let rxq_core::format::Indent::Spaces(n) = options.indent {
@@ -91,13 +115,12 @@ impl Formatter for JsonFormatter {
                 "\t".to_string()
             };
 
-            let formatter = serde_json::ser::PrettyFormatter::with_indent(indent_str.as_bytes());
+            let formatter =
+                ColorFormatter::new(PrettyFormatter::with_indent(indent_str.as_bytes()), &colors);
             let mut serializer = serde_json::Serializer::with_formatter(&mut *writer, formatter);
-
-            use serde::Serialize;
             value
                 .serialize(&mut serializer)
-                .map_err(|e| FormatError::IoError(e.into()))?;
+                .map_err(serialize_error)?;
         }
 
         // Add trailing newline if pretty printing
@@ -109,6 +132,153 @@ impl Formatter for JsonFormatter {
     }
 }
 
+/// Wraps a JSON formatter to color keys, strings, numbers, booleans and null
+/// as they are written
+struct ColorFormatter<'a, F> {
+    inner: F,
+    colors: &'a ColorScheme,
+    /// Whether the string being written is an object key
+    in_key: bool,
+}
+
+impl<'a, F> ColorFormatter<'a, F> {
+    fn new(inner: F, colors: &'a ColorScheme) -> Self {
+        Self {
+            inner,
+            colors,
+            in_key: false,
+        }
+    }
+
+    fn string_color(&self) -> &str {
+        if self.in_key {
+            &self.colors.key
+        } else {
+            &self.colors.string
+        }
+    }
+
+    /// Write `color` and a token, then reset if there was a color
+    fn colored<W: ?Sized + Write>(
+        writer: &mut W,
+        color: &str,
+        reset: &str,
+        token: impl FnOnce(&mut W) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if color.is_empty() {
+            return token(writer);
+        }
+        writer.write_all(color.as_bytes())?;
+        token(writer)?;
+        writer.write_all(reset.as_bytes())
+    }
+}
+
+impl<F: serde_json::ser::Formatter> serde_json::ser::Formatter for ColorFormatter<'_, F> {
+    fn write_null<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        Self::colored(writer, &self.colors.boolean, &self.colors.reset, |w| {
+            self.inner.write_null(w)
+        })
+    }
+
+    fn write_bool<W: ?Sized + Write>(&mut self, writer: &mut W, value: bool) -> io::Result<()> {
+        Self::colored(writer, &self.colors.boolean, &self.colors.reset, |w| {
+            self.inner.write_bool(w, value)
+        })
+    }
+
+    fn write_i64<W: ?Sized + Write>(&mut self, writer: &mut W, value: i64) -> io::Result<()> {
+        Self::colored(writer, &self.colors.number, &self.colors.reset, |w| {
+            self.inner.write_i64(w, value)
+        })
+    }
+
+    fn write_u64<W: ?Sized + Write>(&mut self, writer: &mut W, value: u64) -> io::Result<()> {
+        Self::colored(writer, &self.colors.number, &self.colors.reset, |w| {
+            self.inner.write_u64(w, value)
+        })
+    }
+
+    fn write_f64<W: ?Sized + Write>(&mut self, writer: &mut W, value: f64) -> io::Result<()> {
+        Self::colored(writer, &self.colors.number, &self.colors.reset, |w| {
+            self.inner.write_f64(w, value)
+        })
+    }
+
+    fn write_number_str<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        value: &str,
+    ) -> io::Result<()> {
+        Self::colored(writer, &self.colors.number, &self.colors.reset, |w| {
+            self.inner.write_number_str(w, value)
+        })
+    }
+
+    fn begin_string<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(self.string_color().as_bytes())?;
+        self.inner.begin_string(writer)
+    }
+
+    fn end_string<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_string(writer)?;
+        if !self.string_color().is_empty() {
+            writer.write_all(self.colors.reset.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn begin_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.begin_array(writer)
+    }
+
+    fn end_array<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_array(writer)
+    }
+
+    fn begin_array_value<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        self.inner.begin_array_value(writer, first)
+    }
+
+    fn end_array_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_array_value(writer)
+    }
+
+    fn begin_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.begin_object(writer)
+    }
+
+    fn end_object<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_object(writer)
+    }
+
+    fn begin_object_key<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        self.in_key = true;
+        self.inner.begin_object_key(writer, first)
+    }
+
+    fn end_object_key<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.in_key = false;
+        self.inner.end_object_key(writer)
+    }
+
+    fn begin_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.begin_object_value(writer)
+    }
+
+    fn end_object_value<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        self.inner.end_object_value(writer)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -145,4 +315,25 @@ mod tests {
         let result = String::from_utf8(output).unwrap();
         assert_eq!(result, r#"{"key":"value"}"#);
     }
+
+    #[test]
+    fn test_json_formatter_colors() {
+        let json = r#"{"key": ["value", 1, true, null]}"#;
+        let doc = Document::parse(json, DocumentType::Json).unwrap();
+        let options = FormatOptions {
+            color: rxq_core::ColorMode::Always,
+            compact: true,
+            ..FormatOptions::default()
+        };
+
+        let mut output = Vec::new();
+        JsonFormatter.format(&doc, &mut output, &options).unwrap();
+
+        let result = String::from_utf8(output).unwrap();
+        assert_eq!(
+            result,
+            "{\x1b[1;34m\"key\"\x1b[0m:[\x1b[32m\"value\"\x1b[0m,\x1b[36m1\x1b[0m,\
+             \x1b[35mtrue\x1b[0m,\x1b[35mnull\x1b[0m]}"
+        );
+    }
 }
@@ -5,17 +5,35 @@
 use anyhow::{Context, Result};
 use clap::builder::styling;
 use clap::builder::Styles;
-use clap::Parser;
-use std::fs::File;
-use std::io::{stdin, stdout, BufWriter, Read, Write};
-use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
+use std::borrow::Cow;
+use std::env;
+use std::fs::{self, File};
+use std::io::{stdin, stdout, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-use rxq_core::format::format_query_results;
+use rxq_core::format::{
+    format_query_results, format_stream, format_stream_results, Canonicalizer, ColorScheme,
+    HtmlHighlighter, Minifier,
+};
+use rxq_core::parser::compress::{decompress_reader, CompressWriter, Compression};
+use rxq_core::parser::encoding::{
+    declare_utf8, decode, encoding_for_label, Encoding, EncodingWriter,
+};
+use rxq_core::parser::input::InputBuffer;
+use rxq_core::parser::multi::split_documents;
+use rxq_core::parser::stream::{split_records, XmlReader};
+use rxq_core::parser::{parse_with_options, ParseLimits, ParseOptions};
+use rxq_core::query::StreamQuery;
+use rxq_core::validate::dtd::validate_doctype;
+use rxq_core::validate::load_schema;
 use rxq_core::{
-    execute_query, ColorMode, Document, DocumentType, FormatOptions, Formatter, Indent, Query,
-    QueryOptions,
+    evaluate_xpath, execute_query, AttributeLayout, ColorMode, Document, DocumentType,
+    FormatOptions, Formatter, Indent, Query, QueryOptions, Severity, XPathResult,
 };
 
+mod diff;
 mod formatters;
 use formatters::get_formatter;
 
@@ -32,7 +50,11 @@ fn my_styles() -> Styles {
 #[command(version = env!("CARGO_PKG_VERSION"))]
 #[command(about = "Command-line XML and HTML beautifier and content extractor")]
 #[command(long_about = None, styles = my_styles())]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Input file (stdin if not provided)
     pub file: Option<PathBuf>,
 
@@ -72,6 +94,30 @@ struct Cli {
     #[arg(long = "tab", conflicts_with = "indent")]
     pub use_tabs: bool,
 
+    /// Elements whose content is kept exactly as written (comma-separated)
+    #[arg(
+        long = "preserve-whitespace",
+        value_name = "ELEMENTS",
+        value_delimiter = ','
+    )]
+    pub preserve_whitespace: Vec<String>,
+
+    /// Wrap the attributes of start tags wider than this many characters
+    #[arg(long = "max-width", value_name = "N")]
+    pub max_width: Option<usize>,
+
+    /// How wrapped attributes are laid out: wrap, align or one-per-line
+    #[arg(
+        long = "attribute-layout",
+        value_name = "LAYOUT",
+        default_value = "wrap"
+    )]
+    pub attribute_layout: AttributeLayout,
+
+    /// Sort attributes by name
+    #[arg(long = "sort-attributes")]
+    pub sort_attributes: bool,
+
     /// Force color output
     #[arg(short = 'c', long = "color", conflicts_with = "no_color")]
     pub force_color: bool,
@@ -80,6 +126,10 @@ struct Cli {
     #[arg(long = "no-color")]
     pub no_color: bool,
 
+    /// Read the color theme from FILE instead of the config directory
+    #[arg(long = "theme", value_name = "FILE")]
+    pub theme: Option<PathBuf>,
+
     /// Format file in place
     #[arg(short = 'i', long = "in-place", requires = "file")]
     pub in_place: bool,
@@ -95,6 +145,158 @@ struct Cli {
     /// Count the number of results
     #[arg(short = 'C', long = "count")]
     pub count: bool,
+
+    /// Reject XML that is not well-formed instead of recovering
+    #[arg(long = "strict")]
+    pub strict: bool,
+
+    /// Report every problem the parser recovered from, with source snippets
+    #[arg(
+        long = "lint",
+        conflicts_with_all = ["strict", "in_place", "xpath", "extract", "css_query", "json", "count"]
+    )]
+    pub lint: bool,
+
+    /// Read external DTDs named by the DOCTYPE (local files, relative to the input)
+    #[arg(long = "load-dtd")]
+    pub load_dtd: bool,
+
+    /// Enforce resource limits for untrusted input (nesting depth, node count, sizes)
+    #[arg(long = "untrusted")]
+    pub untrusted: bool,
+
+    /// Input character encoding (overrides detection)
+    #[arg(long = "encoding", value_name = "ENCODING")]
+    pub encoding: Option<String>,
+
+    /// Write output in the input's character encoding instead of UTF-8
+    #[arg(long = "keep-encoding")]
+    pub keep_encoding: bool,
+
+    /// Read the input file into memory instead of memory-mapping it
+    #[arg(long = "no-mmap")]
+    pub no_mmap: bool,
+
+    /// Compress the output (gzip, zstd, bzip2 or xz); in-place edits keep the input's compression
+    #[arg(
+        long = "output-compress",
+        value_name = "FORMAT",
+        conflicts_with_all = ["stream", "stream_element", "lint"]
+    )]
+    pub output_compress: Option<Compression>,
+
+    /// Process XML incrementally in bounded memory (formatting and //tag, /a/b queries)
+    #[arg(
+        long = "stream",
+        conflicts_with_all = ["in_place", "json", "css_query", "html", "keep_encoding", "strict", "load_dtd", "untrusted", "lint"]
+    )]
+    pub stream: bool,
+
+    /// Process each matching element as its own document (NDJSON unless a query is given)
+    #[arg(
+        long = "stream-element",
+        value_name = "ELEMENT",
+        conflicts_with_all = ["in_place", "stream", "html", "keep_encoding", "strict", "load_dtd", "untrusted", "lint"]
+    )]
+    pub stream_element: Option<String>,
+
+    /// Check that files are formatted, listing those that would change (exit status 1 if any)
+    #[arg(
+        long = "check",
+        value_name = "FILES",
+        num_args = 1..,
+        conflicts_with_all = ["file", "in_place", "xpath", "extract", "css_query", "count", "output_compress", "stream", "stream_element", "multi", "lint"]
+    )]
+    pub check: Vec<PathBuf>,
+
+    /// Print a unified diff between each file and its formatted output
+    #[arg(
+        long = "diff",
+        value_name = "FILES",
+        num_args = 1..,
+        conflicts_with_all = ["file", "check", "in_place", "xpath", "extract", "css_query", "count", "output_compress", "stream", "stream_element", "multi", "lint"]
+    )]
+    pub diff: Vec<PathBuf>,
+
+    /// Remove insignificant whitespace from XML and HTML output (compact output for JSON)
+    #[arg(
+        long = "minify",
+        conflicts_with_all = ["c14n", "exc_c14n", "xpath", "extract", "css_query", "count", "stream", "stream_element", "lint"]
+    )]
+    pub minify: bool,
+
+    /// Write the formatted document as syntax-highlighted HTML
+    #[arg(
+        long = "highlight",
+        conflicts_with_all = ["c14n", "exc_c14n", "minify", "xpath", "extract", "css_query", "count", "in_place", "stream", "stream_element", "lint"]
+    )]
+    pub highlight: bool,
+
+    /// Color highlighted HTML with inline styles from the theme instead of classes
+    #[arg(long = "inline-styles", requires = "highlight")]
+    pub inline_styles: bool,
+
+    /// Remove comments when minifying
+    #[arg(long = "strip-comments", requires = "minify")]
+    pub strip_comments: bool,
+
+    /// Remove a default XML declaration and repeated namespace declarations when minifying
+    #[arg(long = "strip-declarations", requires = "minify")]
+    pub strip_declarations: bool,
+
+    /// Write Canonical XML 1.0 (for signing and hashing)
+    #[arg(
+        long = "c14n",
+        conflicts_with_all = ["exc_c14n", "html", "json", "xpath", "extract", "css_query", "count", "in_place", "keep_encoding", "stream", "stream_element", "lint"]
+    )]
+    pub c14n: bool,
+
+    /// Write Exclusive XML Canonicalization
+    #[arg(
+        long = "exc-c14n",
+        conflicts_with_all = ["html", "json", "xpath", "extract", "css_query", "count", "in_place", "keep_encoding", "stream", "stream_element", "lint"]
+    )]
+    pub exc_c14n: bool,
+
+    /// Keep comments in canonical output
+    #[arg(long = "c14n-comments")]
+    pub c14n_comments: bool,
+
+    /// Prefixes declared as in inclusive canonicalization with --exc-c14n (comma-separated, #default for the default namespace)
+    #[arg(
+        long = "inclusive-namespaces",
+        value_name = "PREFIXES",
+        value_delimiter = ',',
+        requires = "exc_c14n"
+    )]
+    pub inclusive_namespaces: Vec<String>,
+
+    /// Treat the input as a series of documents (concatenated XML or NDJSON) and process each
+    #[arg(
+        long = "multi",
+        conflicts_with_all = ["in_place", "stream", "stream_element", "lint"]
+    )]
+    pub multi: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Validate a document against a schema
+    Validate(ValidateArgs),
+}
+
+#[derive(Args, Debug)]
+struct ValidateArgs {
+    /// Schema file (.xsd, .rng, .rnc, .sch or .dtd); the document's DOCTYPE if omitted
+    #[arg(short = 's', long = "schema", value_name = "SCHEMA")]
+    pub schema: Option<PathBuf>,
+
+    /// Input file (stdin if not provided)
+    pub file: Option<PathBuf>,
+
+    /// Input character encoding (overrides detection)
+    #[arg(long = "encoding", value_name = "ENCODING")]
+    pub encoding: Option<String>,
 }
 
 fn validate_indent(s: &str) -> Result<u8, String> {
@@ -106,34 +308,240 @@ fn validate_indent(s: &str) -> Result<u8, String> {
     }
 }
 
-fn main() -> Result<()> {
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {}", error_chain(&err));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// An error and its causes on one line, like `{:#}`, leaving out the
+/// causes that a message already ends with, such as the `{0}` of
+/// `#[error("I/O error: {0}")]`
+fn error_chain(err: &anyhow::Error) -> String {
+    let mut out = String::new();
+    let mut last = String::new();
+    for cause in err.chain() {
+        let message = cause.to_string();
+        if !last.ends_with(&message) {
+            if !out.is_empty() {
+                out.push_str(": ");
+            }
+            out.push_str(&message);
+        }
+        last = message;
+    }
+    out
+}
+
+fn run() -> Result<()> {
     let cli = Cli::parse();
 
+    if let Some(Command::Validate(args)) = &cli.command {
+        return run_validate(args);
+    }
+    if !cli.check.is_empty() {
+        return run_check(&cli, &cli.check, false);
+    }
+    if !cli.diff.is_empty() {
+        return run_check(&cli, &cli.diff, true);
+    }
+    if cli.stream {
+        return run_stream(&cli);
+    }
+    if let Some(element_path) = &cli.stream_element {
+        return run_records(&cli, element_path);
+    }
+
     // Read input (either from file or stdin)
-    let input = read_input(&cli)?;
+    let (bytes, compression) = read_input(&cli)?;
+    let decoded = decode(&bytes, cli.encoding.as_deref()).context("Failed to decode input")?;
+
+    // Files rewritten in place keep their encoding so the declaration stays true
+    let encoding = (cli.keep_encoding || cli.in_place).then_some((decoded.encoding, decoded.bom));
+    // Other output is UTF-8, and its declaration has to say so; lint messages
+    // point into the document as written
+    let input = match encoding {
+        None if !cli.lint => declare_utf8(&decoded.text),
+        _ => Cow::Borrowed(&*decoded.text),
+    };
+    let input: &str = &input;
 
     // Detect or use specified document type
-    let doc_type = determine_doc_type(&cli, &input);
+    let doc_type = determine_doc_type(&cli, input);
 
     // Parse document (zero-copy)
-    let doc = Document::parse(&input, doc_type).context("Failed to parse document")?;
-
+    let parse_opts = parse_options(&cli, cli.file.as_deref());
     // Build query if specified
     let query = build_query(&cli)?;
 
-    // Format options
-    let format_opts = FormatOptions {
-        indent: if cli.use_tabs {
-            Indent::Tab
+    if cli.multi {
+        return run_multi(
+            &cli,
+            input,
+            doc_type,
+            &parse_opts,
+            query,
+            compression,
+            encoding,
+        );
+    }
+
+    let doc =
+        parse_with_options(input, doc_type, &parse_opts).context("Failed to parse document")?;
+    if cli.lint {
+        return run_lint(&cli, &doc);
+    }
+
+    with_output(&cli, compression, encoding, |output| {
+        write_output(&cli, &doc, doc_type, query, output)
+    })
+}
+
+/// Build parse options from CLI flags for the input `file`
+fn parse_options(cli: &Cli, file: Option<&Path>) -> ParseOptions {
+    // Formatted documents keep their entity references and leave out DTD
+    // defaults, so formatting never changes content, unless DTD processing
+    // is asked for
+    let keep_references =
+        !(cli.json || cli.load_dtd || has_query(cli) || canonicalizer(cli).is_some());
+    ParseOptions {
+        // Canonical forms exist only for well-formed documents
+        strict: cli.strict || canonicalizer(cli).is_some(),
+        dtd_base_dir: cli.load_dtd.then(|| dtd_base_dir(file)),
+        limits: if cli.untrusted {
+            ParseLimits::untrusted()
         } else {
-            Indent::Spaces(cli.indent)
+            ParseLimits::default()
         },
-        color: color_mode(&cli),
-        compact: cli.compact,
+        keep_references,
+        ..Default::default()
+    }
+}
+
+/// Check that files are formatted, listing those that would change, or with
+/// `diff` print what would change
+fn run_check(cli: &Cli, files: &[PathBuf], diff: bool) -> Result<()> {
+    let options = format_options(cli)?;
+    let diff_colors = options.colors();
+    let format_opts = FormatOptions {
+        color: ColorMode::Never,
+        ..options
     };
+    let mut stdout = stdout().lock();
+    let mut changed = 0;
+    let mut failed = 0;
+    for path in files {
+        let result = (|| -> Result<bool> {
+            let (bytes, _) = read_file_or_stdin(Some(path), !cli.no_mmap)?;
+            let decoded =
+                decode(&bytes, cli.encoding.as_deref()).context("Failed to decode input")?;
+            let doc_type = determine_doc_type(cli, &decoded.text);
+            let doc = parse_with_options(&decoded.text, doc_type, &parse_options(cli, Some(path)))
+                .context("Failed to parse document")?;
+            let mut formatted = Vec::new();
+            document_formatter(cli, doc_type)
+                .format(&doc, &mut formatted, &format_opts)
+                .context("Failed to format document")?;
+            let formatted =
+                String::from_utf8(formatted).context("Formatted output is not UTF-8")?;
+            if diff {
+                let name = path.display().to_string();
+                diff::write_diff(&mut stdout, &name, &decoded.text, &formatted, &diff_colors)?;
+            }
+            Ok(formatted != decoded.text)
+        })();
+        match result {
+            Ok(false) => {}
+            Ok(true) => {
+                changed += 1;
+                if !diff {
+                    writeln!(stdout, "{}", path.display())?;
+                }
+            }
+            Err(err) => {
+                failed += 1;
+                eprintln!("{}: {}", path.display(), error_chain(&err));
+            }
+        }
+    }
 
-    // Prepare output writer
-    let mut output: Box<dyn Write> = if cli.in_place {
+    match (changed, failed) {
+        (0, 0) => Ok(()),
+        (changed, 0) => anyhow::bail!("{} would be reformatted", plural(changed, "file")),
+        (changed, failed) => anyhow::bail!(
+            "{} would be reformatted, {} could not be checked",
+            plural(changed, "file"),
+            plural(failed, "file")
+        ),
+    }
+}
+
+/// Format or query each document of a multi-document input on its own
+///
+/// A document that fails is reported on stderr and the rest still run.
+fn run_multi(
+    cli: &Cli,
+    input: &str,
+    doc_type: DocumentType,
+    parse_opts: &ParseOptions,
+    query: Option<Query<'static>>,
+    compression: Option<Compression>,
+    encoding: Option<(&'static Encoding, bool)>,
+) -> Result<()> {
+    let mut count = 0;
+    let mut failed = 0;
+    with_output(cli, compression, encoding, |output| {
+        for document in split_documents(input, doc_type) {
+            count += 1;
+            let mut buffer = Vec::new();
+            let source = match encoding {
+                Some(_) => Cow::Borrowed(document.source),
+                None => declare_utf8(document.source),
+            };
+            let result = parse_with_options(&source, doc_type, parse_opts)
+                .context("Failed to parse document")
+                .and_then(|doc| write_output(cli, &doc, doc_type, query.clone(), &mut buffer));
+            match result {
+                Ok(()) => {
+                    // One document per line at least, so compact JSON becomes NDJSON
+                    if !buffer.is_empty() && !buffer.ends_with(b"\n") {
+                        buffer.push(b'\n');
+                    }
+                    output.write_all(&buffer)?;
+                }
+                Err(err) => {
+                    failed += 1;
+                    eprintln!(
+                        "document {} at {}: {}",
+                        document.index + 1,
+                        document.position,
+                        error_chain(&err)
+                    );
+                }
+            }
+        }
+        Ok(())
+    })?;
+
+    if failed > 0 {
+        anyhow::bail!("{} of {} failed", failed, plural(count, "document"));
+    }
+    Ok(())
+}
+
+/// Open stdout or the in-place file, apply output compression and encoding,
+/// and pass the writer to `write`
+fn with_output(
+    cli: &Cli,
+    compression: Option<Compression>,
+    encoding: Option<(&'static Encoding, bool)>,
+    write: impl FnOnce(&mut dyn Write) -> Result<()>,
+) -> Result<()> {
+    let sink: Box<dyn Write> = if cli.in_place {
         let path = cli.file.as_ref().unwrap(); // Safe: requires="file"
         Box::new(BufWriter::new(
             File::create(path).context("Failed to open file for writing")?,
@@ -141,6 +549,44 @@ fn main() -> Result<()> {
     } else {
         Box::new(stdout().lock())
     };
+    let encode = |output: &mut dyn Write| match encoding {
+        Some((encoding, bom)) => {
+            let mut output = EncodingWriter::new(output, encoding, bom);
+            write(&mut output)?;
+            output.flush().map_err(anyhow::Error::from)
+        }
+        None => write(output),
+    };
+
+    // In-place edits keep the file's compression too, so it still matches its extension
+    let output_compression = cli
+        .output_compress
+        .or_else(|| compression.filter(|_| cli.in_place));
+    if let Some(compression) = output_compression {
+        let mut output =
+            CompressWriter::new(sink, compression).context("Failed to start compressed output")?;
+        encode(&mut output)?;
+        output
+            .finish()
+            .context("Failed to finish compressed output")?
+            .flush()?;
+    } else {
+        let mut output = sink;
+        encode(&mut output)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+/// Write the query results, or the whole formatted document, to `output`
+fn write_output(
+    cli: &Cli,
+    doc: &Document,
+    doc_type: DocumentType,
+    query: Option<Query<'static>>,
+    mut output: &mut dyn Write,
+) -> Result<()> {
+    let format_opts = format_options(cli)?;
 
     // Execute query or format entire document
     if let Some(query) = query {
@@ -149,58 +595,309 @@ fn main() -> Result<()> {
             extract_attr: cli.css_attr.clone(),
         };
 
-        let results = execute_query(&doc, query, &query_opts).context("Query execution failed")?;
+        match run_query(doc, query, &query_opts)? {
+            XPathResult::Nodes(results) if cli.count => writeln!(output, "{}", results.count())?,
+            XPathResult::Nodes(results) => {
+                // Use generic writer (&mut dyn Write implements Write)
+                format_query_results(results, &mut output, &query_opts, &format_opts)
+                    .context("Failed to format query results")?;
+            }
+            XPathResult::Values(values) if cli.count => writeln!(output, "{}", values.len())?,
+            XPathResult::Values(values) => {
+                for value in values {
+                    writeln!(output, "{}", value)?;
+                }
+            }
+        }
+    } else {
+        // Format entire document
+        document_formatter(cli, doc_type)
+            .format(doc, &mut output, &format_opts)
+            .context("Failed to format document")?;
+    }
+
+    output.flush()?;
+    Ok(())
+}
+
+/// Run a query; XPath expressions may also evaluate to values, like
+/// `count(//item)`
+fn run_query<'doc, 'input>(
+    doc: &'doc Document<'input>,
+    query: Query<'_>,
+    options: &QueryOptions,
+) -> Result<XPathResult<'doc, 'input>> {
+    match query {
+        Query::XPath(expr) | Query::Extract(expr) => evaluate_xpath(doc, expr),
+        query => execute_query(doc, query, options).map(XPathResult::Nodes),
+    }
+    .context("Query execution failed")
+}
+
+/// The formatter for a whole document
+fn document_formatter(cli: &Cli, doc_type: DocumentType) -> formatters::DocFormatter {
+    let formatter = if cli.json {
+        formatters::DocFormatter::Json(formatters::JsonFormatter)
+    } else if let Some(canonicalizer) = canonicalizer(cli) {
+        formatters::DocFormatter::Canonical(canonicalizer)
+    } else if cli.minify && doc_type != DocumentType::Json {
+        formatters::DocFormatter::Minified(Minifier {
+            strip_comments: cli.strip_comments,
+            strip_declarations: cli.strip_declarations,
+        })
+    } else {
+        get_formatter(doc_type)
+    };
+    if cli.highlight {
+        formatters::DocFormatter::Highlighted(Box::new(HtmlHighlighter {
+            formatter,
+            inline_styles: cli.inline_styles,
+        }))
+    } else {
+        formatter
+    }
+}
+
+/// Format or query the input incrementally, without reading it into memory
+fn run_stream(cli: &Cli) -> Result<()> {
+    let mut reader = open_stream(cli)?;
+    let mut output = BufWriter::new(stdout().lock());
+    if let Some(query) = build_query(cli)? {
+        let query_opts = QueryOptions {
+            with_tags: cli.with_tags,
+            extract_attr: None,
+        };
+        let results = StreamQuery::new(reader, query).context("Query execution failed")?;
 
         if cli.count {
-            writeln!(output, "{}", results.count())?;
+            let mut count = 0;
+            for result in results {
+                result.context("Failed to parse document")?;
+                count += 1;
+            }
+            writeln!(output, "{}", count)?;
         } else {
-            // Use generic writer (Box<dyn Write> implements Write)
-            format_query_results(results, &mut output, &query_opts, &format_opts)
+            format_stream_results(results, &mut output, &query_opts)
                 .context("Failed to format query results")?;
         }
     } else {
-        // Format entire document
-        let formatter = if cli.json {
-            use formatters::JsonFormatter;
-            formatters::DocFormatter::Json(JsonFormatter)
+        format_stream(&mut reader, &mut output, &format_options(cli)?)
+            .context("Failed to format document")?;
+    }
+
+    output.flush()?;
+    Ok(())
+}
+
+/// Process each element matching `--stream-element` as a document of its own
+fn run_records(cli: &Cli, element_path: &str) -> Result<()> {
+    let records = split_records(open_stream(cli)?, element_path).context("Invalid element path")?;
+    let query = build_query(cli)?;
+    let query_opts = QueryOptions {
+        with_tags: cli.with_tags,
+        extract_attr: cli.css_attr.clone(),
+    };
+    // One compact JSON document per line (NDJSON)
+    let json_opts = FormatOptions {
+        compact: true,
+        ..format_options(cli)?
+    };
+
+    let mut output = BufWriter::new(stdout().lock());
+    let mut count = 0;
+    for record in records {
+        let record = record.context("Failed to parse document")?;
+        let doc = record.document();
+
+        if let Some(query) = query.clone() {
+            match run_query(&doc, query, &query_opts)? {
+                XPathResult::Nodes(results) if cli.count => count += results.count(),
+                XPathResult::Nodes(results) => {
+                    format_query_results(results, &mut output, &query_opts, &json_opts)
+                        .context("Failed to format query results")?;
+                }
+                XPathResult::Values(values) if cli.count => count += values.len(),
+                XPathResult::Values(values) => {
+                    for value in values {
+                        writeln!(output, "{}", value)?;
+                    }
+                }
+            }
+        } else if cli.count {
+            count += 1;
         } else {
-            get_formatter(doc_type)
-        };
+            formatters::JsonFormatter
+                .format(&doc, &mut output, &json_opts)
+                .context("Failed to format document")?;
+            writeln!(output)?;
+        }
+    }
 
-        formatter
-            .format(&doc, &mut output, &format_opts)
-            .context("Failed to format document")?;
+    if cli.count {
+        writeln!(output, "{}", count)?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+/// Validate the input against a schema, printing one line per violation
+fn run_validate(args: &ValidateArgs) -> Result<()> {
+    let schema = match &args.schema {
+        Some(path) => Some(load_schema(path).context("Failed to load schema")?),
+        None => None,
+    };
+
+    let (bytes, _) = read_file_or_stdin(args.file.as_deref(), true)?;
+    let decoded = decode(&bytes, args.encoding.as_deref()).context("Failed to decode input")?;
+    // Without a schema, the DOCTYPE's external subset is needed as well
+    let parse_opts = ParseOptions {
+        strict: true,
+        dtd_base_dir: schema.is_none().then(|| dtd_base_dir(args.file.as_deref())),
+        ..Default::default()
+    };
+    let doc = parse_with_options(&decoded.text, DocumentType::Xml, &parse_opts)
+        .context("Failed to parse document")?;
+
+    let label = args
+        .file
+        .as_ref()
+        .map_or_else(|| "-".to_string(), |path| path.display().to_string());
+    let errors = match &schema {
+        Some(schema) => schema.validate(&doc),
+        None => validate_doctype(&doc),
+    };
+    let mut output = BufWriter::new(stdout().lock());
+    for error in &errors {
+        match error.position {
+            Some(position) => writeln!(output, "{}:{}: {}", label, position, error.message)?,
+            None => writeln!(output, "{}: {}", label, error.message)?,
+        }
+    }
+    output.flush()?;
+
+    match errors.len() {
+        0 => {
+            writeln!(output, "{}: valid", label)?;
+            output.flush()?;
+            Ok(())
+        }
+        1 => anyhow::bail!("{} is invalid (1 error)", label),
+        n => anyhow::bail!("{} is invalid ({} errors)", label, n),
+    }
+}
+
+/// Print the problems found while parsing, rustc-style
+fn run_lint(cli: &Cli, doc: &Document) -> Result<()> {
+    let label = cli
+        .file
+        .as_ref()
+        .map_or_else(|| "-".to_string(), |path| path.display().to_string());
+    let diagnostics = doc.diagnostics();
+    let mut output = BufWriter::new(stdout().lock());
+    for diagnostic in diagnostics {
+        writeln!(output, "{}", diagnostic.render(doc.source(), &label))?;
     }
 
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .count();
+    let warnings = diagnostics.len() - errors;
+    let summary = match (errors, warnings) {
+        (0, 0) => "no problems found".to_string(),
+        (0, w) => plural(w, "warning"),
+        (e, 0) => plural(e, "error"),
+        (e, w) => format!("{} and {}", plural(e, "error"), plural(w, "warning")),
+    };
+    if errors > 0 {
+        output.flush()?;
+        anyhow::bail!("{}: {}", label, summary);
+    }
+    writeln!(output, "{}: {}", label, summary)?;
     output.flush()?;
     Ok(())
 }
 
-/// Read input from file or stdin
-fn read_input(cli: &Cli) -> Result<String> {
-    let mut input = String::new();
+fn plural(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {}", noun),
+        n => format!("{} {}s", n, noun),
+    }
+}
+
+/// Open the input as an event reader without reading it into memory
+fn open_stream(cli: &Cli) -> Result<XmlReader<Box<dyn Read>>> {
+    let input: Box<dyn Read> = if let Some(path) = &cli.file {
+        Box::new(
+            File::open(path).with_context(|| format!("Failed to open file: {}", path.display()))?,
+        )
+    } else {
+        if atty::is(atty::Stream::Stdin) {
+            anyhow::bail!("No input provided. Use --help for usage information.");
+        }
+        Box::new(stdin().lock())
+    };
+    let fallback = cli.file.as_deref().and_then(Compression::from_path);
+    let input = decompress_reader(BufReader::new(input), fallback)
+        .context("Failed to read compressed input")?;
+    Ok(match &cli.encoding {
+        Some(label) => XmlReader::with_encoding(input, encoding_for_label(label)?),
+        None => XmlReader::new(input),
+    })
+}
+
+/// External DTDs are resolved against the input file's directory
+fn dtd_base_dir(file: Option<&Path>) -> PathBuf {
+    file.and_then(|path| path.parent())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
+
+/// Read raw input bytes from file or stdin, decompressing them if needed
+fn read_input(cli: &Cli) -> Result<(InputBuffer, Option<Compression>)> {
+    // A file rewritten in place must not stay mapped while it is truncated
+    read_file_or_stdin(cli.file.as_deref(), !(cli.no_mmap || cli.in_place))
+}
 
-    if let Some(path) = &cli.file {
-        File::open(path)
-            .with_context(|| format!("Failed to open file: {}", path.display()))?
-            .read_to_string(&mut input)
-            .context("Failed to read file")?;
+fn read_file_or_stdin(
+    file: Option<&Path>,
+    mmap: bool,
+) -> Result<(InputBuffer, Option<Compression>)> {
+    let input = if let Some(path) = file {
+        let input = if mmap {
+            // SAFETY: rxq never writes to a mapped input (`--in-place` turns
+            // mapping off). Another process rewriting the file while rxq
+            // runs can change what was read or raise SIGBUS; `--no-mmap`
+            // avoids that for files that may change.
+            unsafe { InputBuffer::open(path) }
+        } else {
+            InputBuffer::read_file(path)
+        };
+        input.with_context(|| format!("Failed to read file: {}", path.display()))?
     } else {
         // Check if stdin is a terminal (no piped input)
         if atty::is(atty::Stream::Stdin) {
             anyhow::bail!("No input provided. Use --help for usage information.");
         }
 
-        stdin()
-            .read_to_string(&mut input)
-            .context("Failed to read from stdin")?;
-    }
+        InputBuffer::read(stdin()).context("Failed to read from stdin")?
+    };
+
+    let compression = Compression::detect(&input).or_else(|| file.and_then(Compression::from_path));
+    let input = match compression {
+        Some(compression) => InputBuffer::Owned(
+            compression
+                .decompress(&input)
+                .with_context(|| format!("Failed to decompress {} input", compression))?,
+        ),
+        None => input,
+    };
 
     if input.is_empty() {
         anyhow::bail!("Input is empty");
     }
 
-    Ok(input)
+    Ok((input, compression))
 }
 
 /// Determine document type based on CLI flags and content
@@ -212,6 +909,11 @@ fn determine_doc_type(cli: &Cli, input: &str) -> DocumentType {
     }
 }
 
+/// Whether a query selects what is written instead of the whole document
+fn has_query(cli: &Cli) -> bool {
+    cli.xpath.is_some() || cli.extract.is_some() || cli.css_query.is_some()
+}
+
 /// Build query from CLI arguments
 fn build_query(cli: &Cli) -> Result<Option<Query<'static>>> {
     if let Some(xpath) = &cli.xpath {
@@ -230,14 +932,82 @@ fn build_query(cli: &Cli) -> Result<Option<Query<'static>>> {
     }
 }
 
+/// Build format options from CLI flags
+fn format_options(cli: &Cli) -> Result<FormatOptions> {
+    let color = color_mode(cli);
+    Ok(FormatOptions {
+        indent: if cli.use_tabs {
+            Indent::Tab
+        } else {
+            Indent::Spaces(cli.indent)
+        },
+        color,
+        compact: cli.compact || cli.minify,
+        preserve_whitespace: cli.preserve_whitespace.clone(),
+        max_width: cli.max_width,
+        attribute_layout: cli.attribute_layout,
+        sort_attributes: cli.sort_attributes,
+        theme: if color == ColorMode::Never && !cli.inline_styles {
+            ColorScheme::default()
+        } else {
+            load_theme(cli)?
+        },
+    })
+}
+
+/// The default colors overridden by the theme file, then by `RXQ_THEME`
+///
+/// The theme file is `--theme`, or else `rxq/theme` in the user's config
+/// directory if it exists.
+fn load_theme(cli: &Cli) -> Result<ColorScheme> {
+    let mut theme = ColorScheme::default();
+    let path = cli.theme.clone().or_else(|| {
+        let config = env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config.join("rxq").join("theme")).filter(|path| path.exists())
+    });
+    if let Some(path) = path {
+        let source = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read theme {}", path.display()))?;
+        theme
+            .apply(&source)
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("Invalid theme {}", path.display()))?;
+    }
+    if let Some(spec) = env::var_os("RXQ_THEME") {
+        theme
+            .apply(&spec.to_string_lossy())
+            .map_err(anyhow::Error::msg)
+            .context("Invalid RXQ_THEME")?;
+    }
+    Ok(theme)
+}
+
+/// The canonicalizer selected by `--c14n` or `--exc-c14n`, if any
+fn canonicalizer(cli: &Cli) -> Option<Canonicalizer> {
+    if cli.exc_c14n {
+        Some(Canonicalizer {
+            inclusive_prefixes: cli.inclusive_namespaces.clone(),
+            ..Canonicalizer::exclusive(cli.c14n_comments)
+        })
+    } else if cli.c14n {
+        Some(Canonicalizer::inclusive(cli.c14n_comments))
+    } else {
+        None
+    }
+}
+
 /// Determine color mode from CLI flags
 fn color_mode(cli: &Cli) -> ColorMode {
-    if cli.in_place {
-        // Never use colors for in-place editing
+    if cli.in_place || cli.output_compress.is_some() {
+        // Never use colors for in-place editing or compressed output
         ColorMode::Never
     } else if cli.force_color {
         ColorMode::Always
-    } else if cli.no_color {
+    } else if cli.no_color || env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        // https://no-color.org: an explicit --color still wins
         ColorMode::Never
     } else {
         ColorMode::Auto
@@ -282,4 +1052,15 @@ mod tests {
         let cli = Cli::parse_from(["rxq", "-c"]);
         assert_eq!(color_mode(&cli), ColorMode::Always);
     }
+
+    #[test]
+    fn test_error_chain() {
+        let io = std::io::Error::new(std::io::ErrorKind::BrokenPipe, "broken pipe");
+        let err =
+            anyhow::Error::from(rxq_core::FormatError::from(io)).context("Failed to write output");
+        assert_eq!(
+            error_chain(&err),
+            "Failed to write output: I/O error: broken pipe"
+        );
+    }
 }
@@ -1,7 +1,9 @@
 use assert_cmd::cargo;
 use assert_cmd::Command;
 use predicates::prelude::*;
+use rxq_core::parser::compress::{CompressWriter, Compression};
 use std::fs;
+use std::io::Write;
 use std::path::PathBuf;
 
 // Helper to get path to test data
@@ -76,6 +78,29 @@ fn test_xpath_extract() {
         .stdout(predicate::str::contains("John"));
 }
 
+#[test]
+fn test_xpath_expressions() {
+    let xml = "<r><a id=\"1\">x</a><a id=\"2\">y</a></r>";
+    rxq_cmd()
+        .args(["-e", "count(//a)"])
+        .write_stdin(xml)
+        .assert()
+        .success()
+        .stdout("2\n");
+    rxq_cmd()
+        .args(["-x", "//a[last()]/@id"])
+        .write_stdin(xml)
+        .assert()
+        .success()
+        .stdout("2\n");
+    rxq_cmd()
+        .args(["-x", "//a[. = 'x']"])
+        .write_stdin(xml)
+        .assert()
+        .success()
+        .stdout("x\n");
+}
+
 #[test]
 fn test_html_format() {
     let input = get_test_data_path("html/unformatted.html");
@@ -126,3 +151,836 @@ fn test_depth_limit() {
         .assert()
         .success();
 }
+
+#[test]
+fn test_strict_rejects_malformed_xml() {
+    rxq_cmd()
+        .write_stdin("<root><a></b></root>")
+        .arg("--strict")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "not well-formed at 1:10: mismatched end tag: expected </a>, found </b>",
+        ));
+}
+
+#[test]
+fn test_latin1_input_is_transcoded() {
+    let input = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><a>caf\xE9</a>".to_vec();
+
+    rxq_cmd()
+        .write_stdin(input.clone())
+        .arg("--extract")
+        .arg("//a")
+        .assert()
+        .success()
+        .stdout("café\n");
+
+    rxq_cmd()
+        .write_stdin(input.clone())
+        .arg("--no-color")
+        .arg("--keep-encoding")
+        .arg("--extract")
+        .arg("//a")
+        .assert()
+        .success()
+        .stdout(predicate::eq(b"caf\xE9\n" as &[u8]));
+
+    // Formatted as UTF-8, the document declares UTF-8 and reads back the same
+    let formatted = rxq_cmd()
+        .write_stdin(input.clone())
+        .arg("--no-color")
+        .assert()
+        .success()
+        .stdout("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<a>café</a>\n")
+        .get_output()
+        .stdout
+        .clone();
+    rxq_cmd()
+        .write_stdin(formatted.clone())
+        .arg("--no-color")
+        .assert()
+        .success()
+        .stdout(predicate::eq(formatted.as_slice()));
+
+    rxq_cmd()
+        .write_stdin(input)
+        .arg("--no-color")
+        .arg("--keep-encoding")
+        .assert()
+        .success()
+        .stdout(predicate::eq(
+            b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?>\n<a>caf\xE9</a>\n" as &[u8],
+        ));
+}
+
+#[test]
+fn test_encoding_override() {
+    rxq_cmd()
+        .write_stdin(b"<a>\x82\xA0</a>".to_vec())
+        .arg("--encoding")
+        .arg("shift_jis")
+        .arg("--extract")
+        .arg("//a")
+        .assert()
+        .success()
+        .stdout("あ\n");
+
+    rxq_cmd()
+        .write_stdin("<a/>")
+        .arg("--encoding")
+        .arg("klingon")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown encoding 'klingon'"));
+}
+
+#[test]
+fn test_stream_mode() {
+    let input = "<root><item id=\"1\">a</item><skip><item>b</item></skip><item/></root>";
+
+    rxq_cmd()
+        .write_stdin(input)
+        .arg("--stream")
+        .arg("-x")
+        .arg("/root/item")
+        .assert()
+        .success()
+        .stdout("a\n\n");
+
+    rxq_cmd()
+        .write_stdin(input)
+        .arg("--stream")
+        .arg("-n")
+        .arg("-x")
+        .arg("//item")
+        .assert()
+        .success()
+        .stdout("<item id=\"1\">a</item>\n<item>b</item>\n<item/>\n");
+
+    rxq_cmd()
+        .write_stdin(input)
+        .arg("--stream")
+        .arg("--no-color")
+        .assert()
+        .success()
+        .stdout("<root>\n  <item id=\"1\">a</item>\n  <skip>\n    <item>b</item>\n  </skip>\n  <item/>\n</root>\n");
+
+    rxq_cmd()
+        .write_stdin(input)
+        .arg("--stream")
+        .arg("-x")
+        .arg("/root/item/@id")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not supported in streaming mode"));
+}
+
+#[test]
+fn test_stream_element_ndjson() {
+    let input = "<rows><row id=\"1\"><name>a</name></row><row id=\"2\"><name>b</name></row></rows>";
+
+    rxq_cmd()
+        .write_stdin(input)
+        .arg("--stream-element")
+        .arg("row")
+        .assert()
+        .success()
+        .stdout("{\"row\":{\"@id\":\"1\",\"name\":\"a\"}}\n{\"row\":{\"@id\":\"2\",\"name\":\"b\"}}\n");
+
+    rxq_cmd()
+        .write_stdin(input)
+        .arg("--stream-element")
+        .arg("row")
+        .arg("-x")
+        .arg("//name")
+        .assert()
+        .success()
+        .stdout("a\nb\n");
+}
+
+#[test]
+fn test_dtd_entities_and_defaults() {
+    let input = get_test_data_path("xml/dtd/catalog.xml");
+
+    // Internal subset only: &publisher; lives in the external DTD
+    rxq_cmd()
+        .arg(&input)
+        .arg("--extract")
+        .arg("//book")
+        .assert()
+        .success()
+        .stdout("Animal Books by &publisher;\nAnimal Books\n");
+
+    rxq_cmd()
+        .arg(&input)
+        .arg("--load-dtd")
+        .arg("--extract")
+        .arg("//book")
+        .assert()
+        .success()
+        .stdout("Animal Books by O'Reilly\nAnimal Books\n");
+
+    rxq_cmd()
+        .arg(&input)
+        .arg("--load-dtd")
+        .arg("--no-color")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "<book id=\"b1\" format=\"paper\">Animal Books by O'Reilly</book>",
+        ));
+}
+
+#[test]
+fn test_validate_xsd() {
+    let schema = get_test_data_path("xml/xsd/library.xsd");
+
+    rxq_cmd()
+        .arg("validate")
+        .arg("--schema")
+        .arg(&schema)
+        .arg(get_test_data_path("xml/xsd/library.xml"))
+        .assert()
+        .success()
+        .stdout(predicate::str::ends_with("library.xml: valid\n"));
+
+    let invalid = get_test_data_path("xml/xsd/invalid.xml");
+    rxq_cmd()
+        .arg("validate")
+        .arg("-s")
+        .arg(&schema)
+        .arg(&invalid)
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "invalid.xml:3:3: value '0596007647' of attribute 'isbn' on <book> does not match pattern '\\d{3}-\\d{10}'",
+        ))
+        .stdout(predicate::str::contains(
+            "invalid.xml:5:5: element <year> is not expected here; expected <author>",
+        ))
+        .stdout(predicate::str::contains(
+            "invalid.xml:10:5: value '1200' of element <year> must be at least 1450",
+        ))
+        .stderr(predicate::str::contains("is invalid (4 errors)"));
+}
+
+#[test]
+fn test_validate_relaxng() {
+    let valid = get_test_data_path("xml/xsd/library.xml");
+    let invalid = get_test_data_path("xml/xsd/invalid.xml");
+
+    for schema in ["xml/relaxng/library.rng", "xml/relaxng/library.rnc"] {
+        rxq_cmd()
+            .arg("validate")
+            .arg("-s")
+            .arg(get_test_data_path(schema))
+            .arg(&valid)
+            .assert()
+            .success()
+            .stdout(predicate::str::ends_with("library.xml: valid\n"));
+
+        rxq_cmd()
+            .arg("validate")
+            .arg("-s")
+            .arg(get_test_data_path(schema))
+            .arg(&invalid)
+            .assert()
+            .failure()
+            .stdout(predicate::str::contains(
+                "invalid.xml:3:3: value 'yes' of attribute 'available' on <book> is not a valid boolean",
+            ))
+            .stdout(predicate::str::contains(
+                "invalid.xml:5:5: element <year> is not expected here; expected <author>",
+            ))
+            .stdout(predicate::str::contains(
+                "invalid.xml:10:5: value '1200' of element <year> must be at least 1450",
+            ))
+            .stderr(predicate::str::contains("is invalid (5 errors)"));
+    }
+}
+
+#[test]
+fn test_validate_schematron() {
+    let schema = get_test_data_path("xml/schematron/library.sch");
+
+    rxq_cmd()
+        .arg("validate")
+        .arg("--schema")
+        .arg(&schema)
+        .arg(get_test_data_path("xml/xsd/library.xml"))
+        .assert()
+        .success();
+
+    rxq_cmd()
+        .arg("validate")
+        .arg("--schema")
+        .arg(&schema)
+        .arg(get_test_data_path("xml/xsd/invalid.xml"))
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "invalid.xml:3:3: Book \"XML in a Nutshell\" has no author",
+        ))
+        .stdout(predicate::str::contains("invalid.xml:3:3: Use true or false for available"))
+        .stdout(predicate::str::contains("invalid.xml:7:3: Year 1200 is before printing"))
+        .stderr(predicate::str::contains("is invalid (3 errors)"));
+}
+
+#[test]
+fn test_validate_reports_schema_errors() {
+    rxq_cmd()
+        .arg("validate")
+        .arg("--schema")
+        .arg(get_test_data_path("xml/xsd/missing.xsd"))
+        .arg(get_test_data_path("xml/xsd/library.xml"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Failed to load schema"));
+}
+
+#[test]
+fn test_validate_dtd() {
+    rxq_cmd()
+        .arg("validate")
+        .arg(get_test_data_path("xml/dtd/catalog.xml"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("catalog.xml: valid"));
+
+    rxq_cmd()
+        .arg("validate")
+        .arg("--schema")
+        .arg(get_test_data_path("xml/dtd/catalog.dtd"))
+        .arg(get_test_data_path("xml/dtd/catalog.xml"))
+        .assert()
+        .success();
+
+    rxq_cmd()
+        .arg("validate")
+        .arg(get_test_data_path("xml/dtd/invalid.xml"))
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "invalid.xml:4:3: value 'hardcover' of attribute 'format' on <book> must be one of 'paper', 'ebook'",
+        ))
+        .stdout(predicate::str::contains("invalid.xml:5:3: value 'b1' of attribute 'id' on <book> is a duplicate ID"))
+        .stdout(predicate::str::contains("invalid.xml:5:17: no declaration for element <title>"))
+        .stdout(predicate::str::contains("invalid.xml:6:3: missing required attribute 'id' on <book>"))
+        .stderr(predicate::str::contains("is invalid (5 errors)"));
+
+    rxq_cmd()
+        .arg("validate")
+        .arg(get_test_data_path("xml/xsd/library.xml"))
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("document has no DOCTYPE"));
+}
+
+#[test]
+fn test_untrusted_limits() {
+    let deep = format!("{}{}", "<a>".repeat(300), "</a>".repeat(300));
+
+    rxq_cmd().write_stdin(deep.clone()).assert().success();
+
+    rxq_cmd()
+        .arg("--untrusted")
+        .write_stdin(deep)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "nesting depth limit exceeded at 1:769: more than 256 levels",
+        ));
+}
+
+#[test]
+fn test_lint() {
+    rxq_cmd()
+        .arg("--lint")
+        .write_stdin("<root>\n  <a>&nbsp;</b>\n</root>")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "error[undefined-entity]: undefined entity '&nbsp;'\n --> -:2:6\n  |\n2 |   <a>&nbsp;</b>\n  |      ^^^^^^\n",
+        ))
+        .stdout(predicate::str::contains("error[stray-end-tag]"))
+        .stdout(predicate::str::contains(
+            "error[unclosed-tag]: element <a> is never closed\n --> -:2:3\n",
+        ))
+        .stderr(predicate::str::contains("-: 4 errors"));
+
+    rxq_cmd()
+        .arg("--lint")
+        .arg(get_test_data_path("xml/dtd/catalog.xml"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("warning[unexpanded-entity]"))
+        .stdout(predicate::str::contains("catalog.xml: 1 warning"));
+
+    rxq_cmd()
+        .arg("--lint")
+        .write_stdin("<root/>")
+        .assert()
+        .success()
+        .stdout("-: no problems found\n");
+
+    rxq_cmd()
+        .args(["-m", "--lint"])
+        .write_stdin("<p>x</div><span class=a class=b>")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "error[stray-end-tag]: unexpected end tag </div>\n --> -:1:5\n",
+        ))
+        .stdout(predicate::str::contains("error[duplicate-attribute]"))
+        .stdout(predicate::str::contains("error[unclosed-tag]: element <span> is never closed"))
+        .stderr(predicate::str::contains("-: 3 errors"));
+}
+
+#[test]
+fn test_no_mmap() {
+    let file_path = get_test_data_path("xml/formatted.xml");
+    let mapped = rxq_cmd().arg(&file_path).assert().success();
+    let expected = String::from_utf8(mapped.get_output().stdout.clone()).unwrap();
+
+    rxq_cmd()
+        .arg("--no-mmap")
+        .arg(&file_path)
+        .assert()
+        .success()
+        .stdout(expected);
+
+    let empty = tempfile::NamedTempFile::new().unwrap();
+    rxq_cmd()
+        .arg(empty.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Input is empty"));
+}
+
+fn compress(data: &[u8], compression: Compression) -> Vec<u8> {
+    let mut writer = CompressWriter::new(Vec::new(), compression).unwrap();
+    writer.write_all(data).unwrap();
+    writer.finish().unwrap()
+}
+
+#[test]
+fn test_compressed_input() {
+    let input = fs::read(get_test_data_path("xml/unformatted.xml")).unwrap();
+    let expected = fs::read_to_string(get_test_data_path("xml/formatted.xml")).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+
+    for compression in [
+        Compression::Gzip,
+        Compression::Zstd,
+        Compression::Bzip2,
+        Compression::Xz,
+    ] {
+        let compressed = compress(&input, compression);
+        rxq_cmd()
+            .write_stdin(compressed.clone())
+            .assert()
+            .success()
+            .stdout(expected.clone());
+
+        let path = dir
+            .path()
+            .join(format!("input.xml.{}", compression.extension()));
+        fs::write(&path, &compressed).unwrap();
+        rxq_cmd()
+            .arg(&path)
+            .arg("--stream")
+            .assert()
+            .success()
+            .stdout(expected.clone());
+    }
+
+    let path = dir.path().join("plain.xml.gz");
+    fs::write(&path, &input).unwrap();
+    rxq_cmd()
+        .arg(&path)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Failed to decompress gzip input"));
+}
+
+#[test]
+fn test_output_compress() {
+    let input = fs::read(get_test_data_path("xml/unformatted.xml")).unwrap();
+    let expected = fs::read_to_string(get_test_data_path("xml/formatted.xml")).unwrap();
+
+    let output = rxq_cmd()
+        .arg("--output-compress")
+        .arg("zstd")
+        .write_stdin(input.clone())
+        .assert()
+        .success();
+    let output = &output.get_output().stdout;
+    assert_eq!(Compression::detect(output), Some(Compression::Zstd));
+    assert_eq!(
+        Compression::Zstd.decompress(output).unwrap(),
+        expected.as_bytes()
+    );
+
+    // In-place edits keep the compression of the file
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("input.xml.gz");
+    fs::write(&path, compress(&input, Compression::Gzip)).unwrap();
+    rxq_cmd().arg("-i").arg(&path).assert().success();
+    let rewritten = fs::read(&path).unwrap();
+    assert_eq!(
+        Compression::Gzip.decompress(&rewritten).unwrap(),
+        expected.as_bytes()
+    );
+}
+
+#[test]
+fn test_multi_document() {
+    let document = fs::read_to_string(get_test_data_path("xml/multiple.xml")).unwrap();
+
+    rxq_cmd()
+        .arg("--multi")
+        .arg("-C")
+        .arg("-x")
+        .arg("//item")
+        .write_stdin(format!("{0}{0}", document))
+        .assert()
+        .success()
+        .stdout("4\n4\n");
+
+    rxq_cmd()
+        .arg("--multi")
+        .arg("--json")
+        .arg("--compact")
+        .write_stdin("<?xml version=\"1.0\"?>\n<a>1</a>\n<?xml version=\"1.0\"?>\n<b x=\"2\"/>\n")
+        .assert()
+        .success()
+        .stdout("{\"a\":\"1\"}\n{\"b\":{\"@x\":\"2\"}}\n");
+
+    // A broken document is reported and the others are still processed
+    rxq_cmd()
+        .arg("--multi")
+        .arg("--strict")
+        .arg("-x")
+        .arg("//b")
+        .write_stdin("<a><b>1</b></a>\n<a><b>2</c></a>\n<a><b>3</b></a>\n")
+        .assert()
+        .failure()
+        .stdout("1\n3\n")
+        .stderr(predicate::str::contains(
+            "document 2 at 2:1: Failed to parse document",
+        ))
+        .stderr(predicate::str::contains("1 of 3 documents failed"));
+
+    // Invalid JSON is reported as such, with its cause once
+    rxq_cmd()
+        .arg("--multi")
+        .write_stdin("{\"a\":1}\n{\"b\":}\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "document 2 at 2:1: Failed to format document: formatting failed: invalid JSON: expected value at line 1 column 6\n",
+        ));
+}
+
+#[test]
+fn test_multi_ndjson() {
+    rxq_cmd()
+        .arg("--multi")
+        .arg("--compact")
+        .write_stdin("{\"id\": 1}\n\n{\"id\": [2, 3]}\n")
+        .assert()
+        .success()
+        .stdout("{\"id\":1}\n{\"id\":[2,3]}\n");
+}
+
+#[test]
+fn test_html_formatter() {
+    rxq_cmd()
+        .arg("--html")
+        .write_stdin("<p>a<br>b <em>c</em></p><pre> x\n  y</pre><input disabled>")
+        .assert()
+        .success()
+        .stdout(
+            "<html>\n  <head></head>\n  <body>\n    <p>a<br>b <em>c</em></p>\n    \
+             <pre> x\n  y</pre>\n    <input disabled>\n  </body>\n</html>\n",
+        );
+}
+
+#[test]
+fn test_check() {
+    let dir = tempfile::tempdir().unwrap();
+    let formatted = dir.path().join("formatted.xml");
+    let unformatted = dir.path().join("unformatted.xml");
+    fs::write(&unformatted, "<?xml version=\"1.0\"?><a><?pi x?><b> text </b></a>").unwrap();
+
+    // Formatting the output again changes nothing
+    let output = rxq_cmd().arg(&unformatted).output().unwrap();
+    fs::write(&formatted, &output.stdout).unwrap();
+    rxq_cmd()
+        .arg(&formatted)
+        .assert()
+        .success()
+        .stdout(String::from_utf8(output.stdout).unwrap());
+
+    rxq_cmd()
+        .arg("--check")
+        .arg(&formatted)
+        .assert()
+        .success()
+        .stdout("");
+
+    rxq_cmd()
+        .arg("--check")
+        .arg(&formatted)
+        .arg(&unformatted)
+        .assert()
+        .failure()
+        .stdout(format!("{}\n", unformatted.display()))
+        .stderr(predicate::str::contains("1 file would be reformatted"));
+}
+
+#[test]
+fn test_diff() {
+    let dir = tempfile::tempdir().unwrap();
+    let formatted = dir.path().join("formatted.xml");
+    let unformatted = dir.path().join("unformatted.xml");
+    fs::write(&formatted, "<a>\n  <b/>\n</a>\n").unwrap();
+    fs::write(&unformatted, "<a><b/></a>\n").unwrap();
+
+    rxq_cmd()
+        .arg("--diff")
+        .arg(&formatted)
+        .arg(&unformatted)
+        .assert()
+        .code(1)
+        .stdout(format!(
+            "--- a/{0}\n+++ b/{0}\n@@ -1 +1,3 @@\n-<a><b/></a>\n+<a>\n+  <b/>\n+</a>\n",
+            unformatted.display()
+        ))
+        .stderr(predicate::str::contains("1 file would be reformatted"));
+
+    rxq_cmd().arg("--diff").arg(&formatted).assert().success().stdout("");
+
+    rxq_cmd()
+        .arg("--diff")
+        .arg(&unformatted)
+        .arg("--color")
+        .env("RXQ_THEME", "diff-insert=blue")
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains("\x1b[31m-<a><b/></a>\x1b[0m"))
+        .stdout(predicate::str::contains("\x1b[34m+<a>\x1b[0m"));
+}
+
+#[test]
+fn test_color_theme() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::create_dir(dir.path().join("rxq")).unwrap();
+    fs::write(dir.path().join("rxq/theme"), "# warm\ntag = bold 208\nvalue = #ff8000\n").unwrap();
+
+    rxq_cmd()
+        .arg("--color")
+        .env("XDG_CONFIG_HOME", dir.path())
+        .write_stdin("<a x=\"1\">t</a>")
+        .assert()
+        .success()
+        .stdout(
+            "\x1b[1;38;5;208m<a\x1b[0m \x1b[32mx\x1b[0m=\x1b[38;2;255;128;0m\"1\"\x1b[0m\
+             \x1b[1;38;5;208m>\x1b[0mt\x1b[1;38;5;208m</a>\x1b[0m\n",
+        );
+
+    // RXQ_THEME overrides the theme file
+    rxq_cmd()
+        .arg("--color")
+        .env("XDG_CONFIG_HOME", dir.path())
+        .env("RXQ_THEME", "tag=blue; text=underline")
+        .write_stdin("<a>t</a>")
+        .assert()
+        .success()
+        .stdout("\x1b[34m<a\x1b[0m\x1b[34m>\x1b[0m\x1b[4mt\x1b[0m\x1b[34m</a>\x1b[0m\n");
+
+    rxq_cmd()
+        .env("NO_COLOR", "1")
+        .env("RXQ_THEME", "tag=red")
+        .write_stdin("<a>t</a>")
+        .assert()
+        .success()
+        .stdout("<a>t</a>\n");
+
+    rxq_cmd()
+        .arg("--color")
+        .env("RXQ_THEME", "tag=reddish")
+        .write_stdin("<a/>")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid color 'reddish'"));
+}
+
+#[test]
+fn test_minify() {
+    let input = "<?xml version=\"1.0\"?>\n<!-- settings -->\n<config>\n  <name> x </name>\n  \
+                 <p>Hello <b>big</b> <i>world</i></p>\n  <pre xml:space=\"preserve\"> a\n b </pre>\n</config>\n";
+
+    rxq_cmd()
+        .arg("--minify")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(
+            "<?xml version=\"1.0\"?><!-- settings --><config><name> x </name><p>Hello <b>big</b> <i>world</i></p>\
+             <pre xml:space=\"preserve\"> a\n b </pre></config>\n",
+        );
+
+    rxq_cmd()
+        .arg("--minify")
+        .arg("--strip-comments")
+        .arg("--strip-declarations")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("<config><name>"));
+
+    rxq_cmd()
+        .arg("--html")
+        .arg("--minify")
+        .write_stdin("<div class=\"a\" title=\"x y\">\n  <p>One\n  two</p>\n</div>")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("<div class=a title=\"x y\"><p>One two</p></div>"));
+}
+
+#[test]
+fn test_canonical_xml() {
+    let input = "<?xml version=\"1.0\"?>\r\n<!-- c --><doc xmlns:a=\"urn:a\" b='2' a=\"1\"><e/><a:x>&#65;<![CDATA[<]]></a:x></doc>\n";
+
+    rxq_cmd()
+        .arg("--c14n")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("<doc xmlns:a=\"urn:a\" a=\"1\" b=\"2\"><e></e><a:x>A&lt;</a:x></doc>");
+
+    rxq_cmd()
+        .arg("--exc-c14n")
+        .arg("--c14n-comments")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout("<!-- c -->\n<doc a=\"1\" b=\"2\"><e></e><a:x xmlns:a=\"urn:a\">A&lt;</a:x></doc>");
+
+    // Canonical forms exist only for well-formed documents
+    rxq_cmd()
+        .arg("--c14n")
+        .write_stdin("<a><b></a>")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("mismatched end tag"));
+}
+
+#[test]
+fn test_attribute_wrapping() {
+    let input = "<root><item id=\"1\" name=\"long enough\" xmlns:x=\"urn:x\"/><b z=\"1\" a=\"2\"/></root>";
+
+    rxq_cmd()
+        .arg("--max-width")
+        .arg("30")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(
+            "<root>\n  <item\n    id=\"1\"\n    name=\"long enough\"\n    xmlns:x=\"urn:x\"/>\n  \
+             <b z=\"1\" a=\"2\"/>\n</root>\n",
+        );
+
+    rxq_cmd()
+        .arg("--stream")
+        .arg("--max-width")
+        .arg("30")
+        .arg("--attribute-layout")
+        .arg("align")
+        .arg("--sort-attributes")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(
+            "<root>\n  <item xmlns:x=\"urn:x\"\n        id=\"1\"\n        name=\"long enough\"/>\n  \
+             <b a=\"2\" z=\"1\"/>\n</root>\n",
+        );
+}
+
+#[test]
+fn test_mixed_content_and_preserved_whitespace() {
+    let input = "<doc><p>Hello <b>world</b>!</p><pre xml:space=\"preserve\">  a\n b</pre><code> x </code></doc>";
+
+    rxq_cmd()
+        .arg("--preserve-whitespace")
+        .arg("code")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(
+            "<doc>\n  <p>Hello <b>world</b>!</p>\n  <pre xml:space=\"preserve\">  a\n b</pre>\n  \
+             <code> x </code>\n</doc>\n",
+        );
+
+    // Streaming produces the same layout
+    rxq_cmd()
+        .arg("--stream")
+        .arg("--preserve-whitespace")
+        .arg("code")
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(
+            "<doc>\n  <p>Hello <b>world</b>!</p>\n  <pre xml:space=\"preserve\">  a\n b</pre>\n  \
+             <code> x </code>\n</doc>\n",
+        );
+}
+
+#[test]
+fn test_highlight() {
+    rxq_cmd()
+        .arg("--highlight")
+        .write_stdin("<a x=\"1\">t &amp; u</a>")
+        .assert()
+        .success()
+        .stdout(
+            "<pre class=\"rxq\"><span class=\"rxq-tag\">&lt;a</span> <span class=\"rxq-attr\">x</span>=\
+             <span class=\"rxq-value\">\"1\"</span><span class=\"rxq-tag\">&gt;</span>\
+             <span class=\"rxq-text\">t</span> <span class=\"rxq-entity\">&amp;amp;</span> \
+             <span class=\"rxq-text\">u</span><span class=\"rxq-tag\">&lt;/a&gt;</span>\n</pre>\n",
+        );
+
+    rxq_cmd()
+        .arg("--highlight")
+        .arg("--inline-styles")
+        .env("RXQ_THEME", "tag=#112233")
+        .write_stdin("<a/>")
+        .assert()
+        .success()
+        .stdout(
+            "<pre class=\"rxq\"><span style=\"color: #112233\">&lt;a</span>\
+             <span style=\"color: #112233\">/&gt;</span>\n</pre>\n",
+        );
+}
+
+#[test]
+fn test_json_colors() {
+    rxq_cmd()
+        .arg("--json")
+        .arg("--color")
+        .env("RXQ_THEME", "key=red; string=blue")
+        .write_stdin("<a>t</a>")
+        .assert()
+        .success()
+        .stdout("{\n  \x1b[31m\"a\"\x1b[0m: \x1b[34m\"t\"\x1b[0m\n}\n");
+
+    rxq_cmd()
+        .arg("--json")
+        .arg("--no-color")
+        .write_stdin("<a>t</a>")
+        .assert()
+        .success()
+        .stdout("{\n  \"a\": \"t\"\n}\n");
+}
@@ -25,6 +25,35 @@ fn bench_parse_medium(c: &mut Criterion) {
     });
 }
 
+/// Many records with attributes, text, comments and entity references
+fn large_xml() -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n<records>\n");
+    for i in 0..5000 {
+        xml.push_str(&format!(
+            "  <record id=\"{i}\" kind=\"item\">\n    <name>Record &amp; {i}</name>\n    \
+             <!-- note -->\n    <value unit=\"cm\">{i}.5</value>\n  </record>\n"
+        ));
+    }
+    xml.push_str("</records>\n");
+    xml
+}
+
+/// The XML parser against tl, which parsed XML before it; tl only
+/// tokenizes here, without building a tree
+fn bench_parse_large(c: &mut Criterion) {
+    let xml = large_xml();
+    let mut group = c.benchmark_group("parse_large_xml");
+    group.bench_function("xml_parser", |b| {
+        b.iter(|| Document::parse(black_box(&xml), DocumentType::Xml));
+    });
+    group.bench_function("tl", |b| {
+        b.iter(|| {
+            tl::parse(black_box(&xml), tl::ParserOptions::default()).map(|dom| dom.nodes().len())
+        });
+    });
+    group.finish();
+}
+
 fn bench_xpath_simple(c: &mut Criterion) {
     let doc = Document::parse(SMALL_XML, DocumentType::Xml).unwrap();
 
@@ -88,6 +117,7 @@ criterion_group!(
     benches,
     bench_parse_small,
     bench_parse_medium,
+    bench_parse_large,
     bench_xpath_simple,
     bench_xpath_predicate,
     bench_css_selector,
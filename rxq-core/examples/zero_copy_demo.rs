@@ -107,7 +107,7 @@ fn demonstrate_lifetime_safety() {
     
     println!("  ✓ Document created (borrows from xml)");
     println!("  ✓ Source string address: {:p}", xml.as_ptr());
-    println!("  ✓ Document references same memory");
+    println!("  ✓ Document references same memory: {:p}", doc.source().as_ptr());
     
     // This demonstrates safety - uncommenting would cause compile error:
     // drop(xml);  // ERROR: cannot drop xml while doc borrows it
@@ -130,9 +130,10 @@ fn memory_comparison_example() {
     // }
     
     // Zero-copy approach (rxq):
-    // struct NodeRef<'input> {
-    //     vdom: &'input VDom<'input>,  // Reference only
-    //     handle: NodeHandle,          // Small integer
+    // struct NodeRef<'a> {
+    //     source: &'a str,  // Reference only
+    //     tree: &'a Tree,   // Nodes store byte spans into source
+    //     id: usize,        // Small integer
     // }
     
     println!("Memory comparison for 1000 nodes:");
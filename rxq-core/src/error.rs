@@ -1,13 +1,186 @@
 //! Error types for rxq-core
 
+use std::fmt;
+use std::ops::Range;
 use thiserror::Error;
 
+/// A location in the source text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    /// Byte offset from the start of the input
+    pub offset: usize,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column, counted in characters
+    pub column: usize,
+}
+
+impl Position {
+    /// Compute line and column for a byte offset into `source`
+    pub fn from_offset(source: &str, offset: usize) -> Self {
+        let offset = offset.min(source.len());
+        let before = &source.as_bytes()[..offset];
+        let line_start = before
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |i| i + 1);
+        let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+        // Count characters, not bytes: skip UTF-8 continuation bytes
+        let column = before[line_start..]
+            .iter()
+            .filter(|&&b| b & 0xC0 != 0x80)
+            .count()
+            + 1;
+
+        Self {
+            offset,
+            line,
+            column,
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// How serious a [`Diagnostic`] is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// Well-formed, but probably not what was meant
+    Warning,
+    /// The document is not well-formed; the parser recovered
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A problem found while parsing, which the parser recovered from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Stable identifier of the kind of problem, such as `unclosed-tag`
+    pub code: &'static str,
+    /// Byte range of the offending source text
+    pub span: Range<usize>,
+    /// Start of `span`
+    pub position: Position,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Render in the style of rustc, with the offending line and a marker
+    ///
+    /// `origin` names the input, e.g. a file path. The marker covers the
+    /// span up to the end of its first line.
+    pub fn render(&self, source: &str, origin: &str) -> String {
+        let start = self.span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+        let line = source[line_start..line_end].trim_end_matches('\r');
+        // A span starting on the trimmed `\r` still gets a one-column marker
+        let end = self.span.end.min(line_start + line.len()).max(start);
+
+        // Keep tabs in the indent so the marker lines up with the text
+        let indent: String = source[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = source[start..end].chars().count().max(1);
+        let number = self.position.line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        format!(
+            "{}[{}]: {}\n{}--> {}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+            self.severity,
+            self.code,
+            self.message,
+            gutter,
+            origin,
+            self.position,
+            gutter,
+            number,
+            line,
+            gutter,
+            indent,
+            "^".repeat(width)
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}[{}]: {}", self.position, self.severity, self.code, self.message)
+    }
+}
+
+/// Offsets of line starts, for converting many byte offsets to positions
+pub(crate) struct LineIndex {
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub(crate) fn new(source: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { line_starts }
+    }
+
+    pub(crate) fn position_in(&self, source: &str, offset: usize) -> Position {
+        let offset = offset.min(source.len());
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        let line_start = self.line_starts[line - 1];
+        let column = source.as_bytes()[line_start..offset]
+            .iter()
+            .filter(|&&b| b & 0xC0 != 0x80)
+            .count()
+            + 1;
+
+        Position {
+            offset,
+            line,
+            column,
+        }
+    }
+}
+
 /// Errors that can occur during document parsing
 #[derive(Error, Debug)]
 pub enum ParseError {
     #[error("XML/HTML syntax error: {0}")]
     SyntaxError(String),
+
+    #[error("not well-formed at {position}: {message}")]
+    NotWellFormed { position: Position, message: String },
     
+    #[error("entity expansion limit exceeded at {position}: {message}")]
+    EntityExpansionLimit { position: Position, message: String },
+
+    #[error("nesting depth limit exceeded at {position}: more than {limit} levels")]
+    DepthLimit { position: Position, limit: usize },
+
+    #[error("attribute count limit exceeded at {position}: more than {limit} attributes")]
+    AttributeCountLimit { position: Position, limit: usize },
+
+    #[error("attribute length limit exceeded at {position}: value longer than {limit} bytes")]
+    AttributeLengthLimit { position: Position, limit: usize },
+
+    #[error("node count limit exceeded at {position}: more than {limit} nodes")]
+    NodeCountLimit { position: Position, limit: usize },
+
+    #[error("text length limit exceeded at {position}: text longer than {limit} bytes")]
+    TextLengthLimit { position: Position, limit: usize },
+
     #[error("unsupported document type")]
     UnsupportedType,
     
@@ -16,6 +189,25 @@ pub enum ParseError {
     
     #[error("empty or invalid input")]
     EmptyInput,
+
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+/// Errors that can occur while loading a schema
+#[derive(Error, Debug)]
+pub enum SchemaError {
+    #[error("cannot read schema {path}: {source}")]
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+
+    #[error("schema {path} is not well-formed: {source}")]
+    Parse { path: String, source: ParseError },
+
+    #[error("invalid schema {path}: {message}")]
+    Invalid { path: String, message: String },
 }
 
 /// Errors that can occur during query execution
@@ -35,6 +227,9 @@ pub enum QueryError {
     
     #[error("attribute '{0}' not found")]
     AttributeNotFound(String),
+
+    #[error("query not supported in streaming mode: {0}")]
+    UnsupportedStreaming(String),
 }
 
 /// Errors that can occur during formatting
@@ -51,6 +246,9 @@ pub enum FormatError {
     
     #[error("color output not supported")]
     ColorNotSupported,
+
+    #[error(transparent)]
+    ParseError(#[from] ParseError),
 }
 
 #[cfg(test)]
@@ -63,10 +261,53 @@ mod tests {
         assert_eq!(err.to_string(), "XML/HTML syntax error: unexpected token");
     }
 
+    #[test]
+    fn test_position_from_offset() {
+        let source = "<a>\n  <b>\n</a>";
+        let pos = Position::from_offset(source, 6);
+        assert_eq!((pos.line, pos.column), (2, 3));
+        assert_eq!(pos.to_string(), "2:3");
+
+        let pos = Position::from_offset(source, 0);
+        assert_eq!((pos.line, pos.column), (1, 1));
+    }
+
+    #[test]
+    fn test_diagnostic_render() {
+        let source = "<a>\n\t<b>&nbsp;</b>\n</a>";
+        let diagnostic = Diagnostic {
+            severity: Severity::Error,
+            code: "undefined-entity",
+            span: 8..14,
+            position: Position::from_offset(source, 8),
+            message: "undefined entity '&nbsp;'".to_string(),
+        };
+        assert_eq!(
+            diagnostic.render(source, "doc.xml"),
+            "error[undefined-entity]: undefined entity '&nbsp;'\n --> doc.xml:2:5\n  |\n2 | \t<b>&nbsp;</b>\n  | \t   ^^^^^^\n"
+        );
+        assert_eq!(diagnostic.to_string(), "2:5: error[undefined-entity]: undefined entity '&nbsp;'");
+
+        // A span at the end of input, after a trailing \r
+        let diagnostic = Diagnostic {
+            severity: Severity::Error,
+            code: "no-root",
+            span: 1..1,
+            position: Position::from_offset("\r", 1),
+            message: "no root element".to_string(),
+        };
+        assert_eq!(
+            diagnostic.render("\r", "-"),
+            "error[no-root]: no root element\n --> -:1:2\n  |\n1 | \n  |  ^\n"
+        );
+    }
+
     #[test]
     fn test_error_from() {
         let io_err = std::io::Error::new(std::io::ErrorKind::NotFound, "file not found");
         let format_err = FormatError::from(io_err);
         assert!(matches!(format_err, FormatError::IoError(_)));
+        assert_eq!(format_err.to_string(), "I/O error: file not found");
+        assert_eq!(std::error::Error::source(&format_err).unwrap().to_string(), "file not found");
     }
 }
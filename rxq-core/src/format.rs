@@ -1,9 +1,25 @@
 //! Formatting and beautification with syntax highlighting
 
 use crate::error::FormatError;
-use crate::query::QueryOptions;
+use crate::parser::stream::{Event, XmlReader};
+use crate::query::{QueryOptions, StreamQuery};
 use crate::types::{Document, NodeRef, NodeType};
-use std::io::Write;
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+mod c14n;
+mod highlight;
+mod html;
+mod minify;
+mod theme;
+
+pub use c14n::Canonicalizer;
+pub use highlight::{stylesheet, HtmlHighlighter};
+pub use html::HtmlFormatter;
+pub use minify::Minifier;
+pub use theme::ColorScheme;
 
 /// Color mode for output
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -49,12 +65,63 @@ impl Indent {
     }
 }
 
+/// How the attributes of a start tag are laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AttributeLayout {
+    /// One per line, indented one level deeper, when the tag would be
+    /// wider than `max_width`
+    #[default]
+    Wrap,
+    /// One per line, aligned with the first attribute, when the tag would
+    /// be wider than `max_width`
+    Align,
+    /// Always one per line when there are two or more
+    OnePerLine,
+}
+
+impl fmt::Display for AttributeLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Wrap => "wrap",
+            Self::Align => "align",
+            Self::OnePerLine => "one-per-line",
+        })
+    }
+}
+
+impl FromStr for AttributeLayout {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "wrap" => Ok(Self::Wrap),
+            "align" => Ok(Self::Align),
+            "one-per-line" => Ok(Self::OnePerLine),
+            _ => Err(format!(
+                "unknown attribute layout '{}' (expected wrap, align or one-per-line)",
+                name
+            )),
+        }
+    }
+}
+
 /// Complete formatting configuration
 #[derive(Clone)]
 pub struct FormatOptions {
     pub indent: Indent,
     pub color: ColorMode,
     pub compact: bool, // For JSON: no indentation
+    /// Elements whose content is written exactly as parsed, like
+    /// `xml:space="preserve"`
+    pub preserve_whitespace: Vec<String>,
+    /// Preferred maximum line width, in characters; start tags that would be
+    /// wider have their attributes wrapped
+    pub max_width: Option<usize>,
+    pub attribute_layout: AttributeLayout,
+    /// Sort attributes by name (namespace declarations first)
+    pub sort_attributes: bool,
+    /// Colors used when colors are enabled
+    pub theme: ColorScheme,
 }
 
 impl Default for FormatOptions {
@@ -63,11 +130,21 @@ impl Default for FormatOptions {
             indent: Indent::Spaces(2),
             color: ColorMode::Auto,
             compact: false,
+            preserve_whitespace: Vec::new(),
+            max_width: None,
+            attribute_layout: AttributeLayout::Wrap,
+            sort_attributes: false,
+            theme: ColorScheme::default(),
         }
     }
 }
 
 impl FormatOptions {
+    /// Whether the content of an element must be written exactly as parsed
+    pub fn preserves_whitespace(&self, name: &str, xml_space: Option<&str>) -> bool {
+        xml_space == Some("preserve") || self.preserve_whitespace.iter().any(|n| n == name)
+    }
+
     /// Check if colors should be enabled
     pub fn use_colors(&self) -> bool {
         match self.color {
@@ -76,36 +153,13 @@ impl FormatOptions {
             ColorMode::Auto => atty::is(atty::Stream::Stdout),
         }
     }
-}
 
-/// ANSI color codes
-#[derive(Debug, Clone, Copy)]
-pub struct ColorScheme {
-    pub tag: &'static str,     // Yellow for tags
-    pub attr: &'static str,    // Green for attributes
-    pub comment: &'static str, // Blue for comments
-    pub value: &'static str,   // Green for values
-    pub reset: &'static str,
-}
-
-impl ColorScheme {
-    pub const fn default() -> Self {
-        Self {
-            tag: "\x1b[33m",     // Yellow
-            attr: "\x1b[32m",    // Green
-            comment: "\x1b[94m", // Bright Blue
-            value: "\x1b[32m",   // Green
-            reset: "\x1b[0m",
-        }
-    }
-
-    pub const fn none() -> Self {
-        Self {
-            tag: "",
-            attr: "",
-            comment: "",
-            value: "",
-            reset: "",
+    /// The theme if colors are enabled, no colors otherwise
+    pub fn colors(&self) -> ColorScheme {
+        if self.use_colors() {
+            self.theme.clone()
+        } else {
+            ColorScheme::none()
         }
     }
 }
@@ -120,7 +174,13 @@ pub trait Formatter {
     ) -> Result<(), FormatError>;
 }
 
-/// XML/HTML formatter
+/// XML formatter
+///
+/// Elements with mixed content (text next to child elements), elements with
+/// `xml:space="preserve"` and elements listed in
+/// [`FormatOptions::preserve_whitespace`] are written on one line exactly as
+/// parsed, so formatting never adds or removes significant whitespace.
+/// Use [`HtmlFormatter`] for HTML documents.
 pub struct XmlFormatter;
 
 impl Formatter for XmlFormatter {
@@ -130,16 +190,9 @@ impl Formatter for XmlFormatter {
         writer: &mut W,
         options: &FormatOptions,
     ) -> Result<(), FormatError> {
-        let colors = if options.use_colors() {
-            ColorScheme::default()
-        } else {
-            ColorScheme::none()
-        };
+        let colors = options.colors();
 
-        let vdom = doc.vdom();
-        for handle in vdom.children() {
-            let node = NodeRef::new(vdom, Some(*handle));
-            // eprintln!("Visiting node: {:?} Type: {:?}", node.tag_name(), node.node_type());
+        for node in doc.children() {
             self.format_node(node, writer, options, &colors, 0)?;
         }
         Ok(())
@@ -157,8 +210,13 @@ impl XmlFormatter {
     ) -> Result<(), FormatError> {
         match node.node_type() {
             NodeType::Element => self.format_element(node, writer, options, colors, level),
-            NodeType::Text => self.format_text(node, writer),
+            NodeType::Text => self.format_text(node, writer, colors),
             NodeType::Comment => self.format_comment(node, writer, options, colors, level),
+            NodeType::CData => self.format_cdata(node, writer, options, colors, level),
+            NodeType::ProcessingInstruction => {
+                self.format_processing_instruction(node, writer, options, colors, level)
+            }
+            NodeType::Doctype => self.format_doctype(node, writer, options, colors, level),
             _ => Ok(()),
         }
     }
@@ -175,50 +233,45 @@ impl XmlFormatter {
         let tag_name_str = tag_name.as_deref().unwrap_or("");
         let indent_str = options.indent.as_str(level);
 
-        // Handle XML Processing Instructions (e.g. <?xml ... ?>) which tl parses as empty tag
-        // Handle XML Processing Instructions (e.g. <?xml ... ?>) which tl parses as empty tag
-        if tag_name_str.is_empty() {
-            // Reconstruct XML declaration if possible, or print raw start
-            // Assuming standard <?xml ... ?> if empty tag
-            write!(writer, "<?xml")?;
-            for (name, value) in node.attributes() {
-                // tl parser hack: correct mangled "ersion" to "version"
-                let name = if name == "ersion" { "version" } else { &name };
-                write!(writer, " {}=\"{}\"", name, value)?;
-            }
-            writeln!(writer, "?>")?;
-
-            // Recurse children
-            for child in node.children() {
-                self.format_node(child, writer, options, colors, level)?;
-            }
-            return Ok(());
-        }
-
         // Opening tag
-        write!(writer, "{}{}<{}", indent_str, colors.tag, tag_name_str)?;
+        write!(
+            writer,
+            "{}{}<{}{}",
+            indent_str, colors.tag, tag_name_str, colors.reset
+        )?;
 
         // Attributes
-        for (name, value) in node.attributes() {
-            write!(
-                writer,
-                " {}{}{}=\"{}\"{}",
-                name, colors.attr, colors.reset, value, colors.reset
-            )?;
-        }
-
-        if node.has_children() {
+        let attributes = node.attributes().map(|(name, value)| (name, Some(value)));
+        let mut markup = String::new();
+        write_attributes(&mut markup, tag_name_str, attributes, options, colors, Some(level));
+        write!(writer, "{}", markup)?;
+
+        let xml_space = node.attr("xml:space");
+        if node.has_children()
+            && (options.preserves_whitespace(tag_name_str, xml_space.as_deref())
+                || is_mixed_content(node))
+        {
+            write!(writer, "{}>{}", colors.tag, colors.reset)?;
+            for child in node.children() {
+                write_verbatim(child, writer, colors)?;
+            }
+            writeln!(writer, "{}</{}>{}", colors.tag, tag_name_str, colors.reset)?;
+        } else if node.has_children() {
             // Check if children are only text
-            let is_text_only = node
-                .children()
-                .all(|c| c.node_type() == NodeType::Text || c.node_type() == NodeType::Raw);
+            let is_text_only = node.children().all(|c| {
+                matches!(c.node_type(), NodeType::Text | NodeType::CData | NodeType::Raw)
+            });
 
             if is_text_only {
                 write!(writer, "{}>{}", colors.tag, colors.reset)?;
-                // Format children inline
+                // Text content is written as is, so formatting never changes it
                 for child in node.children() {
                     if let Some(text) = child.text() {
-                        write!(writer, "{}", text.trim())?;
+                        if child.node_type() == NodeType::CData {
+                            write!(writer, "{}", colors.cdata(&text))?;
+                        } else {
+                            write!(writer, "{}", colors.text(&text))?;
+                        }
                     }
                 }
                 writeln!(writer, "{}</{}>{}", colors.tag, tag_name_str, colors.reset)?;
@@ -250,11 +303,12 @@ impl XmlFormatter {
         &self,
         node: NodeRef<'a, 'input>,
         writer: &mut W,
+        colors: &ColorScheme,
     ) -> Result<(), FormatError> {
         if let Some(text) = node.text() {
             let trimmed = text.trim();
             if !trimmed.is_empty() {
-                writeln!(writer, "{}", trimmed)?;
+                writeln!(writer, "{}", colors.text(trimmed))?;
             }
         }
         Ok(())
@@ -278,6 +332,199 @@ impl XmlFormatter {
         }
         Ok(())
     }
+
+    fn format_cdata<'a, 'input, W: Write>(
+        &self,
+        node: NodeRef<'a, 'input>,
+        writer: &mut W,
+        options: &FormatOptions,
+        colors: &ColorScheme,
+        level: usize,
+    ) -> Result<(), FormatError> {
+        let indent_str = options.indent.as_str(level);
+        if let Some(text) = node.text() {
+            writeln!(writer, "{}{}", indent_str, colors.cdata(&text))?;
+        }
+        Ok(())
+    }
+
+    fn format_processing_instruction<'a, 'input, W: Write>(
+        &self,
+        node: NodeRef<'a, 'input>,
+        writer: &mut W,
+        options: &FormatOptions,
+        colors: &ColorScheme,
+        level: usize,
+    ) -> Result<(), FormatError> {
+        let indent_str = options.indent.as_str(level);
+        if let Some((target, data)) = node.processing_instruction() {
+            writeln!(
+                writer,
+                "{}{}",
+                indent_str,
+                colors.processing_instruction(&target, &data)
+            )?;
+        }
+        Ok(())
+    }
+
+    fn format_doctype<'a, 'input, W: Write>(
+        &self,
+        node: NodeRef<'a, 'input>,
+        writer: &mut W,
+        options: &FormatOptions,
+        colors: &ColorScheme,
+        level: usize,
+    ) -> Result<(), FormatError> {
+        let indent_str = options.indent.as_str(level);
+        if let Some(doctype) = node.doctype() {
+            writeln!(
+                writer,
+                "{}{}{}{}",
+                indent_str, colors.tag, doctype, colors.reset
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Write the attributes of a start tag, each preceded by its separator
+///
+/// Attributes without a value (HTML `<input disabled>`) are written as a
+/// bare name. `level` is the indentation level of a tag that starts a line;
+/// tags within a line (`None`) are never wrapped.
+pub(crate) fn write_attributes<'a>(
+    out: &mut String,
+    tag_name: &str,
+    attributes: impl Iterator<Item = (Cow<'a, str>, Option<Cow<'a, str>>)>,
+    options: &FormatOptions,
+    colors: &ColorScheme,
+    level: Option<usize>,
+) {
+    let mut attributes: Vec<_> = attributes.collect();
+    if options.sort_attributes {
+        // Namespace declarations first, as they scope the other names
+        attributes.sort_by(|(a, _), (b, _)| {
+            let is_namespace = |name: &str| name == "xmlns" || name.starts_with("xmlns:");
+            is_namespace(b).cmp(&is_namespace(a)).then_with(|| a.cmp(b))
+        });
+    }
+
+    // Rendered attributes with their width in characters, without colors
+    let rendered: Vec<(String, usize)> = attributes
+        .iter()
+        .map(|(name, value)| match value {
+            Some(value) => {
+                let quote = if value.contains('"') { '\'' } else { '"' };
+                (
+                    colors.attribute(name, quote, value),
+                    name.chars().count() + value.chars().count() + 3,
+                )
+            }
+            None => (colors.paint(&colors.attr, name), name.chars().count()),
+        })
+        .collect();
+
+    let wrap = match level {
+        Some(level) if rendered.len() > 1 => match options.attribute_layout {
+            AttributeLayout::OnePerLine => Some(level),
+            _ => {
+                let indent_width = options.indent.as_str(level).chars().count();
+                let width = indent_width
+                    + tag_name.chars().count()
+                    + rendered.iter().map(|(_, width)| width + 1).sum::<usize>()
+                    + 2;
+                options
+                    .max_width
+                    .is_some_and(|max| width > max)
+                    .then_some(level)
+            }
+        },
+        _ => None,
+    };
+
+    let Some(level) = wrap else {
+        for (attribute, _) in &rendered {
+            out.push(' ');
+            out.push_str(attribute);
+        }
+        return;
+    };
+    let continuation = match options.attribute_layout {
+        AttributeLayout::Align => format!(
+            "{}{}",
+            options.indent.as_str(level),
+            " ".repeat(tag_name.chars().count() + 2)
+        ),
+        AttributeLayout::Wrap | AttributeLayout::OnePerLine => options.indent.as_str(level + 1),
+    };
+    for (i, (attribute, _)) in rendered.iter().enumerate() {
+        if i == 0 && options.attribute_layout == AttributeLayout::Align {
+            out.push(' ');
+        } else {
+            out.push('\n');
+            out.push_str(&continuation);
+        }
+        out.push_str(attribute);
+    }
+}
+
+/// Whether an element has both non-whitespace text (or CDATA) and child
+/// elements, comments or processing instructions
+fn is_mixed_content(node: NodeRef<'_, '_>) -> bool {
+    let mut text = false;
+    let mut markup = false;
+    for child in node.children() {
+        match child.node_type() {
+            NodeType::Text => text |= child.text().is_some_and(|t| !t.trim().is_empty()),
+            NodeType::CData => text = true,
+            NodeType::Element | NodeType::Comment | NodeType::ProcessingInstruction => {
+                markup = true
+            }
+            _ => {}
+        }
+    }
+    text && markup
+}
+
+/// Write a node and its descendants exactly as parsed
+fn write_verbatim<W: Write>(
+    node: NodeRef<'_, '_>,
+    writer: &mut W,
+    colors: &ColorScheme,
+) -> Result<(), FormatError> {
+    match node.node_type() {
+        NodeType::Element => {
+            let tag_name = node.tag_name().unwrap_or_default();
+            write!(writer, "{}<{}{}", colors.tag, tag_name, colors.reset)?;
+            for (name, value) in node.attributes() {
+                write!(writer, " {}", colors.attribute(&name, '"', &value))?;
+            }
+            if node.has_children() {
+                write!(writer, "{}>{}", colors.tag, colors.reset)?;
+                for child in node.children() {
+                    write_verbatim(child, writer, colors)?;
+                }
+                write!(writer, "{}</{}>{}", colors.tag, tag_name, colors.reset)?;
+            } else {
+                write!(writer, "{}/>{}", colors.tag, colors.reset)?;
+            }
+        }
+        NodeType::Text => write!(writer, "{}", colors.text(&node.text().unwrap_or_default()))?,
+        NodeType::CData => write!(writer, "{}", colors.cdata(&node.text().unwrap_or_default()))?,
+        NodeType::Comment => {
+            if let Some(comment) = node.comment() {
+                write!(writer, "{}{}{}", colors.comment, comment, colors.reset)?;
+            }
+        }
+        NodeType::ProcessingInstruction => {
+            if let Some((target, data)) = node.processing_instruction() {
+                write!(writer, "{}", colors.processing_instruction(&target, &data))?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
 }
 
 /// Format query results (streaming)
@@ -308,6 +555,269 @@ pub fn format_query_results<'doc, 'input: 'doc, W: Write>(
     Ok(())
 }
 
+/// Format query results taken from a stream
+pub fn format_stream_results<R: Read, W: Write>(
+    results: StreamQuery<R>,
+    writer: &mut W,
+    options: &QueryOptions,
+) -> Result<(), FormatError> {
+    for result in results {
+        let result = result?;
+        if options.with_tags {
+            writeln!(writer, "{}", result.markup)?;
+        } else {
+            writeln!(writer, "{}", result.text.trim())?;
+        }
+    }
+    Ok(())
+}
+
+/// Format an XML event stream without building a tree
+///
+/// Produces the same layout as [`XmlFormatter`] while holding only the text
+/// of the innermost open element in memory: an element's start tag is kept
+/// open until its first non-text child shows whether it fits on one line.
+/// Mixed content is recognized by text before the first child element; text
+/// that only follows child elements cannot be seen in time.
+pub fn format_stream<R: Read, W: Write>(
+    reader: &mut XmlReader<R>,
+    writer: &mut W,
+    options: &FormatOptions,
+) -> Result<(), FormatError> {
+    let colors = options.colors();
+
+    // Text and CDATA seen since the pending start tag
+    let mut pending: Option<Vec<(bool, String)>> = None;
+    let mut level = 0;
+    // Inside an element written as parsed
+    let mut verbatim: Option<Verbatim> = None;
+
+    while let Some(event) = reader.next_event()? {
+        if let Some(state) = &mut verbatim {
+            write_verbatim_event(writer, &colors, &event, state)?;
+            if state.depth == 0 {
+                verbatim = None;
+                level -= 1;
+                writeln!(writer)?;
+            }
+            continue;
+        }
+
+        if let Event::EndElement { name } = &event {
+            level -= 1;
+            let indent_str = options.indent.as_str(level);
+            match pending.take() {
+                Some(texts) if texts.is_empty() => {
+                    writeln!(writer, "{}/>{}", colors.tag, colors.reset)?;
+                }
+                Some(texts) => {
+                    write!(writer, "{}>{}", colors.tag, colors.reset)?;
+                    for (cdata, text) in texts {
+                        if cdata {
+                            write!(writer, "{}", colors.cdata(&text))?;
+                        } else {
+                            write!(writer, "{}", colors.text(&text))?;
+                        }
+                    }
+                    writeln!(writer, "{}</{}>{}", colors.tag, name, colors.reset)?;
+                }
+                None => {
+                    writeln!(
+                        writer,
+                        "{}{}</{}>{}",
+                        indent_str, colors.tag, name, colors.reset
+                    )?;
+                }
+            }
+            continue;
+        }
+
+        match (&mut pending, &event) {
+            (Some(texts), Event::Text(text)) => {
+                texts.push((false, text.to_string()));
+                continue;
+            }
+            (Some(texts), Event::CData(text)) => {
+                texts.push((true, text.to_string()));
+                continue;
+            }
+            _ => {}
+        }
+
+        if let Some(texts) = pending.take() {
+            // Text followed by markup: mixed content is written as parsed
+            let mixed = texts.iter().any(|(cdata, text)| *cdata || !text.trim().is_empty());
+            let markup = matches!(
+                event,
+                Event::StartElement { .. } | Event::Comment(_) | Event::ProcessingInstruction { .. }
+            );
+            if mixed && markup {
+                write!(writer, "{}>{}", colors.tag, colors.reset)?;
+                for (cdata, text) in texts {
+                    if cdata {
+                        write!(writer, "{}", colors.cdata(&text))?;
+                    } else {
+                        write!(writer, "{}", colors.text(&text))?;
+                    }
+                }
+                let mut state = Verbatim {
+                    depth: 1,
+                    open: false,
+                };
+                write_verbatim_event(writer, &colors, &event, &mut state)?;
+                verbatim = Some(state);
+                continue;
+            }
+
+            // A non-text child: the pending element is laid out as a block
+            writeln!(writer, "{}>{}", colors.tag, colors.reset)?;
+            for (cdata, text) in texts {
+                write_stream_text(writer, options, &colors, level, cdata, &text)?;
+            }
+        }
+
+        let indent_str = options.indent.as_str(level);
+        match event {
+            Event::StartElement {
+                name, attributes, ..
+            } => {
+                write!(
+                    writer,
+                    "{}{}<{}{}",
+                    indent_str, colors.tag, name, colors.reset
+                )?;
+                let xml_space = attributes
+                    .iter()
+                    .find(|(name, _)| *name == "xml:space")
+                    .map(|(_, value)| *value);
+                let mut markup = String::new();
+                write_attributes(
+                    &mut markup,
+                    name,
+                    attributes
+                        .iter()
+                        .map(|(name, value)| (Cow::Borrowed(*name), Some(Cow::Borrowed(*value)))),
+                    options,
+                    &colors,
+                    Some(level),
+                );
+                write!(writer, "{}", markup)?;
+                if options.preserves_whitespace(name, xml_space) {
+                    verbatim = Some(Verbatim {
+                        depth: 1,
+                        open: true,
+                    });
+                } else {
+                    pending = Some(Vec::new());
+                }
+                level += 1;
+            }
+            Event::Text(text) => write_stream_text(writer, options, &colors, level, false, text)?,
+            Event::CData(text) => write_stream_text(writer, options, &colors, level, true, text)?,
+            Event::Comment(comment) => {
+                writeln!(
+                    writer,
+                    "{}{}{}{}",
+                    indent_str, colors.comment, comment, colors.reset
+                )?;
+            }
+            Event::ProcessingInstruction { target, data } => {
+                writeln!(
+                    writer,
+                    "{}{}",
+                    indent_str,
+                    colors.processing_instruction(target, data)
+                )?;
+            }
+            Event::Doctype(doctype) => {
+                writeln!(
+                    writer,
+                    "{}{}{}{}",
+                    indent_str, colors.tag, doctype, colors.reset
+                )?;
+            }
+            Event::EndElement { .. } => unreachable!("handled above"),
+        }
+    }
+    Ok(())
+}
+
+/// State of an element that [`format_stream`] writes as parsed
+struct Verbatim {
+    /// Open elements, including the outermost verbatim one
+    depth: usize,
+    /// Whether the innermost start tag still lacks its `>`; it becomes `/>`
+    /// if the element turns out to be empty
+    open: bool,
+}
+
+/// Write one event inside an element written as parsed
+fn write_verbatim_event<W: Write>(
+    writer: &mut W,
+    colors: &ColorScheme,
+    event: &Event<'_>,
+    state: &mut Verbatim,
+) -> Result<(), FormatError> {
+    if let Event::EndElement { name } = event {
+        if state.open {
+            write!(writer, "{}/>{}", colors.tag, colors.reset)?;
+        } else {
+            write!(writer, "{}</{}>{}", colors.tag, name, colors.reset)?;
+        }
+        state.open = false;
+        state.depth -= 1;
+        return Ok(());
+    }
+
+    if state.open {
+        write!(writer, "{}>{}", colors.tag, colors.reset)?;
+        state.open = false;
+    }
+    match event {
+        Event::StartElement {
+            name, attributes, ..
+        } => {
+            write!(writer, "{}<{}{}", colors.tag, name, colors.reset)?;
+            for (name, value) in attributes {
+                write!(writer, " {}", colors.attribute(name, '"', value))?;
+            }
+            state.open = true;
+            state.depth += 1;
+        }
+        Event::Text(text) => write!(writer, "{}", colors.text(text))?,
+        Event::CData(text) => write!(writer, "{}", colors.cdata(text))?,
+        Event::Comment(comment) => {
+            write!(writer, "{}{}{}", colors.comment, comment, colors.reset)?
+        }
+        Event::ProcessingInstruction { target, data } => {
+            write!(writer, "{}", colors.processing_instruction(target, data))?
+        }
+        Event::Doctype(doctype) => write!(writer, "{}", colors.paint(&colors.tag, doctype))?,
+        Event::EndElement { .. } => unreachable!("handled above"),
+    }
+    Ok(())
+}
+
+/// Write text or CDATA that is laid out on its own line
+fn write_stream_text<W: Write>(
+    writer: &mut W,
+    options: &FormatOptions,
+    colors: &ColorScheme,
+    level: usize,
+    cdata: bool,
+    text: &str,
+) -> Result<(), FormatError> {
+    if cdata {
+        writeln!(writer, "{}{}", options.indent.as_str(level), colors.cdata(text))?;
+    } else {
+        let trimmed = text.trim();
+        if !trimmed.is_empty() {
+            writeln!(writer, "{}", colors.text(trimmed))?;
+        }
+    }
+    Ok(())
+}
+
 /// Simple text formatter (no processing)
 pub struct TextFormatter;
 
@@ -351,7 +861,7 @@ mod tests {
         let options = FormatOptions {
             indent: Indent::Spaces(2),
             color: ColorMode::Never,
-            compact: false,
+            ..FormatOptions::default()
         };
 
         formatter.format(&doc, &mut output, &options).unwrap();
@@ -361,6 +871,123 @@ mod tests {
         assert!(result.contains("</root>"));
     }
 
+    #[test]
+    fn test_format_mixed_content() {
+        let xml = "<doc><p>Hello <b>world</b>!</p><pre xml:space=\"preserve\"> a\n  <i> b </i></pre>\
+                   <list><item> x </item></list><code>\n  x</code></doc>";
+        let doc = Document::parse(xml, DocumentType::Xml).unwrap();
+        let options = FormatOptions {
+            color: ColorMode::Never,
+            preserve_whitespace: vec!["code".to_string()],
+            ..FormatOptions::default()
+        };
+
+        let mut output = Vec::new();
+        XmlFormatter.format(&doc, &mut output, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "<doc>\n  <p>Hello <b>world</b>!</p>\n  <pre xml:space=\"preserve\"> a\n  <i> b </i></pre>\n  \
+             <list>\n    <item> x </item>\n  </list>\n  <code>\n  x</code>\n</doc>\n"
+        );
+    }
+
+    #[test]
+    fn test_format_is_idempotent() {
+        let xml = "<?xml version=\"1.0\"?>\n<!DOCTYPE doc [<!ENTITY who \"World\"><!ATTLIST doc lang CDATA \"en\">]>\n\
+                   <?xml-stylesheet href=\"a.xsl\"?><doc a='say \"hi\"'>\n  <name>  padded  </name><blank> </blank>\
+                   <greeting>Hello, &who;!</greeting><code><![CDATA[a < b]]></code><note>x<!-- why --></note>\
+                   <?pi data?><empty></empty></doc>";
+        let options = FormatOptions {
+            color: ColorMode::Never,
+            ..FormatOptions::default()
+        };
+        let format = |source: &str| {
+            let parse_options = crate::parser::ParseOptions {
+                keep_references: true,
+                ..Default::default()
+            };
+            let doc = crate::parser::parse_with_options(source, DocumentType::Xml, &parse_options).unwrap();
+            let mut output = Vec::new();
+            XmlFormatter.format(&doc, &mut output, &options).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        let formatted = format(xml);
+        assert_eq!(
+            formatted,
+            "<?xml version=\"1.0\"?>\n<!DOCTYPE doc [<!ENTITY who \"World\"><!ATTLIST doc lang CDATA \"en\">]>\n\
+             <?xml-stylesheet href=\"a.xsl\"?>\n<doc a='say \"hi\"'>\n  <name>  padded  </name>\n  <blank> </blank>\n  \
+             <greeting>Hello, &who;!</greeting>\n  <code><![CDATA[a < b]]></code>\n  <note>x<!-- why --></note>\n  \
+             <?pi data?>\n  <empty/>\n</doc>\n"
+        );
+        assert_eq!(format(&formatted), formatted);
+
+        // A comment left open swallows the rest of the input
+        let formatted = format("<a><!-- open</a>");
+        assert_eq!(formatted, "<a>\n  <!-- open</a>-->\n</a>\n");
+        assert_eq!(format(&formatted), formatted);
+    }
+
+    #[test]
+    fn test_attribute_layout() {
+        let xml = "<root><item id=\"1\" name=\"long enough\" xmlns:x=\"urn:x\"/><b z=\"1\" a=\"2\"/></root>";
+        let doc = Document::parse(xml, DocumentType::Xml).unwrap();
+        let format = |attribute_layout, sort_attributes| {
+            let options = FormatOptions {
+                color: ColorMode::Never,
+                max_width: Some(30),
+                attribute_layout,
+                sort_attributes,
+                ..FormatOptions::default()
+            };
+            let mut output = Vec::new();
+            XmlFormatter.format(&doc, &mut output, &options).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(
+            format(AttributeLayout::Wrap, false),
+            "<root>\n  <item\n    id=\"1\"\n    name=\"long enough\"\n    xmlns:x=\"urn:x\"/>\n  <b z=\"1\" a=\"2\"/>\n</root>\n"
+        );
+        assert_eq!(
+            format(AttributeLayout::Align, true),
+            "<root>\n  <item xmlns:x=\"urn:x\"\n        id=\"1\"\n        name=\"long enough\"/>\n  <b a=\"2\" z=\"1\"/>\n</root>\n"
+        );
+        assert_eq!(
+            format(AttributeLayout::OnePerLine, false),
+            "<root>\n  <item\n    id=\"1\"\n    name=\"long enough\"\n    xmlns:x=\"urn:x\"/>\n  <b\n    z=\"1\"\n    a=\"2\"/>\n</root>\n"
+        );
+        assert_eq!("one-per-line".parse(), Ok(AttributeLayout::OnePerLine));
+        assert!("columns".parse::<AttributeLayout>().is_err());
+    }
+
+    #[test]
+    fn test_format_stream_matches_formatter() {
+        let xml = "<?xml version=\"1.0\"?><root a=\"1\"><!-- c --><empty z=\"1\" a=\"2\"></empty><text> hi </text><mixed>x<b/>y</mixed>\
+                   <pre xml:space=\"preserve\">  a\n <b> b </b><c/></pre><code>\n  x\n</code></root>";
+        let options = FormatOptions {
+            color: ColorMode::Never,
+            preserve_whitespace: vec!["code".to_string()],
+            ..FormatOptions::default()
+        };
+
+        let doc = Document::parse(xml, DocumentType::Xml).unwrap();
+        let format = |options: &FormatOptions| {
+            let mut expected = Vec::new();
+            XmlFormatter.format(&doc, &mut expected, options).unwrap();
+            let mut streamed = Vec::new();
+            format_stream(&mut XmlReader::new(xml.as_bytes()), &mut streamed, options).unwrap();
+            assert_eq!(String::from_utf8(streamed).unwrap(), String::from_utf8(expected).unwrap());
+        };
+        format(&options);
+        format(&FormatOptions {
+            max_width: Some(10),
+            attribute_layout: AttributeLayout::Align,
+            sort_attributes: true,
+            ..options.clone()
+        });
+    }
+
     #[test]
     fn test_color_scheme() {
         let colors = ColorScheme::default();
@@ -0,0 +1,385 @@
+//! Canonical XML output for signing and hashing
+//!
+//! Implements [Canonical XML 1.0](https://www.w3.org/TR/xml-c14n) and
+//! [Exclusive XML Canonicalization](https://www.w3.org/TR/xml-exc-c14n/)
+//! for whole documents, with or without comments. Two documents that are
+//! logically equivalent produce the same bytes:
+//!
+//! - the XML declaration and DOCTYPE are removed, CDATA sections become
+//!   escaped text, and empty elements are written with an end tag
+//! - line endings are normalized to `\n`, attribute values are normalized
+//!   (with DTD attribute types applied) and all text is re-escaped
+//! - namespace declarations come first, sorted by prefix, followed by the
+//!   attributes sorted by namespace URI and local name; superfluous
+//!   declarations are dropped
+//!
+//! Exclusive canonicalization only declares the namespaces an element or
+//! its attributes use, plus the prefixes listed in
+//! [`Canonicalizer::inclusive_prefixes`].
+
+use super::{FormatOptions, Formatter};
+use crate::error::FormatError;
+use crate::parser::dtd::AttributeType;
+use crate::types::{Document, DocumentType, NodeRef, NodeType};
+use crate::validate::{is_namespace_declaration, split_qname, unescape};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// Namespace prefixes mapped to their URI; the default namespace is `""`
+type Namespaces = BTreeMap<String, String>;
+
+/// Canonical XML serializer
+///
+/// Colors and indentation in [`FormatOptions`] are ignored: the output is
+/// always plain UTF-8 without added whitespace.
+#[derive(Debug, Clone, Default)]
+pub struct Canonicalizer {
+    /// Use Exclusive XML Canonicalization instead of Canonical XML 1.0
+    pub exclusive: bool,
+    /// Keep comments (the `#WithComments` variants)
+    pub with_comments: bool,
+    /// Prefixes handled as in inclusive canonicalization when `exclusive`
+    /// is set (the InclusiveNamespaces PrefixList); `#default` names the
+    /// default namespace
+    pub inclusive_prefixes: Vec<String>,
+}
+
+impl Formatter for Canonicalizer {
+    fn format<'input, W: Write>(
+        &self,
+        doc: &Document<'input>,
+        writer: &mut W,
+        _options: &FormatOptions,
+    ) -> Result<(), FormatError> {
+        if doc.doc_type() != DocumentType::Xml {
+            return Err(FormatError::FormatFailed(
+                "canonicalization requires an XML document".to_string(),
+            ));
+        }
+
+        let mut out = String::new();
+        let mut after_root = false;
+        for node in doc.children() {
+            match node.node_type() {
+                NodeType::Element => {
+                    self.write_element(node, doc, &Namespaces::new(), &Namespaces::new(), &mut out);
+                    after_root = true;
+                }
+                NodeType::Comment | NodeType::ProcessingInstruction => {
+                    let mut markup = String::new();
+                    self.write_node(
+                        node,
+                        doc,
+                        &Namespaces::new(),
+                        &Namespaces::new(),
+                        &mut markup,
+                    );
+                    if markup.is_empty() {
+                        continue;
+                    }
+                    // Nodes outside the document element are separated from it by a newline
+                    if after_root {
+                        out.push('\n');
+                        out.push_str(&markup);
+                    } else {
+                        out.push_str(&markup);
+                        out.push('\n');
+                    }
+                }
+                _ => {}
+            }
+        }
+        writer.write_all(out.as_bytes())?;
+        Ok(())
+    }
+}
+
+impl Canonicalizer {
+    /// Canonical XML 1.0, optionally with comments
+    pub fn inclusive(with_comments: bool) -> Self {
+        Self {
+            with_comments,
+            ..Self::default()
+        }
+    }
+
+    /// Exclusive XML Canonicalization, optionally with comments
+    pub fn exclusive(with_comments: bool) -> Self {
+        Self {
+            exclusive: true,
+            with_comments,
+            ..Self::default()
+        }
+    }
+
+    /// Write a child node
+    ///
+    /// `in_scope` holds the namespaces declared on the ancestors, and
+    /// `rendered` the declarations already written by output ancestors.
+    fn write_node(
+        &self,
+        node: NodeRef<'_, '_>,
+        doc: &Document<'_>,
+        in_scope: &Namespaces,
+        rendered: &Namespaces,
+        out: &mut String,
+    ) {
+        match node.node_type() {
+            NodeType::Element => self.write_element(node, doc, in_scope, rendered, out),
+            NodeType::Text => {
+                let text = node.text().unwrap_or_default();
+                escape_text(&unescape(&normalize_line_endings(&text)), out);
+            }
+            NodeType::CData => {
+                let text = node.text().unwrap_or_default();
+                escape_text(&normalize_line_endings(&text), out);
+            }
+            NodeType::Comment if self.with_comments => {
+                if let Some(comment) = node.comment() {
+                    out.push_str(&normalize_line_endings(&comment));
+                }
+            }
+            NodeType::ProcessingInstruction => {
+                if let Some((target, data)) = node.processing_instruction() {
+                    // The XML declaration is not part of the canonical form
+                    if target.eq_ignore_ascii_case("xml") {
+                        return;
+                    }
+                    out.push_str("<?");
+                    out.push_str(&target);
+                    if !data.is_empty() {
+                        out.push(' ');
+                        out.push_str(&normalize_line_endings(&data));
+                    }
+                    out.push_str("?>");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn write_element(
+        &self,
+        node: NodeRef<'_, '_>,
+        doc: &Document<'_>,
+        in_scope: &Namespaces,
+        rendered: &Namespaces,
+        out: &mut String,
+    ) {
+        let name = node.tag_name().unwrap_or_default();
+
+        let mut in_scope = in_scope.clone();
+        let mut attributes = Vec::new();
+        for (attr_name, value) in node.attributes() {
+            let value = normalize_attribute(doc, &name, &attr_name, &value);
+            if is_namespace_declaration(&attr_name) {
+                let prefix = attr_name.strip_prefix("xmlns").unwrap_or_default();
+                let prefix = prefix.strip_prefix(':').unwrap_or(prefix);
+                if prefix != "xml" {
+                    in_scope.insert(prefix.to_string(), value);
+                }
+            } else {
+                attributes.push((attr_name, value));
+            }
+        }
+
+        // Namespace declarations to write, sorted by prefix
+        let mut declarations = Namespaces::new();
+        let mut consider = |prefix: &str| {
+            let uri = in_scope.get(prefix).map_or("", String::as_str);
+            let written = rendered.get(prefix).map_or("", String::as_str);
+            if uri != written && (in_scope.contains_key(prefix) || prefix.is_empty()) {
+                declarations.insert(prefix.to_string(), uri.to_string());
+            }
+        };
+        if self.exclusive {
+            // Only the namespaces this element visibly uses
+            let (prefix, _) = split_qname(&name);
+            consider(prefix);
+            for (attr_name, _) in &attributes {
+                let (prefix, _) = split_qname(attr_name);
+                if !prefix.is_empty() && prefix != "xml" {
+                    consider(prefix);
+                }
+            }
+            for prefix in &self.inclusive_prefixes {
+                let prefix = if prefix == "#default" { "" } else { prefix };
+                if in_scope.contains_key(prefix) {
+                    consider(prefix);
+                }
+            }
+        } else {
+            for prefix in in_scope.keys() {
+                consider(prefix);
+            }
+            consider("");
+        }
+
+        // Attributes sorted by namespace URI, then local name
+        let mut attributes: Vec<_> = attributes
+            .into_iter()
+            .map(|(attr_name, value)| {
+                let (prefix, local) = split_qname(&attr_name);
+                let namespace = match prefix {
+                    "" => String::new(),
+                    "xml" => XML_NAMESPACE.to_string(),
+                    prefix => in_scope.get(prefix).cloned().unwrap_or_default(),
+                };
+                ((namespace, local.to_string()), attr_name.to_string(), value)
+            })
+            .collect();
+        attributes.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+
+        out.push('<');
+        out.push_str(&name);
+        for (prefix, uri) in &declarations {
+            out.push_str(" xmlns");
+            if !prefix.is_empty() {
+                out.push(':');
+                out.push_str(prefix);
+            }
+            out.push_str("=\"");
+            escape_attribute(uri, out);
+            out.push('"');
+        }
+        for (_, attr_name, value) in &attributes {
+            out.push(' ');
+            out.push_str(attr_name);
+            out.push_str("=\"");
+            escape_attribute(value, out);
+            out.push('"');
+        }
+        out.push('>');
+
+        let mut rendered = rendered.clone();
+        rendered.extend(declarations);
+        for child in node.children() {
+            self.write_node(child, doc, &in_scope, &rendered, out);
+        }
+
+        out.push_str("</");
+        out.push_str(&name);
+        out.push('>');
+    }
+}
+
+/// Normalize a raw attribute value as an XML processor would
+///
+/// Literal whitespace becomes a space and references are replaced; values of
+/// attributes the DTD declares with a type other than CDATA also have their
+/// spaces collapsed and trimmed.
+fn normalize_attribute(doc: &Document<'_>, element: &str, name: &str, value: &str) -> String {
+    let value = normalize_line_endings(value).replace(['\t', '\n'], " ");
+    let value = unescape(&value).into_owned();
+
+    let tokenized = doc
+        .dtd()
+        .and_then(|dtd| dtd.attributes.get(element))
+        .and_then(|decls| decls.iter().find(|decl| decl.name == name))
+        .is_some_and(|decl| decl.kind != AttributeType::CData);
+    if tokenized {
+        value
+            .split(' ')
+            .filter(|token| !token.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    } else {
+        value
+    }
+}
+
+/// Replace `\r\n` and lone `\r` with `\n`
+fn normalize_line_endings(text: &str) -> String {
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+fn escape_text(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn escape_attribute(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' => out.push_str("&#x9;"),
+            '\n' => out.push_str("&#xA;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_with_options, ParseOptions};
+
+    fn canonicalize(xml: &str, canonicalizer: &Canonicalizer) -> String {
+        let options = ParseOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let doc = parse_with_options(xml, DocumentType::Xml, &options).unwrap();
+        let mut output = Vec::new();
+        canonicalizer
+            .format(&doc, &mut output, &FormatOptions::default())
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_document_structure() {
+        // Based on examples 3.1 and 3.3 of the Canonical XML specification
+        let xml = "<?xml version=\"1.0\"?>\r\n<?xml-stylesheet href=\"doc.xsl\"\r\n   type=\"text/xsl\"   ?>\n\
+                   <!DOCTYPE doc [<!ATTLIST e9 attr CDATA \"default\"><!ATTLIST e2 id ID #IMPLIED>]>\n\
+                   <!-- Comment 1 --><doc>Hello, <![CDATA[a < b]]> &#x20AC;\r\n<e1   /><e2  id = '  x  y '></e2>\
+                   <e3 b=\"2\"  a='1&#9;\"&lt;'/><e9/>  <!-- Comment 2 --></doc>\n<?pi?>\n<!-- Comment 3 -->";
+        let expected = "<?xml-stylesheet href=\"doc.xsl\"\n   type=\"text/xsl\"   ?>\n<doc>Hello, a &lt; b \u{20AC}\n\
+                        <e1></e1><e2 id=\"x y\"></e2><e3 a=\"1&#x9;&quot;&lt;\" b=\"2\"></e3><e9 attr=\"default\"></e9>  \
+                        </doc>\n<?pi?>";
+        assert_eq!(
+            canonicalize(xml, &Canonicalizer::inclusive(false)),
+            expected
+        );
+
+        let with_comments = canonicalize(xml, &Canonicalizer::inclusive(true));
+        assert!(with_comments.contains("?>\n<!-- Comment 1 -->\n<doc>"));
+        assert!(with_comments.contains("  <!-- Comment 2 --></doc>"));
+        assert!(with_comments.ends_with("<?pi?>\n<!-- Comment 3 -->"));
+    }
+
+    #[test]
+    fn test_namespaces() {
+        let xml = "<doc xmlns=\"urn:default\" xmlns:a=\"urn:a\" xmlns:b=\"urn:b\" xmlns:unused=\"urn:u\">\
+                   <a:e b:y=\"2\" x=\"1\" a:z=\"3\" xmlns:a=\"urn:a\"><e xmlns=\"\"><f xmlns=\"\"/></e></a:e></doc>";
+        assert_eq!(
+            canonicalize(xml, &Canonicalizer::inclusive(false)),
+            "<doc xmlns=\"urn:default\" xmlns:a=\"urn:a\" xmlns:b=\"urn:b\" xmlns:unused=\"urn:u\">\
+             <a:e x=\"1\" a:z=\"3\" b:y=\"2\"><e xmlns=\"\"><f></f></e></a:e></doc>"
+        );
+        assert_eq!(
+            canonicalize(xml, &Canonicalizer::exclusive(false)),
+            "<doc xmlns=\"urn:default\"><a:e xmlns:a=\"urn:a\" xmlns:b=\"urn:b\" x=\"1\" a:z=\"3\" b:y=\"2\">\
+             <e xmlns=\"\"><f></f></e></a:e></doc>"
+        );
+
+        let canonicalizer = Canonicalizer {
+            inclusive_prefixes: vec!["unused".to_string()],
+            ..Canonicalizer::exclusive(false)
+        };
+        assert!(canonicalize(xml, &canonicalizer)
+            .starts_with("<doc xmlns=\"urn:default\" xmlns:unused=\"urn:u\"><a:e xmlns:a"));
+    }
+}
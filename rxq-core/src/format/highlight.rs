@@ -0,0 +1,293 @@
+//! Syntax-highlighted HTML output
+//!
+//! The wrapped formatter writes the document with a theme of marker
+//! characters instead of ANSI colors, and the markers are turned into
+//! `<span>`s, so the HTML has exactly the tokens and layout of the terminal
+//! output. The marker character is one the plain output does not contain,
+//! so no text of the document can be taken for a marker.
+
+use super::theme::TOKENS;
+use super::{ColorMode, ColorScheme, FormatOptions, Formatter};
+use crate::error::FormatError;
+use crate::types::Document;
+use std::collections::HashSet;
+use std::io::Write;
+
+/// Follows the marker character to end the current token
+const RESET: char = 'z';
+
+/// The 16 basic ANSI colors, as xterm renders them
+const PALETTE: [&str; 16] = [
+    "#000000", "#cd0000", "#00cd00", "#cdcd00", "#0000ee", "#cd00cd", "#00cdcd", "#e5e5e5",
+    "#7f7f7f", "#ff0000", "#00ff00", "#ffff00", "#5c5cff", "#ff00ff", "#00ffff", "#ffffff",
+];
+
+/// Formatter that writes another formatter's output as highlighted HTML
+///
+/// The output is a `<pre class="rxq">` element with a
+/// `<span class="rxq-tag">` (`rxq-attr`, `rxq-value`, `rxq-comment`, ...)
+/// around each token; [`stylesheet`] renders a theme as CSS for these
+/// classes. With `inline_styles`, the spans have the colors of
+/// [`FormatOptions::theme`] as `style` attributes instead.
+#[derive(Debug, Clone, Default)]
+pub struct HtmlHighlighter<F> {
+    pub formatter: F,
+    pub inline_styles: bool,
+}
+
+impl<F> HtmlHighlighter<F> {
+    pub fn new(formatter: F) -> Self {
+        Self {
+            formatter,
+            inline_styles: false,
+        }
+    }
+}
+
+impl<F: Formatter> HtmlHighlighter<F> {
+    /// The output of the wrapped formatter
+    fn output(&self, doc: &Document<'_>, options: &FormatOptions) -> Result<String, FormatError> {
+        let mut output = Vec::new();
+        // Through `dyn Write`: a wrapped formatter that can contain a
+        // highlighter would otherwise nest writer types without end
+        self.formatter
+            .format(doc, &mut (&mut output as &mut dyn Write), options)?;
+        String::from_utf8(output).map_err(|e| FormatError::FormatFailed(e.to_string()))
+    }
+}
+
+impl<F: Formatter> Formatter for HtmlHighlighter<F> {
+    fn format<'input, W: Write>(
+        &self,
+        doc: &Document<'input>,
+        writer: &mut W,
+        options: &FormatOptions,
+    ) -> Result<(), FormatError> {
+        let spans: Vec<String> = if self.inline_styles {
+            options
+                .theme
+                .token_colors()
+                .iter()
+                .map(|color| match css(color) {
+                    style if style.is_empty() => String::new(),
+                    style => format!("<span style=\"{}\">", style),
+                })
+                .collect()
+        } else {
+            TOKENS
+                .iter()
+                .map(|token| format!("<span class=\"rxq-{}\">", token))
+                .collect()
+        };
+
+        let plain = self.output(
+            doc,
+            &FormatOptions {
+                color: ColorMode::Never,
+                ..options.clone()
+            },
+        )?;
+        let used: HashSet<char> = plain.chars().filter(|&c| c >= '\u{e000}').collect();
+        let marker = ('\u{e000}'..='\u{f8ff}')
+            .chain('\u{f0000}'..='\u{ffffd}')
+            .find(|c| !used.contains(c))
+            .ok_or_else(|| FormatError::FormatFailed("no free marker character".to_string()))?;
+        let marked = self.output(
+            doc,
+            &FormatOptions {
+                color: ColorMode::Always,
+                theme: markers(marker),
+                ..options.clone()
+            },
+        )?;
+
+        writer.write_all(b"<pre class=\"rxq\">")?;
+        writer.write_all(to_html(&marked, marker, &spans).as_bytes())?;
+        writer.write_all(b"</pre>\n")?;
+        Ok(())
+    }
+}
+
+/// CSS rules giving the `rxq-*` classes the colors of a theme
+pub fn stylesheet(theme: &ColorScheme) -> String {
+    let mut css_rules = String::new();
+    for (token, color) in TOKENS.iter().zip(theme.token_colors()) {
+        let style = css(color);
+        if !style.is_empty() {
+            css_rules.push_str(&format!(".rxq-{} {{ {} }}\n", token, style));
+        }
+    }
+    css_rules
+}
+
+/// A theme of `marker` followed by a letter for each token
+fn markers(marker: char) -> ColorScheme {
+    let token = |letter: char| format!("{}{}", marker, letter);
+    let letter = |index: u8| token(char::from(b'a' + index));
+    ColorScheme {
+        tag: letter(0),
+        attr: letter(1),
+        value: letter(2),
+        text: letter(3),
+        comment: letter(4),
+        pi: letter(5),
+        cdata: letter(6),
+        entity: letter(7),
+        key: letter(8),
+        string: letter(9),
+        number: letter(10),
+        boolean: letter(11),
+        reset: token(RESET),
+        ..ColorScheme::none()
+    }
+}
+
+/// Escape `marked` for HTML, replacing the markers with spans
+///
+/// `spans` has the start tag for each token; empty for tokens without a span
+fn to_html(marked: &str, marker: char, spans: &[String]) -> String {
+    let mut out = String::with_capacity(marked.len());
+    let mut open = false;
+    let mut chars = marked.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            _ if c == marker => {
+                let token = chars.next().unwrap_or(RESET);
+                if open {
+                    out.push_str("</span>");
+                    open = false;
+                }
+                let index = (token as usize).wrapping_sub('a' as usize);
+                if let Some(span) = spans.get(index).filter(|s| !s.is_empty()) {
+                    out.push_str(span);
+                    open = true;
+                }
+            }
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            _ => out.push(c),
+        }
+    }
+    if open {
+        out.push_str("</span>");
+    }
+    out
+}
+
+/// CSS declarations for an ANSI color code
+fn css(code: &str) -> String {
+    let Some(params) = code.strip_prefix("\x1b[").and_then(|c| c.strip_suffix('m')) else {
+        return String::new();
+    };
+    let mut params = params.split(';').filter_map(|p| p.parse::<u8>().ok());
+    let mut declarations = Vec::new();
+    while let Some(param) = params.next() {
+        match param {
+            1 => declarations.push("font-weight: bold".to_string()),
+            2 => declarations.push("opacity: 0.7".to_string()),
+            3 => declarations.push("font-style: italic".to_string()),
+            4 => declarations.push("text-decoration: underline".to_string()),
+            30..=37 => declarations.push(format!("color: {}", PALETTE[param as usize - 30])),
+            90..=97 => declarations.push(format!("color: {}", PALETTE[param as usize - 82])),
+            38 => match (params.next(), params.next()) {
+                (Some(5), Some(index)) => {
+                    declarations.push(format!("color: {}", xterm_color(index)));
+                }
+                (Some(2), Some(r)) => {
+                    let (g, b) = (params.next().unwrap_or(0), params.next().unwrap_or(0));
+                    declarations.push(format!("color: #{:02x}{:02x}{:02x}", r, g, b));
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    declarations.join("; ")
+}
+
+/// The RGB value of an xterm 256-color index
+fn xterm_color(index: u8) -> String {
+    match index {
+        0..=15 => PALETTE[index as usize].to_string(),
+        16..=231 => {
+            let level = |n: u8| if n == 0 { 0 } else { 55 + 40 * n };
+            let n = index - 16;
+            format!(
+                "#{:02x}{:02x}{:02x}",
+                level(n / 36),
+                level(n / 6 % 6),
+                level(n % 6)
+            )
+        }
+        _ => {
+            let gray = 8 + 10 * (index - 232);
+            format!("#{:02x}{:02x}{:02x}", gray, gray, gray)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::XmlFormatter;
+    use crate::types::DocumentType;
+
+    fn highlight(source: &str, highlighter: &HtmlHighlighter<XmlFormatter>) -> String {
+        let doc = Document::parse(source, DocumentType::Xml).unwrap();
+        let mut output = Vec::new();
+        highlighter
+            .format(&doc, &mut output, &FormatOptions::default())
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_highlight_classes() {
+        let xml = "<?pi x?><a x=\"1 &amp; 2\"><!-- c --><b>t &lt; u</b></a>";
+        assert_eq!(
+            highlight(xml, &HtmlHighlighter::new(XmlFormatter)),
+            "<pre class=\"rxq\"><span class=\"rxq-pi\">&lt;?pi x?&gt;</span>\n\
+             <span class=\"rxq-tag\">&lt;a</span> <span class=\"rxq-attr\">x</span>=\
+             <span class=\"rxq-value\">\"1</span> <span class=\"rxq-entity\">&amp;amp;</span> \
+             <span class=\"rxq-value\">2\"</span><span class=\"rxq-tag\">&gt;</span>\n  \
+             <span class=\"rxq-comment\">&lt;!-- c --&gt;</span>\n  \
+             <span class=\"rxq-tag\">&lt;b</span><span class=\"rxq-tag\">&gt;</span>\
+             <span class=\"rxq-text\">t</span> <span class=\"rxq-entity\">&amp;lt;</span> \
+             <span class=\"rxq-text\">u</span><span class=\"rxq-tag\">&lt;/b&gt;</span>\n\
+             <span class=\"rxq-tag\">&lt;/a&gt;</span>\n</pre>\n"
+        );
+    }
+
+    #[test]
+    fn test_highlight_inline_styles() {
+        let highlighter = HtmlHighlighter {
+            formatter: XmlFormatter,
+            inline_styles: true,
+        };
+        assert_eq!(
+            highlight("<a>t</a>", &highlighter),
+            "<pre class=\"rxq\"><span style=\"color: #cdcd00\">&lt;a</span>\
+             <span style=\"color: #cdcd00\">&gt;</span>t\
+             <span style=\"color: #cdcd00\">&lt;/a&gt;</span>\n</pre>\n"
+        );
+        assert_eq!(css("\x1b[1;38;5;208m"), "font-weight: bold; color: #ff8700");
+        assert_eq!(css("\x1b[38;2;163;190;140m"), "color: #a3be8c");
+        assert!(stylesheet(&ColorScheme::default()).contains(".rxq-comment { color: #5c5cff }\n"));
+    }
+
+    #[test]
+    fn test_highlight_keeps_escape_and_marker_characters() {
+        // The old markers (ESC + letter) and the first marker character
+        let html = highlight(
+            "<p>x\x1bay\x1bzq\u{e000}a</p>",
+            &HtmlHighlighter::new(XmlFormatter),
+        );
+        assert_eq!(
+            html,
+            "<pre class=\"rxq\"><span class=\"rxq-tag\">&lt;p</span>\
+             <span class=\"rxq-tag\">&gt;</span><span class=\"rxq-text\">x\x1bay\x1bzq\u{e000}a</span>\
+             <span class=\"rxq-tag\">&lt;/p&gt;</span>\n</pre>\n"
+        );
+    }
+}
@@ -0,0 +1,371 @@
+//! HTML formatting following the HTML serialization rules
+//!
+//! Unlike [`XmlFormatter`](super::XmlFormatter), void elements are written
+//! without an end tag or `/>`, the content of raw-text (`script`, `style`)
+//! and whitespace-preserving (`pre`, `textarea`) elements is written exactly
+//! as parsed, and inline elements stay on the line of the surrounding text.
+
+use super::{write_attributes, ColorScheme, FormatOptions, Formatter};
+use crate::error::FormatError;
+use crate::types::{Document, NodeRef, NodeType};
+use std::io::Write;
+
+/// Elements that never have content or an end tag
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements whose content is text that is not parsed as markup
+const RAW_TEXT_ELEMENTS: &[&str] = &["script", "style"];
+
+/// Elements whose whitespace is significant
+const PRESERVE_WHITESPACE_ELEMENTS: &[&str] = &["pre", "textarea", "listing"];
+
+/// Phrasing elements that are laid out within a line of text
+const INLINE_ELEMENTS: &[&str] = &[
+    "a", "abbr", "b", "bdi", "bdo", "br", "button", "cite", "code", "data", "del", "dfn", "em",
+    "i", "img", "input", "ins", "kbd", "label", "mark", "meter", "output", "progress", "q", "rp",
+    "rt", "ruby", "s", "samp", "select", "small", "span", "strong", "sub", "sup", "textarea",
+    "time", "u", "var", "wbr",
+];
+
+/// Containers that put child elements on lines of their own, even inline ones
+const BLOCK_CONTAINERS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "body",
+    "details",
+    "div",
+    "dl",
+    "fieldset",
+    "figure",
+    "footer",
+    "form",
+    "head",
+    "header",
+    "html",
+    "main",
+    "nav",
+    "ol",
+    "section",
+    "table",
+    "tbody",
+    "tfoot",
+    "thead",
+    "tr",
+    "ul",
+];
+
+/// HTML formatter
+pub struct HtmlFormatter;
+
+impl Formatter for HtmlFormatter {
+    fn format<'input, W: Write>(
+        &self,
+        doc: &Document<'input>,
+        writer: &mut W,
+        options: &FormatOptions,
+    ) -> Result<(), FormatError> {
+        let colors = options.colors();
+        self.format_block_children(doc.children(), writer, options, &colors, 0)
+    }
+}
+
+impl HtmlFormatter {
+    /// Lay out nodes one per line, keeping runs of text and inline elements together
+    fn format_block_children<'a, 'input: 'a, W: Write>(
+        &self,
+        children: impl Iterator<Item = NodeRef<'a, 'input>>,
+        writer: &mut W,
+        options: &FormatOptions,
+        colors: &ColorScheme,
+        level: usize,
+    ) -> Result<(), FormatError> {
+        let indent_str = options.indent.as_str(level);
+        let mut run = String::new();
+        for child in children {
+            if is_inline(child) {
+                write_inline(child, &mut run, options, colors, false);
+                continue;
+            }
+            write_run(writer, &indent_str, &mut run)?;
+            self.format_node(child, writer, options, colors, level)?;
+        }
+        write_run(writer, &indent_str, &mut run)
+    }
+
+    fn format_node<'a, 'input, W: Write>(
+        &self,
+        node: NodeRef<'a, 'input>,
+        writer: &mut W,
+        options: &FormatOptions,
+        colors: &ColorScheme,
+        level: usize,
+    ) -> Result<(), FormatError> {
+        let indent_str = options.indent.as_str(level);
+        match node.node_type() {
+            NodeType::Element => self.format_element(node, writer, options, colors, level),
+            NodeType::Comment => {
+                if let Some(comment) = node.comment() {
+                    writeln!(
+                        writer,
+                        "{}{}{}{}",
+                        indent_str, colors.comment, comment, colors.reset
+                    )?;
+                }
+                Ok(())
+            }
+            NodeType::Doctype => {
+                if let Some(doctype) = node.doctype() {
+                    writeln!(
+                        writer,
+                        "{}{}{}{}",
+                        indent_str, colors.tag, doctype, colors.reset
+                    )?;
+                }
+                Ok(())
+            }
+            _ => {
+                let mut markup = String::new();
+                write_inline(node, &mut markup, options, colors, true);
+                writeln!(writer, "{}{}", indent_str, markup.trim())?;
+                Ok(())
+            }
+        }
+    }
+
+    fn format_element<'a, 'input, W: Write>(
+        &self,
+        node: NodeRef<'a, 'input>,
+        writer: &mut W,
+        options: &FormatOptions,
+        colors: &ColorScheme,
+        level: usize,
+    ) -> Result<(), FormatError> {
+        let name = node.tag_name().unwrap_or_default();
+        let indent_str = options.indent.as_str(level);
+
+        if is_void(&name) || is_verbatim(&name) {
+            let mut line = String::new();
+            write_start_tag(node, &name, &mut line, options, colors, Some(level));
+            if !is_void(&name) {
+                write_content(node, &name, &mut line, options, colors, false);
+            }
+            writeln!(writer, "{}{}", indent_str, line)?;
+            return Ok(());
+        }
+
+        let mut start = String::new();
+        write_start_tag(node, &name, &mut start, options, colors, Some(level));
+        let inline = if BLOCK_CONTAINERS.contains(&name.as_ref()) {
+            node.children()
+                .all(|child| child.node_type() == NodeType::Text)
+        } else {
+            node.children().all(is_inline)
+        };
+        if inline {
+            let mut content = String::new();
+            for child in node.children() {
+                write_inline(child, &mut content, options, colors, false);
+            }
+            writeln!(
+                writer,
+                "{}{}{}{}</{}>{}",
+                indent_str,
+                start,
+                content.trim(),
+                colors.tag,
+                name,
+                colors.reset
+            )?;
+            return Ok(());
+        }
+
+        writeln!(writer, "{}{}", indent_str, start)?;
+        self.format_block_children(node.children(), writer, options, colors, level + 1)?;
+        writeln!(
+            writer,
+            "{}{}</{}>{}",
+            indent_str, colors.tag, name, colors.reset
+        )?;
+        Ok(())
+    }
+}
+
+/// Write a run of inline content on its own line, if it has any text
+fn write_run<W: Write>(
+    writer: &mut W,
+    indent_str: &str,
+    run: &mut String,
+) -> Result<(), FormatError> {
+    let line = run.trim();
+    if !line.is_empty() {
+        writeln!(writer, "{}{}", indent_str, line)?;
+    }
+    run.clear();
+    Ok(())
+}
+
+/// Serialize a node on a single line, collapsing whitespace unless `preserve` is set
+pub(super) fn write_inline(
+    node: NodeRef<'_, '_>,
+    out: &mut String,
+    options: &FormatOptions,
+    colors: &ColorScheme,
+    preserve: bool,
+) {
+    match node.node_type() {
+        NodeType::Element => {
+            let name = node.tag_name().unwrap_or_default();
+            write_start_tag(node, &name, out, options, colors, None);
+            if !is_void(&name) {
+                write_content(node, &name, out, options, colors, preserve);
+            }
+        }
+        NodeType::Text => {
+            let text = node.text().unwrap_or_default();
+            if preserve {
+                out.push_str(&colors.text(&text));
+            } else {
+                let mut collapsed = String::new();
+                collapse_whitespace(&text, &mut collapsed);
+                out.push_str(&colors.text(&collapsed));
+            }
+        }
+        NodeType::Comment => {
+            if let Some(comment) = node.comment() {
+                out.push_str(&format!("{}{}{}", colors.comment, comment, colors.reset));
+            }
+        }
+        NodeType::Doctype => {
+            out.push_str(&colors.paint(&colors.tag, &node.doctype().unwrap_or_default()))
+        }
+        NodeType::CData => out.push_str(&colors.cdata(&node.text().unwrap_or_default())),
+        NodeType::ProcessingInstruction => {
+            if let Some((target, data)) = node.processing_instruction() {
+                let separator = if data.is_empty() { "" } else { " " };
+                let pi = format!("<?{}{}{}>", target, separator, data);
+                out.push_str(&colors.paint(&colors.pi, &pi));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Write the content and end tag of a non-void element on the current line
+pub(super) fn write_content(
+    node: NodeRef<'_, '_>,
+    name: &str,
+    out: &mut String,
+    options: &FormatOptions,
+    colors: &ColorScheme,
+    preserve: bool,
+) {
+    let verbatim = preserve || is_verbatim(name);
+    let mut inner = String::new();
+    for child in node.children() {
+        write_inline(child, &mut inner, options, colors, verbatim);
+    }
+    // The parser drops a newline right after <pre>, so write one to keep a leading newline
+    if PRESERVE_WHITESPACE_ELEMENTS.contains(&name) && inner.starts_with('\n') {
+        out.push('\n');
+    }
+    out.push_str(&inner);
+    out.push_str(&format!("{}</{}>{}", colors.tag, name, colors.reset));
+}
+
+/// Write a start tag; `level` is set for tags that start a line, whose
+/// attributes may be wrapped
+fn write_start_tag(
+    node: NodeRef<'_, '_>,
+    name: &str,
+    out: &mut String,
+    options: &FormatOptions,
+    colors: &ColorScheme,
+    level: Option<usize>,
+) {
+    out.push_str(&format!("{}<{}{}", colors.tag, name, colors.reset));
+    write_attributes(out, name, node.all_attributes(), options, colors, level);
+    out.push_str(&format!("{}>{}", colors.tag, colors.reset));
+}
+
+/// Replace each run of whitespace with a single space
+pub(super) fn collapse_whitespace(text: &str, out: &mut String) {
+    let mut in_space = false;
+    for c in text.chars() {
+        if c.is_ascii_whitespace() {
+            if !in_space {
+                out.push(' ');
+            }
+            in_space = true;
+        } else {
+            out.push(c);
+            in_space = false;
+        }
+    }
+}
+
+pub(super) fn is_inline(node: NodeRef<'_, '_>) -> bool {
+    match node.node_type() {
+        NodeType::Text => true,
+        NodeType::Element => node
+            .tag_name()
+            .is_some_and(|name| INLINE_ELEMENTS.contains(&name.as_ref())),
+        _ => false,
+    }
+}
+
+pub(super) fn is_void(name: &str) -> bool {
+    VOID_ELEMENTS.contains(&name)
+}
+
+/// Elements whose content is written exactly as parsed
+pub(super) fn is_verbatim(name: &str) -> bool {
+    RAW_TEXT_ELEMENTS.contains(&name) || PRESERVE_WHITESPACE_ELEMENTS.contains(&name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::ColorMode;
+    use crate::types::DocumentType;
+
+    fn format(html: &str) -> String {
+        let doc = Document::parse(html, DocumentType::Html).unwrap();
+        let options = FormatOptions {
+            color: ColorMode::Never,
+            ..FormatOptions::default()
+        };
+        let mut output = Vec::new();
+        HtmlFormatter.format(&doc, &mut output, &options).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_void_and_inline_elements() {
+        let html = "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>T</title></head>\
+                    <body><p>Hello,\n   <b>big</b> world<br>again</p><div>Text <i>here</i><hr>\
+                    <input type=\"checkbox\" disabled></div><p></p></body></html>";
+        assert_eq!(
+            format(html),
+            "<!DOCTYPE html>\n<html>\n  <head>\n    <meta charset=\"utf-8\">\n    <title>T</title>\n  </head>\n  \
+             <body>\n    <p>Hello, <b>big</b> world<br>again</p>\n    <div>\n      Text <i>here</i>\n      \
+             <hr>\n      <input type=\"checkbox\" disabled>\n    </div>\n    <p></p>\n  </body>\n</html>\n"
+        );
+    }
+
+    #[test]
+    fn test_verbatim_content() {
+        let html =
+            "<body><pre>\n\n  a  <b>b</b>\n    c</pre><script>if (a < b) {\n  run();\n}</script>\
+                    <textarea>  keep\n me</textarea></body>";
+        assert_eq!(
+            format(html),
+            "<html>\n  <head></head>\n  <body>\n    <pre>\n\n  a  <b>b</b>\n    c</pre>\n    \
+             <script>if (a < b) {\n  run();\n}</script>\n    <textarea>  keep\n me</textarea>\n  </body>\n</html>\n"
+        );
+    }
+}
@@ -0,0 +1,309 @@
+//! Minified XML and HTML output
+//!
+//! Whitespace between tags is removed only where it cannot matter: never in
+//! mixed content, `xml:space="preserve"` elements or elements listed in
+//! [`FormatOptions::preserve_whitespace`], nor in HTML `pre`, `textarea`,
+//! `script` and `style`. In HTML, runs of whitespace in text collapse to one
+//! space, as browsers render them, and attribute quotes are dropped where the
+//! value does not need them.
+
+use super::html::{collapse_whitespace, is_inline, is_verbatim, is_void, write_content};
+use super::{is_mixed_content, ColorScheme, FormatOptions, Formatter};
+use crate::error::FormatError;
+use crate::types::{Document, DocumentType, NodeRef, NodeType};
+use crate::validate::is_namespace_declaration;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Namespace prefixes mapped to their URI; the default namespace is `""`
+type Namespaces = BTreeMap<String, String>;
+
+/// XML and HTML minifier
+///
+/// Colors and indentation in [`FormatOptions`] are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct Minifier {
+    /// Remove comments (HTML conditional comments are kept)
+    pub strip_comments: bool,
+    /// Remove an XML declaration that only states the defaults
+    /// (`version="1.0"` and UTF-8) and namespace declarations that repeat
+    /// one already in scope
+    pub strip_declarations: bool,
+}
+
+impl Formatter for Minifier {
+    fn format<'input, W: Write>(
+        &self,
+        doc: &Document<'input>,
+        writer: &mut W,
+        options: &FormatOptions,
+    ) -> Result<(), FormatError> {
+        let mut out = String::new();
+        match doc.doc_type() {
+            DocumentType::Xml => {
+                // Whitespace outside the root element never matters
+                for node in doc
+                    .children()
+                    .filter(|node| node.node_type() != NodeType::Text)
+                {
+                    self.write_xml(node, options, &Namespaces::new(), false, &mut out);
+                }
+            }
+            DocumentType::Html => {
+                let children: Vec<_> = doc.children().collect();
+                self.write_html_children(&children, false, options, &mut out);
+            }
+            DocumentType::Json => {
+                return Err(FormatError::FormatFailed(
+                    "use compact JSON output to minify JSON".to_string(),
+                ));
+            }
+        }
+        writer.write_all(out.as_bytes())?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+impl Minifier {
+    /// Write an XML node; `preserve` is set within elements whose
+    /// whitespace is significant
+    fn write_xml(
+        &self,
+        node: NodeRef<'_, '_>,
+        options: &FormatOptions,
+        in_scope: &Namespaces,
+        preserve: bool,
+        out: &mut String,
+    ) {
+        match node.node_type() {
+            NodeType::Element => {
+                let name = node.tag_name().unwrap_or_default();
+                let mut in_scope = in_scope.clone();
+                let mut xml_space = None;
+
+                out.push('<');
+                out.push_str(&name);
+                for (attr_name, value) in node.attributes() {
+                    if attr_name == "xml:space" {
+                        xml_space = Some(value.clone());
+                    }
+                    if is_namespace_declaration(&attr_name) {
+                        let prefix = attr_name.strip_prefix("xmlns").unwrap_or_default();
+                        let prefix = prefix.strip_prefix(':').unwrap_or(prefix).to_string();
+                        let redundant = in_scope.get(&prefix) == Some(&value.to_string());
+                        if redundant && self.strip_declarations {
+                            continue;
+                        }
+                        in_scope.insert(prefix, value.to_string());
+                    }
+                    write_attribute(&attr_name, &value, out);
+                }
+
+                let preserve = match xml_space.as_deref() {
+                    Some("default") => options.preserve_whitespace.iter().any(|n| *n == name),
+                    xml_space => preserve || options.preserves_whitespace(&name, xml_space),
+                };
+                // Whitespace is only dropped between tags, never as the whole content
+                let has_elements = node.children().any(|c| c.node_type() == NodeType::Element);
+                let keep_text = preserve || !has_elements || is_mixed_content(node);
+                let mut content = String::new();
+                for child in node.children() {
+                    if child.node_type() == NodeType::Text && !keep_text {
+                        let text = child.text().unwrap_or_default();
+                        if text.trim().is_empty() {
+                            continue;
+                        }
+                    }
+                    self.write_xml(child, options, &in_scope, preserve, &mut content);
+                }
+
+                if content.is_empty() {
+                    out.push_str("/>");
+                } else {
+                    out.push('>');
+                    out.push_str(&content);
+                    out.push_str(&format!("</{}>", name));
+                }
+            }
+            NodeType::Text => out.push_str(&node.text().unwrap_or_default()),
+            NodeType::CData => {
+                out.push_str(&format!("<![CDATA[{}]]>", node.text().unwrap_or_default()));
+            }
+            NodeType::Comment if !self.strip_comments => {
+                out.push_str(&node.comment().unwrap_or_default());
+            }
+            NodeType::ProcessingInstruction => {
+                if let Some((target, data)) = node.processing_instruction() {
+                    if target == "xml" && self.strip_declarations && is_default_declaration(&data) {
+                        return;
+                    }
+                    let separator = if data.is_empty() { "" } else { " " };
+                    out.push_str(&format!("<?{}{}{}?>", target, separator, data));
+                }
+            }
+            NodeType::Doctype => out.push_str(&node.doctype().unwrap_or_default()),
+            _ => {}
+        }
+    }
+
+    /// Write HTML nodes, trimming whitespace next to block boundaries unless
+    /// the parent is an inline element
+    fn write_html_children(
+        &self,
+        children: &[NodeRef<'_, '_>],
+        inline_parent: bool,
+        options: &FormatOptions,
+        out: &mut String,
+    ) {
+        for (i, &child) in children.iter().enumerate() {
+            if child.node_type() != NodeType::Text {
+                self.write_html(child, options, out);
+                continue;
+            }
+
+            let mut text = String::new();
+            collapse_whitespace(&child.text().unwrap_or_default(), &mut text);
+            let mut text = text.as_str();
+            if !inline_parent {
+                if i == 0 || !is_inline(children[i - 1]) {
+                    text = text.trim_start();
+                }
+                if children.get(i + 1).is_none_or(|next| !is_inline(*next)) {
+                    text = text.trim_end();
+                }
+            }
+            out.push_str(text);
+        }
+    }
+
+    fn write_html(&self, node: NodeRef<'_, '_>, options: &FormatOptions, out: &mut String) {
+        match node.node_type() {
+            NodeType::Element => {
+                let name = node.tag_name().unwrap_or_default();
+                out.push('<');
+                out.push_str(&name);
+                for (attr_name, value) in node.all_attributes() {
+                    match value {
+                        Some(value) if is_unquoted_safe(&value) => {
+                            out.push_str(&format!(" {}={}", attr_name, value));
+                        }
+                        Some(value) => write_attribute(&attr_name, &value, out),
+                        None => out.push_str(&format!(" {}", attr_name)),
+                    }
+                }
+                out.push('>');
+
+                if is_void(&name) {
+                    return;
+                }
+                if is_verbatim(&name) {
+                    write_content(node, &name, out, options, &ColorScheme::none(), true);
+                    return;
+                }
+                let children: Vec<_> = node.children().collect();
+                self.write_html_children(&children, is_inline(node), options, out);
+                out.push_str(&format!("</{}>", name));
+            }
+            NodeType::Comment => {
+                let comment = node.comment().unwrap_or_default();
+                if !self.strip_comments || comment.starts_with("<!--[if") {
+                    out.push_str(&comment);
+                }
+            }
+            NodeType::Doctype => out.push_str(&node.doctype().unwrap_or_default()),
+            _ => super::html::write_inline(node, out, options, &ColorScheme::none(), false),
+        }
+    }
+}
+
+/// Write ` name="value"`, with single quotes if the value contains `"`
+fn write_attribute(name: &str, value: &str, out: &mut String) {
+    let quote = if value.contains('"') { '\'' } else { '"' };
+    out.push_str(&format!(" {}={}{}{}", name, quote, value, quote));
+}
+
+/// Whether an HTML attribute value can be written without quotes
+fn is_unquoted_safe(value: &str) -> bool {
+    !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_ascii_whitespace() || matches!(c, '"' | '\'' | '=' | '<' | '>' | '`'))
+}
+
+/// Whether an XML declaration states only what a parser assumes anyway
+fn is_default_declaration(data: &str) -> bool {
+    let data = data.replace('\'', "\"");
+    match data.split_whitespace().collect::<Vec<_>>()[..] {
+        ["version=\"1.0\""] => true,
+        ["version=\"1.0\"", encoding] => encoding.eq_ignore_ascii_case("encoding=\"utf-8\""),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_with_options, ParseOptions};
+
+    fn minify(source: &str, doc_type: DocumentType, minifier: &Minifier) -> String {
+        let options = ParseOptions {
+            strict: doc_type == DocumentType::Xml,
+            ..Default::default()
+        };
+        let doc = parse_with_options(source, doc_type, &options).unwrap();
+        let mut output = Vec::new();
+        minifier
+            .format(&doc, &mut output, &FormatOptions::default())
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_minify_xml() {
+        let xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!-- config -->\n\
+                   <config xmlns:a=\"urn:a\">\n  <item a:x=\"1\" xmlns:a=\"urn:a\">\n    <name>  value </name>\n  </item>\n  \
+                   <p>Hello <b>big</b> <i>world</i></p>\n  <pre xml:space=\"preserve\">\n  <c/> </pre>\n  \
+                   <empty>  </empty>\n  <data><![CDATA[ x ]]></data>\n</config>\n";
+        assert_eq!(
+            minify(xml, DocumentType::Xml, &Minifier::default()),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?><!-- config --><config xmlns:a=\"urn:a\">\
+             <item a:x=\"1\" xmlns:a=\"urn:a\"><name>  value </name></item><p>Hello <b>big</b> <i>world</i></p>\
+             <pre xml:space=\"preserve\">\n  <c/> </pre><empty>  </empty><data><![CDATA[ x ]]></data></config>\n"
+        );
+
+        let minifier = Minifier {
+            strip_comments: true,
+            strip_declarations: true,
+        };
+        assert_eq!(
+            minify(xml, DocumentType::Xml, &minifier),
+            "<config xmlns:a=\"urn:a\"><item a:x=\"1\"><name>  value </name></item>\
+             <p>Hello <b>big</b> <i>world</i></p><pre xml:space=\"preserve\">\n  <c/> </pre><empty>  </empty>\
+             <data><![CDATA[ x ]]></data></config>\n"
+        );
+        let standalone = "<?xml version=\"1.0\" standalone=\"yes\"?><a/>";
+        assert_eq!(
+            minify(standalone, DocumentType::Xml, &minifier),
+            "<?xml version=\"1.0\" standalone=\"yes\"?><a/>\n"
+        );
+    }
+
+    #[test]
+    fn test_minify_html() {
+        let html = "<!DOCTYPE html>\n<html>\n <head>\n  <title> Page </title>\n </head>\n <body class=\"main\">\n  \
+                    <!-- note --><!--[if IE]>old<![endif]-->\n  <p id=\"intro\" title=\"two words\">\n    Hello,\n    \
+                    <b> big </b> <i>world</i>\n  </p>\n  <pre>  keep\n   this</pre>\n  <input type=\"checkbox\" disabled>\n  \
+                    <script>if (a < b)  run();</script>\n </body>\n</html>\n";
+        let minifier = Minifier {
+            strip_comments: true,
+            ..Minifier::default()
+        };
+        assert_eq!(
+            minify(html, DocumentType::Html, &minifier),
+            "<!DOCTYPE html><html><head><title>Page</title></head><body class=main><!--[if IE]>old<![endif]-->\
+             <p id=intro title=\"two words\">Hello, <b> big </b> <i>world</i></p><pre>  keep\n   this</pre>\
+             <input type=checkbox disabled><script>if (a < b)  run();</script></body></html>\n"
+        );
+    }
+}
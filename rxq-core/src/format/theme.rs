@@ -0,0 +1,343 @@
+//! Color themes for terminal output
+//!
+//! A theme is a list of `key=color` entries separated by newlines or `;`,
+//! such as `tag=yellow; attr=bold green; value=#a3be8c`. Lines starting with
+//! `#` are comments. A color is a list of words: `bold`, `dim`, `italic`,
+//! `underline`, one of the color names `black`, `red`, `green`, `yellow`,
+//! `blue`, `magenta`, `cyan` and `white` (optionally prefixed with
+//! `bright-`), a 256-color index `0`–`255` or a truecolor `#rrggbb`. `none`
+//! leaves a token uncolored. The `diff-*` keys color the output of
+//! `rxq --diff`.
+
+use std::str::FromStr;
+
+/// Names of the eight basic ANSI colors, in SGR order
+const COLOR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+/// Theme keys, one per kind of token
+pub(crate) const TOKENS: [&str; 12] = [
+    "tag", "attr", "value", "text", "comment", "pi", "cdata", "entity", "key", "string", "number",
+    "boolean",
+];
+
+/// ANSI color codes for each kind of token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorScheme {
+    /// Tag names and markup, DOCTYPE
+    pub tag: String,
+    /// Attribute names
+    pub attr: String,
+    /// Attribute values, with their quotes
+    pub value: String,
+    pub text: String,
+    pub comment: String,
+    /// Processing instructions, including the XML declaration
+    pub pi: String,
+    /// CDATA sections
+    pub cdata: String,
+    /// Entity and character references in text and attribute values
+    pub entity: String,
+    /// JSON object keys
+    pub key: String,
+    /// JSON strings
+    pub string: String,
+    /// JSON numbers
+    pub number: String,
+    /// JSON booleans and null
+    pub boolean: String,
+    /// `---`/`+++` file headers of diffs
+    pub diff_header: String,
+    /// `@@` hunk headers of diffs
+    pub diff_hunk: String,
+    /// Removed lines of diffs
+    pub diff_delete: String,
+    /// Added lines of diffs
+    pub diff_insert: String,
+    pub reset: String,
+}
+
+impl Default for ColorScheme {
+    fn default() -> Self {
+        Self {
+            tag: "\x1b[33m".to_string(),   // Yellow
+            attr: "\x1b[32m".to_string(),  // Green
+            value: "\x1b[36m".to_string(), // Cyan
+            text: String::new(),
+            comment: "\x1b[94m".to_string(), // Bright Blue
+            pi: "\x1b[35m".to_string(),      // Magenta
+            cdata: "\x1b[90m".to_string(),   // Bright Black
+            entity: "\x1b[91m".to_string(),  // Bright Red
+            key: "\x1b[1;34m".to_string(),   // Bold Blue
+            string: "\x1b[32m".to_string(),  // Green
+            number: "\x1b[36m".to_string(),  // Cyan
+            boolean: "\x1b[35m".to_string(), // Magenta
+            diff_header: "\x1b[1m".to_string(),
+            diff_hunk: "\x1b[36m".to_string(),   // Cyan
+            diff_delete: "\x1b[31m".to_string(), // Red
+            diff_insert: "\x1b[32m".to_string(), // Green
+            reset: "\x1b[0m".to_string(),
+        }
+    }
+}
+
+impl FromStr for ColorScheme {
+    type Err = String;
+
+    /// Parse a theme on top of the default colors
+    fn from_str(theme: &str) -> Result<Self, Self::Err> {
+        let mut scheme = Self::default();
+        scheme.apply(theme)?;
+        Ok(scheme)
+    }
+}
+
+impl ColorScheme {
+    pub const fn none() -> Self {
+        Self {
+            tag: String::new(),
+            attr: String::new(),
+            value: String::new(),
+            text: String::new(),
+            comment: String::new(),
+            pi: String::new(),
+            cdata: String::new(),
+            entity: String::new(),
+            key: String::new(),
+            string: String::new(),
+            number: String::new(),
+            boolean: String::new(),
+            diff_header: String::new(),
+            diff_hunk: String::new(),
+            diff_delete: String::new(),
+            diff_insert: String::new(),
+            reset: String::new(),
+        }
+    }
+
+    /// Override the colors named in a theme, keeping the others
+    pub fn apply(&mut self, theme: &str) -> Result<(), String> {
+        for line in theme.lines() {
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            for entry in line.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+                let (key, color) = entry
+                    .split_once('=')
+                    .ok_or_else(|| format!("expected key=color, found '{}'", entry))?;
+                let code = parse_color(color.trim())?;
+                *self.color_mut(key.trim())? = code;
+            }
+        }
+        Ok(())
+    }
+
+    /// The colors in the order of [`TOKENS`]
+    pub(crate) fn token_colors(&self) -> [&str; 12] {
+        [
+            &self.tag,
+            &self.attr,
+            &self.value,
+            &self.text,
+            &self.comment,
+            &self.pi,
+            &self.cdata,
+            &self.entity,
+            &self.key,
+            &self.string,
+            &self.number,
+            &self.boolean,
+        ]
+    }
+
+    fn color_mut(&mut self, key: &str) -> Result<&mut String, String> {
+        Ok(match key {
+            "tag" => &mut self.tag,
+            "attr" => &mut self.attr,
+            "value" => &mut self.value,
+            "text" => &mut self.text,
+            "comment" => &mut self.comment,
+            "pi" => &mut self.pi,
+            "cdata" => &mut self.cdata,
+            "entity" => &mut self.entity,
+            "key" => &mut self.key,
+            "string" => &mut self.string,
+            "number" => &mut self.number,
+            "boolean" => &mut self.boolean,
+            "diff-header" => &mut self.diff_header,
+            "diff-hunk" => &mut self.diff_hunk,
+            "diff-delete" => &mut self.diff_delete,
+            "diff-insert" => &mut self.diff_insert,
+            _ => return Err(format!("unknown theme key '{}'", key)),
+        })
+    }
+
+    /// `text` in `color`; surrounding whitespace is left uncolored so that
+    /// it can still be trimmed
+    pub fn paint(&self, color: &str, text: &str) -> String {
+        let core = text.trim();
+        if color.is_empty() || core.is_empty() {
+            return text.to_string();
+        }
+        let start = text.len() - text.trim_start().len();
+        format!(
+            "{}{}{}{}{}",
+            &text[..start],
+            color,
+            core,
+            self.reset,
+            &text[start + core.len()..]
+        )
+    }
+
+    /// Text content with its entity references highlighted
+    pub(crate) fn text(&self, text: &str) -> String {
+        self.with_references(&self.text, text)
+    }
+
+    /// `name="value"` with the value quoted as given
+    pub(crate) fn attribute(&self, name: &str, quote: char, value: &str) -> String {
+        format!(
+            "{}={}",
+            self.paint(&self.attr, name),
+            self.with_references(&self.value, &format!("{}{}{}", quote, value, quote))
+        )
+    }
+
+    pub(crate) fn cdata(&self, text: &str) -> String {
+        self.paint(&self.cdata, &format!("<![CDATA[{}]]>", text))
+    }
+
+    pub(crate) fn processing_instruction(&self, target: &str, data: &str) -> String {
+        let separator = if data.is_empty() { "" } else { " " };
+        self.paint(&self.pi, &format!("<?{}{}{}?>", target, separator, data))
+    }
+
+    /// `text` in `color`, with entity and character references in the
+    /// entity color
+    fn with_references(&self, color: &str, text: &str) -> String {
+        if self.entity.is_empty() {
+            return self.paint(color, text);
+        }
+        let mut out = String::new();
+        let mut plain = 0;
+        let mut i = 0;
+        while let Some(offset) = text[i..].find('&') {
+            let start = i + offset;
+            match reference_len(&text[start..]) {
+                Some(len) => {
+                    out.push_str(&self.paint(color, &text[plain..start]));
+                    out.push_str(&self.paint(&self.entity, &text[start..start + len]));
+                    i = start + len;
+                    plain = i;
+                }
+                None => i = start + 1,
+            }
+        }
+        out.push_str(&self.paint(color, &text[plain..]));
+        out
+    }
+}
+
+/// Length of the entity or character reference at the start of `text`
+fn reference_len(text: &str) -> Option<usize> {
+    let end = text.find(';')?;
+    let name = &text[1..end];
+    let valid = if let Some(hex) = name.strip_prefix("#x") {
+        !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit())
+    } else if let Some(digits) = name.strip_prefix('#') {
+        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+    } else {
+        let mut chars = name.chars();
+        chars
+            .next()
+            .is_some_and(|c| c.is_alphabetic() || c == '_' || c == ':')
+            && chars.all(|c| c.is_alphanumeric() || matches!(c, '_' | ':' | '-' | '.'))
+    };
+    valid.then_some(end + 1)
+}
+
+/// Translate a color to its ANSI escape sequence
+fn parse_color(color: &str) -> Result<String, String> {
+    if color == "none" {
+        return Ok(String::new());
+    }
+    let mut codes = Vec::new();
+    for word in color.split_whitespace() {
+        let code = match word {
+            "bold" => "1".to_string(),
+            "dim" => "2".to_string(),
+            "italic" => "3".to_string(),
+            "underline" => "4".to_string(),
+            _ => {
+                if let Some(i) = COLOR_NAMES.iter().position(|name| *name == word) {
+                    (30 + i).to_string()
+                } else if let Some(i) = word
+                    .strip_prefix("bright-")
+                    .and_then(|word| COLOR_NAMES.iter().position(|name| *name == word))
+                {
+                    (90 + i).to_string()
+                } else if let Ok(index) = word.parse::<u8>() {
+                    format!("38;5;{}", index)
+                } else if let Some(rgb) = parse_hex(word) {
+                    format!("38;2;{};{};{}", rgb[0], rgb[1], rgb[2])
+                } else {
+                    return Err(format!("invalid color '{}'", word));
+                }
+            }
+        };
+        codes.push(code);
+    }
+    if codes.is_empty() {
+        return Err("missing color".to_string());
+    }
+    Ok(format!("\x1b[{}m", codes.join(";")))
+}
+
+/// Parse a `#rrggbb` color
+fn parse_hex(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_theme() {
+        let theme: ColorScheme =
+            "# comment\ntag = bold bright-red; attr=208\nvalue=#ff8000\ntext=none\ndiff-insert=blue"
+                .parse()
+                .unwrap();
+        assert_eq!(theme.tag, "\x1b[1;91m");
+        assert_eq!(theme.attr, "\x1b[38;5;208m");
+        assert_eq!(theme.value, "\x1b[38;2;255;128;0m");
+        assert_eq!(theme.text, "");
+        assert_eq!(theme.comment, ColorScheme::default().comment);
+        assert_eq!(theme.diff_insert, "\x1b[34m");
+
+        assert!("tag".parse::<ColorScheme>().is_err());
+        assert!("tags=red".parse::<ColorScheme>().is_err());
+        assert!("tag=reddish".parse::<ColorScheme>().is_err());
+        assert!("tag=256".parse::<ColorScheme>().is_err());
+        assert!("tag=#ff00".parse::<ColorScheme>().is_err());
+    }
+
+    #[test]
+    fn test_paint_references() {
+        let colors = ColorScheme::default();
+        assert_eq!(
+            colors.attribute("a", '"', "x &amp; y & z"),
+            "\x1b[32ma\x1b[0m=\x1b[36m\"x\x1b[0m \x1b[91m&amp;\x1b[0m \x1b[36my & z\"\x1b[0m"
+        );
+        assert_eq!(colors.text(" a&#x41; "), " a\x1b[91m&#x41;\x1b[0m ");
+        assert_eq!(ColorScheme::none().text(" a&lt; "), " a&lt; ");
+    }
+}
@@ -42,7 +42,7 @@ fn element_to_json<'a, 'input>(node: NodeRef<'a, 'input>) -> Value {
                 has_element_children = true;
                 if let Some(tag_cow) = child.tag_name() {
                     let tag = tag_cow.into_owned();
-                    // Skip empty tags (e.g. processing instructions parsed by tl)
+                    // Skip elements without a name, which `tl` can produce
                     if tag.is_empty() {
                         continue;
                     }
@@ -35,12 +35,15 @@ pub mod parser;
 pub mod query;
 pub mod format;
 pub mod error;
+pub mod validate;
+mod tree;
 
 // Re-export main types
-pub use types::{Document, DocumentType, NodeRef, NodeType};
-pub use query::{Query, QueryOptions, QueryIter, execute_query};
-pub use format::{Formatter, FormatOptions, ColorMode, Indent};
-pub use error::{ParseError, QueryError, FormatError};
+pub use types::{Document, DocumentType, NodeRef, NodeType, OwnedDocument};
+pub use query::{Query, QueryOptions, QueryIter, XPathResult, evaluate_xpath, execute_query};
+pub use format::{Formatter, FormatOptions, AttributeLayout, ColorMode, Indent};
+pub use error::{Diagnostic, ParseError, Position, QueryError, FormatError, SchemaError, Severity};
+pub use validate::{ValidationError, Validator};
 
 #[cfg(feature = "json-output")]
 pub mod json;
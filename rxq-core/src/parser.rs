@@ -1,14 +1,34 @@
 //! Parser utilities and helpers
 //!
 //! This module provides additional parsing utilities beyond the basic
-//! Document::parse() function. Future extensions may include:
-//! - Custom encoding detection
-//! - Streaming parsers
+//! Document::parse() function:
+//! - Strict XML 1.0 parsing with well-formedness checking
+//! - DTD declarations, entity expansion and default attributes
+//! - HTML5 tree construction following the WHATWG parsing algorithm
+//! - Encoding detection and transcoding of raw input bytes
+//! - Memory-mapped file input, falling back to reading for streams
+//! - Transparent gzip, zstd, bzip2 and xz decompression (`compression` feature)
+//! - A streaming pull parser for documents larger than memory
+//! - Splitting concatenated XML documents and NDJSON into documents
+//! - Resource limits enforced while parsing untrusted input
+//!
+//! Future extensions may include:
 //! - Fragment parsing
 //! - XML namespace handling
 
+#[cfg(feature = "compression")]
+pub mod compress;
+pub mod dtd;
+pub mod encoding;
+pub(crate) mod html;
+pub mod input;
+pub mod multi;
+pub mod stream;
+pub(crate) mod xml;
+
 use crate::types::{Document, DocumentType};
-use crate::error::ParseError;
+use crate::error::{ParseError, Position};
+use std::path::PathBuf;
 
 /// Parse options for fine-grained control
 #[derive(Default, Clone)]
@@ -17,10 +37,116 @@ pub struct ParseOptions {
     pub auto_detect: bool,
     
     /// Strict mode (fail on any error)
+    ///
+    /// For XML this uses the well-formedness checking parser instead of the
+    /// lenient one and returns the first violation as an error.
     pub strict: bool,
     
     /// Maximum document size (bytes)
     pub max_size: Option<usize>,
+
+    /// Directory to load external DTDs from
+    ///
+    /// DTD files named by a DOCTYPE or an external parameter entity are
+    /// resolved against this directory. Only local files are read, and with
+    /// `None` (the default) nothing is loaded at all.
+    pub dtd_base_dir: Option<PathBuf>,
+
+    /// Resource limits enforced while parsing
+    pub limits: ParseLimits,
+
+    /// Keep references to entities declared in the DTD as written and leave
+    /// out default attribute values, so the tree matches the source text
+    ///
+    /// Formatting uses this to write documents back without changing them.
+    pub keep_references: bool,
+}
+
+/// Resource limits for parsing untrusted input
+///
+/// Every limit is off when `None`. Limits are checked as the document is
+/// parsed, and the first one exceeded aborts the parse with its own
+/// [`ParseError`] variant.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// Deepest nesting of elements
+    pub max_depth: Option<usize>,
+
+    /// Most attributes on one element
+    pub max_attributes: Option<usize>,
+
+    /// Longest attribute value in bytes, after entity expansion
+    pub max_attribute_length: Option<usize>,
+
+    /// Most nodes of any kind in the document
+    pub max_nodes: Option<usize>,
+
+    /// Longest text node in bytes, after entity expansion
+    pub max_text_length: Option<usize>,
+
+    /// Most bytes of entity replacement text per byte of input
+    ///
+    /// Expansion up to [`ParseLimits::EXPANSION_ALLOWANCE`] bytes is always
+    /// allowed, so that small documents can use entities freely.
+    pub max_expansion_ratio: Option<usize>,
+}
+
+impl ParseLimits {
+    /// Entity expansion allowed regardless of `max_expansion_ratio`
+    pub const EXPANSION_ALLOWANCE: usize = 64 * 1024;
+
+    /// Limits suited to untrusted input that still accept any ordinary document
+    pub fn untrusted() -> Self {
+        Self {
+            max_depth: Some(256),
+            max_attributes: Some(256),
+            max_attribute_length: Some(1024 * 1024),
+            max_nodes: Some(10_000_000),
+            max_text_length: Some(16 * 1024 * 1024),
+            max_expansion_ratio: Some(10),
+        }
+    }
+
+    /// Whether any limit is set
+    pub fn is_enabled(&self) -> bool {
+        *self != Self::default()
+    }
+
+    /// Bytes of replacement text allowed for an input of `input_len` bytes
+    pub(crate) fn expansion_budget(&self, input_len: usize) -> Option<usize> {
+        self.max_expansion_ratio
+            .map(|ratio| ratio.saturating_mul(input_len).max(Self::EXPANSION_ALLOWANCE))
+    }
+}
+
+/// A resource limit hit during parsing, before its position is resolved
+#[derive(Debug)]
+pub(crate) enum Exceeded {
+    Depth(usize),
+    Attributes(usize),
+    AttributeLength(usize),
+    Nodes(usize),
+    TextLength(usize),
+    /// Entity expansion, described by a message
+    Expansion(String),
+}
+
+impl Exceeded {
+    /// `Some` when `value` is over `limit`
+    pub(crate) fn check(limit: Option<usize>, value: usize, kind: fn(usize) -> Self) -> Option<Self> {
+        limit.filter(|&limit| value > limit).map(kind)
+    }
+
+    pub(crate) fn into_error(self, position: Position) -> ParseError {
+        match self {
+            Self::Depth(limit) => ParseError::DepthLimit { position, limit },
+            Self::Attributes(limit) => ParseError::AttributeCountLimit { position, limit },
+            Self::AttributeLength(limit) => ParseError::AttributeLengthLimit { position, limit },
+            Self::Nodes(limit) => ParseError::NodeCountLimit { position, limit },
+            Self::TextLength(limit) => ParseError::TextLengthLimit { position, limit },
+            Self::Expansion(message) => ParseError::EntityExpansionLimit { position, message },
+        }
+    }
 }
 
 /// Parse with options
@@ -44,10 +170,42 @@ pub fn parse_with_options<'input>(
     } else {
         doc_type
     };
-    
+
+    let limited = options.limits.is_enabled();
+    if doc_type == DocumentType::Html && limited {
+        let parsed = html::parse_with_limits(source, &options.limits)?;
+        return Ok(Document::from_tree(source, parsed.tree, doc_type).with_diagnostics(parsed.diagnostics));
+    }
+
+    // XML always goes through the own parser, which recovers from errors but
+    // keeps declarations, processing instructions and CDATA exactly
+    if doc_type == DocumentType::Xml {
+        let parsed = xml::parse(source, options);
+        if let Some(err) = parsed.fatal {
+            return Err(err);
+        }
+        if options.strict {
+            if let Some(err) = parsed.errors.into_iter().next() {
+                return Err(err);
+            }
+        }
+        return Ok(Document::from_tree(source, parsed.tree, doc_type)
+            .with_dtd(parsed.dtd)
+            .with_diagnostics(parsed.diagnostics));
+    }
+
     Document::parse(source, doc_type)
 }
 
+/// Check an XML document for well-formedness.
+///
+/// Returns every violation found (empty if the document is well-formed),
+/// each as a [`ParseError::NotWellFormed`] carrying its position.
+pub fn check_well_formed(source: &str) -> Vec<ParseError> {
+    let parsed = xml::parse(source, &ParseOptions::default());
+    parsed.errors.into_iter().chain(parsed.fatal).collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -75,4 +233,291 @@ mod tests {
         let doc = parse_with_options(html, DocumentType::Xml, &options).unwrap();
         assert_eq!(doc.doc_type(), DocumentType::Html);
     }
+
+    fn strict() -> ParseOptions {
+        ParseOptions {
+            strict: true,
+            ..Default::default()
+        }
+    }
+
+    fn messages(source: &str) -> Vec<String> {
+        check_well_formed(source)
+            .into_iter()
+            .map(|e| match e {
+                ParseError::NotWellFormed { position, message } => {
+                    format!("{}: {}", position, message)
+                }
+                other => other.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_strict_parses_well_formed_xml() {
+        let xml = "<?xml version=\"1.0\"?>\n<!-- c --><root a=\"1\"><b/><c><![CDATA[<x>]]></c></root>";
+        let doc = parse_with_options(xml, DocumentType::Xml, &strict()).unwrap();
+
+        let root = doc.root();
+        assert_eq!(root.tag_name().as_deref(), Some("root"));
+        assert_eq!(root.attr("a").as_deref(), Some("1"));
+        let names: Vec<_> = root.children().filter_map(|c| c.tag_name()).collect();
+        assert_eq!(names, ["b", "c"]);
+        assert_eq!(root.text().as_deref(), Some("<x>"));
+        assert!(check_well_formed(xml).is_empty());
+    }
+
+    #[test]
+    fn test_strict_rejects_mismatched_tags() {
+        let xml = "<root>\n  <a></b>\n</root>";
+        let err = parse_with_options(xml, DocumentType::Xml, &strict()).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "not well-formed at 2:6: mismatched end tag: expected </a>, found </b>"
+        );
+
+        // Lenient mode still accepts it
+        assert!(parse_with_options(xml, DocumentType::Xml, &ParseOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_well_formedness_violations() {
+        assert_eq!(messages("<a/><b/>"), ["1:5: document must have a single root element"]);
+        assert_eq!(messages("<a x=\"1\" x=\"2\"/>"), ["1:10: duplicate attribute 'x'"]);
+        assert_eq!(messages("<a x=1/>"), ["1:6: value of attribute 'x' must be quoted"]);
+        assert_eq!(messages("<a x=é>t</a>"), ["1:6: value of attribute 'x' must be quoted"]);
+        assert_eq!(messages("<a x=\"<\"/>"), ["1:7: '<' is not allowed in attribute values"]);
+        assert_eq!(messages("<1a/>"), ["1:1: '<' must start a tag or be escaped as &lt;", "1:2: text is not allowed outside the root element", "1:6: document has no root element"]);
+        assert_eq!(messages("<a>&nbsp;</a>"), ["1:4: undefined entity '&nbsp;'"]);
+        assert_eq!(messages("<a>&#0;</a>"), ["1:4: invalid character reference '&#0;'"]);
+        assert_eq!(messages("<a><!-- a -- b --></a>"), ["1:11: '--' is not allowed inside comments"]);
+        assert_eq!(messages("<a>\n<b>"), ["2:1: element <b> is never closed", "1:1: element <a> is never closed"]);
+        assert_eq!(messages("<a/><?xml version=\"1.0\"?>"), ["1:5: XML declaration is only allowed at the start of the document"]);
+        assert!(messages("<!DOCTYPE a [<!ENTITY e \"x\">]><a>&e;</a>").is_empty());
+    }
+
+    #[test]
+    fn test_internal_entities_are_expanded() {
+        let xml = r#"<!DOCTYPE doc [
+  <!ENTITY co "ACME &amp; Sons">
+  <!ENTITY sig "<b>&co;</b> Ltd">
+  <!ATTLIST doc lang CDATA "en" version CDATA #FIXED "2">
+]>
+<doc lang="de" owner="&co;"><p>By &sig;.</p></doc>"#;
+        let doc = Document::parse(xml, DocumentType::Xml).unwrap();
+        let root = doc.root();
+        assert_eq!(root.attr("owner").as_deref(), Some("ACME &amp; Sons"));
+        // Defaults fill in missing attributes only
+        assert_eq!(root.attr("lang").as_deref(), Some("de"));
+        assert_eq!(root.attr("version").as_deref(), Some("2"));
+
+        let p = root.children().find(|c| c.tag_name().is_some()).unwrap();
+        let b = p.children().find(|c| c.tag_name().is_some()).unwrap();
+        assert_eq!(b.tag_name().as_deref(), Some("b"));
+        assert_eq!(b.text().as_deref(), Some("ACME &amp; Sons"));
+        assert_eq!(p.text().as_deref(), Some("By ACME &amp; Sons Ltd."));
+
+        let dtd = doc.dtd().unwrap();
+        assert_eq!(dtd.name, "doc");
+        assert!(dtd.entities.contains_key("sig"));
+        assert!(check_well_formed(xml).is_empty());
+    }
+
+    #[test]
+    fn test_entity_errors() {
+        assert_eq!(
+            messages("<!DOCTYPE a [<!ENTITY e \"&e;\">]><a>&e;</a>"),
+            ["1:36: entity '&e;' references itself"]
+        );
+        assert_eq!(
+            messages("<!DOCTYPE a [<!ENTITY e \"<b>\">]><a x=\"&e;\">&nope;</a>"),
+            [
+                "1:39: '<' is not allowed in attribute values (in entity '&e;')",
+                "1:44: undefined entity '&nope;'",
+            ]
+        );
+        // An unread external subset may declare anything
+        assert!(messages("<!DOCTYPE a SYSTEM \"a.dtd\"><a>&nbsp;</a>").is_empty());
+    }
+
+    #[test]
+    fn test_entity_expansion_limits() {
+        let mut laughs = String::from("<!DOCTYPE lolz [<!ENTITY lol0 \"lol\">");
+        for i in 1..10 {
+            let refs = format!("&lol{};", i - 1).repeat(10);
+            laughs.push_str(&format!("<!ENTITY lol{} \"{}\">", i, refs));
+        }
+        laughs.push_str("]><lolz>&lol9;</lolz>");
+        let err = Document::parse(&laughs, DocumentType::Xml).err().unwrap();
+        assert!(matches!(err, ParseError::EntityExpansionLimit { .. }), "{}", err);
+
+        let mut deep = String::from("<!DOCTYPE a [<!ENTITY e0 \"x\">");
+        for i in 1..20 {
+            deep.push_str(&format!("<!ENTITY e{} \"&e{};\">", i, i - 1));
+        }
+        deep.push_str("]><a>&e19;</a>");
+        let err = Document::parse(&deep, DocumentType::Xml).err().unwrap();
+        assert!(err.to_string().contains("nested more than 16 levels"), "{}", err);
+    }
+
+    #[test]
+    fn test_external_dtd_is_loaded_from_base_dir() {
+        let dir = std::env::temp_dir().join(format!("rxq-dtd-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("note.dtd"),
+            "<!ENTITY % extra \"<!ENTITY who 'World'>\">\n%extra;\n<!ATTLIST note kind CDATA \"memo\">",
+        )
+        .unwrap();
+
+        let xml = "<!DOCTYPE note SYSTEM \"note.dtd\"><note>Hello &who;</note>";
+        let options = ParseOptions {
+            dtd_base_dir: Some(dir.clone()),
+            ..Default::default()
+        };
+        let doc = parse_with_options(xml, DocumentType::Xml, &options).unwrap();
+        assert_eq!(doc.root().text().as_deref(), Some("Hello World"));
+        assert_eq!(doc.root().attr("kind").as_deref(), Some("memo"));
+
+        // Without a base directory nothing is read and the reference stays
+        let doc = Document::parse(xml, DocumentType::Xml).unwrap();
+        assert_eq!(doc.root().text().as_deref(), Some("Hello &who;"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn limited(limits: ParseLimits) -> ParseOptions {
+        ParseOptions {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    fn limit_error(source: &str, doc_type: DocumentType, limits: ParseLimits) -> String {
+        match parse_with_options(source, doc_type, &limited(limits)) {
+            Ok(_) => "parsed".to_string(),
+            Err(err) => err.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        let depth = ParseLimits {
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            limit_error("<a><b><c/></b></a>", DocumentType::Xml, depth.clone()),
+            "nesting depth limit exceeded at 1:7: more than 2 levels"
+        );
+        assert_eq!(limit_error("<a><b/><b/></a>", DocumentType::Xml, depth), "parsed");
+
+        let attributes = ParseLimits {
+            max_attributes: Some(2),
+            max_attribute_length: Some(3),
+            ..Default::default()
+        };
+        let err = parse_with_options("<a x=\"1\" y=\"2\" z=\"3\"/>", DocumentType::Xml, &limited(attributes.clone()));
+        assert!(matches!(err, Err(ParseError::AttributeCountLimit { limit: 2, .. })));
+        assert_eq!(
+            limit_error("<a x=\"1234\"/>", DocumentType::Xml, attributes),
+            "attribute length limit exceeded at 1:7: value longer than 3 bytes"
+        );
+
+        let nodes = ParseLimits {
+            max_nodes: Some(3),
+            ..Default::default()
+        };
+        let err = parse_with_options("<a><b/>x<c/></a>", DocumentType::Xml, &limited(nodes));
+        assert!(matches!(err, Err(ParseError::NodeCountLimit { limit: 3, .. })));
+
+        let text = ParseLimits {
+            max_text_length: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(
+            limit_error("<a>hello</a>", DocumentType::Xml, text.clone()),
+            "text length limit exceeded at 1:4: text longer than 4 bytes"
+        );
+        // Measured after entity expansion
+        let xml = "<!DOCTYPE a [<!ENTITY e \"hello\">]><a>&e;</a>";
+        let err = parse_with_options(xml, DocumentType::Xml, &limited(text));
+        assert!(matches!(err, Err(ParseError::TextLengthLimit { .. })));
+    }
+
+    #[test]
+    fn test_expansion_ratio_limit() {
+        let mut xml = String::from("<!DOCTYPE a [<!ENTITY e0 \"0123456789\">");
+        for i in 1..5 {
+            let refs = format!("&e{};", i - 1).repeat(10);
+            xml.push_str(&format!("<!ENTITY e{} \"{}\">", i, refs));
+        }
+        xml.push_str("]><a>&e4;</a>");
+
+        // 100 KB of replacement text is under the fixed cap but over the ratio
+        assert!(Document::parse(&xml, DocumentType::Xml).is_ok());
+        let limits = ParseLimits {
+            max_expansion_ratio: Some(10),
+            ..Default::default()
+        };
+        let err = limit_error(&xml, DocumentType::Xml, limits);
+        assert!(err.starts_with("entity expansion limit exceeded at 1:"), "{}", err);
+        assert!(err.ends_with("expansion exceeds 10 times the input size"), "{}", err);
+    }
+
+    #[test]
+    fn test_html_resource_limits() {
+        let limits = ParseLimits {
+            max_depth: Some(4),
+            ..Default::default()
+        };
+        let html = "<div><div><div></div></div></div>";
+        let err = parse_with_options(html, DocumentType::Html, &limited(limits.clone()));
+        assert!(matches!(err, Err(ParseError::DepthLimit { limit: 4, .. })));
+        assert!(parse_with_options("<p>a<p>b", DocumentType::Html, &limited(limits)).is_ok());
+
+        let text = ParseLimits {
+            max_text_length: Some(4),
+            ..ParseLimits::untrusted()
+        };
+        let err = parse_with_options("<p>hello</p>", DocumentType::Html, &limited(text));
+        assert!(matches!(err, Err(ParseError::TextLengthLimit { limit: 4, .. })));
+    }
+
+    #[test]
+    fn test_lenient_parse_diagnostics() {
+        let xml = "<root>\n  <a x=1 x=\"2\">&nbsp;</b>\n  <c>\n</root>";
+        let doc = Document::parse(xml, DocumentType::Xml).unwrap();
+        let diagnostics: Vec<_> = doc.diagnostics().iter().map(|d| d.to_string()).collect();
+        assert_eq!(
+            diagnostics,
+            [
+                "2:8: error[bad-attribute]: value of attribute 'x' must be quoted",
+                "2:10: error[duplicate-attribute]: duplicate attribute 'x'",
+                "2:16: error[undefined-entity]: undefined entity '&nbsp;'",
+                // </b> closes nothing, while </root> leaves <c> and <a> unclosed
+                "2:22: error[stray-end-tag]: mismatched end tag: expected </a>, found </b>",
+                "4:1: error[mismatched-tag]: mismatched end tag: expected </c>, found </root>",
+                "3:3: error[unclosed-tag]: element <c> is never closed",
+                "2:3: error[unclosed-tag]: element <a> is never closed",
+            ]
+        );
+        let undefined = &doc.diagnostics()[2];
+        assert_eq!(&xml[undefined.span.clone()], "&nbsp;");
+
+        assert!(Document::parse("<a><b/></a>", DocumentType::Xml).unwrap().diagnostics().is_empty());
+        let doc = Document::parse("<p>x</div>", DocumentType::Html).unwrap();
+        assert_eq!(doc.diagnostics()[0].code, "stray-end-tag");
+        assert!(Document::parse("<p>x</p>", DocumentType::Html).unwrap().diagnostics().is_empty());
+    }
+
+    #[test]
+    fn test_warnings_are_not_errors() {
+        let xml = "<!DOCTYPE a SYSTEM \"a.dtd\"><a>&ext;</a>";
+        let doc = parse_with_options(xml, DocumentType::Xml, &strict()).unwrap();
+        let diagnostics = doc.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, crate::error::Severity::Warning);
+        assert_eq!(diagnostics[0].code, "unexpanded-entity");
+        assert!(check_well_formed(xml).is_empty());
+    }
 }
@@ -0,0 +1,241 @@
+//! Transparent gzip, zstd, bzip2 and xz compression
+//!
+//! Compressed input is recognized by its magic bytes, falling back to the
+//! file extension. Concatenated streams (`cat a.gz b.gz`) decompress as one.
+
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// A supported compression format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    /// Recognize compressed data by its leading magic bytes
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(Self::Gzip)
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::Zstd)
+        } else if bytes.starts_with(b"BZh") {
+            Some(Self::Bzip2)
+        } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Self::Xz)
+        } else {
+            None
+        }
+    }
+
+    /// Guess the format from a file extension such as `.gz` or `.zst`
+    pub fn from_path(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?;
+        match extension.to_ascii_lowercase().as_str() {
+            "gz" | "gzip" => Some(Self::Gzip),
+            "zst" | "zstd" => Some(Self::Zstd),
+            "bz2" | "bzip2" => Some(Self::Bzip2),
+            "xz" => Some(Self::Xz),
+            _ => None,
+        }
+    }
+
+    /// The usual file extension, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Gzip => "gz",
+            Self::Zstd => "zst",
+            Self::Bzip2 => "bz2",
+            Self::Xz => "xz",
+        }
+    }
+
+    /// Wrap `reader` so that it yields the decompressed data
+    pub fn decoder<'a>(self, reader: impl BufRead + 'a) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Self::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+            Self::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+            Self::Bzip2 => Box::new(bzip2::bufread::MultiBzDecoder::new(reader)),
+            Self::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(reader)),
+        })
+    }
+
+    /// Decompress a whole buffer
+    pub fn decompress(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(bytes.len().saturating_mul(4));
+        self.decoder(bytes)?.read_to_end(&mut output)?;
+        Ok(output)
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+            Self::Bzip2 => "bzip2",
+            Self::Xz => "xz",
+        })
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "gzip" | "gz" => Ok(Self::Gzip),
+            "zstd" | "zst" => Ok(Self::Zstd),
+            "bzip2" | "bz2" => Ok(Self::Bzip2),
+            "xz" => Ok(Self::Xz),
+            _ => Err(format!(
+                "unknown compression '{}' (expected gzip, zstd, bzip2 or xz)",
+                name
+            )),
+        }
+    }
+}
+
+/// Wrap a reader so that compressed input is decompressed transparently
+///
+/// The format is detected from the first bytes, or taken from `fallback`
+/// (usually [`Compression::from_path`]) when no magic bytes match.
+/// Uncompressed input passes through unchanged.
+pub fn decompress_reader<'a>(
+    mut reader: impl BufRead + 'a,
+    fallback: Option<Compression>,
+) -> io::Result<Box<dyn Read + 'a>> {
+    match Compression::detect(reader.fill_buf()?).or(fallback) {
+        Some(compression) => compression.decoder(reader),
+        None => Ok(Box::new(reader)),
+    }
+}
+
+/// A writer that compresses everything written to it
+///
+/// Call [`CompressWriter::finish`] to write the end of the stream; a writer
+/// that is only dropped may produce a truncated file.
+pub enum CompressWriter<W: Write> {
+    Gzip(flate2::write::GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    Bzip2(bzip2::write::BzEncoder<W>),
+    Xz(xz2::write::XzEncoder<W>),
+}
+
+impl<W: Write> CompressWriter<W> {
+    /// Compress into `inner` with the default level of `compression`
+    pub fn new(inner: W, compression: Compression) -> io::Result<Self> {
+        Ok(match compression {
+            Compression::Gzip => Self::Gzip(flate2::write::GzEncoder::new(
+                inner,
+                flate2::Compression::default(),
+            )),
+            Compression::Zstd => Self::Zstd(zstd::Encoder::new(inner, 0)?),
+            Compression::Bzip2 => Self::Bzip2(bzip2::write::BzEncoder::new(
+                inner,
+                bzip2::Compression::default(),
+            )),
+            Compression::Xz => Self::Xz(xz2::write::XzEncoder::new(inner, 6)),
+        })
+    }
+
+    /// Finish the compressed stream and return the inner writer
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Self::Gzip(encoder) => encoder.finish(),
+            Self::Zstd(encoder) => encoder.finish(),
+            Self::Bzip2(encoder) => encoder.finish(),
+            Self::Xz(encoder) => encoder.finish(),
+        }
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Self::Gzip(encoder) => encoder,
+            Self::Zstd(encoder) => encoder,
+            Self::Bzip2(encoder) => encoder,
+            Self::Xz(encoder) => encoder,
+        }
+    }
+}
+
+impl<W: Write> Write for CompressWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMATS: [Compression; 4] = [
+        Compression::Gzip,
+        Compression::Zstd,
+        Compression::Bzip2,
+        Compression::Xz,
+    ];
+
+    fn compress(data: &[u8], compression: Compression) -> Vec<u8> {
+        let mut writer = CompressWriter::new(Vec::new(), compression).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let xml = b"<root><item>compressed</item></root>";
+        for compression in FORMATS {
+            let compressed = compress(xml, compression);
+            assert_eq!(Compression::detect(&compressed), Some(compression));
+            assert_eq!(compression.decompress(&compressed).unwrap(), xml);
+
+            let mut output = Vec::new();
+            decompress_reader(&compressed[..], None)
+                .unwrap()
+                .read_to_end(&mut output)
+                .unwrap();
+            assert_eq!(output, xml, "{}", compression);
+        }
+    }
+
+    #[test]
+    fn test_concatenated_streams() {
+        for compression in FORMATS {
+            let mut compressed = compress(b"<a/>", compression);
+            compressed.extend(compress(b"<b/>", compression));
+            assert_eq!(compression.decompress(&compressed).unwrap(), b"<a/><b/>");
+        }
+    }
+
+    #[test]
+    fn test_detection() {
+        let mut output = String::new();
+        decompress_reader(&b"<root/>"[..], None)
+            .unwrap()
+            .read_to_string(&mut output)
+            .unwrap();
+        assert_eq!(output, "<root/>");
+
+        assert_eq!(Compression::from_path("data.XML.GZ"), Some(Compression::Gzip));
+        assert_eq!(Compression::from_path("data.xml.zst"), Some(Compression::Zstd));
+        assert_eq!(Compression::from_path("data.xml"), None);
+        assert_eq!("bz2".parse(), Ok(Compression::Bzip2));
+        assert!("lz4".parse::<Compression>().is_err());
+
+        // An extension alone does not make plain text decompress
+        assert!(decompress_reader(&b"<root/>"[..], Some(Compression::Gzip))
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .is_err());
+    }
+}
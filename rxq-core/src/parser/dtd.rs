@@ -0,0 +1,982 @@
+//! Document type definitions
+//!
+//! Parses the internal subset of a DOCTYPE declaration, and optionally DTD
+//! files on the local file system, into element, attribute-list and entity
+//! declarations. The XML parser uses the result to expand entity references
+//! and to add default attribute values.
+
+use crate::parser::encoding::decode;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Deepest nesting of entity references inside replacement text
+pub(crate) const MAX_ENTITY_DEPTH: usize = 16;
+
+/// Most bytes of replacement text entity expansion may produce per document
+pub(crate) const MAX_EXPANDED_BYTES: usize = 8 * 1024 * 1024;
+
+/// Declarations collected from a document type definition
+#[derive(Debug, Clone, Default)]
+pub struct Dtd {
+    /// Root element name given in the DOCTYPE
+    pub name: String,
+    /// Content model per element type
+    pub elements: HashMap<String, ContentSpec>,
+    /// Attribute declarations per element type, in declaration order
+    pub attributes: HashMap<String, Vec<AttributeDecl>>,
+    /// General entities
+    pub entities: HashMap<String, Entity>,
+    parameter_entities: HashMap<String, Entity>,
+    /// Set when declarations may be missing because an external subset or
+    /// parameter entity was not read
+    pub(crate) incomplete: bool,
+}
+
+/// Content model from an `<!ELEMENT>` declaration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContentSpec {
+    Empty,
+    Any,
+    /// `(#PCDATA | a | b)*`; the list holds the allowed element names
+    Mixed(Vec<String>),
+    /// Element content
+    Children(ContentParticle),
+}
+
+/// One particle of an element content model
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentParticle {
+    pub kind: ParticleKind,
+    pub occurrence: Occurrence,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParticleKind {
+    Element(String),
+    /// `(a, b, c)`
+    Sequence(Vec<ContentParticle>),
+    /// `(a | b | c)`
+    Choice(Vec<ContentParticle>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Occurrence {
+    Once,
+    /// `?`
+    Optional,
+    /// `*`
+    ZeroOrMore,
+    /// `+`
+    OneOrMore,
+}
+
+/// One attribute from an `<!ATTLIST>` declaration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeDecl {
+    pub name: String,
+    pub kind: AttributeType,
+    pub default: AttributeDefault,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeType {
+    CData,
+    Id,
+    IdRef,
+    IdRefs,
+    Entity,
+    Entities,
+    NmToken,
+    NmTokens,
+    Notation(Vec<String>),
+    Enumeration(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttributeDefault {
+    Required,
+    Implied,
+    Fixed(String),
+    Value(String),
+}
+
+/// An `<!ENTITY>` declaration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Entity {
+    /// Replacement text, with character references already resolved
+    Internal(String),
+    External {
+        public_id: Option<String>,
+        system_id: String,
+        /// Notation of an unparsed entity (`NDATA`)
+        notation: Option<String>,
+    },
+}
+
+impl Dtd {
+    /// Parse a subset (the text between `[` and `]` of a DOCTYPE)
+    ///
+    /// `offset` is the position of `text` in the document; errors are added to
+    /// `errors` as byte offset and message. External files are resolved
+    /// against `base_dir`, and not loaded at all without one.
+    pub(crate) fn parse_internal_subset(
+        &mut self,
+        text: &str,
+        offset: usize,
+        base_dir: Option<&Path>,
+        errors: &mut Vec<(usize, String)>,
+    ) {
+        let mut parser = SubsetParser {
+            dtd: self,
+            errors,
+            base_dir,
+            expanding: Vec::new(),
+        };
+        parser.parse(text, &Location::internal(offset), false);
+    }
+
+    /// Load and parse the external subset named by a DOCTYPE
+    ///
+    /// Errors are reported at `offset`, the position of the DOCTYPE.
+    pub(crate) fn parse_external_subset(
+        &mut self,
+        system_id: &str,
+        offset: usize,
+        base_dir: Option<&Path>,
+        errors: &mut Vec<(usize, String)>,
+    ) {
+        let mut parser = SubsetParser {
+            dtd: self,
+            errors,
+            base_dir,
+            expanding: Vec::new(),
+        };
+        if let Some(text) = parser.load(system_id, offset) {
+            parser.parse(&text, &Location::external(offset, system_id), true);
+        }
+    }
+
+    /// Parse the text of a standalone DTD file
+    ///
+    /// Errors are reported at offsets into `text`; external parameter
+    /// entities are resolved against `base_dir`.
+    pub(crate) fn parse_file(
+        &mut self,
+        text: &str,
+        base_dir: Option<&Path>,
+        errors: &mut Vec<(usize, String)>,
+    ) {
+        let mut parser = SubsetParser {
+            dtd: self,
+            errors,
+            base_dir,
+            expanding: Vec::new(),
+        };
+        parser.parse(text, &Location::internal(0), true);
+    }
+}
+
+/// Where errors found in a piece of DTD text are reported
+struct Location {
+    base: usize,
+    /// Text from another file: every error is reported at `base`
+    fixed: bool,
+    context: Option<String>,
+}
+
+impl Location {
+    fn internal(base: usize) -> Self {
+        Self {
+            base,
+            fixed: false,
+            context: None,
+        }
+    }
+
+    fn external(at: usize, context: &str) -> Self {
+        Self {
+            base: at,
+            fixed: true,
+            context: Some(context.to_string()),
+        }
+    }
+
+    fn offset(&self, i: usize) -> usize {
+        if self.fixed {
+            self.base
+        } else {
+            self.base + i
+        }
+    }
+}
+
+struct SubsetParser<'a> {
+    dtd: &'a mut Dtd,
+    errors: &'a mut Vec<(usize, String)>,
+    base_dir: Option<&'a Path>,
+    /// Parameter entities being expanded, to detect recursion
+    expanding: Vec<String>,
+}
+
+impl SubsetParser<'_> {
+    fn parse(&mut self, text: &str, location: &Location, external: bool) {
+        let mut pos = 0;
+        loop {
+            pos += text[pos..].len() - text[pos..].trim_start().len();
+            let rest = &text[pos..];
+            if rest.is_empty() {
+                return;
+            }
+
+            if rest.starts_with("<!--") {
+                match rest.find("-->") {
+                    Some(i) => pos += i + 3,
+                    None => {
+                        self.error(location, pos, "unterminated comment");
+                        return;
+                    }
+                }
+            } else if rest.starts_with("<?") {
+                match rest.find("?>") {
+                    Some(i) => pos += i + 2,
+                    None => {
+                        self.error(location, pos, "unterminated processing instruction");
+                        return;
+                    }
+                }
+            } else if rest.starts_with("<![") {
+                pos += self.parse_conditional_section(rest, pos, location, external);
+            } else if rest.starts_with("<!") {
+                let Some(len) = declaration_len(rest) else {
+                    self.error(location, pos, "unterminated markup declaration");
+                    return;
+                };
+                let declaration = &rest[..len];
+                if external && declaration.contains('%') {
+                    let expanded = self.expand_parameter_entities(declaration, pos, location);
+                    self.parse_declaration(&expanded, pos, location);
+                } else {
+                    self.parse_declaration(declaration, pos, location);
+                }
+                pos += len;
+            } else if rest.starts_with('%') {
+                let Some(end) = rest.find(';') else {
+                    self.error(location, pos, "unterminated parameter entity reference");
+                    return;
+                };
+                let name = &rest[1..end];
+                self.include_parameter_entity(name, pos, location, external);
+                pos += end + 1;
+            } else {
+                self.error(location, pos, "unexpected content in DTD");
+                pos += rest[1..].find('<').map_or(rest.len(), |i| i + 1);
+            }
+        }
+    }
+
+    /// Handle `<![INCLUDE[ ... ]]>` and `<![IGNORE[ ... ]]>`; returns the length
+    fn parse_conditional_section(
+        &mut self,
+        rest: &str,
+        pos: usize,
+        location: &Location,
+        external: bool,
+    ) -> usize {
+        let end = conditional_section_len(rest).unwrap_or(rest.len());
+        if !external {
+            self.error(
+                location,
+                pos,
+                "conditional sections are only allowed in external subsets",
+            );
+            return end;
+        }
+
+        let Some(bracket) = rest[3..].find('[') else {
+            self.error(location, pos, "malformed conditional section");
+            return end;
+        };
+        let keyword = rest[3..3 + bracket].trim();
+        let keyword = match keyword.strip_prefix('%').and_then(|k| k.strip_suffix(';')) {
+            Some(name) => match self.dtd.parameter_entities.get(name) {
+                Some(Entity::Internal(value)) => value.trim().to_string(),
+                _ => String::new(),
+            },
+            None => keyword.to_string(),
+        };
+        match keyword.as_str() {
+            "INCLUDE" => {
+                let body_start = 3 + bracket + 1;
+                let body_end = end.saturating_sub(3).max(body_start);
+                self.parse(&rest[body_start..body_end], location, external);
+            }
+            "IGNORE" => {}
+            _ => self.error(location, pos, "conditional section must be INCLUDE or IGNORE"),
+        }
+        end
+    }
+
+    fn include_parameter_entity(
+        &mut self,
+        name: &str,
+        pos: usize,
+        location: &Location,
+        external: bool,
+    ) {
+        let Some(entity) = self.dtd.parameter_entities.get(name).cloned() else {
+            // Undeclared parameter entities make the DTD incomplete, not invalid
+            self.dtd.incomplete = true;
+            return;
+        };
+        if self.expanding.iter().any(|n| n == name) {
+            self.error(location, pos, format!("recursive parameter entity '%{};'", name));
+            return;
+        }
+        if self.expanding.len() >= MAX_ENTITY_DEPTH {
+            self.error(location, pos, "parameter entities are nested too deeply");
+            return;
+        }
+
+        let text = match entity {
+            Entity::Internal(value) => value,
+            Entity::External { system_id, .. } => {
+                let Some(text) = self.load(&system_id, location.offset(pos)) else {
+                    return;
+                };
+                text
+            }
+        };
+        self.expanding.push(name.to_string());
+        let nested = Location {
+            base: location.offset(pos),
+            fixed: true,
+            context: Some(format!("%{};", name)),
+        };
+        self.parse(&text, &nested, external);
+        self.expanding.pop();
+    }
+
+    /// Replace `%name;` references inside a declaration of an external subset
+    fn expand_parameter_entities(
+        &mut self,
+        declaration: &str,
+        pos: usize,
+        location: &Location,
+    ) -> String {
+        let mut out = String::with_capacity(declaration.len());
+        let mut quote = None;
+        let mut rest = declaration;
+        while let Some(c) = rest.chars().next() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (None, '"' | '\'') => quote = Some(c),
+                (_, '%') => {
+                    if let Some(end) = rest.find(';').filter(|&end| is_name(&rest[1..end])) {
+                        let name = &rest[1..end];
+                        match self.dtd.parameter_entities.get(name) {
+                            Some(Entity::Internal(value)) => {
+                                // Outside literals the replacement is padded with spaces
+                                let pad = if quote.is_none() { " " } else { "" };
+                                out.push_str(pad);
+                                out.push_str(value);
+                                out.push_str(pad);
+                            }
+                            _ => {
+                                self.dtd.incomplete = true;
+                                self.error(
+                                    location,
+                                    pos,
+                                    format!("undefined parameter entity '%{};'", name),
+                                );
+                            }
+                        }
+                        rest = &rest[end + 1..];
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+        out
+    }
+
+    fn parse_declaration(&mut self, declaration: &str, pos: usize, location: &Location) {
+        let mut cursor = Cursor::new(&declaration[2..declaration.len() - 1]);
+        let keyword = cursor.name().unwrap_or_default();
+        let result = match keyword {
+            "ELEMENT" => self.parse_element_decl(&mut cursor),
+            "ATTLIST" => self.parse_attlist_decl(&mut cursor),
+            "ENTITY" => self.parse_entity_decl(&mut cursor),
+            "NOTATION" => Ok(()),
+            _ => Err(format!("unknown markup declaration '<!{}'", keyword)),
+        };
+        if let Err(message) = result {
+            self.error(location, pos, message);
+        }
+    }
+
+    fn parse_element_decl(&mut self, cursor: &mut Cursor<'_>) -> Result<(), String> {
+        let name = cursor
+            .after_whitespace()
+            .and_then(|c| c.name())
+            .ok_or("<!ELEMENT> must name an element type")?;
+        if !cursor.skip_whitespace() {
+            return Err(format!("malformed <!ELEMENT {}> declaration", name));
+        }
+
+        let spec = if cursor.eat("EMPTY") {
+            ContentSpec::Empty
+        } else if cursor.eat("ANY") {
+            ContentSpec::Any
+        } else if cursor.eat("(") {
+            cursor.skip_whitespace();
+            if cursor.eat("#PCDATA") {
+                parse_mixed(cursor)
+            } else {
+                parse_group(cursor, 0).map(ContentSpec::Children)
+            }
+            .ok_or_else(|| format!("malformed content model for <{}>", name))?
+        } else {
+            return Err(format!("malformed content model for <{}>", name));
+        };
+
+        cursor.skip_whitespace();
+        if !cursor.at_end() {
+            return Err(format!("malformed content model for <{}>", name));
+        }
+        self.dtd.elements.entry(name.to_string()).or_insert(spec);
+        Ok(())
+    }
+
+    fn parse_attlist_decl(&mut self, cursor: &mut Cursor<'_>) -> Result<(), String> {
+        let element = cursor
+            .after_whitespace()
+            .and_then(|c| c.name())
+            .ok_or("<!ATTLIST> must name an element type")?;
+
+        let mut declared = Vec::new();
+        loop {
+            let had_whitespace = cursor.skip_whitespace();
+            if cursor.at_end() {
+                break;
+            }
+            let malformed = || format!("malformed <!ATTLIST {}> declaration", element);
+            let name = cursor
+                .name()
+                .filter(|_| had_whitespace)
+                .ok_or_else(malformed)?;
+
+            cursor.skip_whitespace();
+            let kind = match cursor.name() {
+                Some("CDATA") => AttributeType::CData,
+                Some("ID") => AttributeType::Id,
+                Some("IDREF") => AttributeType::IdRef,
+                Some("IDREFS") => AttributeType::IdRefs,
+                Some("ENTITY") => AttributeType::Entity,
+                Some("ENTITIES") => AttributeType::Entities,
+                Some("NMTOKEN") => AttributeType::NmToken,
+                Some("NMTOKENS") => AttributeType::NmTokens,
+                Some("NOTATION") => {
+                    cursor.skip_whitespace();
+                    AttributeType::Notation(parse_enumeration(cursor).ok_or_else(malformed)?)
+                }
+                None if cursor.peek() == Some('(') => {
+                    AttributeType::Enumeration(parse_enumeration(cursor).ok_or_else(malformed)?)
+                }
+                _ => return Err(malformed()),
+            };
+
+            cursor.skip_whitespace();
+            let default = if cursor.eat("#REQUIRED") {
+                AttributeDefault::Required
+            } else if cursor.eat("#IMPLIED") {
+                AttributeDefault::Implied
+            } else if cursor.eat("#FIXED") {
+                cursor.skip_whitespace();
+                AttributeDefault::Fixed(cursor.literal().ok_or_else(malformed)?.to_string())
+            } else {
+                AttributeDefault::Value(cursor.literal().ok_or_else(malformed)?.to_string())
+            };
+
+            declared.push(AttributeDecl {
+                name: name.to_string(),
+                kind,
+                default,
+            });
+        }
+
+        // The first declaration of an attribute is binding
+        let attributes = self.dtd.attributes.entry(element.to_string()).or_default();
+        for decl in declared {
+            if !attributes.iter().any(|a| a.name == decl.name) {
+                attributes.push(decl);
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_entity_decl(&mut self, cursor: &mut Cursor<'_>) -> Result<(), String> {
+        let mut named = cursor.skip_whitespace();
+        let parameter = cursor.eat("%");
+        if parameter {
+            named = cursor.skip_whitespace();
+        }
+        let name = cursor
+            .name()
+            .filter(|_| named)
+            .ok_or("<!ENTITY> must name the entity")?;
+        let malformed = || format!("malformed <!ENTITY {}> declaration", name);
+        if !cursor.skip_whitespace() {
+            return Err(malformed());
+        }
+
+        let entity = if let Some(value) = cursor.literal() {
+            Entity::Internal(resolve_char_refs(value))
+        } else {
+            let (public_id, system_id) = parse_external_id(cursor).ok_or_else(malformed)?;
+            let mut notation = None;
+            if cursor.skip_whitespace() && cursor.eat("NDATA") {
+                if parameter {
+                    return Err(format!("parameter entity '{}' cannot be unparsed", name));
+                }
+                cursor.skip_whitespace();
+                notation = Some(cursor.name().ok_or_else(malformed)?.to_string());
+            }
+            Entity::External {
+                public_id,
+                system_id,
+                notation,
+            }
+        };
+
+        cursor.skip_whitespace();
+        if !cursor.at_end() {
+            return Err(malformed());
+        }
+        let entities = if parameter {
+            &mut self.dtd.parameter_entities
+        } else {
+            &mut self.dtd.entities
+        };
+        entities.entry(name.to_string()).or_insert(entity);
+        Ok(())
+    }
+
+    /// Read a DTD file relative to the base directory; files outside it are
+    /// refused
+    fn load(&mut self, system_id: &str, offset: usize) -> Option<String> {
+        let Some(base_dir) = self.base_dir else {
+            self.dtd.incomplete = true;
+            return None;
+        };
+        let path = system_id.strip_prefix("file://").unwrap_or(system_id);
+        if path.contains("://") {
+            self.dtd.incomplete = true;
+            self.errors.push((
+                offset,
+                format!("external DTD '{}' is not a local file", system_id),
+            ));
+            return None;
+        }
+
+        let result = base_dir
+            .canonicalize()
+            .and_then(|base| Ok((base_dir.join(path).canonicalize()?, base)))
+            .map_err(|e| e.to_string())
+            .and_then(|(file, base)| {
+                if file.starts_with(&base) {
+                    std::fs::read(file).map_err(|e| e.to_string())
+                } else {
+                    Err(format!("{} is outside {}", file.display(), base.display()))
+                }
+            })
+            .and_then(|bytes| {
+                decode(&bytes, None)
+                    .map(|decoded| decoded.text.into_owned())
+                    .map_err(|e| e.to_string())
+            });
+        match result {
+            Ok(text) => Some(text),
+            Err(e) => {
+                self.dtd.incomplete = true;
+                self.errors
+                    .push((offset, format!("cannot load DTD '{}': {}", system_id, e)));
+                None
+            }
+        }
+    }
+
+    fn error(&mut self, location: &Location, pos: usize, message: impl Into<String>) {
+        let message = message.into();
+        let message = match &location.context {
+            Some(context) => format!("in {}: {}", context, message),
+            None => message,
+        };
+        self.errors.push((location.offset(pos), message));
+    }
+}
+
+/// Parse `SYSTEM "uri"` or `PUBLIC "id" "uri"`
+pub(crate) fn parse_external_id(cursor: &mut Cursor<'_>) -> Option<(Option<String>, String)> {
+    if cursor.eat("SYSTEM") {
+        cursor.skip_whitespace();
+        Some((None, cursor.literal()?.to_string()))
+    } else if cursor.eat("PUBLIC") {
+        cursor.skip_whitespace();
+        let public_id = cursor.literal()?.to_string();
+        cursor.skip_whitespace();
+        Some((Some(public_id), cursor.literal()?.to_string()))
+    } else {
+        None
+    }
+}
+
+/// `(#PCDATA | a | b)*`, after `#PCDATA`
+fn parse_mixed(cursor: &mut Cursor<'_>) -> Option<ContentSpec> {
+    let mut names = Vec::new();
+    loop {
+        cursor.skip_whitespace();
+        if cursor.eat(")") {
+            break;
+        }
+        if !cursor.eat("|") {
+            return None;
+        }
+        cursor.skip_whitespace();
+        names.push(cursor.name()?.to_string());
+    }
+    // With element names the group must be repeatable
+    if !cursor.eat("*") && !names.is_empty() {
+        return None;
+    }
+    Some(ContentSpec::Mixed(names))
+}
+
+/// A choice or sequence, after its opening parenthesis
+fn parse_group(cursor: &mut Cursor<'_>, depth: usize) -> Option<ContentParticle> {
+    if depth > MAX_ENTITY_DEPTH * 4 {
+        return None;
+    }
+    let mut items = Vec::new();
+    let mut separator = None;
+    loop {
+        cursor.skip_whitespace();
+        let kind = if cursor.eat("(") {
+            parse_group(cursor, depth + 1)?
+        } else {
+            let name = cursor.name()?.to_string();
+            ContentParticle {
+                kind: ParticleKind::Element(name),
+                occurrence: parse_occurrence(cursor),
+            }
+        };
+        items.push(kind);
+
+        cursor.skip_whitespace();
+        if cursor.eat(")") {
+            break;
+        }
+        let sep = cursor.peek().filter(|&c| c == ',' || c == '|')?;
+        if separator.is_some_and(|s| s != sep) {
+            return None;
+        }
+        separator = Some(sep);
+        cursor.eat(if sep == ',' { "," } else { "|" });
+    }
+
+    let kind = if separator == Some('|') {
+        ParticleKind::Choice(items)
+    } else {
+        ParticleKind::Sequence(items)
+    };
+    Some(ContentParticle {
+        kind,
+        occurrence: parse_occurrence(cursor),
+    })
+}
+
+fn parse_occurrence(cursor: &mut Cursor<'_>) -> Occurrence {
+    if cursor.eat("?") {
+        Occurrence::Optional
+    } else if cursor.eat("*") {
+        Occurrence::ZeroOrMore
+    } else if cursor.eat("+") {
+        Occurrence::OneOrMore
+    } else {
+        Occurrence::Once
+    }
+}
+
+/// `(a | b | c)` of name tokens
+fn parse_enumeration(cursor: &mut Cursor<'_>) -> Option<Vec<String>> {
+    if !cursor.eat("(") {
+        return None;
+    }
+    let mut values = Vec::new();
+    loop {
+        cursor.skip_whitespace();
+        values.push(cursor.name_token()?.to_string());
+        cursor.skip_whitespace();
+        if cursor.eat(")") {
+            return Some(values);
+        }
+        if !cursor.eat("|") {
+            return None;
+        }
+    }
+}
+
+/// Resolve `&#...;` character references, as done for entity values
+fn resolve_char_refs(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(i) = rest.find("&#") {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let decoded = rest.find(';').and_then(|end| {
+            let number = &rest[2..end];
+            let code = match number.strip_prefix('x') {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => number.parse().ok(),
+            };
+            code.and_then(char::from_u32).map(|c| (c, end))
+        });
+        match decoded {
+            Some((c, end)) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Length of a markup declaration, honouring quoted literals
+fn declaration_len(text: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Length of a conditional section including nested sections
+fn conditional_section_len(text: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < text.len() {
+        if text[i..].starts_with("<![") {
+            depth += 1;
+            i += 3;
+        } else if text[i..].starts_with("]]>") {
+            depth -= 1;
+            i += 3;
+            if depth == 0 {
+                return Some(i);
+            }
+        } else {
+            i += text[i..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    None
+}
+
+fn is_name(s: &str) -> bool {
+    super::xml::is_name(s)
+}
+
+/// Minimal scanner over declaration text
+pub(crate) struct Cursor<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub(crate) fn new(text: &'a str) -> Self {
+        Self { text, pos: 0 }
+    }
+
+    /// Bytes consumed so far
+    pub(crate) fn position(&self) -> usize {
+        self.pos
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    pub(crate) fn at_end(&self) -> bool {
+        self.pos >= self.text.len()
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    pub(crate) fn skip_whitespace(&mut self) -> bool {
+        let rest = self.rest();
+        let len = rest.len() - rest.trim_start_matches([' ', '\t', '\r', '\n']).len();
+        self.pos += len;
+        len > 0
+    }
+
+    fn after_whitespace(&mut self) -> Option<&mut Self> {
+        self.skip_whitespace().then_some(self)
+    }
+
+    pub(crate) fn eat(&mut self, token: &str) -> bool {
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn name(&mut self) -> Option<&'a str> {
+        let rest = self.rest();
+        let mut chars = rest.char_indices();
+        if !chars
+            .next()
+            .is_some_and(|(_, c)| super::xml::is_name_start_char(c))
+        {
+            return None;
+        }
+        let len = chars
+            .find(|&(_, c)| !super::xml::is_name_char(c))
+            .map_or(rest.len(), |(i, _)| i);
+        self.pos += len;
+        Some(&rest[..len])
+    }
+
+    fn name_token(&mut self) -> Option<&'a str> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !super::xml::is_name_char(c))
+            .unwrap_or(rest.len());
+        self.pos += len;
+        (len > 0).then(|| &rest[..len])
+    }
+
+    /// A quoted literal, without the quotes
+    pub(crate) fn literal(&mut self) -> Option<&'a str> {
+        let rest = self.rest();
+        let quote = rest.chars().next().filter(|&q| q == '"' || q == '\'')?;
+        let len = rest[1..].find(quote)?;
+        self.pos += len + 2;
+        Some(&rest[1..1 + len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(subset: &str) -> (Dtd, Vec<(usize, String)>) {
+        let mut dtd = Dtd::default();
+        let mut errors = Vec::new();
+        dtd.parse_internal_subset(subset, 0, None, &mut errors);
+        (dtd, errors)
+    }
+
+    #[test]
+    fn test_element_declarations() {
+        let (dtd, errors) = parse(
+            "<!ELEMENT doc (head?, (p | list)+)>
+             <!ELEMENT p (#PCDATA | em)*>
+             <!ELEMENT br EMPTY>",
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(dtd.elements["br"], ContentSpec::Empty);
+        assert_eq!(dtd.elements["p"], ContentSpec::Mixed(vec!["em".to_string()]));
+
+        let ContentSpec::Children(doc) = &dtd.elements["doc"] else {
+            panic!("expected element content");
+        };
+        let ParticleKind::Sequence(items) = &doc.kind else {
+            panic!("expected a sequence");
+        };
+        assert_eq!(items[0].occurrence, Occurrence::Optional);
+        assert!(matches!(&items[1].kind, ParticleKind::Choice(c) if c.len() == 2));
+        assert_eq!(items[1].occurrence, Occurrence::OneOrMore);
+    }
+
+    #[test]
+    fn test_attlist_and_entities() {
+        let (dtd, errors) = parse(
+            r#"<!ATTLIST item id ID #REQUIRED
+                           kind (a|b) "a"
+                           lang CDATA #FIXED 'en'>
+               <!ENTITY % common "<!ENTITY co 'ACME'>">
+               %common;
+               <!ENTITY lt2 "&#60;">
+               <!ENTITY logo SYSTEM "logo.png" NDATA png>"#,
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let attrs = &dtd.attributes["item"];
+        assert_eq!(attrs[0].kind, AttributeType::Id);
+        assert_eq!(attrs[0].default, AttributeDefault::Required);
+        assert_eq!(
+            attrs[1].kind,
+            AttributeType::Enumeration(vec!["a".into(), "b".into()])
+        );
+        assert_eq!(attrs[2].default, AttributeDefault::Fixed("en".into()));
+
+        assert_eq!(dtd.entities["co"], Entity::Internal("ACME".into()));
+        assert_eq!(dtd.entities["lt2"], Entity::Internal("<".into()));
+        assert!(matches!(
+            &dtd.entities["logo"],
+            Entity::External { notation: Some(n), .. } if n == "png"
+        ));
+    }
+
+    #[test]
+    fn test_malformed_declarations() {
+        let (_, errors) = parse("<!ELEMENT a (b,c|d)>\n<!FOO bar>");
+        assert_eq!(errors[0], (0, "malformed content model for <a>".to_string()));
+        assert_eq!(errors[1], (21, "unknown markup declaration '<!FOO'".to_string()));
+    }
+
+    #[test]
+    fn test_external_files_stay_in_base_dir() {
+        let dir = std::env::temp_dir().join(format!("rxq-dtd-escape-{}", std::process::id()));
+        let base = dir.join("base");
+        std::fs::create_dir_all(base.join("sub")).unwrap();
+        std::fs::write(dir.join("secret.dtd"), "<!ENTITY secret 'leaked'>").unwrap();
+        std::fs::write(base.join("sub/ok.dtd"), "<!ENTITY ok 'loaded'>").unwrap();
+
+        let load = |system_id: &str| {
+            let mut dtd = Dtd::default();
+            let mut errors = Vec::new();
+            dtd.parse_external_subset(system_id, 0, Some(&base), &mut errors);
+            (dtd, errors)
+        };
+
+        let absolute = dir.join("secret.dtd").display().to_string();
+        for system_id in [absolute.as_str(), "../secret.dtd", "sub/../../secret.dtd"] {
+            let (dtd, errors) = load(system_id);
+            assert!(dtd.incomplete);
+            assert!(dtd.entities.is_empty());
+            assert_eq!(errors.len(), 1);
+            assert!(errors[0].1.contains("is outside"), "{:?}", errors);
+        }
+
+        let (dtd, errors) = load("sub/../sub/ok.dtd");
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(dtd.entities["ok"], Entity::Internal("loaded".into()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,373 @@
+//! Character encoding detection and transcoding
+//!
+//! Documents are parsed as UTF-8 text. Input bytes in other encodings are
+//! detected the way XML and HTML processors do it: byte order mark first,
+//! then the `encoding` pseudo-attribute of the XML declaration, then a
+//! `<meta charset>` in the first kilobyte, falling back to UTF-8. UTF-8 input
+//! without a BOM is borrowed, not copied.
+
+use crate::error::ParseError;
+use encoding_rs::{UTF_16BE, UTF_16LE, UTF_8};
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::ops::Range;
+
+pub use encoding_rs::Encoding;
+
+/// How far into the input to look for an encoding declaration
+const PRESCAN_LIMIT: usize = 1024;
+
+/// Input decoded to UTF-8
+#[derive(Debug)]
+pub struct DecodedInput<'a> {
+    /// The document text
+    pub text: Cow<'a, str>,
+    /// Encoding the input was decoded from
+    pub encoding: &'static Encoding,
+    /// Whether the input started with a byte order mark
+    pub bom: bool,
+}
+
+/// Look up an encoding by one of its WHATWG labels (e.g. `latin1`, `sjis`)
+pub fn encoding_for_label(label: &str) -> Result<&'static Encoding, ParseError> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| ParseError::EncodingError(format!("unknown encoding '{}'", label)))
+}
+
+/// Detect the encoding of raw input bytes
+pub fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+
+    // An XML declaration in UTF-16 without a BOM
+    match bytes {
+        [b'<', 0, b'?', 0, ..] => return UTF_16LE,
+        [0, b'<', 0, b'?', ..] => return UTF_16BE,
+        _ => {}
+    }
+
+    let head = &bytes[..bytes.len().min(PRESCAN_LIMIT)];
+    declared_encoding(head)
+        .and_then(Encoding::for_label)
+        // A UTF-16 label in an ASCII-compatible byte stream cannot be right
+        .map(|encoding| {
+            if encoding == UTF_16LE || encoding == UTF_16BE {
+                UTF_8
+            } else {
+                encoding
+            }
+        })
+        .unwrap_or(UTF_8)
+}
+
+/// Decode raw input to UTF-8
+///
+/// `label` overrides detection. A byte order mark matching the encoding is
+/// stripped. Byte sequences that are invalid in the encoding are an error
+/// rather than being replaced.
+pub fn decode<'a>(bytes: &'a [u8], label: Option<&str>) -> Result<DecodedInput<'a>, ParseError> {
+    let encoding = match label {
+        Some(label) => encoding_for_label(label)?,
+        None => detect_encoding(bytes),
+    };
+
+    let (body, bom) = match Encoding::for_bom(bytes) {
+        Some((bom_encoding, len)) if bom_encoding == encoding => (&bytes[len..], true),
+        _ => (bytes, false),
+    };
+
+    let text = if encoding == UTF_8 {
+        let text = std::str::from_utf8(body).map_err(|e| {
+            ParseError::EncodingError(format!(
+                "invalid UTF-8 at byte {}",
+                e.valid_up_to() + (bytes.len() - body.len())
+            ))
+        })?;
+        Cow::Borrowed(text)
+    } else {
+        let text = encoding
+            .decode_without_bom_handling_and_without_replacement(body)
+            .ok_or_else(|| {
+                ParseError::EncodingError(format!("input is not valid {}", encoding.name()))
+            })?;
+        Cow::Owned(text.into_owned())
+    };
+
+    Ok(DecodedInput {
+        text,
+        encoding,
+        bom,
+    })
+}
+
+/// Find the encoding named by an XML declaration or an HTML `<meta>` tag
+fn declared_encoding(head: &[u8]) -> Option<&[u8]> {
+    if head.starts_with(b"<?xml") {
+        let end = find(head, b"?>")?;
+        return attribute_value(&head[..end], b"encoding");
+    }
+
+    let lower = head.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(start) = find(&lower[from..], b"<meta") {
+        let start = from + start;
+        let end = lower[start..]
+            .iter()
+            .position(|&b| b == b'>')
+            .map_or(lower.len(), |i| start + i);
+        // Covers both <meta charset=...> and http-equiv content="...; charset=..."
+        if let Some(i) = find(&lower[start..end], b"charset=") {
+            let value_start = start + i + b"charset=".len();
+            let value = &head[value_start..end];
+            let value = value
+                .strip_prefix(b"\"")
+                .or_else(|| value.strip_prefix(b"'"))
+                .unwrap_or(value);
+            let len = value
+                .iter()
+                .position(|&b| {
+                    matches!(b, b'"' | b'\'' | b';' | b'/' | b' ' | b'\t' | b'\n' | b'\r')
+                })
+                .unwrap_or(value.len());
+            if len > 0 {
+                return Some(&value[..len]);
+            }
+        }
+        from = end;
+    }
+    None
+}
+
+/// Value of `name="..."` or `name='...'` inside an XML declaration
+fn attribute_value<'a>(decl: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    attribute_range(decl, name).map(|range| &decl[range])
+}
+
+/// Position of the value of `name="..."` or `name='...'` in `decl`
+fn attribute_range(decl: &[u8], name: &[u8]) -> Option<Range<usize>> {
+    let skip_whitespace = |at: usize| {
+        decl[at..]
+            .iter()
+            .position(|&b| !b.is_ascii_whitespace())
+            .map(|i| at + i)
+    };
+    let at = skip_whitespace(find(decl, name)? + name.len())?;
+    if decl[at] != b'=' {
+        return None;
+    }
+    let at = skip_whitespace(at + 1)?;
+    let quote = decl[at];
+    if quote != b'"' && quote != b'\'' {
+        return None;
+    }
+    let start = at + 1;
+    let len = decl[start..].iter().position(|&b| b == quote)?;
+    Some(start..start + len)
+}
+
+/// Make the XML declaration of UTF-8 output say so
+///
+/// Text decoded from another encoding keeps the declaration it had, such as
+/// `encoding="ISO-8859-1"`; written as UTF-8 it would then be misread. The
+/// `encoding` value is replaced with `UTF-8` unless it already names UTF-8.
+pub fn declare_utf8(text: &str) -> Cow<'_, str> {
+    let Some(end) = text.starts_with("<?xml").then(|| text.find("?>")).flatten() else {
+        return Cow::Borrowed(text);
+    };
+    match attribute_range(&text.as_bytes()[..end], b"encoding") {
+        Some(range) if Encoding::for_label(text[range.clone()].as_bytes()) != Some(UTF_8) => {
+            Cow::Owned(format!("{}UTF-8{}", &text[..range.start], &text[range.end..]))
+        }
+        _ => Cow::Borrowed(text),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Writer that transcodes UTF-8 output into another encoding
+///
+/// Characters the target encoding cannot represent are written as decimal
+/// character references (`&#1234;`), which keeps XML and HTML output valid.
+pub struct EncodingWriter<W: Write> {
+    inner: W,
+    encoding: &'static Encoding,
+    /// Byte order mark still to be written before the first output
+    bom: bool,
+    /// Trailing bytes of an incomplete UTF-8 sequence
+    pending: Vec<u8>,
+}
+
+impl<W: Write> EncodingWriter<W> {
+    /// Wrap `inner`; when `bom` is set a byte order mark is written first
+    pub fn new(inner: W, encoding: &'static Encoding, bom: bool) -> Self {
+        Self {
+            inner,
+            encoding,
+            bom,
+            pending: Vec::new(),
+        }
+    }
+
+    fn write_text(&mut self, text: &str) -> io::Result<()> {
+        if std::mem::take(&mut self.bom) {
+            let bom: &[u8] = if self.encoding == UTF_16LE {
+                &[0xFF, 0xFE]
+            } else if self.encoding == UTF_16BE {
+                &[0xFE, 0xFF]
+            } else if self.encoding == UTF_8 {
+                &[0xEF, 0xBB, 0xBF]
+            } else {
+                &[]
+            };
+            self.inner.write_all(bom)?;
+        }
+
+        // encoding_rs only decodes UTF-16, so encode it here
+        if self.encoding == UTF_16LE || self.encoding == UTF_16BE {
+            let big_endian = self.encoding == UTF_16BE;
+            let bytes: Vec<u8> = text
+                .encode_utf16()
+                .flat_map(|unit| {
+                    if big_endian {
+                        unit.to_be_bytes()
+                    } else {
+                        unit.to_le_bytes()
+                    }
+                })
+                .collect();
+            return self.inner.write_all(&bytes);
+        }
+
+        let (bytes, _, _) = self.encoding.encode(text);
+        self.inner.write_all(&bytes)
+    }
+}
+
+impl<W: Write> Write for EncodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            // An incomplete sequence at the end waits for the next write
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => {
+                self.pending.clear();
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "output is not valid UTF-8",
+                ));
+            }
+        };
+
+        let rest = self.pending.split_off(valid);
+        let complete = std::mem::replace(&mut self.pending, rest);
+        // Validated above
+        let text = std::str::from_utf8(&complete)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.write_text(text)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_bom() {
+        assert_eq!(detect_encoding(b"\xEF\xBB\xBF<a/>"), UTF_8);
+        assert_eq!(detect_encoding(b"\xFF\xFE<\0a\0/\0>\0"), UTF_16LE);
+        assert_eq!(detect_encoding(b"\xFE\xFF\0<\0a\0/\0>"), UTF_16BE);
+        assert_eq!(detect_encoding(b"<a/>"), UTF_8);
+    }
+
+    #[test]
+    fn test_detect_declarations() {
+        let xml = b"<?xml version=\"1.0\" encoding='ISO-8859-1'?><a/>";
+        assert_eq!(detect_encoding(xml).name(), "windows-1252");
+
+        let html = b"<html><head><meta charset=\"shift_jis\"></head></html>";
+        assert_eq!(detect_encoding(html).name(), "Shift_JIS");
+
+        let http_equiv =
+            b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=euc-kr\">";
+        assert_eq!(detect_encoding(http_equiv).name(), "EUC-KR");
+    }
+
+    #[test]
+    fn test_decode_latin1() {
+        let decoded = decode(
+            b"<?xml version=\"1.0\" encoding=\"latin1\"?><a>caf\xE9</a>",
+            None,
+        )
+        .unwrap();
+        assert!(decoded.text.ends_with("<a>café</a>"));
+        assert!(matches!(decoded.text, Cow::Owned(_)));
+    }
+
+    #[test]
+    fn test_decode_utf16_strips_bom() {
+        let bytes: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain("<a>é</a>".encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        let decoded = decode(&bytes, None).unwrap();
+        assert_eq!(decoded.text, "<a>é</a>");
+        assert_eq!(decoded.encoding, UTF_16LE);
+        assert!(decoded.bom);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert!(matches!(
+            decode(b"<a>\xFF</a>", None),
+            Err(ParseError::EncodingError(msg)) if msg == "invalid UTF-8 at byte 3"
+        ));
+        assert!(matches!(
+            decode(b"<a/>", Some("nope")),
+            Err(ParseError::EncodingError(_))
+        ));
+        // The override wins over the (wrong) declaration
+        let decoded = decode(b"<a>\xE9</a>", Some("latin1")).unwrap();
+        assert_eq!(decoded.text, "<a>é</a>");
+    }
+
+    #[test]
+    fn test_declare_utf8() {
+        assert_eq!(
+            declare_utf8("<?xml version=\"1.0\" encoding = 'ISO-8859-1'?><a>é</a>"),
+            "<?xml version=\"1.0\" encoding = 'UTF-8'?><a>é</a>"
+        );
+        let utf8 = "<?xml version=\"1.0\" encoding=\"utf8\"?><a/>";
+        assert!(matches!(declare_utf8(utf8), Cow::Borrowed(text) if text == utf8));
+        assert!(matches!(declare_utf8("<?xml version=\"1.0\"?><a/>"), Cow::Borrowed(_)));
+        assert!(matches!(declare_utf8("<a encoding=\"latin1\"/>"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_encoding_writer_round_trip() {
+        let mut out = Vec::new();
+        {
+            let mut writer =
+                EncodingWriter::new(&mut out, encoding_for_label("latin1").unwrap(), false);
+            // Split inside the two-byte 'é' and include an unmappable character
+            let text = "<a>café ☃</a>".as_bytes();
+            writer.write_all(&text[..7]).unwrap();
+            writer.write_all(&text[7..]).unwrap();
+        }
+        assert_eq!(out, b"<a>caf\xE9 &#9731;</a>");
+
+        let mut out = Vec::new();
+        EncodingWriter::new(&mut out, UTF_16BE, true)
+            .write_all(b"<a/>")
+            .unwrap();
+        assert_eq!(out, b"\xFE\xFF\0<\0a\0/\0>");
+    }
+}
@@ -0,0 +1,2748 @@
+//! HTML5 tree construction
+//!
+//! A tokenizer and tree builder following the WHATWG HTML parsing algorithm,
+//! so the resulting tree matches what browsers (and their DevTools) show:
+//! implied `<html>`, `<head>` and `<body>`, auto-closed `<p>`, `<li>` and
+//! friends, foster parenting of misplaced table content, and the adoption
+//! agency algorithm for misnested formatting elements.
+//!
+//! Deviations from the specification, all in line with the rest of rxq:
+//! character references are kept as written instead of being decoded,
+//! scripting is assumed enabled (`<noscript>` content is raw text), and
+//! `<template>` contents are parsed as ordinary children of the template
+//! element. Of the parse errors, the common ones are reported as
+//! diagnostics: stray end tags, elements left open, duplicate attributes
+//! and unknown character references.
+
+mod entities;
+
+use super::{Exceeded, ParseLimits};
+use crate::error::{Diagnostic, LineIndex, ParseError, Position, Severity};
+use crate::tree::{Attribute, NodeId, NodeKind, Str, Tree};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Result of parsing an HTML document
+pub(crate) struct HtmlParse {
+    pub(crate) tree: Tree,
+    /// The parse errors the tree builder recovered from
+    pub(crate) diagnostics: Vec<Diagnostic>,
+}
+
+/// Parse an HTML document into a tree
+pub(crate) fn parse(source: &str) -> HtmlParse {
+    let mut builder = TreeBuilder::new(source);
+    builder.run();
+    builder.finish()
+}
+
+/// Parse an HTML document, stopping at the first resource limit exceeded
+pub(crate) fn parse_with_limits(source: &str, limits: &ParseLimits) -> Result<HtmlParse, ParseError> {
+    let mut builder = TreeBuilder::new(source);
+    builder.limits = limits.clone();
+    builder.run();
+    match builder.exceeded {
+        Some((offset, exceeded)) => Err(exceeded.into_error(Position::from_offset(source, offset))),
+        None => Ok(builder.finish()),
+    }
+}
+
+/// A parse error, reported as a [`Diagnostic`]
+struct Problem {
+    code: &'static str,
+    span: Range<usize>,
+    message: String,
+}
+
+// ---------------------------------------------------------------------------
+// Tokenizer
+// ---------------------------------------------------------------------------
+
+#[derive(Debug)]
+enum Token<'s> {
+    /// Complete `<!DOCTYPE ...>` markup
+    Doctype(Range<usize>),
+    StartTag(Tag<'s>),
+    EndTag(Tag<'s>),
+    /// Complete comment markup (also used for bogus comments like `<?php ...>`)
+    Comment(Range<usize>),
+    Text(Range<usize>),
+    /// Content of a CDATA section (only recognized in foreign content)
+    CData(Range<usize>),
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+struct Tag<'s> {
+    /// ASCII-lowercased tag name
+    name: Cow<'s, str>,
+    name_span: Range<usize>,
+    attributes: Vec<Attribute>,
+    self_closing: bool,
+}
+
+impl<'s> Tag<'s> {
+    /// A tag the tree builder synthesizes (e.g. an implied `<tbody>`)
+    fn implied(name: &'static str) -> Self {
+        Self {
+            name: Cow::Borrowed(name),
+            name_span: 0..0,
+            attributes: Vec::new(),
+            self_closing: false,
+        }
+    }
+
+    fn name_str(&self) -> Str {
+        match &self.name {
+            Cow::Borrowed(_) if self.name_span.is_empty() => {
+                Str::Owned(self.name.as_ref().into())
+            }
+            Cow::Borrowed(_) => Str::Span(self.name_span.clone()),
+            Cow::Owned(name) => Str::Owned(name.as_str().into()),
+        }
+    }
+}
+
+/// How the tokenizer treats content after a start tag
+enum RawMode {
+    /// RCDATA, RAWTEXT and script data: text until the matching end tag
+    Until(String),
+    /// PLAINTEXT: everything until the end of input
+    Eof,
+}
+
+struct Tokenizer<'s> {
+    source: &'s str,
+    bytes: &'s [u8],
+    pos: usize,
+    raw: Option<RawMode>,
+    /// Errors found since the tree builder last took them
+    problems: Vec<Problem>,
+}
+
+impl<'s> Tokenizer<'s> {
+    fn new(source: &'s str) -> Self {
+        Self {
+            source,
+            bytes: source.as_bytes(),
+            pos: 0,
+            raw: None,
+            problems: Vec::new(),
+        }
+    }
+
+    fn next_token(&mut self, in_foreign_content: bool) -> Token<'s> {
+        if let Some(raw) = &self.raw {
+            let end = match raw {
+                RawMode::Eof => self.bytes.len(),
+                RawMode::Until(name) => self.find_end_tag(name).unwrap_or(self.bytes.len()),
+            };
+            if end > self.pos {
+                let start = self.pos;
+                self.pos = end;
+                return Token::Text(start..end);
+            }
+            self.raw = None;
+        }
+
+        loop {
+            let start = self.pos;
+            let Some(&b) = self.bytes.get(start) else {
+                return Token::Eof;
+            };
+            if b != b'<' {
+                self.pos = self.find_byte(b'<').unwrap_or(self.bytes.len());
+                self.check_references(start..self.pos);
+                return Token::Text(start..self.pos);
+            }
+
+            let next = self.bytes.get(start + 1).copied();
+            let rest = &self.source[start..];
+            match next {
+                Some(c) if c.is_ascii_alphabetic() => match self.tag(false) {
+                    Some(tag) => return Token::StartTag(tag),
+                    None => return Token::Eof,
+                },
+                Some(b'/') => match self.bytes.get(start + 2) {
+                    Some(c) if c.is_ascii_alphabetic() => match self.tag(true) {
+                        Some(tag) => return Token::EndTag(tag),
+                        None => return Token::Eof,
+                    },
+                    Some(b'>') => {
+                        self.pos += 3;
+                        continue;
+                    }
+                    None => {
+                        self.pos = self.bytes.len();
+                        return Token::Text(start..self.pos);
+                    }
+                    Some(_) => return self.bogus_comment(),
+                },
+                Some(b'!') if rest.starts_with("<!--") => return self.comment(),
+                Some(b'!')
+                    if rest.len() >= 9 && rest.as_bytes()[2..9].eq_ignore_ascii_case(b"DOCTYPE") =>
+                {
+                    self.skip_past(b'>');
+                    return Token::Doctype(start..self.pos);
+                }
+                Some(b'!') if in_foreign_content && rest.starts_with("<![CDATA[") => {
+                    let content = start + "<![CDATA[".len();
+                    let end = self.source[content..]
+                        .find("]]>")
+                        .map_or(self.bytes.len(), |i| content + i);
+                    self.pos = (end + 3).min(self.bytes.len());
+                    return Token::CData(content..end);
+                }
+                Some(b'!') | Some(b'?') => return self.bogus_comment(),
+                _ => {
+                    self.pos += 1;
+                    return Token::Text(start..self.pos);
+                }
+            }
+        }
+    }
+
+    /// Tokenize a start or end tag; `None` if the input ends inside it
+    fn tag(&mut self, end: bool) -> Option<Tag<'s>> {
+        self.pos += if end { 2 } else { 1 };
+        let name_start = self.pos;
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|&b| !is_whitespace(b) && b != b'/' && b != b'>')
+        {
+            self.pos += 1;
+        }
+        let name_span = name_start..self.pos;
+        let mut tag = Tag {
+            name: lowercase(&self.source[name_span.clone()]),
+            name_span,
+            attributes: Vec::new(),
+            self_closing: false,
+        };
+
+        loop {
+            while self
+                .bytes
+                .get(self.pos)
+                .is_some_and(|&b| is_whitespace(b) || b == b'/')
+            {
+                if self.source[self.pos..].starts_with("/>") {
+                    break;
+                }
+                self.pos += 1;
+            }
+            match self.bytes.get(self.pos) {
+                None => return None,
+                Some(b'>') => {
+                    self.pos += 1;
+                    return Some(tag);
+                }
+                Some(b'/') => {
+                    self.pos += 2;
+                    tag.self_closing = true;
+                    return Some(tag);
+                }
+                Some(_) => {}
+            }
+
+            // Attribute name (the first character may be '=')
+            let attr_start = self.pos;
+            self.pos += 1;
+            while self
+                .bytes
+                .get(self.pos)
+                .is_some_and(|&b| !is_whitespace(b) && !matches!(b, b'/' | b'>' | b'='))
+            {
+                self.pos += 1;
+            }
+            let attr_span = attr_start..self.pos;
+            self.skip_whitespace();
+
+            let mut value = None;
+            if self.bytes.get(self.pos) == Some(&b'=') {
+                self.pos += 1;
+                self.skip_whitespace();
+                value = match self.bytes.get(self.pos) {
+                    None => return None,
+                    Some(&quote) if quote == b'"' || quote == b'\'' => {
+                        let start = self.pos + 1;
+                        let len = self.bytes[start..].iter().position(|&b| b == quote)?;
+                        self.pos = start + len + 1;
+                        self.check_references(start..start + len);
+                        Some(Str::Span(start..start + len))
+                    }
+                    Some(b'>') => Some(Str::Span(self.pos..self.pos)),
+                    Some(_) => {
+                        let start = self.pos;
+                        while self
+                            .bytes
+                            .get(self.pos)
+                            .is_some_and(|&b| !is_whitespace(b) && b != b'>')
+                        {
+                            self.pos += 1;
+                        }
+                        self.check_references(start..self.pos);
+                        Some(Str::Span(start..self.pos))
+                    }
+                };
+            }
+
+            let attr_name = lowercase(&self.source[attr_span.clone()]);
+            let duplicate = tag
+                .attributes
+                .iter()
+                .any(|a| a.name.resolve(self.source) == attr_name);
+            if duplicate && !end {
+                self.problems.push(Problem {
+                    code: "duplicate-attribute",
+                    span: attr_span.clone(),
+                    message: format!("duplicate attribute '{}'", attr_name),
+                });
+            }
+            if !end && !duplicate {
+                tag.attributes.push(Attribute {
+                    name: match attr_name {
+                        Cow::Borrowed(_) => Str::Span(attr_span),
+                        Cow::Owned(name) => Str::Owned(name.into()),
+                    },
+                    value,
+                });
+            }
+        }
+    }
+
+    /// Report named character references that HTML does not define
+    fn check_references(&mut self, range: Range<usize>) {
+        let text = &self.source[range.clone()];
+        let mut from = 0;
+        while let Some(i) = text[from..].find('&') {
+            let start = from + i;
+            let name_end = start
+                + 1
+                + text.as_bytes()[start + 1..]
+                    .iter()
+                    .take_while(|b| b.is_ascii_alphanumeric())
+                    .count();
+            from = name_end.max(start + 1);
+            let name = &text[start + 1..name_end];
+            if !name.is_empty()
+                && text.as_bytes().get(name_end) == Some(&b';')
+                && entities::NAMED_REFERENCES.binary_search(&name).is_err()
+            {
+                self.problems.push(Problem {
+                    code: "undefined-entity",
+                    span: range.start + start..range.start + name_end + 1,
+                    message: format!("undefined entity '&{};'", name),
+                });
+            }
+        }
+    }
+
+    fn comment(&mut self) -> Token<'s> {
+        let start = self.pos;
+        let rest = &self.source[start + 4..];
+        self.pos = if rest.starts_with('>') {
+            start + 5
+        } else if rest.starts_with("->") {
+            start + 6
+        } else {
+            rest.find("-->")
+                .map_or(self.bytes.len(), |i| start + 4 + i + 3)
+        };
+        Token::Comment(start..self.pos)
+    }
+
+    fn bogus_comment(&mut self) -> Token<'s> {
+        let start = self.pos;
+        self.skip_past(b'>');
+        Token::Comment(start..self.pos)
+    }
+
+    /// Find `</name` followed by whitespace, `/` or `>` (case-insensitive)
+    fn find_end_tag(&self, name: &str) -> Option<usize> {
+        let mut from = self.pos;
+        while let Some(i) = self.source[from..].find("</") {
+            let at = from + i;
+            let name_end = at + 2 + name.len();
+            if self.bytes.get(at + 2..name_end).is_some_and(|n| n.eq_ignore_ascii_case(name.as_bytes()))
+                && self
+                    .bytes
+                    .get(name_end)
+                    .is_some_and(|&b| is_whitespace(b) || b == b'/' || b == b'>')
+            {
+                return Some(at);
+            }
+            from = at + 2;
+        }
+        None
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.bytes.get(self.pos).is_some_and(|&b| is_whitespace(b)) {
+            self.pos += 1;
+        }
+    }
+
+    fn skip_past(&mut self, byte: u8) {
+        self.pos = self.find_byte(byte).map_or(self.bytes.len(), |i| i + 1);
+    }
+
+    fn find_byte(&self, byte: u8) -> Option<usize> {
+        self.bytes[self.pos..]
+            .iter()
+            .position(|&b| b == byte)
+            .map(|i| self.pos + i)
+    }
+}
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r' | b'\x0C')
+}
+
+fn lowercase(s: &str) -> Cow<'_, str> {
+    if s.bytes().any(|b| b.is_ascii_uppercase()) {
+        Cow::Owned(s.to_ascii_lowercase())
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Tree builder
+// ---------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Initial,
+    BeforeHtml,
+    BeforeHead,
+    InHead,
+    AfterHead,
+    InBody,
+    Text,
+    InTable,
+    InTableText,
+    InCaption,
+    InColumnGroup,
+    InTableBody,
+    InRow,
+    InCell,
+    InSelect,
+    InSelectInTable,
+    AfterBody,
+    InFrameset,
+    AfterFrameset,
+    AfterAfterBody,
+    AfterAfterFrameset,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Namespace {
+    Html,
+    Svg,
+    MathMl,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Default,
+    ListItem,
+    Button,
+    Table,
+    Select,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Formatting {
+    Marker,
+    Element(NodeId),
+}
+
+struct TreeBuilder<'s> {
+    source: &'s str,
+    tokenizer: Tokenizer<'s>,
+    tree: Tree,
+    mode: Mode,
+    original_mode: Mode,
+    open: Vec<NodeId>,
+    formatting: Vec<Formatting>,
+    /// Namespace of foreign (SVG/MathML) elements; everything else is HTML
+    foreign: HashMap<NodeId, Namespace>,
+    head: Option<NodeId>,
+    form: Option<NodeId>,
+    frameset_ok: bool,
+    /// Set when the doctype is missing or legacy; a table no longer closes `<p>`
+    quirks: bool,
+    foster_parenting: bool,
+    skip_newline: bool,
+    pending_table_text: Vec<Range<usize>>,
+    limits: ParseLimits,
+    /// Offset of the limit that stopped the parse, and which one it was
+    exceeded: Option<(usize, Exceeded)>,
+    /// `<name` of the start tag of each element that has one in the source
+    start_tags: HashMap<NodeId, Range<usize>>,
+    problems: Vec<Problem>,
+}
+
+impl<'s> TreeBuilder<'s> {
+    fn new(source: &'s str) -> Self {
+        Self {
+            source,
+            tokenizer: Tokenizer::new(source),
+            tree: Tree::default(),
+            mode: Mode::Initial,
+            original_mode: Mode::Initial,
+            open: Vec::new(),
+            formatting: Vec::new(),
+            foreign: HashMap::new(),
+            head: None,
+            form: None,
+            frameset_ok: true,
+            quirks: false,
+            foster_parenting: false,
+            skip_newline: false,
+            pending_table_text: Vec::new(),
+            limits: ParseLimits::default(),
+            exceeded: None,
+            start_tags: HashMap::new(),
+            problems: Vec::new(),
+        }
+    }
+
+    fn run(&mut self) {
+        loop {
+            let in_foreign = self
+                .current()
+                .is_some_and(|id| self.namespace(id) != Namespace::Html);
+            let start = self.tokenizer.pos;
+            let mut token = self.tokenizer.next_token(in_foreign);
+            self.problems.append(&mut self.tokenizer.problems);
+
+            if std::mem::take(&mut self.skip_newline) {
+                if let Token::Text(range) = &mut token {
+                    if self.source[range.clone()].starts_with('\n') {
+                        range.start += 1;
+                        if range.start == range.end {
+                            continue;
+                        }
+                    }
+                }
+            }
+
+            if let Token::StartTag(tag) = &token {
+                self.check_attributes(start, tag);
+            }
+            let eof = matches!(token, Token::Eof);
+            if self.exceeded.is_none() {
+                self.dispatch(token);
+                self.check_tree(start);
+            }
+            if eof || self.exceeded.is_some() {
+                break;
+            }
+        }
+
+        if self.exceeded.is_none() {
+            for index in (0..self.open.len()).rev() {
+                self.unclosed(self.open[index]);
+            }
+        }
+    }
+
+    fn finish(self) -> HtmlParse {
+        let index = LineIndex::new(self.source);
+        let diagnostics = self
+            .problems
+            .into_iter()
+            .map(|problem| Diagnostic {
+                severity: Severity::Error,
+                code: problem.code,
+                position: index.position_in(self.source, problem.span.start),
+                span: problem.span,
+                message: problem.message,
+            })
+            .collect();
+        HtmlParse {
+            tree: self.tree,
+            diagnostics,
+        }
+    }
+
+    // -- Parse errors --
+
+    fn stray_end_tag(&mut self, tag: &Tag<'s>) {
+        if tag.name_span.is_empty() {
+            return;
+        }
+        self.problems.push(Problem {
+            code: "stray-end-tag",
+            span: tag.name_span.start - 2..tag.name_span.end,
+            message: format!("unexpected end tag </{}>", tag.name),
+        });
+    }
+
+    /// Report an element whose end tag is missing, unless it may be left out
+    fn unclosed(&mut self, id: NodeId) {
+        if self.is_html(id, &OPTIONAL_END_TAGS) {
+            return;
+        }
+        if let Some(span) = self.start_tags.get(&id).cloned() {
+            let message = format!("element <{}> is never closed", self.name(id));
+            self.problems.push(Problem {
+                code: "unclosed-tag",
+                span,
+                message,
+            });
+        }
+    }
+
+    // -- Resource limits --
+
+    fn check_limit(&mut self, at: usize, exceeded: Option<Exceeded>) {
+        if self.exceeded.is_none() {
+            self.exceeded = exceeded.map(|exceeded| (at, exceeded));
+        }
+    }
+
+    fn check_attributes(&mut self, at: usize, tag: &Tag<'s>) {
+        let count = tag.attributes.len();
+        self.check_limit(at, Exceeded::check(self.limits.max_attributes, count, Exceeded::Attributes));
+        let longest = tag
+            .attributes
+            .iter()
+            .filter_map(|a| a.value.as_ref())
+            .map(|v| v.resolve(self.source).len())
+            .max()
+            .unwrap_or(0);
+        let exceeded = Exceeded::check(self.limits.max_attribute_length, longest, Exceeded::AttributeLength);
+        self.check_limit(at, exceeded);
+    }
+
+    /// Check depth and node count after a token was processed
+    fn check_tree(&mut self, at: usize) {
+        let depth = self.open.len();
+        self.check_limit(at, Exceeded::check(self.limits.max_depth, depth, Exceeded::Depth));
+        let nodes = self.tree.node_count();
+        self.check_limit(at, Exceeded::check(self.limits.max_nodes, nodes, Exceeded::Nodes));
+    }
+
+    // -- Helpers for names, namespaces and the stack of open elements --
+
+    fn name(&self, id: NodeId) -> &str {
+        match &self.tree.get(id).kind {
+            NodeKind::Element { name, .. } => name.resolve(self.source),
+            _ => "",
+        }
+    }
+
+    fn namespace(&self, id: NodeId) -> Namespace {
+        self.foreign.get(&id).copied().unwrap_or(Namespace::Html)
+    }
+
+    fn is_html(&self, id: NodeId, names: &[&str]) -> bool {
+        self.namespace(id) == Namespace::Html && names.contains(&self.name(id))
+    }
+
+    fn current(&self) -> Option<NodeId> {
+        self.open.last().copied()
+    }
+
+    fn current_is(&self, names: &[&str]) -> bool {
+        self.current().is_some_and(|id| self.is_html(id, names))
+    }
+
+    fn in_scope(&self, names: &[&str], scope: Scope) -> bool {
+        for &id in self.open.iter().rev() {
+            if self.is_html(id, names) {
+                return true;
+            }
+            if self.is_scope_boundary(id, scope) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn is_scope_boundary(&self, id: NodeId, scope: Scope) -> bool {
+        let name = self.name(id);
+        match self.namespace(id) {
+            Namespace::Html => match scope {
+                Scope::Default | Scope::ListItem | Scope::Button => {
+                    matches!(
+                        name,
+                        "applet" | "caption" | "html" | "table" | "td" | "th" | "marquee"
+                            | "object" | "template"
+                    ) || (scope == Scope::ListItem && matches!(name, "ol" | "ul"))
+                        || (scope == Scope::Button && name == "button")
+                }
+                Scope::Table => matches!(name, "html" | "table" | "template"),
+                Scope::Select => !matches!(name, "optgroup" | "option"),
+            },
+            Namespace::MathMl => {
+                scope == Scope::Select
+                    || (scope != Scope::Table
+                        && matches!(name, "mi" | "mo" | "mn" | "ms" | "mtext" | "annotation-xml"))
+            }
+            Namespace::Svg => {
+                scope == Scope::Select
+                    || (scope != Scope::Table && matches!(name, "foreignObject" | "desc" | "title"))
+            }
+        }
+    }
+
+    fn is_special(&self, id: NodeId) -> bool {
+        let name = self.name(id);
+        match self.namespace(id) {
+            Namespace::Html => SPECIAL.contains(&name),
+            Namespace::MathMl => matches!(name, "mi" | "mo" | "mn" | "ms" | "mtext" | "annotation-xml"),
+            Namespace::Svg => matches!(name, "foreignObject" | "desc" | "title"),
+        }
+    }
+
+    fn pop(&mut self) -> Option<NodeId> {
+        self.open.pop()
+    }
+
+    /// Pop elements until one of `names` (in the HTML namespace) has been popped
+    fn pop_until(&mut self, names: &[&str]) {
+        while let Some(id) = self.pop() {
+            if self.is_html(id, names) {
+                break;
+            }
+        }
+    }
+
+    /// Like [`Self::pop_until`], reporting the elements popped on the way
+    fn close_elements(&mut self, names: &[&str]) {
+        while let Some(id) = self.pop() {
+            if self.is_html(id, names) {
+                break;
+            }
+            self.unclosed(id);
+        }
+    }
+
+    fn generate_implied_end_tags(&mut self, except: Option<&str>) {
+        while let Some(id) = self.current() {
+            let name = self.name(id);
+            if self.namespace(id) != Namespace::Html
+                || Some(name) == except
+                || !matches!(
+                    name,
+                    "dd" | "dt" | "li" | "optgroup" | "option" | "p" | "rb" | "rp" | "rt" | "rtc"
+                )
+            {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    fn generate_all_implied_end_tags(&mut self) {
+        while self.current_is(&[
+            "caption", "colgroup", "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt",
+            "rtc", "tbody", "td", "tfoot", "th", "thead", "tr",
+        ]) {
+            self.pop();
+        }
+    }
+
+    fn close_p_element(&mut self) {
+        self.generate_implied_end_tags(Some("p"));
+        self.pop_until(&["p"]);
+    }
+
+    fn close_p_if_in_button_scope(&mut self) {
+        if self.in_scope(&["p"], Scope::Button) {
+            self.close_p_element();
+        }
+    }
+
+    fn clear_stack_back_to(&mut self, names: &[&str]) {
+        while let Some(id) = self.current() {
+            if self.is_html(id, names) || self.is_html(id, &["html", "template"]) {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    fn remove_from_stack(&mut self, id: NodeId) {
+        self.open.retain(|&open| open != id);
+    }
+
+    // -- Node insertion --
+
+    /// Where new nodes go: the current node, or the foster parent location
+    fn insertion_location(&self, target: Option<NodeId>) -> Location {
+        let Some(target) = target.or_else(|| self.current()) else {
+            return Location::Append(None);
+        };
+        if self.foster_parenting && self.is_html(target, &["table", "tbody", "tfoot", "thead", "tr"]) {
+            if let Some(index) = self.open.iter().rposition(|&id| self.is_html(id, &["table"])) {
+                let table = self.open[index];
+                if self.tree.get(table).parent.is_some() {
+                    return Location::Before(table);
+                }
+                if index > 0 {
+                    return Location::Append(Some(self.open[index - 1]));
+                }
+            }
+            return Location::Append(self.open.first().copied());
+        }
+        Location::Append(Some(target))
+    }
+
+    fn insert_at(&mut self, location: Location, id: NodeId) {
+        match location {
+            Location::Append(parent) => self.tree.append_child(parent, id),
+            Location::Before(sibling) => self.tree.insert_before(sibling, id),
+        }
+    }
+
+    fn create_element(&mut self, tag: &Tag<'s>, namespace: Namespace) -> NodeId {
+        let mut name = tag.name_str();
+        let mut attributes = tag.attributes.clone();
+        match namespace {
+            Namespace::Svg => {
+                if let Some(adjusted) = adjust_case(&tag.name, SVG_TAG_NAMES) {
+                    name = Str::Static(adjusted);
+                }
+                for attr in &mut attributes {
+                    if let Some(adjusted) = adjust_case(attr.name.resolve(self.source), SVG_ATTRIBUTES) {
+                        attr.name = Str::Static(adjusted);
+                    }
+                }
+            }
+            Namespace::MathMl => {
+                for attr in &mut attributes {
+                    if attr.name.resolve(self.source) == "definitionurl" {
+                        attr.name = Str::Static("definitionURL");
+                    }
+                }
+            }
+            Namespace::Html => {}
+        }
+
+        let id = self.tree.create(NodeKind::Element { name, attributes });
+        if !tag.name_span.is_empty() {
+            self.start_tags.insert(id, tag.name_span.start - 1..tag.name_span.end);
+        }
+        if namespace != Namespace::Html {
+            self.foreign.insert(id, namespace);
+        }
+        id
+    }
+
+    fn insert_element(&mut self, tag: &Tag<'s>) -> NodeId {
+        self.insert_foreign_element(tag, Namespace::Html)
+    }
+
+    fn insert_foreign_element(&mut self, tag: &Tag<'s>, namespace: Namespace) -> NodeId {
+        let location = self.insertion_location(None);
+        let id = self.create_element(tag, namespace);
+        self.insert_at(location, id);
+        self.open.push(id);
+        id
+    }
+
+    /// Insert an element that never has children (`<br>`, `<img>`, ...)
+    fn insert_void(&mut self, tag: &Tag<'s>) {
+        self.insert_element(tag);
+        self.pop();
+    }
+
+    fn insert_text(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        let location = self.insertion_location(None);
+        let previous = match location {
+            Location::Append(parent) => match parent {
+                Some(p) => self.tree.get(p).children.last().copied(),
+                None => return,
+            },
+            Location::Before(sibling) => {
+                let parent = self.tree.get(sibling).parent;
+                let siblings = match parent {
+                    Some(p) => &self.tree.get(p).children,
+                    None => self.tree.roots(),
+                };
+                let index = siblings.iter().position(|&s| s == sibling).unwrap_or(0);
+                index.checked_sub(1).map(|i| siblings[i])
+            }
+        };
+
+        // Merge with an adjacent text node, as the DOM never has two in a row
+        if let Some(previous) = previous {
+            let merged_len = match &self.tree.get(previous).kind {
+                NodeKind::Text(text) => text.resolve(self.source).len() + range.len(),
+                _ => range.len(),
+            };
+            let exceeded = Exceeded::check(self.limits.max_text_length, merged_len, Exceeded::TextLength);
+            self.check_limit(range.start, exceeded);
+            if let NodeKind::Text(text) = &mut self.tree.get_mut(previous).kind {
+                *text = match &*text {
+                    Str::Span(span) if span.end == range.start => Str::Span(span.start..range.end),
+                    other => {
+                        let mut merged = other.resolve(self.source).to_string();
+                        merged.push_str(&self.source[range]);
+                        Str::Owned(merged.into())
+                    }
+                };
+                return;
+            }
+        }
+
+        let exceeded = Exceeded::check(self.limits.max_text_length, range.len(), Exceeded::TextLength);
+        self.check_limit(range.start, exceeded);
+        let id = self.tree.create(NodeKind::Text(Str::Span(range)));
+        self.insert_at(location, id);
+    }
+
+    fn insert_comment(&mut self, range: Range<usize>, parent: Option<Option<NodeId>>) {
+        let id = self.tree.create(NodeKind::Comment(Str::Span(range)));
+        let location = match parent {
+            Some(parent) => Location::Append(parent),
+            None => self.insertion_location(None),
+        };
+        self.insert_at(location, id);
+    }
+
+    /// Add attributes from a repeated `<html>` or `<body>` tag that are not yet present
+    fn merge_attributes(&mut self, id: NodeId, tag: &Tag<'s>) {
+        let source = self.source;
+        if let NodeKind::Element { attributes, .. } = &mut self.tree.get_mut(id).kind {
+            for attr in &tag.attributes {
+                let name = attr.name.resolve(source);
+                if !attributes.iter().any(|a| a.name.resolve(source) == name) {
+                    attributes.push(attr.clone());
+                }
+            }
+        }
+    }
+
+    /// Start a raw text element (`<title>`, `<style>`, `<script>`, ...)
+    fn parse_raw_text(&mut self, tag: &Tag<'s>) {
+        self.insert_element(tag);
+        self.tokenizer.raw = Some(RawMode::Until(tag.name.to_string()));
+        self.original_mode = self.mode;
+        self.mode = Mode::Text;
+    }
+
+    // -- Active formatting elements --
+
+    fn push_formatting(&mut self, id: NodeId) {
+        // Noah's Ark clause: at most three identical entries after the last marker
+        let mut same = Vec::new();
+        for (index, entry) in self.formatting.iter().enumerate().rev() {
+            match *entry {
+                Formatting::Marker => break,
+                Formatting::Element(other) if self.same_element(id, other) => same.push(index),
+                Formatting::Element(_) => {}
+            }
+        }
+        if same.len() >= 3 {
+            self.formatting.remove(*same.last().expect("three entries"));
+        }
+        self.formatting.push(Formatting::Element(id));
+    }
+
+    fn same_element(&self, a: NodeId, b: NodeId) -> bool {
+        let attributes = |id: NodeId| {
+            let mut attrs: Vec<(&str, Option<&str>)> = match &self.tree.get(id).kind {
+                NodeKind::Element { attributes, .. } => attributes
+                    .iter()
+                    .map(|a| {
+                        (
+                            a.name.resolve(self.source),
+                            a.value.as_ref().map(|v| v.resolve(self.source)),
+                        )
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            };
+            attrs.sort();
+            attrs
+        };
+        self.name(a) == self.name(b) && attributes(a) == attributes(b)
+    }
+
+    fn clear_formatting_to_marker(&mut self) {
+        while let Some(entry) = self.formatting.pop() {
+            if entry == Formatting::Marker {
+                break;
+            }
+        }
+    }
+
+    /// Create a fresh element with the same name and attributes as `id`
+    fn clone_element(&mut self, id: NodeId) -> NodeId {
+        let kind = self.tree.get(id).kind.clone();
+        let clone = self.tree.create(kind);
+        if let Some(&namespace) = self.foreign.get(&id) {
+            self.foreign.insert(clone, namespace);
+        }
+        clone
+    }
+
+    fn reconstruct_formatting(&mut self) {
+        let Some(&last) = self.formatting.last() else {
+            return;
+        };
+        match last {
+            Formatting::Marker => return,
+            Formatting::Element(id) if self.open.contains(&id) => return,
+            Formatting::Element(_) => {}
+        }
+
+        let mut index = self.formatting.len() - 1;
+        while index > 0 {
+            match self.formatting[index - 1] {
+                Formatting::Marker => break,
+                Formatting::Element(id) if self.open.contains(&id) => break,
+                Formatting::Element(_) => index -= 1,
+            }
+        }
+
+        for entry in index..self.formatting.len() {
+            let Formatting::Element(id) = self.formatting[entry] else {
+                continue;
+            };
+            let location = self.insertion_location(None);
+            let clone = self.clone_element(id);
+            self.insert_at(location, clone);
+            self.open.push(clone);
+            self.formatting[entry] = Formatting::Element(clone);
+        }
+    }
+
+    /// The adoption agency algorithm; returns `false` when the end tag should
+    /// be handled like any other end tag instead
+    fn adoption_agency(&mut self, subject: &str) -> bool {
+        if let Some(current) = self.current() {
+            if self.is_html(current, &[subject])
+                && !self.formatting.contains(&Formatting::Element(current))
+            {
+                self.pop();
+                return true;
+            }
+        }
+
+        for _ in 0..8 {
+            let Some(fe_index) = self
+                .formatting
+                .iter()
+                .rposition(|entry| match *entry {
+                    Formatting::Marker => true,
+                    Formatting::Element(id) => self.name(id) == subject,
+                })
+                .filter(|&i| self.formatting[i] != Formatting::Marker)
+            else {
+                return false;
+            };
+            let Formatting::Element(formatting_element) = self.formatting[fe_index] else {
+                return false;
+            };
+
+            let Some(stack_index) = self.open.iter().position(|&id| id == formatting_element) else {
+                self.formatting.remove(fe_index);
+                return true;
+            };
+            if !self.in_scope(&[subject], Scope::Default) {
+                return true;
+            }
+
+            let Some(fb_index) = (stack_index + 1..self.open.len()).find(|&i| self.is_special(self.open[i])) else {
+                self.open.truncate(stack_index);
+                self.formatting.remove(fe_index);
+                return true;
+            };
+            let furthest_block = self.open[fb_index];
+            let common_ancestor = self.open[stack_index - 1];
+            let mut bookmark = fe_index;
+
+            let mut node_index = fb_index;
+            let mut last_node = furthest_block;
+            let mut inner = 0;
+            loop {
+                inner += 1;
+                node_index -= 1;
+                let node = self.open[node_index];
+                if node == formatting_element {
+                    break;
+                }
+                let entry = self.formatting.iter().position(|e| *e == Formatting::Element(node));
+                if inner > 3 {
+                    if let Some(entry) = entry {
+                        self.formatting.remove(entry);
+                        if entry < bookmark {
+                            bookmark -= 1;
+                        }
+                        self.open.remove(node_index);
+                        continue;
+                    }
+                }
+                let Some(entry) = entry else {
+                    self.open.remove(node_index);
+                    continue;
+                };
+
+                let clone = self.clone_element(node);
+                self.formatting[entry] = Formatting::Element(clone);
+                self.open[node_index] = clone;
+                if last_node == furthest_block {
+                    bookmark = entry + 1;
+                }
+                self.tree.detach(last_node);
+                self.tree.append_child(Some(clone), last_node);
+                last_node = clone;
+            }
+
+            self.tree.detach(last_node);
+            let location = self.insertion_location(Some(common_ancestor));
+            self.insert_at(location, last_node);
+
+            let clone = self.clone_element(formatting_element);
+            let children = std::mem::take(&mut self.tree.get_mut(furthest_block).children);
+            for child in children {
+                self.tree.get_mut(child).parent = None;
+                self.tree.append_child(Some(clone), child);
+            }
+            self.tree.append_child(Some(furthest_block), clone);
+
+            let fe_entry = self
+                .formatting
+                .iter()
+                .position(|e| *e == Formatting::Element(formatting_element))
+                .expect("formatting element is in the list");
+            self.formatting.remove(fe_entry);
+            if fe_entry < bookmark {
+                bookmark -= 1;
+            }
+            self.formatting.insert(bookmark.min(self.formatting.len()), Formatting::Element(clone));
+
+            self.remove_from_stack(formatting_element);
+            let fb = self
+                .open
+                .iter()
+                .position(|&id| id == furthest_block)
+                .expect("furthest block is open");
+            self.open.insert(fb + 1, clone);
+        }
+        true
+    }
+
+    fn reset_insertion_mode(&mut self) {
+        for (index, &id) in self.open.iter().enumerate().rev() {
+            let last = index == 0;
+            let mode = match self.name(id) {
+                "select" => {
+                    let in_table = self.open[..index]
+                        .iter()
+                        .rev()
+                        .take_while(|&&a| !self.is_html(a, &["template"]))
+                        .any(|&a| self.is_html(a, &["table"]));
+                    if in_table {
+                        Mode::InSelectInTable
+                    } else {
+                        Mode::InSelect
+                    }
+                }
+                "td" | "th" if !last => Mode::InCell,
+                "tr" => Mode::InRow,
+                "tbody" | "thead" | "tfoot" => Mode::InTableBody,
+                "caption" => Mode::InCaption,
+                "colgroup" => Mode::InColumnGroup,
+                "table" => Mode::InTable,
+                "template" => Mode::InBody,
+                "head" if !last => Mode::InHead,
+                "body" => Mode::InBody,
+                "frameset" => Mode::InFrameset,
+                "html" => {
+                    if self.head.is_none() {
+                        Mode::BeforeHead
+                    } else {
+                        Mode::AfterHead
+                    }
+                }
+                _ if last => Mode::InBody,
+                _ => continue,
+            };
+            self.mode = mode;
+            return;
+        }
+        self.mode = Mode::InBody;
+    }
+
+    // -- Dispatch --
+
+    fn dispatch(&mut self, token: Token<'s>) {
+        let mut token = Some(token);
+        while let Some(t) = token.take() {
+            token = if self.use_foreign_rules(&t) {
+                self.foreign_content(t)
+            } else {
+                self.step(self.mode, t)
+            };
+        }
+    }
+
+    fn use_foreign_rules(&self, token: &Token<'s>) -> bool {
+        let Some(current) = self.current() else {
+            return false;
+        };
+        let namespace = self.namespace(current);
+        if namespace == Namespace::Html || matches!(token, Token::Eof) {
+            return false;
+        }
+        let name = self.name(current);
+        let mathml_text_point =
+            namespace == Namespace::MathMl && matches!(name, "mi" | "mo" | "mn" | "ms" | "mtext");
+        let html_point = namespace == Namespace::Svg && matches!(name, "foreignObject" | "desc" | "title");
+
+        match token {
+            Token::StartTag(tag) if mathml_text_point => matches!(tag.name.as_ref(), "mglyph" | "malignmark"),
+            Token::StartTag(tag) if namespace == Namespace::MathMl && name == "annotation-xml" => {
+                tag.name != "svg"
+            }
+            Token::StartTag(_) if html_point => false,
+            Token::Text(_) if mathml_text_point || html_point => false,
+            _ => true,
+        }
+    }
+
+    fn foreign_content(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        match token {
+            Token::Text(range) => {
+                if !self.source[range.clone()].bytes().all(is_whitespace) {
+                    self.frameset_ok = false;
+                }
+                self.insert_text(range);
+            }
+            Token::CData(range) => self.insert_text(range),
+            Token::Comment(range) => self.insert_comment(range, None),
+            Token::Doctype(_) => {}
+            Token::StartTag(tag) => {
+                let breakout = BREAKOUT.contains(&tag.name.as_ref())
+                    || (tag.name == "font"
+                        && tag.attributes.iter().any(|a| {
+                            matches!(a.name.resolve(self.source), "color" | "face" | "size")
+                        }));
+                if breakout {
+                    while let Some(current) = self.current() {
+                        let namespace = self.namespace(current);
+                        let name = self.name(current);
+                        let integration_point = (namespace == Namespace::MathMl
+                            && matches!(name, "mi" | "mo" | "mn" | "ms" | "mtext"))
+                            || (namespace == Namespace::Svg
+                                && matches!(name, "foreignObject" | "desc" | "title"));
+                        if namespace == Namespace::Html || integration_point {
+                            break;
+                        }
+                        self.pop();
+                    }
+                    return self.step(self.mode, Token::StartTag(tag));
+                }
+
+                let namespace = self.current().map_or(Namespace::Html, |id| self.namespace(id));
+                self.insert_foreign_element(&tag, namespace);
+                if tag.self_closing {
+                    self.pop();
+                }
+            }
+            Token::EndTag(tag) => {
+                let mut index = self.open.len() - 1;
+                loop {
+                    let id = self.open[index];
+                    if self.name(id).eq_ignore_ascii_case(&tag.name) {
+                        self.open.truncate(index);
+                        return None;
+                    }
+                    if index == 0 {
+                        return None;
+                    }
+                    index -= 1;
+                    if self.namespace(self.open[index]) == Namespace::Html {
+                        return self.step(self.mode, Token::EndTag(tag));
+                    }
+                }
+            }
+            Token::Eof => return self.step(self.mode, Token::Eof),
+        }
+        None
+    }
+
+    /// Process a token with the rules of `mode`; returns a token to reprocess
+    /// with the (possibly changed) current insertion mode
+    fn step(&mut self, mode: Mode, token: Token<'s>) -> Option<Token<'s>> {
+        match mode {
+            Mode::Initial => self.initial(token),
+            Mode::BeforeHtml => self.before_html(token),
+            Mode::BeforeHead => self.before_head(token),
+            Mode::InHead => self.in_head(token),
+            Mode::AfterHead => self.after_head(token),
+            Mode::InBody => self.in_body(token),
+            Mode::Text => self.text(token),
+            Mode::InTable => self.in_table(token),
+            Mode::InTableText => self.in_table_text(token),
+            Mode::InCaption => self.in_caption(token),
+            Mode::InColumnGroup => self.in_column_group(token),
+            Mode::InTableBody => self.in_table_body(token),
+            Mode::InRow => self.in_row(token),
+            Mode::InCell => self.in_cell(token),
+            Mode::InSelect => self.in_select(token),
+            Mode::InSelectInTable => self.in_select_in_table(token),
+            Mode::AfterBody => self.after_body(token),
+            Mode::InFrameset | Mode::AfterFrameset => self.in_frameset(mode, token),
+            Mode::AfterAfterBody | Mode::AfterAfterFrameset => self.after_after(mode, token),
+        }
+    }
+
+    /// Split a text token into its leading whitespace and the remainder
+    fn split_whitespace(&self, range: Range<usize>) -> (Range<usize>, Option<Token<'s>>) {
+        let len = self.source.as_bytes()[range.clone()]
+            .iter()
+            .take_while(|&&b| is_whitespace(b))
+            .count();
+        let split = range.start + len;
+        let rest = (split < range.end).then_some(Token::Text(split..range.end));
+        (range.start..split, rest)
+    }
+
+    /// Whether a text token is empty or starts with whitespace; other text
+    /// goes to the "anything else" branch of a mode
+    fn starts_with_whitespace(&self, range: &Range<usize>) -> bool {
+        self.source.as_bytes()[range.clone()]
+            .first()
+            .is_none_or(|&b| is_whitespace(b))
+    }
+
+    fn initial(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        match token {
+            Token::Text(range) => {
+                let (_, rest) = self.split_whitespace(range);
+                let rest = rest?;
+                self.mode = Mode::BeforeHtml;
+                Some(rest)
+            }
+            Token::Comment(range) => {
+                self.insert_comment(range, Some(None));
+                None
+            }
+            Token::Doctype(range) => {
+                self.quirks = is_quirks_doctype(&self.source[range.clone()]);
+                self.tree.append(None, NodeKind::Doctype(Str::Span(range)));
+                self.mode = Mode::BeforeHtml;
+                None
+            }
+            token => {
+                self.quirks = true;
+                self.mode = Mode::BeforeHtml;
+                Some(token)
+            }
+        }
+    }
+
+    fn before_html(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        match token {
+            Token::Doctype(_) => None,
+            Token::Comment(range) => {
+                self.insert_comment(range, Some(None));
+                None
+            }
+            Token::Text(range) if self.starts_with_whitespace(&range) => {
+                let (_, rest) = self.split_whitespace(range);
+                let rest = rest?;
+                self.before_html(rest)
+            }
+            Token::StartTag(tag) if tag.name == "html" => {
+                self.insert_element(&tag);
+                self.mode = Mode::BeforeHead;
+                None
+            }
+            Token::EndTag(tag) if !matches!(tag.name.as_ref(), "head" | "body" | "html" | "br") => {
+                self.stray_end_tag(&tag);
+                None
+            }
+            token => {
+                self.insert_element(&Tag::implied("html"));
+                self.mode = Mode::BeforeHead;
+                Some(token)
+            }
+        }
+    }
+
+    fn before_head(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        match token {
+            Token::Text(range) if self.starts_with_whitespace(&range) => {
+                let (_, rest) = self.split_whitespace(range);
+                let rest = rest?;
+                self.before_head(rest)
+            }
+            Token::Comment(range) => {
+                self.insert_comment(range, None);
+                None
+            }
+            Token::Doctype(_) => None,
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::StartTag(tag) if tag.name == "head" => {
+                self.head = Some(self.insert_element(&tag));
+                self.mode = Mode::InHead;
+                None
+            }
+            Token::EndTag(tag) if !matches!(tag.name.as_ref(), "head" | "body" | "html" | "br") => {
+                self.stray_end_tag(&tag);
+                None
+            }
+            token => {
+                self.head = Some(self.insert_element(&Tag::implied("head")));
+                self.mode = Mode::InHead;
+                Some(token)
+            }
+        }
+    }
+
+    fn in_head(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        match token {
+            Token::Text(range) => {
+                let (ws, rest) = self.split_whitespace(range);
+                self.insert_text(ws);
+                let rest = rest?;
+                self.pop();
+                self.mode = Mode::AfterHead;
+                Some(rest)
+            }
+            Token::Comment(range) => {
+                self.insert_comment(range, None);
+                None
+            }
+            Token::Doctype(_) => None,
+            Token::StartTag(tag) => match tag.name.as_ref() {
+                "html" => self.in_body(Token::StartTag(tag)),
+                "base" | "basefont" | "bgsound" | "link" | "meta" => {
+                    self.insert_void(&tag);
+                    None
+                }
+                "title" | "noscript" | "noframes" | "style" | "script" => {
+                    self.parse_raw_text(&tag);
+                    None
+                }
+                "template" => {
+                    self.insert_element(&tag);
+                    self.formatting.push(Formatting::Marker);
+                    self.frameset_ok = false;
+                    self.mode = Mode::InBody;
+                    None
+                }
+                "head" => None,
+                _ => {
+                    self.pop();
+                    self.mode = Mode::AfterHead;
+                    Some(Token::StartTag(tag))
+                }
+            },
+            Token::EndTag(tag) => match tag.name.as_ref() {
+                "head" => {
+                    self.pop();
+                    self.mode = Mode::AfterHead;
+                    None
+                }
+                "template" => {
+                    if self.open.iter().any(|&id| self.is_html(id, &["template"])) {
+                        self.generate_all_implied_end_tags();
+                        self.close_elements(&["template"]);
+                        self.clear_formatting_to_marker();
+                        self.reset_insertion_mode();
+                    } else {
+                        self.stray_end_tag(&tag);
+                    }
+                    None
+                }
+                "body" | "html" | "br" => {
+                    self.pop();
+                    self.mode = Mode::AfterHead;
+                    Some(Token::EndTag(tag))
+                }
+                _ => {
+                    self.stray_end_tag(&tag);
+                    None
+                }
+            },
+            token => {
+                self.pop();
+                self.mode = Mode::AfterHead;
+                Some(token)
+            }
+        }
+    }
+
+    fn after_head(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        match token {
+            Token::Text(range) => {
+                let (ws, rest) = self.split_whitespace(range);
+                self.insert_text(ws);
+                let rest = rest?;
+                self.insert_element(&Tag::implied("body"));
+                self.mode = Mode::InBody;
+                Some(rest)
+            }
+            Token::Comment(range) => {
+                self.insert_comment(range, None);
+                None
+            }
+            Token::Doctype(_) => None,
+            Token::StartTag(tag) => match tag.name.as_ref() {
+                "html" => self.in_body(Token::StartTag(tag)),
+                "body" => {
+                    self.insert_element(&tag);
+                    self.frameset_ok = false;
+                    self.mode = Mode::InBody;
+                    None
+                }
+                "frameset" => {
+                    self.insert_element(&tag);
+                    self.mode = Mode::InFrameset;
+                    None
+                }
+                "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script"
+                | "style" | "template" | "title" => {
+                    let head = self.head?;
+                    self.open.push(head);
+                    let result = self.in_head(Token::StartTag(tag));
+                    self.remove_from_stack(head);
+                    result
+                }
+                "head" => None,
+                _ => {
+                    self.insert_element(&Tag::implied("body"));
+                    self.mode = Mode::InBody;
+                    Some(Token::StartTag(tag))
+                }
+            },
+            Token::EndTag(tag) => match tag.name.as_ref() {
+                "template" => self.in_head(Token::EndTag(tag)),
+                "body" | "html" | "br" => {
+                    self.insert_element(&Tag::implied("body"));
+                    self.mode = Mode::InBody;
+                    Some(Token::EndTag(tag))
+                }
+                _ => {
+                    self.stray_end_tag(&tag);
+                    None
+                }
+            },
+            token => {
+                self.insert_element(&Tag::implied("body"));
+                self.mode = Mode::InBody;
+                Some(token)
+            }
+        }
+    }
+
+    fn in_body(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        match token {
+            Token::Text(range) => {
+                self.reconstruct_formatting();
+                if !self.source[range.clone()].bytes().all(is_whitespace) {
+                    self.frameset_ok = false;
+                }
+                self.insert_text(range);
+                None
+            }
+            Token::CData(range) => {
+                self.insert_text(range);
+                None
+            }
+            Token::Comment(range) => {
+                self.insert_comment(range, None);
+                None
+            }
+            Token::Doctype(_) => None,
+            Token::StartTag(tag) => self.in_body_start_tag(tag),
+            Token::EndTag(tag) => self.in_body_end_tag(tag),
+            Token::Eof => None,
+        }
+    }
+
+    fn in_body_start_tag(&mut self, tag: Tag<'s>) -> Option<Token<'s>> {
+        match tag.name.as_ref() {
+            "html" => {
+                if let Some(&html) = self.open.first() {
+                    self.merge_attributes(html, &tag);
+                }
+            }
+            "base" | "basefont" | "bgsound" | "link" | "meta" | "noframes" | "script" | "style"
+            | "template" | "title" => return self.in_head(Token::StartTag(tag)),
+            "body" => {
+                if let Some(&body) = self.open.get(1) {
+                    if self.is_html(body, &["body"]) {
+                        self.frameset_ok = false;
+                        self.merge_attributes(body, &tag);
+                    }
+                }
+            }
+            "frameset" => {
+                let body = self.open.get(1).copied().filter(|&b| self.is_html(b, &["body"]));
+                if let (Some(body), true) = (body, self.frameset_ok) {
+                    self.tree.detach(body);
+                    self.open.truncate(1);
+                    self.insert_element(&tag);
+                    self.mode = Mode::InFrameset;
+                }
+            }
+            "address" | "article" | "aside" | "blockquote" | "center" | "details" | "dialog"
+            | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer" | "header"
+            | "hgroup" | "main" | "menu" | "nav" | "ol" | "p" | "search" | "section" | "summary"
+            | "ul" => {
+                self.close_p_if_in_button_scope();
+                self.insert_element(&tag);
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.close_p_if_in_button_scope();
+                if self.current_is(&HEADINGS) {
+                    self.pop();
+                }
+                self.insert_element(&tag);
+            }
+            "pre" | "listing" => {
+                self.close_p_if_in_button_scope();
+                self.insert_element(&tag);
+                self.skip_newline = true;
+                self.frameset_ok = false;
+            }
+            "form" => {
+                let in_template = self.open.iter().any(|&id| self.is_html(id, &["template"]));
+                if self.form.is_none() || in_template {
+                    self.close_p_if_in_button_scope();
+                    let id = self.insert_element(&tag);
+                    if !in_template {
+                        self.form = Some(id);
+                    }
+                }
+            }
+            "li" | "dd" | "dt" => {
+                self.frameset_ok = false;
+                let closes: &[&str] = if tag.name == "li" { &["li"] } else { &["dd", "dt"] };
+                for index in (0..self.open.len()).rev() {
+                    let id = self.open[index];
+                    if self.is_html(id, closes) {
+                        let name = self.name(id).to_string();
+                        self.generate_implied_end_tags(Some(&name));
+                        self.pop_until(&[&name]);
+                        break;
+                    }
+                    if self.is_special(id) && !self.is_html(id, &["address", "div", "p"]) {
+                        break;
+                    }
+                }
+                self.close_p_if_in_button_scope();
+                self.insert_element(&tag);
+            }
+            "plaintext" => {
+                self.close_p_if_in_button_scope();
+                self.insert_element(&tag);
+                self.tokenizer.raw = Some(RawMode::Eof);
+            }
+            "button" => {
+                if self.in_scope(&["button"], Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&["button"]);
+                }
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+                self.frameset_ok = false;
+            }
+            "a" => {
+                let existing = self
+                    .formatting
+                    .iter()
+                    .rev()
+                    .take_while(|e| **e != Formatting::Marker)
+                    .find_map(|e| match *e {
+                        Formatting::Element(id) if self.name(id) == "a" => Some(id),
+                        _ => None,
+                    });
+                if let Some(existing) = existing {
+                    self.adoption_agency("a");
+                    self.formatting.retain(|e| *e != Formatting::Element(existing));
+                    self.remove_from_stack(existing);
+                }
+                self.reconstruct_formatting();
+                let id = self.insert_element(&tag);
+                self.push_formatting(id);
+            }
+            "b" | "big" | "code" | "em" | "font" | "i" | "s" | "small" | "strike" | "strong"
+            | "tt" | "u" => {
+                self.reconstruct_formatting();
+                let id = self.insert_element(&tag);
+                self.push_formatting(id);
+            }
+            "nobr" => {
+                self.reconstruct_formatting();
+                if self.in_scope(&["nobr"], Scope::Default) {
+                    self.adoption_agency("nobr");
+                    self.reconstruct_formatting();
+                }
+                let id = self.insert_element(&tag);
+                self.push_formatting(id);
+            }
+            "applet" | "marquee" | "object" => {
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+                self.formatting.push(Formatting::Marker);
+                self.frameset_ok = false;
+            }
+            "table" => {
+                if !self.quirks {
+                    self.close_p_if_in_button_scope();
+                }
+                self.insert_element(&tag);
+                self.frameset_ok = false;
+                self.mode = Mode::InTable;
+            }
+            "area" | "br" | "embed" | "img" | "keygen" | "wbr" => {
+                self.reconstruct_formatting();
+                self.insert_void(&tag);
+                self.frameset_ok = false;
+            }
+            "input" => {
+                self.reconstruct_formatting();
+                self.insert_void(&tag);
+                if !is_hidden_input(&tag, self.source) {
+                    self.frameset_ok = false;
+                }
+            }
+            "param" | "source" | "track" => self.insert_void(&tag),
+            "hr" => {
+                self.close_p_if_in_button_scope();
+                self.insert_void(&tag);
+                self.frameset_ok = false;
+            }
+            "image" => {
+                let mut tag = tag;
+                tag.name = Cow::Borrowed("img");
+                tag.name_span = 0..0;
+                return Some(Token::StartTag(tag));
+            }
+            "textarea" => {
+                self.parse_raw_text(&tag);
+                self.skip_newline = true;
+                self.frameset_ok = false;
+            }
+            "xmp" => {
+                self.close_p_if_in_button_scope();
+                self.reconstruct_formatting();
+                self.frameset_ok = false;
+                self.parse_raw_text(&tag);
+            }
+            "iframe" => {
+                self.frameset_ok = false;
+                self.parse_raw_text(&tag);
+            }
+            "noembed" | "noscript" => self.parse_raw_text(&tag),
+            "select" => {
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+                self.frameset_ok = false;
+                self.mode = match self.mode {
+                    Mode::InTable | Mode::InCaption | Mode::InTableBody | Mode::InRow | Mode::InCell => {
+                        Mode::InSelectInTable
+                    }
+                    _ => Mode::InSelect,
+                };
+            }
+            "optgroup" | "option" => {
+                if self.current_is(&["option"]) {
+                    self.pop();
+                }
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+            }
+            "rb" | "rtc" => {
+                if self.in_scope(&["ruby"], Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                }
+                self.insert_element(&tag);
+            }
+            "rp" | "rt" => {
+                if self.in_scope(&["ruby"], Scope::Default) {
+                    self.generate_implied_end_tags(Some("rtc"));
+                }
+                self.insert_element(&tag);
+            }
+            "math" | "svg" => {
+                self.reconstruct_formatting();
+                let namespace = if tag.name == "math" {
+                    Namespace::MathMl
+                } else {
+                    Namespace::Svg
+                };
+                self.insert_foreign_element(&tag, namespace);
+                if tag.self_closing {
+                    self.pop();
+                }
+            }
+            "caption" | "col" | "colgroup" | "frame" | "head" | "tbody" | "td" | "tfoot" | "th"
+            | "thead" | "tr" => {}
+            _ => {
+                self.reconstruct_formatting();
+                self.insert_element(&tag);
+            }
+        }
+        None
+    }
+
+    fn in_body_end_tag(&mut self, tag: Tag<'s>) -> Option<Token<'s>> {
+        match tag.name.as_ref() {
+            "template" => return self.in_head(Token::EndTag(tag)),
+            "body" => {
+                if self.in_scope(&["body"], Scope::Default) {
+                    self.mode = Mode::AfterBody;
+                } else {
+                    self.stray_end_tag(&tag);
+                }
+            }
+            "html" => {
+                if self.in_scope(&["body"], Scope::Default) {
+                    self.mode = Mode::AfterBody;
+                    return Some(Token::EndTag(tag));
+                }
+                self.stray_end_tag(&tag);
+            }
+            "address" | "article" | "aside" | "blockquote" | "button" | "center" | "details"
+            | "dialog" | "dir" | "div" | "dl" | "fieldset" | "figcaption" | "figure" | "footer"
+            | "header" | "hgroup" | "listing" | "main" | "menu" | "nav" | "ol" | "pre"
+            | "search" | "section" | "summary" | "ul" => {
+                let name = tag.name.as_ref();
+                if self.in_scope(&[name], Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                    self.close_elements(&[name]);
+                } else {
+                    self.stray_end_tag(&tag);
+                }
+            }
+            "form" => {
+                let open = self.form.take().filter(|form| {
+                    self.in_scope(&["form"], Scope::Default) && self.open.contains(form)
+                });
+                match open {
+                    Some(form) => {
+                        self.generate_implied_end_tags(None);
+                        self.remove_from_stack(form);
+                    }
+                    None => self.stray_end_tag(&tag),
+                }
+            }
+            "p" => {
+                if !self.in_scope(&["p"], Scope::Button) {
+                    self.stray_end_tag(&tag);
+                    self.insert_element(&Tag::implied("p"));
+                }
+                self.close_p_element();
+            }
+            "li" => {
+                if self.in_scope(&["li"], Scope::ListItem) {
+                    self.generate_implied_end_tags(Some("li"));
+                    self.close_elements(&["li"]);
+                } else {
+                    self.stray_end_tag(&tag);
+                }
+            }
+            "dd" | "dt" => {
+                let name = tag.name.as_ref();
+                if self.in_scope(&[name], Scope::Default) {
+                    self.generate_implied_end_tags(Some(name));
+                    self.close_elements(&[name]);
+                } else {
+                    self.stray_end_tag(&tag);
+                }
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                if self.in_scope(&HEADINGS, Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                    self.close_elements(&HEADINGS);
+                } else {
+                    self.stray_end_tag(&tag);
+                }
+            }
+            "a" | "b" | "big" | "code" | "em" | "font" | "i" | "nobr" | "s" | "small"
+            | "strike" | "strong" | "tt" | "u" => {
+                if !self.adoption_agency(&tag.name) {
+                    self.any_other_end_tag(&tag);
+                }
+            }
+            "applet" | "marquee" | "object" => {
+                let name = tag.name.as_ref();
+                if self.in_scope(&[name], Scope::Default) {
+                    self.generate_implied_end_tags(None);
+                    self.close_elements(&[name]);
+                    self.clear_formatting_to_marker();
+                } else {
+                    self.stray_end_tag(&tag);
+                }
+            }
+            "br" => {
+                return self.in_body_start_tag(Tag::implied("br"));
+            }
+            _ => self.any_other_end_tag(&tag),
+        }
+        None
+    }
+
+    fn any_other_end_tag(&mut self, tag: &Tag<'s>) {
+        for index in (0..self.open.len()).rev() {
+            let id = self.open[index];
+            if self.is_html(id, &[tag.name.as_ref()]) {
+                self.generate_implied_end_tags(Some(&tag.name));
+                while self.open.len() > index + 1 {
+                    if let Some(id) = self.pop() {
+                        self.unclosed(id);
+                    }
+                }
+                self.open.truncate(index);
+                return;
+            }
+            if self.is_special(id) {
+                break;
+            }
+        }
+        self.stray_end_tag(tag);
+    }
+
+    fn text(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        match token {
+            Token::Text(range) => {
+                self.insert_text(range);
+                None
+            }
+            Token::Eof => {
+                self.pop();
+                self.mode = self.original_mode;
+                Some(Token::Eof)
+            }
+            _ => {
+                self.pop();
+                self.mode = self.original_mode;
+                None
+            }
+        }
+    }
+
+    fn in_table(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        match token {
+            Token::Text(range)
+                if self.current_is(&["table", "tbody", "template", "tfoot", "thead", "tr"]) =>
+            {
+                self.pending_table_text.clear();
+                self.original_mode = self.mode;
+                self.mode = Mode::InTableText;
+                Some(Token::Text(range))
+            }
+            Token::Comment(range) => {
+                self.insert_comment(range, None);
+                None
+            }
+            Token::Doctype(_) => None,
+            Token::StartTag(tag) => match tag.name.as_ref() {
+                "caption" => {
+                    self.clear_stack_back_to(&["table"]);
+                    self.formatting.push(Formatting::Marker);
+                    self.insert_element(&tag);
+                    self.mode = Mode::InCaption;
+                    None
+                }
+                "colgroup" => {
+                    self.clear_stack_back_to(&["table"]);
+                    self.insert_element(&tag);
+                    self.mode = Mode::InColumnGroup;
+                    None
+                }
+                "col" => {
+                    self.clear_stack_back_to(&["table"]);
+                    self.insert_element(&Tag::implied("colgroup"));
+                    self.mode = Mode::InColumnGroup;
+                    Some(Token::StartTag(tag))
+                }
+                "tbody" | "tfoot" | "thead" => {
+                    self.clear_stack_back_to(&["table"]);
+                    self.insert_element(&tag);
+                    self.mode = Mode::InTableBody;
+                    None
+                }
+                "td" | "th" | "tr" => {
+                    self.clear_stack_back_to(&["table"]);
+                    self.insert_element(&Tag::implied("tbody"));
+                    self.mode = Mode::InTableBody;
+                    Some(Token::StartTag(tag))
+                }
+                "table" => {
+                    if !self.in_scope(&["table"], Scope::Table) {
+                        return None;
+                    }
+                    self.pop_until(&["table"]);
+                    self.reset_insertion_mode();
+                    Some(Token::StartTag(tag))
+                }
+                "style" | "script" | "template" => self.in_head(Token::StartTag(tag)),
+                "input" if is_hidden_input(&tag, self.source) => {
+                    self.insert_void(&tag);
+                    None
+                }
+                "form" => {
+                    let in_template = self.open.iter().any(|&id| self.is_html(id, &["template"]));
+                    if !in_template && self.form.is_none() {
+                        self.form = Some(self.insert_element(&tag));
+                        self.pop();
+                    }
+                    None
+                }
+                _ => self.foster(Token::StartTag(tag)),
+            },
+            Token::EndTag(tag) => match tag.name.as_ref() {
+                "table" => {
+                    if self.in_scope(&["table"], Scope::Table) {
+                        self.pop_until(&["table"]);
+                        self.reset_insertion_mode();
+                    }
+                    None
+                }
+                "body" | "caption" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot"
+                | "th" | "thead" | "tr" => None,
+                "template" => self.in_head(Token::EndTag(tag)),
+                _ => self.foster(Token::EndTag(tag)),
+            },
+            Token::Eof => self.in_body(Token::Eof),
+            token => self.foster(token),
+        }
+    }
+
+    /// Process a token with the in-body rules while foster parenting
+    fn foster(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        self.foster_parenting = true;
+        let result = self.in_body(token);
+        self.foster_parenting = false;
+        result
+    }
+
+    fn in_table_text(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        if let Token::Text(range) = token {
+            self.pending_table_text.push(range);
+            return None;
+        }
+
+        let pending = std::mem::take(&mut self.pending_table_text);
+        let all_whitespace = pending
+            .iter()
+            .all(|r| self.source.as_bytes()[r.clone()].iter().all(|&b| is_whitespace(b)));
+        for range in pending {
+            if all_whitespace {
+                self.insert_text(range);
+            } else {
+                self.foster(Token::Text(range));
+            }
+        }
+        self.mode = self.original_mode;
+        Some(token)
+    }
+
+    fn in_caption(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        let closes_caption = match &token {
+            Token::EndTag(tag) if tag.name == "caption" => {
+                self.close_caption();
+                return None;
+            }
+            Token::StartTag(tag) => matches!(
+                tag.name.as_ref(),
+                "caption" | "col" | "colgroup" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+            ),
+            Token::EndTag(tag) if tag.name == "table" => true,
+            Token::EndTag(tag) => {
+                if matches!(
+                    tag.name.as_ref(),
+                    "body" | "col" | "colgroup" | "html" | "tbody" | "td" | "tfoot" | "th"
+                        | "thead" | "tr"
+                ) {
+                    return None;
+                }
+                false
+            }
+            _ => false,
+        };
+
+        if closes_caption {
+            return self.close_caption().then_some(token);
+        }
+        self.in_body(token)
+    }
+
+    fn close_caption(&mut self) -> bool {
+        if !self.in_scope(&["caption"], Scope::Table) {
+            return false;
+        }
+        self.generate_implied_end_tags(None);
+        self.pop_until(&["caption"]);
+        self.clear_formatting_to_marker();
+        self.mode = Mode::InTable;
+        true
+    }
+
+    fn in_column_group(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        match token {
+            Token::Text(range) if self.starts_with_whitespace(&range) => {
+                let (ws, rest) = self.split_whitespace(range);
+                self.insert_text(ws);
+                let rest = rest?;
+                self.in_column_group(rest)
+            }
+            Token::Comment(range) => {
+                self.insert_comment(range, None);
+                None
+            }
+            Token::Doctype(_) => None,
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::StartTag(tag) if tag.name == "col" => {
+                self.insert_void(&tag);
+                None
+            }
+            Token::StartTag(tag) if tag.name == "template" => self.in_head(Token::StartTag(tag)),
+            Token::EndTag(tag) if tag.name == "colgroup" => {
+                if self.current_is(&["colgroup"]) {
+                    self.pop();
+                    self.mode = Mode::InTable;
+                }
+                None
+            }
+            Token::EndTag(tag) if tag.name == "col" => None,
+            Token::EndTag(tag) if tag.name == "template" => self.in_head(Token::EndTag(tag)),
+            Token::Eof => self.in_body(Token::Eof),
+            token => {
+                if !self.current_is(&["colgroup"]) {
+                    return None;
+                }
+                self.pop();
+                self.mode = Mode::InTable;
+                Some(token)
+            }
+        }
+    }
+
+    fn in_table_body(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        const SECTIONS: [&str; 3] = ["tbody", "tfoot", "thead"];
+        match token {
+            Token::StartTag(tag) if tag.name == "tr" => {
+                self.clear_stack_back_to(&SECTIONS);
+                self.insert_element(&tag);
+                self.mode = Mode::InRow;
+                None
+            }
+            Token::StartTag(tag) if matches!(tag.name.as_ref(), "th" | "td") => {
+                self.clear_stack_back_to(&SECTIONS);
+                self.insert_element(&Tag::implied("tr"));
+                self.mode = Mode::InRow;
+                Some(Token::StartTag(tag))
+            }
+            Token::EndTag(tag) if SECTIONS.contains(&tag.name.as_ref()) => {
+                if self.in_scope(&[tag.name.as_ref()], Scope::Table) {
+                    self.clear_stack_back_to(&SECTIONS);
+                    self.pop();
+                    self.mode = Mode::InTable;
+                }
+                None
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_ref(),
+                    "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead"
+                ) =>
+            {
+                self.close_table_section().then_some(token)
+            }
+            Token::EndTag(ref tag) if tag.name == "table" => self.close_table_section().then_some(token),
+            Token::EndTag(tag)
+                if matches!(
+                    tag.name.as_ref(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th" | "tr"
+                ) =>
+            {
+                None
+            }
+            token => self.in_table(token),
+        }
+    }
+
+    fn close_table_section(&mut self) -> bool {
+        if !self.in_scope(&["tbody", "thead", "tfoot"], Scope::Table) {
+            return false;
+        }
+        self.clear_stack_back_to(&["tbody", "tfoot", "thead"]);
+        self.pop();
+        self.mode = Mode::InTable;
+        true
+    }
+
+    fn in_row(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        match token {
+            Token::StartTag(tag) if matches!(tag.name.as_ref(), "th" | "td") => {
+                self.clear_stack_back_to(&["tr"]);
+                self.insert_element(&tag);
+                self.mode = Mode::InCell;
+                self.formatting.push(Formatting::Marker);
+                None
+            }
+            Token::EndTag(tag) if tag.name == "tr" => {
+                self.close_row();
+                None
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_ref(),
+                    "caption" | "col" | "colgroup" | "tbody" | "tfoot" | "thead" | "tr"
+                ) =>
+            {
+                self.close_row().then_some(token)
+            }
+            Token::EndTag(ref tag) if tag.name == "table" => self.close_row().then_some(token),
+            Token::EndTag(ref tag) if matches!(tag.name.as_ref(), "tbody" | "tfoot" | "thead") => {
+                if !self.in_scope(&[tag.name.as_ref()], Scope::Table) {
+                    return None;
+                }
+                self.close_row().then_some(token)
+            }
+            Token::EndTag(tag)
+                if matches!(
+                    tag.name.as_ref(),
+                    "body" | "caption" | "col" | "colgroup" | "html" | "td" | "th"
+                ) =>
+            {
+                None
+            }
+            token => self.in_table(token),
+        }
+    }
+
+    fn close_row(&mut self) -> bool {
+        if !self.in_scope(&["tr"], Scope::Table) {
+            return false;
+        }
+        self.clear_stack_back_to(&["tr"]);
+        self.pop();
+        self.mode = Mode::InTableBody;
+        true
+    }
+
+    fn in_cell(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        match token {
+            Token::EndTag(tag) if matches!(tag.name.as_ref(), "td" | "th") => {
+                let name = tag.name.as_ref();
+                if self.in_scope(&[name], Scope::Table) {
+                    self.generate_implied_end_tags(None);
+                    self.pop_until(&[name]);
+                    self.clear_formatting_to_marker();
+                    self.mode = Mode::InRow;
+                }
+                None
+            }
+            Token::StartTag(ref tag)
+                if matches!(
+                    tag.name.as_ref(),
+                    "caption" | "col" | "colgroup" | "tbody" | "td" | "tfoot" | "th" | "thead" | "tr"
+                ) =>
+            {
+                if !self.in_scope(&["td", "th"], Scope::Table) {
+                    return None;
+                }
+                self.close_cell();
+                Some(token)
+            }
+            Token::EndTag(tag)
+                if matches!(tag.name.as_ref(), "body" | "caption" | "col" | "colgroup" | "html") =>
+            {
+                None
+            }
+            Token::EndTag(ref tag)
+                if matches!(tag.name.as_ref(), "table" | "tbody" | "tfoot" | "thead" | "tr") =>
+            {
+                if !self.in_scope(&[tag.name.as_ref()], Scope::Table) {
+                    return None;
+                }
+                self.close_cell();
+                Some(token)
+            }
+            token => self.in_body(token),
+        }
+    }
+
+    fn close_cell(&mut self) {
+        self.generate_implied_end_tags(None);
+        self.pop_until(&["td", "th"]);
+        self.clear_formatting_to_marker();
+        self.mode = Mode::InRow;
+    }
+
+    fn in_select(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        match token {
+            Token::Text(range) => {
+                self.insert_text(range);
+                None
+            }
+            Token::Comment(range) => {
+                self.insert_comment(range, None);
+                None
+            }
+            Token::StartTag(tag) => match tag.name.as_ref() {
+                "html" => self.in_body(Token::StartTag(tag)),
+                "option" => {
+                    if self.current_is(&["option"]) {
+                        self.pop();
+                    }
+                    self.insert_element(&tag);
+                    None
+                }
+                "optgroup" | "hr" => {
+                    if self.current_is(&["option"]) {
+                        self.pop();
+                    }
+                    if self.current_is(&["optgroup"]) {
+                        self.pop();
+                    }
+                    if tag.name == "hr" {
+                        self.insert_void(&tag);
+                    } else {
+                        self.insert_element(&tag);
+                    }
+                    None
+                }
+                "select" => {
+                    if self.in_scope(&["select"], Scope::Select) {
+                        self.pop_until(&["select"]);
+                        self.reset_insertion_mode();
+                    }
+                    None
+                }
+                "input" | "keygen" | "textarea" => {
+                    if !self.in_scope(&["select"], Scope::Select) {
+                        return None;
+                    }
+                    self.pop_until(&["select"]);
+                    self.reset_insertion_mode();
+                    Some(Token::StartTag(tag))
+                }
+                "script" | "template" => self.in_head(Token::StartTag(tag)),
+                _ => None,
+            },
+            Token::EndTag(tag) => match tag.name.as_ref() {
+                "optgroup" => {
+                    let len = self.open.len();
+                    if self.current_is(&["option"])
+                        && len >= 2
+                        && self.is_html(self.open[len - 2], &["optgroup"])
+                    {
+                        self.pop();
+                    }
+                    if self.current_is(&["optgroup"]) {
+                        self.pop();
+                    }
+                    None
+                }
+                "option" => {
+                    if self.current_is(&["option"]) {
+                        self.pop();
+                    }
+                    None
+                }
+                "select" => {
+                    if self.in_scope(&["select"], Scope::Select) {
+                        self.pop_until(&["select"]);
+                        self.reset_insertion_mode();
+                    }
+                    None
+                }
+                "template" => self.in_head(Token::EndTag(tag)),
+                _ => None,
+            },
+            Token::Eof => self.in_body(Token::Eof),
+            _ => None,
+        }
+    }
+
+    fn in_select_in_table(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        const TABLE: [&str; 8] = ["caption", "table", "tbody", "tfoot", "thead", "tr", "td", "th"];
+        match token {
+            Token::StartTag(ref tag) if TABLE.contains(&tag.name.as_ref()) => {
+                self.pop_until(&["select"]);
+                self.reset_insertion_mode();
+                Some(token)
+            }
+            Token::EndTag(ref tag) if TABLE.contains(&tag.name.as_ref()) => {
+                if !self.in_scope(&[tag.name.as_ref()], Scope::Table) {
+                    return None;
+                }
+                self.pop_until(&["select"]);
+                self.reset_insertion_mode();
+                Some(token)
+            }
+            token => self.in_select(token),
+        }
+    }
+
+    fn after_body(&mut self, token: Token<'s>) -> Option<Token<'s>> {
+        match token {
+            Token::Text(range) if self.source.as_bytes()[range.clone()].iter().all(|&b| is_whitespace(b)) => {
+                self.in_body(Token::Text(range))
+            }
+            Token::Comment(range) => {
+                let html = self.open.first().copied();
+                self.insert_comment(range, Some(html));
+                None
+            }
+            Token::Doctype(_) => None,
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::EndTag(tag) if tag.name == "html" => {
+                self.mode = Mode::AfterAfterBody;
+                None
+            }
+            Token::Eof => None,
+            token => {
+                self.mode = Mode::InBody;
+                Some(token)
+            }
+        }
+    }
+
+    fn in_frameset(&mut self, mode: Mode, token: Token<'s>) -> Option<Token<'s>> {
+        match token {
+            Token::Text(range) => {
+                let whitespace: Vec<usize> = (range.clone())
+                    .filter(|&i| is_whitespace(self.source.as_bytes()[i]))
+                    .collect();
+                for i in whitespace {
+                    self.insert_text(i..i + 1);
+                }
+                None
+            }
+            Token::Comment(range) => {
+                self.insert_comment(range, None);
+                None
+            }
+            Token::StartTag(tag) => match tag.name.as_ref() {
+                "html" => self.in_body(Token::StartTag(tag)),
+                "frameset" if mode == Mode::InFrameset => {
+                    self.insert_element(&tag);
+                    None
+                }
+                "frame" if mode == Mode::InFrameset => {
+                    self.insert_void(&tag);
+                    None
+                }
+                "noframes" => self.in_head(Token::StartTag(tag)),
+                _ => None,
+            },
+            Token::EndTag(tag) if tag.name == "frameset" && mode == Mode::InFrameset => {
+                if self.open.len() > 1 {
+                    self.pop();
+                    if !self.current_is(&["frameset"]) {
+                        self.mode = Mode::AfterFrameset;
+                    }
+                }
+                None
+            }
+            Token::EndTag(tag) if tag.name == "html" && mode == Mode::AfterFrameset => {
+                self.mode = Mode::AfterAfterFrameset;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn after_after(&mut self, mode: Mode, token: Token<'s>) -> Option<Token<'s>> {
+        match token {
+            Token::Comment(range) => {
+                self.insert_comment(range, Some(None));
+                None
+            }
+            Token::Doctype(_) => None,
+            Token::Text(range) if self.source.as_bytes()[range.clone()].iter().all(|&b| is_whitespace(b)) => {
+                self.in_body(Token::Text(range))
+            }
+            Token::StartTag(tag) if tag.name == "html" => self.in_body(Token::StartTag(tag)),
+            Token::Eof => None,
+            Token::StartTag(tag) if tag.name == "noframes" && mode == Mode::AfterAfterFrameset => {
+                self.in_head(Token::StartTag(tag))
+            }
+            token if mode == Mode::AfterAfterBody => {
+                self.mode = Mode::InBody;
+                Some(token)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Where to insert a node: as last child of a parent, or before a sibling
+#[derive(Debug, Clone, Copy)]
+enum Location {
+    Append(Option<NodeId>),
+    Before(NodeId),
+}
+
+fn is_hidden_input(tag: &Tag<'_>, source: &str) -> bool {
+    tag.attributes.iter().any(|a| {
+        a.name.resolve(source) == "type"
+            && a.value
+                .as_ref()
+                .is_some_and(|v| v.resolve(source).eq_ignore_ascii_case("hidden"))
+    })
+}
+
+/// Find the correctly cased spelling of a lowercased SVG name
+fn adjust_case(name: &str, table: &[&'static str]) -> Option<&'static str> {
+    table
+        .iter()
+        .find(|candidate| candidate.eq_ignore_ascii_case(name) && **candidate != name)
+        .copied()
+}
+
+const HEADINGS: [&str; 6] = ["h1", "h2", "h3", "h4", "h5", "h6"];
+
+/// Elements whose end tag may be left out
+const OPTIONAL_END_TAGS: [&str; 19] = [
+    "body", "dd", "dt", "head", "html", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+    "tbody", "td", "tfoot", "th", "thead", "tr",
+];
+
+/// Elements in the HTML "special" category
+const SPECIAL: &[&str] = &[
+    "address", "applet", "area", "article", "aside", "base", "basefont", "bgsound", "blockquote",
+    "body", "br", "button", "caption", "center", "col", "colgroup", "dd", "details", "dir", "div",
+    "dl", "dt", "embed", "fieldset", "figcaption", "figure", "footer", "form", "frame", "frameset",
+    "h1", "h2", "h3", "h4", "h5", "h6", "head", "header", "hgroup", "hr", "html", "iframe", "img",
+    "input", "keygen", "li", "link", "listing", "main", "marquee", "menu", "meta", "nav",
+    "noembed", "noframes", "noscript", "object", "ol", "p", "param", "plaintext", "pre", "script",
+    "search", "section", "select", "source", "style", "summary", "table", "tbody", "td",
+    "template", "textarea", "tfoot", "th", "thead", "title", "tr", "track", "ul", "wbr", "xmp",
+];
+
+/// HTML start tags that break out of SVG/MathML content
+const BREAKOUT: &[&str] = &[
+    "b", "big", "blockquote", "body", "br", "center", "code", "dd", "div", "dl", "dt", "em",
+    "embed", "h1", "h2", "h3", "h4", "h5", "h6", "head", "hr", "i", "img", "li", "listing", "menu",
+    "meta", "nobr", "ol", "p", "pre", "ruby", "s", "small", "span", "strong", "strike", "sub",
+    "sup", "table", "tt", "u", "ul", "var",
+];
+
+const SVG_TAG_NAMES: &[&str] = &[
+    "altGlyph", "altGlyphDef", "altGlyphItem", "animateColor", "animateMotion",
+    "animateTransform", "clipPath", "feBlend", "feColorMatrix", "feComponentTransfer",
+    "feComposite", "feConvolveMatrix", "feDiffuseLighting", "feDisplacementMap",
+    "feDistantLight", "feDropShadow", "feFlood", "feFuncA", "feFuncB", "feFuncG", "feFuncR",
+    "feGaussianBlur", "feImage", "feMerge", "feMergeNode", "feMorphology", "feOffset",
+    "fePointLight", "feSpecularLighting", "feSpotLight", "feTile", "feTurbulence",
+    "foreignObject", "glyphRef", "linearGradient", "radialGradient", "textPath",
+];
+
+const SVG_ATTRIBUTES: &[&str] = &[
+    "attributeName", "attributeType", "baseFrequency", "baseProfile", "calcMode",
+    "clipPathUnits", "diffuseConstant", "edgeMode", "filterUnits", "glyphRef",
+    "gradientTransform", "gradientUnits", "kernelMatrix", "kernelUnitLength", "keyPoints",
+    "keySplines", "keyTimes", "lengthAdjust", "limitingConeAngle", "markerHeight", "markerUnits",
+    "markerWidth", "maskContentUnits", "maskUnits", "numOctaves", "pathLength",
+    "patternContentUnits", "patternTransform", "patternUnits", "pointsAtX", "pointsAtY",
+    "pointsAtZ", "preserveAlpha", "preserveAspectRatio", "primitiveUnits", "refX", "refY",
+    "repeatCount", "repeatDur", "requiredExtensions", "requiredFeatures", "specularConstant",
+    "specularExponent", "spreadMethod", "startOffset", "stdDeviation", "stitchTiles",
+    "surfaceScale", "systemLanguage", "tableValues", "targetX", "targetY", "textLength",
+    "viewBox", "viewTarget", "xChannelSelector", "yChannelSelector", "zoomAndPan",
+];
+
+/// Whether a doctype puts the document in quirks mode
+///
+/// Covers a missing `html` name and the common legacy public identifiers
+/// rather than the full list from the specification.
+fn is_quirks_doctype(markup: &str) -> bool {
+    let lower = markup.to_ascii_lowercase();
+    let rest = lower
+        .trim_start_matches("<!doctype")
+        .trim_end_matches('>')
+        .trim();
+    let Some(rest) = rest.strip_prefix("html") else {
+        return true;
+    };
+    let Some(public) = rest.trim_start().strip_prefix("public") else {
+        return false;
+    };
+    let mut literals = public.split(['"', '\'']).skip(1).step_by(2);
+    let public_id = literals.next().unwrap_or("");
+    let has_system_id = literals.next().is_some();
+
+    const LEGACY: &[&str] = &[
+        "-//w3c//dtd html 3",
+        "-//w3c//dtd html 2",
+        "-//ietf//",
+        "-//w3o//",
+        "-//netscape comm. corp.//",
+        "-//microsoft//",
+        "-//softquad",
+        "-//webtechs//",
+    ];
+    LEGACY.iter().any(|prefix| public_id.starts_with(prefix))
+        || (!has_system_id
+            && (public_id.starts_with("-//w3c//dtd html 4.01 transitional//")
+                || public_id.starts_with("-//w3c//dtd html 4.01 frameset//")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::types::{Document, DocumentType, NodeRef, NodeType};
+
+    /// Render the element structure as an indented outline
+    fn outline(html: &str) -> String {
+        fn walk(node: NodeRef<'_, '_>, depth: usize, out: &mut String) {
+            match node.node_type() {
+                NodeType::Element => {
+                    out.push_str(&format!("{}{}\n", "  ".repeat(depth), node.tag_name().unwrap()));
+                    for child in node.children() {
+                        walk(child, depth + 1, out);
+                    }
+                }
+                NodeType::Text => {
+                    let text = node.text().unwrap();
+                    if !text.trim().is_empty() {
+                        out.push_str(&format!("{}\"{}\"\n", "  ".repeat(depth), text));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let doc = Document::parse(html, DocumentType::Html).unwrap();
+        let mut out = String::new();
+        for node in doc.children() {
+            walk(node, 0, &mut out);
+        }
+        out
+    }
+
+    #[test]
+    fn test_implied_html_head_body() {
+        assert_eq!(
+            outline("<title>T</title><p>Hi"),
+            "html\n  head\n    title\n      \"T\"\n  body\n    p\n      \"Hi\"\n"
+        );
+    }
+
+    #[test]
+    fn test_text_before_first_tag() {
+        assert_eq!(outline("hello"), "html\n  head\n  body\n    \"hello\"\n");
+        assert_eq!(
+            outline("a<b>c</b>"),
+            "html\n  head\n  body\n    \"a\"\n    b\n      \"c\"\n"
+        );
+        assert_eq!(
+            outline("<!DOCTYPE html>\n Hello"),
+            "html\n  head\n  body\n    \"Hello\"\n"
+        );
+        assert_eq!(
+            outline("<html> x<p>y"),
+            "html\n  head\n  body\n    \"x\"\n    p\n      \"y\"\n"
+        );
+    }
+
+    #[test]
+    fn test_auto_closed_paragraphs_and_list_items() {
+        assert_eq!(
+            outline("<p>a<p>b<ul><li>1<li>2</ul>"),
+            "html\n  head\n  body\n    p\n      \"a\"\n    p\n      \"b\"\n    ul\n      li\n        \"1\"\n      li\n        \"2\"\n"
+        );
+    }
+
+    #[test]
+    fn test_foster_parenting_and_implied_tbody() {
+        assert_eq!(
+            outline("<table><tr><td>x</td></tr>oops</table>"),
+            "html\n  head\n  body\n    \"oops\"\n    table\n      tbody\n        tr\n          td\n            \"x\"\n"
+        );
+    }
+
+    #[test]
+    fn test_text_in_column_group() {
+        assert_eq!(
+            outline("<table><col>script"),
+            "html\n  head\n  body\n    \"script\"\n    table\n      colgroup\n        col\n"
+        );
+        assert_eq!(
+            outline("<table><colgroup> x</colgroup></table>"),
+            "html\n  head\n  body\n    \"x\"\n    table\n      colgroup\n"
+        );
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let diagnostics = |html| -> Vec<String> {
+            parse(html).diagnostics.iter().map(|d| d.to_string()).collect()
+        };
+        assert_eq!(
+            diagnostics("<p>x</div><span class=a class=b>&nbsp;&bogus; &amp</span>\n<div><b>y"),
+            [
+                "1:5: error[stray-end-tag]: unexpected end tag </div>",
+                "1:25: error[duplicate-attribute]: duplicate attribute 'class'",
+                "1:39: error[undefined-entity]: undefined entity '&bogus;'",
+                "2:6: error[unclosed-tag]: element <b> is never closed",
+                "2:1: error[unclosed-tag]: element <div> is never closed",
+            ]
+        );
+        // </section> closes the <em> it skips over
+        assert_eq!(
+            diagnostics("<section><em>x</section>"),
+            ["1:10: error[unclosed-tag]: element <em> is never closed"]
+        );
+        // End tags that may be left out are not errors
+        assert!(diagnostics("<ul><li>a<li>b</ul><p>c").is_empty());
+        assert!(diagnostics("<!DOCTYPE html><title>&lt;&#38;</title><body>x</body>").is_empty());
+    }
+
+    #[test]
+    fn test_table_closes_paragraph_only_in_standards_mode() {
+        assert_eq!(
+            outline("<!DOCTYPE html><p><table></table>"),
+            "html\n  head\n  body\n    p\n    table\n"
+        );
+        assert_eq!(
+            outline("<p><table></table>"),
+            "html\n  head\n  body\n    p\n      table\n"
+        );
+    }
+
+    #[test]
+    fn test_adoption_agency() {
+        assert_eq!(
+            outline("<b>1<p>2</b>3</p>"),
+            "html\n  head\n  body\n    b\n      \"1\"\n    p\n      b\n        \"2\"\n      \"3\"\n"
+        );
+    }
+
+    #[test]
+    fn test_raw_text_and_void_elements() {
+        let html = "<script>if (a<b) {}</script><p>x<br>y<img src=a.png></p>";
+        let doc = Document::parse(html, DocumentType::Html).unwrap();
+        let script = doc.root().children().next().unwrap().children().next().unwrap();
+        assert_eq!(script.tag_name().as_deref(), Some("script"));
+        assert_eq!(script.text().as_deref(), Some("if (a<b) {}"));
+        assert_eq!(
+            outline(html),
+            "html\n  head\n    script\n      \"if (a<b) {}\"\n  body\n    p\n      \"x\"\n      br\n      \"y\"\n      img\n"
+        );
+    }
+
+    #[test]
+    fn test_svg_names_are_adjusted() {
+        let doc = Document::parse("<svg viewbox='0 0 1 1'><clippath/></svg>", DocumentType::Html).unwrap();
+        let body = doc.root().children().nth(1).unwrap();
+        let svg = body.children().next().unwrap();
+        assert_eq!(svg.attr("viewBox").as_deref(), Some("0 0 1 1"));
+        assert_eq!(svg.children().next().unwrap().tag_name().as_deref(), Some("clipPath"));
+    }
+}
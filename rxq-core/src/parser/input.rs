@@ -0,0 +1,209 @@
+//! Raw input from files and streams
+//!
+//! Regular files are memory-mapped, so a document parsed from one borrows
+//! the page cache instead of a heap copy of the file. Stdin, pipes and other
+//! special files (and platforms without `mmap`) are read into memory
+//! instead; [`InputBuffer`] hides the difference.
+//!
+//! A mapped file must not be truncated or rewritten while it is in use:
+//! the mapping reflects such changes, and reading past a truncated end
+//! raises `SIGBUS`. The compiler cannot check this, so mapping is `unsafe`
+//! ([`InputBuffer::open`], [`MappedFile::map`]). Read files that may be
+//! rewritten, such as the target of an in-place edit, with
+//! [`InputBuffer::read_file`] instead.
+
+use crate::error::ParseError;
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::Deref;
+use std::path::Path;
+
+/// The bytes of an input, mapped from a file or read into memory
+pub enum InputBuffer {
+    Mapped(MappedFile),
+    Owned(Vec<u8>),
+}
+
+impl InputBuffer {
+    /// Map a regular file, or read the file when it cannot be mapped
+    ///
+    /// # Safety
+    ///
+    /// See [`MappedFile::map`]: the file must not be modified or truncated
+    /// while the buffer is alive.
+    pub unsafe fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        // Empty files cannot be mapped, and special files may not support it
+        if metadata.is_file() && metadata.len() > 0 {
+            // SAFETY: passed on to the caller
+            if let Ok(mapped) = unsafe { MappedFile::map(&file) } {
+                return Ok(Self::Mapped(mapped));
+            }
+        }
+        Self::read(file)
+    }
+
+    /// Read a file into memory without mapping it
+    pub fn read_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read(File::open(path)?)
+    }
+
+    /// Read everything from a stream, such as stdin or a pipe
+    pub fn read(mut reader: impl Read) -> io::Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        Ok(Self::Owned(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Mapped(mapped) => mapped.as_bytes(),
+            Self::Owned(bytes) => bytes,
+        }
+    }
+
+    /// The input as UTF-8 text, without copying it
+    ///
+    /// Use [`super::encoding::decode`] for input in other encodings.
+    pub fn as_str(&self) -> Result<&str, ParseError> {
+        std::str::from_utf8(self.as_bytes()).map_err(|e| {
+            ParseError::EncodingError(format!("invalid UTF-8 at byte {}", e.valid_up_to()))
+        })
+    }
+
+    /// Whether the input is memory-mapped rather than read
+    pub fn is_mapped(&self) -> bool {
+        matches!(self, Self::Mapped(_))
+    }
+}
+
+impl Deref for InputBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_bytes()
+    }
+}
+
+/// A read-only memory mapping of a whole file
+pub struct MappedFile {
+    #[cfg(unix)]
+    ptr: std::ptr::NonNull<libc::c_void>,
+    #[cfg(unix)]
+    len: usize,
+}
+
+// The mapping is private and read-only, so it can be shared like a `&[u8]`
+#[cfg(unix)]
+unsafe impl Send for MappedFile {}
+#[cfg(unix)]
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    /// Map the current contents of `file`
+    ///
+    /// Fails for empty files, and on platforms without `mmap`.
+    ///
+    /// # Safety
+    ///
+    /// Nothing, in this process or another, may modify or truncate the file
+    /// while the mapping is alive: the bytes behind [`Self::as_bytes`] would
+    /// change under a shared borrow, and reading past a truncated end raises
+    /// `SIGBUS`.
+    #[cfg(unix)]
+    pub unsafe fn map(file: &File) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file is too large to map"))?;
+        if len == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot map an empty file"));
+        }
+        // SAFETY: a fresh private read-only mapping of an open file; the
+        // result is checked before use and unmapped exactly once in `drop`.
+        // That its contents stay fixed is up to the caller.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let ptr = std::ptr::NonNull::new(ptr).ok_or_else(io::Error::last_os_error)?;
+        Ok(Self { ptr, len })
+    }
+
+    /// # Safety
+    ///
+    /// Always fails; unsafe to match the `mmap` version.
+    #[cfg(not(unix))]
+    pub unsafe fn map(_file: &File) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "memory mapping is not supported on this platform",
+        ))
+    }
+
+    #[cfg(unix)]
+    pub fn as_bytes(&self) -> &[u8] {
+        // SAFETY: the mapping covers `len` readable bytes for as long as `self` lives
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr().cast::<u8>(), self.len) }
+    }
+
+    #[cfg(not(unix))]
+    pub fn as_bytes(&self) -> &[u8] {
+        &[]
+    }
+}
+
+#[cfg(unix)]
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        // SAFETY: `ptr` and `len` describe a mapping created by `map`
+        unsafe {
+            libc::munmap(self.ptr.as_ptr(), self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Document, DocumentType};
+
+    #[test]
+    fn test_file_is_mapped() {
+        let path = std::env::temp_dir().join(format!("rxq-input-{}.xml", std::process::id()));
+        std::fs::write(&path, "<root><a>mapped</a></root>").unwrap();
+
+        // SAFETY: the file is private to this test and only rewritten
+        // after the buffer is dropped
+        let input = unsafe { InputBuffer::open(&path) }.unwrap();
+        assert_eq!(input.is_mapped(), cfg!(unix));
+        let doc = Document::parse(input.as_str().unwrap(), DocumentType::Xml).unwrap();
+        assert_eq!(doc.root().text().as_deref(), Some("mapped"));
+
+        assert!(!InputBuffer::read_file(&path).unwrap().is_mapped());
+        std::fs::write(&path, "").unwrap();
+        // SAFETY: as above
+        assert!(unsafe { InputBuffer::open(&path) }.unwrap().is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_read_validates_utf8() {
+        let input = InputBuffer::read(&b"<a>\xff</a>"[..]).unwrap();
+        assert!(!input.is_mapped());
+        assert_eq!(
+            input.as_str().unwrap_err().to_string(),
+            "character encoding error: invalid UTF-8 at byte 3"
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! Splitting a stream of concatenated documents
+//!
+//! Log collectors and `cat *.xml` produce XML documents back to back, and
+//! NDJSON puts one JSON value on each line. [`split_documents`] finds the
+//! boundaries with a quick scan of the markup, without parsing, so each
+//! document can be parsed (and fail) on its own.
+//!
+//! An XML document ends with its root element. Comments and processing
+//! instructions after it belong to the next document, if there is one.
+//! A root element that is never closed takes the rest of the input.
+
+use crate::error::Position;
+use crate::types::DocumentType;
+
+/// One document of a multi-document input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DocumentSlice<'input> {
+    /// 0-based index of the document in the input
+    pub index: usize,
+    /// The document's source text
+    pub source: &'input str,
+    /// Where the document starts in the whole input
+    pub position: Position,
+}
+
+/// Iterator over the documents of a multi-document input
+pub struct Documents<'input> {
+    source: &'input str,
+    doc_type: DocumentType,
+    offset: usize,
+    index: usize,
+    /// Position of `offset`, advanced incrementally
+    position: Position,
+}
+
+/// Split `source` into successive documents
+///
+/// XML input is split after each root element, and JSON input after each
+/// top-level value (so NDJSON and concatenated JSON both work). HTML is
+/// not split: it is always returned as a single document.
+pub fn split_documents(source: &str, doc_type: DocumentType) -> Documents<'_> {
+    Documents {
+        source,
+        doc_type,
+        offset: 0,
+        index: 0,
+        position: Position::from_offset(source, 0),
+    }
+}
+
+impl<'input> Documents<'input> {
+    /// Move `offset` forward, keeping `position` in step
+    fn advance(&mut self, offset: usize) {
+        let skipped = Position::from_offset(&self.source[self.offset..], offset - self.offset);
+        self.position = Position {
+            offset,
+            line: self.position.line + skipped.line - 1,
+            column: if skipped.line > 1 {
+                skipped.column
+            } else {
+                self.position.column + skipped.column - 1
+            },
+        };
+        self.offset = offset;
+    }
+}
+
+impl<'input> Iterator for Documents<'input> {
+    type Item = DocumentSlice<'input>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.source[self.offset..];
+        let start = self.offset + (rest.len() - rest.trim_start().len());
+        if start == self.source.len() {
+            return None;
+        }
+        self.advance(start);
+
+        let end = match self.doc_type {
+            DocumentType::Xml => xml_document_end(self.source, start),
+            DocumentType::Json => json_value_end(self.source.as_bytes(), start),
+            DocumentType::Html => self.source.len(),
+        };
+        let slice = DocumentSlice {
+            index: self.index,
+            source: self.source[start..end].trim_end(),
+            position: self.position,
+        };
+        self.index += 1;
+        self.advance(end);
+        Some(slice)
+    }
+}
+
+/// End of the XML document starting at `start`
+///
+/// Tracks element depth only; mismatched tags are left to the parser.
+fn xml_document_end(source: &str, start: usize) -> usize {
+    let bytes = source.as_bytes();
+    let mut depth = 0usize;
+    let mut root_end = None;
+    let mut i = start;
+
+    while let Some(found) = find(bytes, i, b"<") {
+        let rest = &bytes[found..];
+        // Once the root element is closed, any further root, declaration or
+        // DOCTYPE starts the next document
+        let starts_document = (rest.starts_with(b"<?xml")
+            && rest.get(5).is_some_and(|b| b.is_ascii_whitespace() || *b == b'?'))
+            || rest.starts_with(b"<!DOCTYPE")
+            || rest.get(1).is_some_and(|&b| b != b'/' && b != b'!' && b != b'?');
+        if let (Some(end), true) = (root_end, depth == 0 && starts_document) {
+            return end;
+        }
+
+        i = if rest.starts_with(b"<!--") {
+            skip_past(bytes, found + 4, b"-->")
+        } else if rest.starts_with(b"<![CDATA[") {
+            skip_past(bytes, found + 9, b"]]>")
+        } else if rest.starts_with(b"<?") {
+            skip_past(bytes, found + 2, b"?>")
+        } else if rest.starts_with(b"<!") {
+            doctype_end(bytes, found + 2)
+        } else {
+            let end = tag_end(bytes, found + 1);
+            if rest.starts_with(b"</") {
+                depth = depth.saturating_sub(1);
+            } else if !bytes[found..end].ends_with(b"/>") {
+                depth += 1;
+            }
+            if depth == 0 {
+                root_end = Some(end);
+            }
+            end
+        };
+    }
+    source.len()
+}
+
+/// End of a start or end tag, skipping `>` inside quoted attribute values
+fn tag_end(bytes: &[u8], mut i: usize) -> usize {
+    let mut quote = None;
+    while i < bytes.len() {
+        match (quote, bytes[i]) {
+            (None, b'"' | b'\'') => quote = Some(bytes[i]),
+            (Some(q), b) if b == q => quote = None,
+            (None, b'>') => return i + 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+/// End of `<!DOCTYPE ...>`, including an internal subset in brackets
+fn doctype_end(bytes: &[u8], mut i: usize) -> usize {
+    let mut quote = None;
+    let mut brackets = 0usize;
+    while i < bytes.len() {
+        match (quote, bytes[i]) {
+            (None, b'"' | b'\'') => quote = Some(bytes[i]),
+            (Some(q), b) if b == q => quote = None,
+            (None, b'[') => brackets += 1,
+            (None, b']') => brackets = brackets.saturating_sub(1),
+            (None, b'>') if brackets == 0 => return i + 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+/// End of the JSON value starting at `start`
+fn json_value_end(bytes: &[u8], start: usize) -> usize {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut i = start;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if in_string {
+            match b {
+                b'\\' => i += 1,
+                b'"' => {
+                    in_string = false;
+                    if depth == 0 {
+                        return i + 1;
+                    }
+                }
+                _ => {}
+            }
+        } else {
+            match b {
+                // A scalar ends at whitespace or where the next value starts
+                b if depth == 0 && i > start && (b.is_ascii_whitespace() || b"{[\"".contains(&b)) => {
+                    return i;
+                }
+                b'"' => in_string = true,
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        return i + 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+fn find(bytes: &[u8], from: usize, needle: &[u8]) -> Option<usize> {
+    bytes
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|i| from + i)
+}
+
+fn skip_past(bytes: &[u8], from: usize, needle: &[u8]) -> usize {
+    find(bytes, from, needle).map_or(bytes.len(), |i| i + needle.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(input: &str, doc_type: DocumentType) -> Vec<&str> {
+        split_documents(input, doc_type).map(|doc| doc.source).collect()
+    }
+
+    #[test]
+    fn test_split_xml() {
+        let input = "<?xml version=\"1.0\"?>\n<a x=\"1>2\"><b/></a>\n<!-- end of a -->\n\
+                     <?xml version=\"1.0\"?>\n<!DOCTYPE c [<!ENTITY e \"]>\">]>\n<c>&e;</c><d/>\n";
+        assert_eq!(
+            sources(input, DocumentType::Xml),
+            [
+                "<?xml version=\"1.0\"?>\n<a x=\"1>2\"><b/></a>",
+                "<!-- end of a -->\n<?xml version=\"1.0\"?>\n<!DOCTYPE c [<!ENTITY e \"]>\">]>\n<c>&e;</c>",
+                "<d/>",
+            ]
+        );
+
+        let positions: Vec<_> = split_documents(input, DocumentType::Xml)
+            .map(|doc| (doc.index, doc.position.to_string()))
+            .collect();
+        assert_eq!(positions, [(0, "1:1".into()), (1, "3:1".into()), (2, "6:11".into())]);
+    }
+
+    #[test]
+    fn test_split_malformed_xml() {
+        // Stray end tags stay with their document; an unclosed root takes
+        // everything after it
+        assert_eq!(
+            sources("<a></a></x><!--c-->\n<b><c></b>\n<d/>", DocumentType::Xml),
+            ["<a></a></x>", "<!--c-->\n<b><c></b>\n<d/>"]
+        );
+        assert_eq!(sources("  \n", DocumentType::Xml), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_split_json() {
+        let input = "{\"a\": \"}\\\"\"}\n\n[1,\n 2]\n\"text\" 42 true{}\n";
+        assert_eq!(
+            sources(input, DocumentType::Json),
+            ["{\"a\": \"}\\\"\"}", "[1,\n 2]", "\"text\"", "42", "true", "{}"]
+        );
+        assert_eq!(sources("<html><p>a</p></html>\n<html></html>", DocumentType::Html).len(), 1);
+    }
+}
@@ -0,0 +1,566 @@
+//! Streaming pull parser over any `std::io::Read`
+//!
+//! [`XmlReader`] reads its input in chunks and hands out one [`Event`] at a
+//! time, so memory use is bounded by the largest single token (a tag, a
+//! comment or a run of text) rather than by the document size. Events borrow
+//! from the reader's buffer and are only valid until the next call.
+//!
+//! The reader checks that start and end tags are balanced; use the strict
+//! parser for full well-formedness checking. As in [`Document`], character
+//! data and attribute values are reported as written, entities unexpanded.
+//!
+//! [`split_records`] builds on the reader to cut a large export into small
+//! documents, one per repeated element.
+//!
+//! [`Document`]: crate::Document
+
+use crate::error::{ParseError, Position, QueryError};
+use crate::parser::encoding::{detect_encoding, Encoding};
+use crate::query::{Query, StreamQuery};
+use crate::types::{Document, DocumentType};
+use encoding_rs::{Decoder, DecoderResult};
+use std::io::{self, Read};
+
+/// Bytes requested from the underlying reader per fill
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Bytes looked at to detect the encoding before decoding starts
+const SNIFF_SIZE: usize = 1024;
+
+/// Long enough to tell every markup construct apart (`<![CDATA[`)
+const LOOKAHEAD: usize = 9;
+
+/// A parsing event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// Start tag; `empty` is set for `<tag/>`, which is followed by a matching
+    /// [`Event::EndElement`] so consumers always see balanced events
+    StartElement {
+        name: &'a str,
+        attributes: Vec<(&'a str, &'a str)>,
+        empty: bool,
+    },
+    EndElement { name: &'a str },
+    Text(&'a str),
+    /// Content of a CDATA section, without delimiters
+    CData(&'a str),
+    /// Comment markup including `<!--` and `-->`
+    Comment(&'a str),
+    ProcessingInstruction { target: &'a str, data: &'a str },
+    /// Complete `<!DOCTYPE ...>` markup
+    Doctype(&'a str),
+}
+
+/// Incremental XML reader
+pub struct XmlReader<R: Read> {
+    reader: R,
+    /// Decoder for the input encoding, created on the first read
+    decoder: Option<Decoder>,
+    encoding: Option<&'static Encoding>,
+    /// Decoded text; everything before `pos` has been consumed
+    buf: String,
+    pos: usize,
+    eof: bool,
+    /// Position of `buf[pos]` in the whole input
+    offset: usize,
+    line: usize,
+    column: usize,
+    /// Names of the open elements
+    stack: Vec<String>,
+    /// End event still to be reported for an empty element
+    pending_end: bool,
+    closed: String,
+}
+
+impl<R: Read> XmlReader<R> {
+    /// Create a reader that detects the input encoding
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            decoder: None,
+            encoding: None,
+            buf: String::new(),
+            pos: 0,
+            eof: false,
+            offset: 0,
+            line: 1,
+            column: 1,
+            stack: Vec::new(),
+            pending_end: false,
+            closed: String::new(),
+        }
+    }
+
+    /// Create a reader for input in a known encoding
+    pub fn with_encoding(reader: R, encoding: &'static Encoding) -> Self {
+        Self {
+            encoding: Some(encoding),
+            ..Self::new(reader)
+        }
+    }
+
+    /// Number of currently open elements
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Position of the next unread character
+    pub fn position(&self) -> Position {
+        Position {
+            offset: self.offset,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// Read the next event; `None` at the end of the input
+    pub fn next_event(&mut self) -> Result<Option<Event<'_>>, ParseError> {
+        if std::mem::take(&mut self.pending_end) {
+            return Ok(Some(Event::EndElement { name: &self.closed }));
+        }
+
+        // Make sure the next token is complete in the buffer
+        let end = loop {
+            if self.remaining() < LOOKAHEAD && !self.eof {
+                self.fill()?;
+                continue;
+            }
+            if self.remaining() == 0 {
+                return match self.stack.last() {
+                    Some(name) => {
+                        Err(self.error(format!("element <{}> is never closed", name)))
+                    }
+                    None => Ok(None),
+                };
+            }
+            match self.token_end() {
+                Some(end) => break end,
+                None if self.eof => {
+                    return Err(self.error("unexpected end of input inside markup".to_string()))
+                }
+                None => self.fill()?,
+            }
+        };
+
+        let start = self.pos;
+        let position = self.position();
+        self.advance(end);
+        let token = &self.buf[start..end];
+
+        if !token.starts_with('<') {
+            return Ok(Some(Event::Text(token)));
+        }
+        if token.starts_with("<!--") {
+            return Ok(Some(Event::Comment(token)));
+        }
+        if let Some(body) = token.strip_prefix("<![CDATA[") {
+            return Ok(Some(Event::CData(&body[..body.len() - 3])));
+        }
+        if token.starts_with("<!") {
+            return Ok(Some(Event::Doctype(token)));
+        }
+        if let Some(body) = token.strip_prefix("<?") {
+            let body = &body[..body.len() - 2];
+            let (target, data) = match body.find(|c: char| c.is_ascii_whitespace()) {
+                Some(i) => (&body[..i], body[i..].trim_start()),
+                None => (body, ""),
+            };
+            return Ok(Some(Event::ProcessingInstruction { target, data }));
+        }
+        if let Some(body) = token.strip_prefix("</") {
+            let name = body[..body.len() - 1].trim_end();
+            return match self.stack.pop() {
+                Some(open) if open == name => Ok(Some(Event::EndElement { name })),
+                Some(open) => Err(ParseError::NotWellFormed {
+                    position,
+                    message: format!(
+                        "mismatched end tag: expected </{}>, found </{}>",
+                        open, name
+                    ),
+                }),
+                None => Err(ParseError::NotWellFormed {
+                    position,
+                    message: format!("unexpected end tag </{}>", name),
+                }),
+            };
+        }
+
+        let empty = token.ends_with("/>");
+        let body = &token[1..token.len() - if empty { 2 } else { 1 }];
+        let name_len = body
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(body.len());
+        let name = &body[..name_len];
+        if name.is_empty() {
+            return Err(ParseError::NotWellFormed {
+                position,
+                message: "'<' must start a tag or be escaped as &lt;".to_string(),
+            });
+        }
+        let attributes = parse_attributes(&body[name_len..]);
+
+        if empty {
+            self.closed.clear();
+            self.closed.push_str(name);
+            self.pending_end = true;
+        } else {
+            self.stack.push(name.to_string());
+        }
+        Ok(Some(Event::StartElement {
+            name,
+            attributes,
+            empty,
+        }))
+    }
+
+    fn remaining(&self) -> usize {
+        self.buf.len() - self.pos
+    }
+
+    /// End of the token at `pos`, or `None` if more input is needed
+    fn token_end(&self) -> Option<usize> {
+        let rest = &self.buf[self.pos..];
+        let find = |needle: &str, from: usize| {
+            rest[from..].find(needle).map(|i| self.pos + from + i + needle.len())
+        };
+
+        if !rest.starts_with('<') {
+            return match rest.find('<') {
+                Some(i) => Some(self.pos + i),
+                None if self.eof => Some(self.buf.len()),
+                // Hand out long text runs in pieces, without splitting a reference
+                None if rest.len() >= CHUNK_SIZE => {
+                    let cut = match rest.rfind('&') {
+                        Some(i) if !rest[i..].contains(';') && i > 0 => i,
+                        _ => rest.len(),
+                    };
+                    Some(self.pos + cut)
+                }
+                None => None,
+            };
+        }
+
+        if rest.starts_with("<!--") {
+            find("-->", 4)
+        } else if rest.starts_with("<![CDATA[") {
+            find("]]>", 9)
+        } else if rest.starts_with("<?") {
+            find("?>", 2)
+        } else if rest.starts_with("<!") {
+            // DOCTYPE, possibly with an internal subset in brackets
+            let mut depth = 0usize;
+            let mut quote = None;
+            rest.char_indices().skip(2).find_map(|(i, c)| {
+                match (quote, c) {
+                    (Some(q), c) if c == q => quote = None,
+                    (Some(_), _) => {}
+                    (None, '"' | '\'') => quote = Some(c),
+                    (None, '[') => depth += 1,
+                    (None, ']') => depth = depth.saturating_sub(1),
+                    (None, '>') if depth == 0 => return Some(self.pos + i + 1),
+                    _ => {}
+                }
+                None
+            })
+        } else {
+            // Start or end tag; '>' may appear inside quoted attribute values
+            let mut quote = None;
+            rest.char_indices().skip(1).find_map(|(i, c)| {
+                match (quote, c) {
+                    (Some(q), c) if c == q => quote = None,
+                    (Some(_), _) => {}
+                    (None, '"' | '\'') => quote = Some(c),
+                    (None, '>') => return Some(self.pos + i + 1),
+                    _ => {}
+                }
+                None
+            })
+        }
+    }
+
+    /// Consume input up to `end`, keeping track of the position
+    fn advance(&mut self, end: usize) {
+        let consumed = &self.buf[self.pos..end];
+        self.offset += consumed.len();
+        match consumed.rfind('\n') {
+            Some(i) => {
+                self.line += consumed.matches('\n').count();
+                self.column = consumed[i + 1..].chars().count() + 1;
+            }
+            None => self.column += consumed.chars().count(),
+        }
+        self.pos = end;
+    }
+
+    /// Read and decode the next chunk of input
+    fn fill(&mut self) -> Result<(), ParseError> {
+        // Drop consumed text so the buffer only holds the current token
+        if self.pos > 0 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let mut len = self.read_chunk(&mut chunk)?;
+        self.eof = len == 0;
+        if self.decoder.is_none() {
+            while !self.eof && len < SNIFF_SIZE {
+                let read = self.read_chunk(&mut chunk[len..])?;
+                self.eof = read == 0;
+                len += read;
+            }
+        }
+        let chunk = &chunk[..len];
+
+        let encoding = *self.encoding.get_or_insert_with(|| detect_encoding(chunk));
+        let decoder = self
+            .decoder
+            .get_or_insert_with(|| encoding.new_decoder_with_bom_removal());
+
+        let mut input = chunk;
+        loop {
+            let needed = decoder
+                .max_utf8_buffer_length_without_replacement(input.len())
+                .unwrap_or(CHUNK_SIZE);
+            self.buf.reserve(needed);
+            let (result, read) =
+                decoder.decode_to_string_without_replacement(input, &mut self.buf, self.eof);
+            input = &input[read..];
+            match result {
+                DecoderResult::InputEmpty => return Ok(()),
+                DecoderResult::OutputFull => continue,
+                DecoderResult::Malformed(_, _) => {
+                    return Err(ParseError::EncodingError(format!(
+                        "input is not valid {} near byte {}",
+                        encoding.name(),
+                        self.offset + self.buf.len()
+                    )))
+                }
+            }
+        }
+    }
+
+    fn read_chunk(&mut self, chunk: &mut [u8]) -> Result<usize, ParseError> {
+        loop {
+            match self.reader.read(chunk) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => return Ok(result?),
+            }
+        }
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        ParseError::NotWellFormed {
+            position: self.position(),
+            message,
+        }
+    }
+}
+
+/// Split the attribute part of a start tag into name/value pairs
+fn parse_attributes(mut rest: &str) -> Vec<(&str, &str)> {
+    let mut attributes = Vec::new();
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return attributes;
+        }
+        let name_len = rest
+            .find(|c: char| c == '=' || c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let name = &rest[..name_len];
+        rest = rest[name_len..].trim_start();
+
+        let Some(after_eq) = rest.strip_prefix('=') else {
+            // Attribute without a value
+            attributes.push((name, ""));
+            continue;
+        };
+        let after_eq = after_eq.trim_start();
+        let value = match after_eq.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let len = after_eq[1..].find(quote).unwrap_or(after_eq.len() - 1);
+                rest = &after_eq[(len + 2).min(after_eq.len())..];
+                &after_eq[1..1 + len]
+            }
+            _ => {
+                let len = after_eq
+                    .find(|c: char| c.is_ascii_whitespace())
+                    .unwrap_or(after_eq.len());
+                rest = &after_eq[len..];
+                &after_eq[..len]
+            }
+        };
+        attributes.push((name, value));
+    }
+}
+
+/// One element cut out of a stream by [`split_records`]
+#[derive(Debug, Clone)]
+pub struct Record {
+    markup: String,
+}
+
+impl Record {
+    /// Markup of the element, including its start and end tag
+    pub fn markup(&self) -> &str {
+        &self.markup
+    }
+
+    /// Parse the record into a document of its own
+    pub fn document(&self) -> Document<'_> {
+        let tree = super::xml::parse(&self.markup, &super::ParseOptions::default()).tree;
+        Document::from_tree(&self.markup, tree, DocumentType::Xml)
+    }
+}
+
+/// Iterator over the records of a stream
+pub struct Records<R: Read> {
+    matches: StreamQuery<R>,
+}
+
+impl<R: Read> Iterator for Records<R> {
+    type Item = Result<Record, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.matches
+            .next()
+            .map(|result| result.map(|m| Record { markup: m.markup }))
+    }
+}
+
+/// Split a stream into records, one per element matching `element_path`
+///
+/// `element_path` is an element name (`row`, matched at any depth), a
+/// relative path (`rows/row`, a `row` in a `rows` at any depth) or one of
+/// the streaming XPath forms (`//row`, `//rows/row`, `/rows/row`). Only the
+/// record being read is held in memory.
+pub fn split_records<R: Read>(
+    reader: XmlReader<R>,
+    element_path: &str,
+) -> Result<Records<R>, QueryError> {
+    let expr = if element_path.starts_with('/') {
+        element_path.to_string()
+    } else {
+        format!("//{}", element_path)
+    };
+    Ok(Records {
+        matches: StreamQuery::new(reader, Query::XPath(&expr))?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Render events as compact strings
+    fn events(input: &[u8], chunked: bool) -> Result<Vec<String>, ParseError> {
+        // Reading one byte at a time exercises every buffer boundary
+        struct OneByte<'a>(&'a [u8]);
+        impl Read for OneByte<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let Some((&first, rest)) = self.0.split_first() else {
+                    return Ok(0);
+                };
+                buf[0] = first;
+                self.0 = rest;
+                Ok(1)
+            }
+        }
+
+        let mut reader: XmlReader<Box<dyn Read + '_>> = if chunked {
+            XmlReader::new(Box::new(OneByte(input)))
+        } else {
+            XmlReader::new(Box::new(input))
+        };
+        let mut out = Vec::new();
+        while let Some(event) = reader.next_event()? {
+            out.push(match event {
+                Event::StartElement {
+                    name,
+                    attributes,
+                    empty,
+                } => format!("start {} {:?} {}", name, attributes, empty),
+                Event::EndElement { name } => format!("end {}", name),
+                Event::Text(text) => format!("text {:?}", text),
+                Event::CData(text) => format!("cdata {:?}", text),
+                Event::Comment(text) => format!("comment {}", text),
+                Event::ProcessingInstruction { target, data } => format!("pi {} {}", target, data),
+                Event::Doctype(text) => format!("doctype {}", text),
+            });
+        }
+        Ok(out)
+    }
+
+    #[test]
+    fn test_events() {
+        let xml = b"<?xml version=\"1.0\"?>\n<!DOCTYPE r [<!ENTITY e \"x>\">]>\
+            <r a=\"1>\" b='2'><!-- c --><i/>t&amp;<![CDATA[<x>]]></r>";
+        let expected = vec![
+            "pi xml version=\"1.0\"",
+            "text \"\\n\"",
+            "doctype <!DOCTYPE r [<!ENTITY e \"x>\">]>",
+            "start r [(\"a\", \"1>\"), (\"b\", \"2\")] false",
+            "comment <!-- c -->",
+            "start i [] true",
+            "end i",
+            "text \"t&amp;\"",
+            "cdata \"<x>\"",
+            "end r",
+        ];
+        assert_eq!(events(xml, false).unwrap(), expected);
+        assert_eq!(events(xml, true).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_transcodes_input() {
+        let bytes: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain("<a>é</a>".encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        assert_eq!(
+            events(&bytes, true).unwrap(),
+            vec!["start a [] false", "text \"é\"", "end a"]
+        );
+    }
+
+    #[test]
+    fn test_unbalanced_tags() {
+        let err = events(b"<a>\n  <b></c></a>", false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "not well-formed at 2:6: mismatched end tag: expected </b>, found </c>"
+        );
+
+        let err = events(b"<a><b></b>", true).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "not well-formed at 1:11: element <a> is never closed"
+        );
+    }
+
+    #[test]
+    fn test_split_records() {
+        let xml = "<rows><row id=\"1\"><v>a</v></row>\n<row id=\"2\"><v/></row></rows>";
+        let records: Vec<Record> = split_records(XmlReader::new(xml.as_bytes()), "row")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].markup(), "<row id=\"2\"><v/></row>");
+        let doc = records[0].document();
+        assert_eq!(doc.root().attr("id").as_deref(), Some("1"));
+        assert_eq!(doc.root().text().as_deref(), Some("a"));
+
+        // A relative path matches at any depth, like an element name
+        let xml = "<db><rows><row>1</row></rows><row>2</row></db>";
+        let records: Vec<Record> = split_records(XmlReader::new(xml.as_bytes()), "rows/row")
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].markup(), "<row>1</row>");
+        assert!(split_records(XmlReader::new(xml.as_bytes()), "rows//row").is_err());
+    }
+}
@@ -0,0 +1,1131 @@
+//! Well-formedness checking XML 1.0 parser
+//!
+//! All XML documents go through this parser, which follows the XML 1.0 grammar:
+//! tags must match, there is exactly one root element, attribute names are
+//! unique and values quoted, and names only use legal characters. Parsing does
+//! not stop at the first violation; each one is recorded with its position and
+//! the parser recovers so that later problems are reported as well.
+
+use super::dtd::{
+    self, AttributeDefault, Cursor, Dtd, Entity, MAX_ENTITY_DEPTH, MAX_EXPANDED_BYTES,
+};
+use super::{Exceeded, ParseLimits, ParseOptions};
+use crate::error::{Diagnostic, LineIndex, ParseError, Severity};
+use crate::tree::{Attribute, NodeId, NodeKind, Str, Tree};
+use std::ops::Range;
+use std::path::Path;
+
+/// Result of an XML parse: the tree plus every well-formedness violation
+pub(crate) struct XmlParse {
+    pub(crate) tree: Tree,
+    pub(crate) errors: Vec<ParseError>,
+    /// Every problem the parser recovered from, errors and warnings
+    pub(crate) diagnostics: Vec<Diagnostic>,
+    /// Declarations from the DOCTYPE, if the document has one
+    pub(crate) dtd: Option<Dtd>,
+    /// A resource limit was hit and parsing was abandoned
+    pub(crate) fatal: Option<ParseError>,
+}
+
+/// Parse `source` as XML 1.0, collecting well-formedness errors
+pub(crate) fn parse(source: &str, options: &ParseOptions) -> XmlParse {
+    let mut parser = XmlParser::new(source, options.dtd_base_dir.as_deref(), &options.limits);
+    parser.keep_references = options.keep_references;
+    parser.run();
+
+    let index = LineIndex::new(source);
+    let diagnostics: Vec<_> = parser
+        .problems
+        .into_iter()
+        .map(|problem| Diagnostic {
+            severity: problem.severity,
+            code: problem.code,
+            position: index.position_in(source, problem.span.start),
+            span: problem.span,
+            message: problem.message,
+        })
+        .collect();
+    let errors = diagnostics
+        .iter()
+        .filter(|d| d.severity == Severity::Error)
+        .map(|d| ParseError::NotWellFormed {
+            position: d.position,
+            message: d.message.clone(),
+        })
+        .collect();
+    let fatal = parser
+        .fatal
+        .map(|(offset, exceeded)| exceeded.into_error(index.position_in(source, offset)));
+
+    XmlParse {
+        tree: parser.tree,
+        errors,
+        diagnostics,
+        dtd: parser.dtd,
+        fatal,
+    }
+}
+
+/// A problem found while parsing, before its position is resolved
+struct Problem {
+    severity: Severity,
+    code: &'static str,
+    span: Range<usize>,
+    message: String,
+}
+
+struct XmlParser<'s> {
+    source: &'s str,
+    bytes: &'s [u8],
+    pos: usize,
+    tree: Tree,
+    /// Open elements with the span of their name
+    stack: Vec<(NodeId, Range<usize>)>,
+    /// Every problem found, in the order they were met
+    problems: Vec<Problem>,
+    root_seen: bool,
+    doctype_seen: bool,
+    dtd: Option<Dtd>,
+    /// Directory external DTDs are loaded from; `None` disables loading
+    base_dir: Option<&'s Path>,
+    /// Parsing entity replacement text: top-level text and elements are allowed
+    fragment: bool,
+    /// Entities being expanded, innermost last
+    expanding: Vec<String>,
+    /// Replacement text produced so far, shared with nested expansions
+    expanded_bytes: usize,
+    /// Offset of the limit that stopped the parse, and which one it was
+    fatal: Option<(usize, Exceeded)>,
+    limits: &'s ParseLimits,
+    /// Length of the document, which the expansion ratio is measured against
+    input_len: usize,
+    /// Depth and node count of the document around replacement text
+    depth_base: usize,
+    node_base: usize,
+    /// Leave declared entity references and default attributes out of the tree
+    keep_references: bool,
+}
+
+impl<'s> XmlParser<'s> {
+    fn new(source: &'s str, base_dir: Option<&'s Path>, limits: &'s ParseLimits) -> Self {
+        Self {
+            source,
+            bytes: source.as_bytes(),
+            pos: 0,
+            tree: Tree::default(),
+            stack: Vec::new(),
+            problems: Vec::new(),
+            root_seen: false,
+            doctype_seen: false,
+            dtd: None,
+            base_dir,
+            fragment: false,
+            expanding: Vec::new(),
+            expanded_bytes: 0,
+            fatal: None,
+            limits,
+            input_len: source.len(),
+            depth_base: 0,
+            node_base: 0,
+            keep_references: false,
+        }
+    }
+
+    fn run(&mut self) {
+        if self.source.starts_with('\u{feff}') {
+            self.pos = '\u{feff}'.len_utf8();
+        }
+        if self.starts_with("<?xml")
+            && self
+                .bytes
+                .get(self.pos + 5)
+                .is_some_and(|&b| is_whitespace(b) || b == b'?')
+        {
+            self.parse_processing_instruction(true);
+        }
+
+        while self.pos < self.bytes.len() && self.fatal.is_none() {
+            let start = self.pos;
+            if self.bytes[self.pos] == b'<' {
+                self.parse_markup();
+            } else {
+                self.parse_text();
+            }
+            let nodes = self.node_base + self.tree.node_count();
+            self.check_limit(start, Exceeded::check(self.limits.max_nodes, nodes, Exceeded::Nodes));
+        }
+
+        while let Some((_, name)) = self.stack.pop() {
+            self.unclosed(name);
+        }
+        if !self.root_seen && !self.fragment {
+            self.error("no-root-element", self.bytes.len(), "document has no root element");
+        }
+    }
+
+    fn parse_markup(&mut self) {
+        if self.starts_with("<!--") {
+            self.parse_comment();
+        } else if self.starts_with("<![CDATA[") {
+            self.parse_cdata();
+        } else if self.starts_with("<!DOCTYPE") {
+            self.parse_doctype();
+        } else if self.starts_with("<?") {
+            self.parse_processing_instruction(false);
+        } else if self.starts_with("</") {
+            self.parse_end_tag();
+        } else if self.starts_with("<!") {
+            self.error("invalid-markup", self.pos, "unknown markup declaration");
+            self.skip_past(b'>');
+        } else {
+            self.parse_start_tag();
+        }
+    }
+
+    fn parse_text(&mut self) {
+        let start = self.pos;
+        let end = self.find_byte(b'<').unwrap_or(self.bytes.len());
+        self.pos = end;
+
+        self.check_chars(start..end);
+        self.check_references(start..end);
+        if let Some(i) = self.source[start..end].find("]]>") {
+            self.error("invalid-text", start + i, "']]>' is not allowed in character data");
+        }
+
+        let parent = match self.stack.last() {
+            Some(&(parent, _)) => Some(parent),
+            None if self.fragment => None,
+            None => {
+                if let Some(i) = self.bytes[start..end]
+                    .iter()
+                    .position(|&b| !is_whitespace(b))
+                {
+                    self.error("content-outside-root", start + i, "text is not allowed outside the root element");
+                }
+                return;
+            }
+        };
+        self.append_text(parent, start..end);
+    }
+
+    /// Record the first limit exceeded, which stops the parse
+    fn check_limit(&mut self, at: usize, exceeded: Option<Exceeded>) -> bool {
+        match exceeded {
+            Some(exceeded) if self.fatal.is_none() => {
+                self.fatal = Some((at, exceeded));
+                true
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// Append character data, replacing references to internal entities
+    fn append_text(&mut self, parent: Option<NodeId>, range: Range<usize>) {
+        let source = self.source;
+        let mut text = String::new();
+        if self.expand_text(&source[range.clone()], Some(range.start), 0, parent, &mut text) {
+            self.flush_text(parent, &mut text, range.start);
+        } else {
+            let exceeded = Exceeded::check(self.limits.max_text_length, range.len(), Exceeded::TextLength);
+            if !self.check_limit(range.start, exceeded) {
+                self.tree.append(parent, NodeKind::Text(Str::Span(range)));
+            }
+        }
+    }
+
+    /// Write character data with internal entities replaced to `text`
+    ///
+    /// Works like [`Self::expand_attribute_text`]; elements from replacement
+    /// text are added under `parent`, after flushing `text`.
+    fn expand_text(
+        &mut self,
+        data: &str,
+        offset: Option<usize>,
+        at: usize,
+        parent: Option<NodeId>,
+        text: &mut String,
+    ) -> bool {
+        let mut copied = 0;
+        let mut i = 0;
+        while let Some(found) = data[i..].find('&') {
+            let amp = i + found;
+            i = amp + 1;
+            let Some(len) = data[amp..].find(';') else {
+                continue;
+            };
+            let name = &data[amp + 1..amp + len];
+            let error_at = offset.map_or(at, |offset| offset + amp);
+            let value = match self.declared_entity(name) {
+                Some(Entity::Internal(value)) => value,
+                Some(Entity::External {
+                    notation: Some(_), ..
+                }) => {
+                    self.error("invalid-entity-reference", error_at, format!("reference to unparsed entity '&{};'", name));
+                    continue;
+                }
+                // External parsed entities are not loaded and stay as written
+                _ => continue,
+            };
+
+            text.push_str(&data[copied..amp]);
+            copied = amp + len + 1;
+            i = copied;
+            self.expand_in_content(name, &value, error_at, parent, text);
+            if self.fatal.is_some() {
+                return true;
+            }
+        }
+
+        if copied == 0 {
+            return false;
+        }
+        text.push_str(&data[copied..]);
+        true
+    }
+
+    /// Expand an entity reference in content at offset `at`
+    ///
+    /// Character data is collected in `text`; replacement text containing
+    /// markup is parsed and its nodes are added under `parent`.
+    fn expand_in_content(
+        &mut self,
+        name: &str,
+        value: &str,
+        at: usize,
+        parent: Option<NodeId>,
+        text: &mut String,
+    ) {
+        if !self.enter_entity(name, at, value.len()) {
+            return;
+        }
+        // Without markup the replacement text is character data
+        if !value.contains('<') {
+            if !self.expand_text(value, None, at, parent, text) {
+                text.push_str(value);
+            }
+            self.expanding.pop();
+            return;
+        }
+
+        let mut sub = XmlParser::new(value, self.base_dir, self.limits);
+        sub.fragment = true;
+        sub.input_len = self.input_len;
+        sub.depth_base = self.depth_base + self.stack.len();
+        sub.node_base = self.node_base + self.tree.node_count();
+        sub.dtd = self.dtd.take();
+        sub.expanding = std::mem::take(&mut self.expanding);
+        sub.expanded_bytes = self.expanded_bytes;
+        sub.run();
+        self.dtd = sub.dtd.take();
+        self.expanding = std::mem::take(&mut sub.expanding);
+        self.expanded_bytes = sub.expanded_bytes;
+        self.expanding.pop();
+
+        if let Some((_, exceeded)) = sub.fatal {
+            self.fatal = Some((at, exceeded));
+            return;
+        }
+        // Problems inside replacement text are reported at the reference
+        for problem in sub.problems {
+            self.problems.push(Problem {
+                span: at..at + name.len() + 2,
+                message: format!("in entity '&{};': {}", name, problem.message),
+                ..problem
+            });
+        }
+        for &id in sub.tree.roots() {
+            match &sub.tree.get(id).kind {
+                NodeKind::Text(s) => text.push_str(s.resolve(value)),
+                _ => {
+                    self.flush_text(parent, text, at);
+                    self.graft(&sub.tree, value, id, parent);
+                }
+            }
+        }
+    }
+
+    /// Track entering an entity; `false` on recursion or when a limit is hit
+    fn enter_entity(&mut self, name: &str, at: usize, len: usize) -> bool {
+        if self.expanding.iter().any(|n| n == name) {
+            self.error("recursive-entity", at, format!("entity '&{};' references itself", name));
+            return false;
+        }
+        if self.expanding.len() >= MAX_ENTITY_DEPTH {
+            let message = format!("entities nested more than {} levels deep", MAX_ENTITY_DEPTH);
+            self.fatal = Some((at, Exceeded::Expansion(message)));
+            return false;
+        }
+        // Count empty replacements too, so that expansion work stays bounded
+        self.expanded_bytes += len.max(1);
+        if self.expanded_bytes > MAX_EXPANDED_BYTES {
+            let message = format!("expansion exceeds {} bytes", MAX_EXPANDED_BYTES);
+            self.fatal = Some((at, Exceeded::Expansion(message)));
+            return false;
+        }
+        if let Some(budget) = self.limits.expansion_budget(self.input_len) {
+            if self.expanded_bytes > budget {
+                let ratio = self.limits.max_expansion_ratio.unwrap_or_default();
+                let message = format!("expansion exceeds {} times the input size", ratio);
+                self.fatal = Some((at, Exceeded::Expansion(message)));
+                return false;
+            }
+        }
+        self.expanding.push(name.to_string());
+        true
+    }
+
+    /// Copy a node parsed from entity replacement text into the tree
+    fn graft(&mut self, from: &Tree, source: &str, id: NodeId, parent: Option<NodeId>) {
+        let owned = |s: &Str| Str::Owned(s.resolve(source).into());
+        let kind = match &from.get(id).kind {
+            NodeKind::Element { name, attributes } => NodeKind::Element {
+                name: owned(name),
+                attributes: attributes
+                    .iter()
+                    .map(|a| Attribute {
+                        name: owned(&a.name),
+                        value: a.value.as_ref().map(owned),
+                    })
+                    .collect(),
+            },
+            NodeKind::Text(s) => NodeKind::Text(owned(s)),
+            NodeKind::CData(s) => NodeKind::CData(owned(s)),
+            NodeKind::Comment(s) => NodeKind::Comment(owned(s)),
+            NodeKind::ProcessingInstruction { target, data } => NodeKind::ProcessingInstruction {
+                target: owned(target),
+                data: owned(data),
+            },
+            NodeKind::Doctype(s) => NodeKind::Doctype(owned(s)),
+        };
+        let new = self.tree.append(parent, kind);
+        for &child in &from.get(id).children {
+            self.graft(from, source, child, Some(new));
+        }
+    }
+
+    fn flush_text(&mut self, parent: Option<NodeId>, text: &mut String, at: usize) {
+        let exceeded = Exceeded::check(self.limits.max_text_length, text.len(), Exceeded::TextLength);
+        if self.check_limit(at, exceeded) {
+            return;
+        }
+        if !text.is_empty() {
+            let text = std::mem::take(text);
+            self.tree
+                .append(parent, NodeKind::Text(Str::Owned(text.into_boxed_str())));
+        }
+    }
+
+    /// A general entity declared in the DTD; predefined entities are never expanded
+    fn declared_entity(&self, name: &str) -> Option<Entity> {
+        if self.keep_references || PREDEFINED_ENTITIES.contains(&name) {
+            return None;
+        }
+        self.dtd.as_ref()?.entities.get(name).cloned()
+    }
+
+    fn parse_start_tag(&mut self) {
+        let tag_start = self.pos;
+        self.pos += 1;
+        let Some(name) = self.parse_name() else {
+            // Treat the '<' as (invalid) character data and carry on
+            self.error("invalid-tag", tag_start, "'<' must start a tag or be escaped as &lt;");
+            return;
+        };
+
+        if self.stack.is_empty() && self.root_seen && !self.fragment {
+            self.error("multiple-roots", tag_start, "document must have a single root element");
+        }
+        let depth = self.depth_base + self.stack.len() + 1;
+        if self.check_limit(tag_start, Exceeded::check(self.limits.max_depth, depth, Exceeded::Depth)) {
+            return;
+        }
+
+        let mut attributes: Vec<Attribute> = Vec::new();
+        let mut self_closing = false;
+        loop {
+            let had_whitespace = self.skip_whitespace();
+            match self.bytes.get(self.pos) {
+                None => {
+                    let message = format!("unexpected end of input in tag <{}>", &self.source[name.clone()]);
+                    self.error("invalid-tag", tag_start, message);
+                    break;
+                }
+                Some(b'>') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b'/') if self.bytes.get(self.pos + 1) == Some(&b'>') => {
+                    self.pos += 2;
+                    self_closing = true;
+                    break;
+                }
+                Some(_) => {
+                    if !had_whitespace {
+                        self.error("bad-attribute", self.pos, "attributes must be separated by whitespace");
+                    }
+                    let attr_start = self.pos;
+                    if let Some(attr) = self.parse_attribute() {
+                        let attr_name = attr.name.resolve(self.source);
+                        if attributes
+                            .iter()
+                            .any(|a| a.name.resolve(self.source) == attr_name)
+                        {
+                            let message = format!("duplicate attribute '{}'", attr_name);
+                            self.error_span("duplicate-attribute", attr_start..self.pos, message);
+                        } else {
+                            attributes.push(attr);
+                        }
+                    }
+                    if self.fatal.is_some() {
+                        return;
+                    }
+                    let exceeded = Exceeded::check(self.limits.max_attributes, attributes.len(), Exceeded::Attributes);
+                    if self.check_limit(attr_start, exceeded) {
+                        return;
+                    }
+                }
+            }
+        }
+
+        self.add_default_attributes(name.clone(), &mut attributes);
+
+        let parent = self.stack.last().map(|&(id, _)| id);
+        let id = self.tree.append(
+            parent,
+            NodeKind::Element {
+                name: Str::Span(name.clone()),
+                attributes,
+            },
+        );
+        self.root_seen = true;
+        if !self_closing {
+            self.stack.push((id, name));
+        }
+    }
+
+    /// Parse `name="value"`; returns `None` when nothing usable was found
+    fn parse_attribute(&mut self) -> Option<Attribute> {
+        let Some(name) = self.parse_name() else {
+            self.error("bad-attribute", self.pos, "invalid attribute name");
+            // Skip the offending token
+            self.pos += self.source[self.pos..].chars().next().map_or(1, char::len_utf8);
+            while self
+                .bytes
+                .get(self.pos)
+                .is_some_and(|&b| !is_whitespace(b) && b != b'>' && b != b'/')
+            {
+                self.pos += 1;
+            }
+            return None;
+        };
+
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) != Some(&b'=') {
+            let message = format!("attribute '{}' has no value", &self.source[name.clone()]);
+            self.error_span("bad-attribute", name.clone(), message);
+            return Some(Attribute {
+                name: Str::Span(name),
+                value: None,
+            });
+        }
+        self.pos += 1;
+        self.skip_whitespace();
+
+        let value = match self.bytes.get(self.pos) {
+            Some(&quote) if quote == b'"' || quote == b'\'' => {
+                let start = self.pos + 1;
+                match self.bytes[start..].iter().position(|&b| b == quote) {
+                    Some(len) => {
+                        self.pos = start + len + 1;
+                        start..start + len
+                    }
+                    None => {
+                        self.error("bad-attribute", self.pos, "unterminated attribute value");
+                        self.pos = self.bytes.len();
+                        return None;
+                    }
+                }
+            }
+            _ => {
+                let message = format!("value of attribute '{}' must be quoted", &self.source[name.clone()]);
+                self.error("bad-attribute", self.pos, message);
+                let start = self.pos;
+                while self.bytes.get(self.pos).is_some_and(|&b| {
+                    !is_whitespace(b) && b != b'>' && !self.bytes[self.pos..].starts_with(b"/>")
+                }) {
+                    self.pos += 1;
+                }
+                start..self.pos
+            }
+        };
+
+        if let Some(i) = self.bytes[value.clone()].iter().position(|&b| b == b'<') {
+            self.error("bad-attribute", value.start + i, "'<' is not allowed in attribute values");
+        }
+        self.check_chars(value.clone());
+        self.check_references(value.clone());
+
+        let mut expanded = String::new();
+        let source = self.source;
+        let value_start = value.start;
+        let value = if self.expand_attribute_text(&source[value.clone()], Some(value.start), 0, &mut expanded) {
+            Str::Owned(expanded.into_boxed_str())
+        } else {
+            Str::Span(value)
+        };
+        let len = value.resolve(source).len();
+        let exceeded = Exceeded::check(self.limits.max_attribute_length, len, Exceeded::AttributeLength);
+        if self.check_limit(value_start, exceeded) {
+            return None;
+        }
+
+        Some(Attribute {
+            name: Str::Span(name),
+            value: Some(value),
+        })
+    }
+
+    /// Write `text` with internal entities replaced to `out`
+    ///
+    /// `offset` is the position of `text` in the source, or `None` for
+    /// replacement text, whose errors are reported at `at`. Returns whether
+    /// anything was replaced; if not, nothing is written.
+    fn expand_attribute_text(
+        &mut self,
+        text: &str,
+        offset: Option<usize>,
+        at: usize,
+        out: &mut String,
+    ) -> bool {
+        let mut copied = 0;
+        let mut i = 0;
+        while let Some(found) = text[i..].find('&') {
+            let amp = i + found;
+            i = amp + 1;
+            let Some(len) = text[amp..].find(';') else {
+                continue;
+            };
+            let name = &text[amp + 1..amp + len];
+            let error_at = offset.map_or(at, |offset| offset + amp);
+            let value = match self.declared_entity(name) {
+                Some(Entity::Internal(value)) => value,
+                Some(Entity::External { .. }) => {
+                    let message = format!("attribute values cannot reference external entity '&{};'", name);
+                    self.error("invalid-entity-reference", error_at, message);
+                    continue;
+                }
+                None => continue,
+            };
+
+            out.push_str(&text[copied..amp]);
+            copied = amp + len + 1;
+            i = copied;
+            if !self.enter_entity(name, error_at, value.len()) {
+                if self.fatal.is_some() {
+                    return true;
+                }
+                continue;
+            }
+            if value.contains('<') {
+                let message = format!("'<' is not allowed in attribute values (in entity '&{};')", name);
+                self.error("bad-attribute", error_at, message);
+            }
+            if !self.expand_attribute_text(&value, None, error_at, out) {
+                out.push_str(&value);
+            }
+            self.expanding.pop();
+        }
+
+        if copied == 0 {
+            return false;
+        }
+        out.push_str(&text[copied..]);
+        true
+    }
+
+    /// Add attributes with a default or fixed value in the DTD that are missing
+    fn add_default_attributes(&self, element: Range<usize>, attributes: &mut Vec<Attribute>) {
+        if self.keep_references {
+            return;
+        }
+        let Some(declared) = self
+            .dtd
+            .as_ref()
+            .and_then(|dtd| dtd.attributes.get(&self.source[element]))
+        else {
+            return;
+        };
+        for decl in declared {
+            let (AttributeDefault::Value(value) | AttributeDefault::Fixed(value)) = &decl.default
+            else {
+                continue;
+            };
+            if !attributes
+                .iter()
+                .any(|a| a.name.resolve(self.source) == decl.name)
+            {
+                attributes.push(Attribute {
+                    name: Str::Owned(decl.name.as_str().into()),
+                    value: Some(Str::Owned(value.as_str().into())),
+                });
+            }
+        }
+    }
+
+    fn parse_end_tag(&mut self) {
+        let tag_start = self.pos;
+        self.pos += 2;
+        let Some(name) = self.parse_name() else {
+            self.error("invalid-tag", tag_start, "invalid end tag");
+            self.skip_past(b'>');
+            return;
+        };
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&b'>') {
+            self.pos += 1;
+        } else {
+            self.error("invalid-tag", self.pos, "expected '>' to close the end tag");
+            self.skip_past(b'>');
+        }
+
+        let name = &self.source[name];
+        match self
+            .stack
+            .iter()
+            .rposition(|(_, open)| &self.source[open.clone()] == name)
+        {
+            Some(i) if i + 1 == self.stack.len() => {
+                self.stack.pop();
+            }
+            Some(i) => {
+                let (_, open) = self.stack.last().cloned().expect("stack is not empty");
+                let message = format!(
+                    "mismatched end tag: expected </{}>, found </{}>",
+                    &self.source[open],
+                    name
+                );
+                self.error_span("mismatched-tag", tag_start..self.pos, message);
+                while self.stack.len() > i + 1 {
+                    let (_, open) = self.stack.pop().expect("stack is not empty");
+                    self.unclosed(open);
+                }
+                self.stack.pop();
+            }
+            None => {
+                let message = match self.stack.last() {
+                    Some((_, open)) => format!(
+                        "mismatched end tag: expected </{}>, found </{}>",
+                        &self.source[open.clone()],
+                        name
+                    ),
+                    None => format!("unexpected end tag </{}>", name),
+                };
+                self.error_span("stray-end-tag", tag_start..self.pos, message);
+            }
+        }
+    }
+
+    /// Report an element left open, at the name in its start tag
+    fn unclosed(&mut self, name: Range<usize>) {
+        let message = format!("element <{}> is never closed", &self.source[name.clone()]);
+        self.error_span("unclosed-tag", name.start - 1..name.end, message);
+    }
+
+    fn parse_comment(&mut self) {
+        let start = self.pos;
+        let content_start = start + 4;
+        let content_end = match self.source[content_start..].find("-->") {
+            Some(i) => content_start + i,
+            None => {
+                self.error("invalid-comment", start, "unterminated comment");
+                self.bytes.len()
+            }
+        };
+        self.pos = (content_end + 3).min(self.bytes.len());
+
+        let content = &self.source[content_start..content_end];
+        if let Some(i) = content.find("--") {
+            self.error("invalid-comment", content_start + i, "'--' is not allowed inside comments");
+        } else if content.ends_with('-') {
+            self.error("invalid-comment", content_end - 1, "comment must not end with '--->'");
+        }
+        self.check_chars(content_start..content_end);
+
+        let parent = self.stack.last().map(|&(id, _)| id);
+        self.tree
+            .append(parent, NodeKind::Comment(Str::Span(start..self.pos)));
+    }
+
+    fn parse_cdata(&mut self) {
+        let start = self.pos;
+        let content_start = start + "<![CDATA[".len();
+        let content_end = match self.source[content_start..].find("]]>") {
+            Some(i) => content_start + i,
+            None => {
+                self.error("invalid-cdata", start, "unterminated CDATA section");
+                self.bytes.len()
+            }
+        };
+        self.pos = (content_end + 3).min(self.bytes.len());
+        self.check_chars(content_start..content_end);
+
+        let len = content_end - content_start;
+        if self.check_limit(start, Exceeded::check(self.limits.max_text_length, len, Exceeded::TextLength)) {
+            return;
+        }
+        match self.stack.last() {
+            Some(&(parent, _)) => {
+                self.tree.append(
+                    Some(parent),
+                    NodeKind::CData(Str::Span(content_start..content_end)),
+                );
+            }
+            None => self.error("content-outside-root", start, "CDATA section is not allowed outside the root element"),
+        }
+    }
+
+    fn parse_processing_instruction(&mut self, is_declaration: bool) {
+        let start = self.pos;
+        self.pos += 2;
+        let Some(target) = self.parse_name() else {
+            self.error("invalid-pi", self.pos, "invalid processing instruction target");
+            self.skip_past_str("?>");
+            return;
+        };
+        if !is_declaration && self.source[target.clone()].eq_ignore_ascii_case("xml") {
+            self.error("xml-declaration", start, "XML declaration is only allowed at the start of the document");
+        }
+
+        let had_whitespace = self.skip_whitespace();
+        let data_start = self.pos;
+        let data_end = match self.source[data_start..].find("?>") {
+            Some(i) => data_start + i,
+            None => {
+                self.error("invalid-pi", start, "unterminated processing instruction");
+                self.bytes.len()
+            }
+        };
+        if !had_whitespace && data_start != data_end {
+            self.error("invalid-pi", data_start, "expected whitespace after processing instruction target");
+        }
+        self.pos = (data_end + 2).min(self.bytes.len());
+        self.check_chars(data_start..data_end);
+        if is_declaration {
+            self.check_xml_declaration(data_start..data_end);
+        }
+
+        let parent = self.stack.last().map(|&(id, _)| id);
+        self.tree.append(
+            parent,
+            NodeKind::ProcessingInstruction {
+                target: Str::Span(target),
+                data: Str::Span(data_start..data_end),
+            },
+        );
+    }
+
+    /// Validate `version`, `encoding` and `standalone` pseudo-attributes
+    fn check_xml_declaration(&mut self, range: Range<usize>) {
+        let mut fields = Vec::new();
+        let mut rest = &self.source[range.clone()];
+        while !rest.trim_start().is_empty() {
+            let offset = range.end - rest.len();
+            let Some((name, after)) = rest.trim_start().split_once('=') else {
+                self.error("xml-declaration", offset, "malformed XML declaration");
+                return;
+            };
+            let after = after.trim_start();
+            let quote = after.chars().next().filter(|&q| q == '"' || q == '\'');
+            let Some((value, remaining)) = quote.and_then(|q| after[1..].split_once(q)) else {
+                self.error("xml-declaration", offset, "malformed XML declaration");
+                return;
+            };
+            fields.push((name.trim().to_string(), value.to_string(), offset));
+            rest = remaining;
+        }
+
+        const ORDER: [&str; 3] = ["version", "encoding", "standalone"];
+        let mut next = 0;
+        for (name, value, offset) in fields {
+            let Some(index) = ORDER[next..].iter().position(|&o| o == name) else {
+                self.error("xml-declaration", offset, format!("unexpected '{}' in XML declaration", name));
+                return;
+            };
+            if next == 0 && index != 0 {
+                self.error("xml-declaration", offset, "XML declaration must start with version");
+                return;
+            }
+            next += index + 1;
+
+            let valid = match name.as_str() {
+                "version" => value
+                    .strip_prefix("1.")
+                    .is_some_and(|minor| !minor.is_empty() && minor.bytes().all(|b| b.is_ascii_digit())),
+                "encoding" => {
+                    value.starts_with(|c: char| c.is_ascii_alphabetic())
+                        && value
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+                }
+                _ => value == "yes" || value == "no",
+            };
+            if !valid {
+                self.error("xml-declaration", offset, format!("invalid {} '{}' in XML declaration", name, value));
+            }
+        }
+        if next == 0 {
+            self.error("xml-declaration", range.start, "XML declaration must specify a version");
+        }
+    }
+
+    fn parse_doctype(&mut self) {
+        let start = self.pos;
+        if self.root_seen || !self.stack.is_empty() {
+            self.error("invalid-doctype", start, "DOCTYPE must appear before the root element");
+        } else if self.doctype_seen {
+            self.error("invalid-doctype", start, "only one DOCTYPE declaration is allowed");
+        }
+        self.doctype_seen = true;
+
+        self.pos += "<!DOCTYPE".len();
+        let name = self
+            .skip_whitespace()
+            .then(|| self.parse_name())
+            .flatten();
+        if name.is_none() {
+            self.error("invalid-doctype", self.pos, "DOCTYPE must name the root element");
+        }
+
+        let mut external_id = None;
+        if self.skip_whitespace() {
+            let mut cursor = Cursor::new(&self.source[self.pos..]);
+            if let Some(id) = dtd::parse_external_id(&mut cursor) {
+                self.pos += cursor.position();
+                external_id = Some(id);
+            }
+        }
+
+        // Find the closing '>', skipping quoted literals, comments and the internal subset
+        let mut subset_start = None;
+        let mut subset = None;
+        let mut closed = false;
+        while self.pos < self.bytes.len() {
+            let in_subset = subset_start.is_some() && subset.is_none();
+            match self.bytes[self.pos] {
+                quote @ (b'"' | b'\'') => {
+                    self.pos += 1;
+                    self.skip_past(quote);
+                    continue;
+                }
+                b'<' if in_subset && self.starts_with("<!--") => {
+                    self.skip_past_str("-->");
+                    continue;
+                }
+                b'[' if subset_start.is_none() => subset_start = Some(self.pos + 1),
+                b']' if in_subset => subset = subset_start.map(|start| start..self.pos),
+                b'>' if !in_subset => {
+                    self.pos += 1;
+                    closed = true;
+                    break;
+                }
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        if !closed {
+            self.error("invalid-doctype", start, "unterminated DOCTYPE declaration");
+        }
+
+        let parent = self.stack.last().map(|&(id, _)| id);
+        self.tree
+            .append(parent, NodeKind::Doctype(Str::Span(start..self.pos)));
+
+        if self.dtd.is_some() || self.fragment {
+            return;
+        }
+        let mut dtd = Dtd::default();
+        if let Some(name) = name {
+            dtd.name = self.source[name].to_string();
+        }
+        let mut errors = Vec::new();
+        if let Some(range) = subset {
+            let text = &self.source[range.clone()];
+            dtd.parse_internal_subset(text, range.start, self.base_dir, &mut errors);
+        }
+        // The internal subset is read first so its declarations take precedence
+        if let Some((_, system_id)) = external_id {
+            dtd.parse_external_subset(&system_id, start, self.base_dir, &mut errors);
+        }
+        for (offset, message) in errors {
+            self.error("invalid-dtd", offset, message);
+        }
+        self.dtd = Some(dtd);
+    }
+
+    /// Check `&...;` references in text or an attribute value
+    fn check_references(&mut self, range: Range<usize>) {
+        let mut i = range.start;
+        while let Some(offset) = self.bytes[i..range.end].iter().position(|&b| b == b'&') {
+            let amp = i + offset;
+            i = amp + 1;
+
+            let Some(len) = self.bytes[amp..range.end].iter().position(|&b| b == b';') else {
+                self.error("invalid-reference", amp, "'&' must start an entity or character reference");
+                continue;
+            };
+            let reference = &self.source[amp + 1..amp + len];
+            if let Some(number) = reference.strip_prefix('#') {
+                let code = match number.strip_prefix('x') {
+                    Some(hex) => u32::from_str_radix(hex, 16),
+                    None => number.parse::<u32>(),
+                };
+                let valid = code
+                    .ok()
+                    .filter(|_| !number.starts_with('+'))
+                    .and_then(char::from_u32)
+                    .is_some_and(is_xml_char);
+                if !valid {
+                    self.error("invalid-reference", amp, format!("invalid character reference '&{};'", reference));
+                }
+            } else if !is_name(reference) {
+                self.error("invalid-reference", amp, "'&' must start an entity or character reference");
+                continue;
+            } else if !PREDEFINED_ENTITIES.contains(&reference) && !self.may_be_declared(reference) {
+                let message = format!("undefined entity '&{};'", reference);
+                self.error_span("undefined-entity", amp..amp + len + 1, message);
+            } else if self.dtd.as_ref().is_some_and(|dtd| dtd.incomplete && !dtd.entities.contains_key(reference)) {
+                let message = format!("entity '&{};' is not expanded because the DTD was not loaded", reference);
+                self.problems.push(Problem {
+                    severity: Severity::Warning,
+                    code: "unexpanded-entity",
+                    span: amp..amp + len + 1,
+                    message,
+                });
+            }
+            i = amp + len + 1;
+        }
+    }
+
+    /// Whether an entity is declared, or may be in a DTD that was not read
+    fn may_be_declared(&self, name: &str) -> bool {
+        self.dtd
+            .as_ref()
+            .is_some_and(|dtd| dtd.incomplete || dtd.entities.contains_key(name))
+    }
+
+    /// Report characters outside the XML `Char` production
+    fn check_chars(&mut self, range: Range<usize>) {
+        let bytes = &self.bytes[range.clone()];
+        for (i, &b) in bytes.iter().enumerate() {
+            let illegal = match b {
+                b'\t' | b'\n' | b'\r' => false,
+                0..=0x1F => true,
+                // U+FFFE and U+FFFF are encoded as EF BF BE / EF BF BF
+                0xEF => bytes.get(i + 1) == Some(&0xBF) && matches!(bytes.get(i + 2), Some(0xBE | 0xBF)),
+                _ => false,
+            };
+            if illegal {
+                let c = self.source[range.start + i..].chars().next().unwrap_or_default();
+                self.error("invalid-character", range.start + i, format!("illegal character U+{:04X}", c as u32));
+            }
+        }
+    }
+
+    /// Parse an XML `Name` at the current position
+    fn parse_name(&mut self) -> Option<Range<usize>> {
+        let start = self.pos;
+        let mut chars = self.source[start..].char_indices();
+        match chars.next() {
+            Some((_, c)) if is_name_start_char(c) => {}
+            _ => return None,
+        }
+        let len = chars
+            .find(|&(_, c)| !is_name_char(c))
+            .map_or(self.bytes.len() - start, |(i, _)| i);
+        self.pos = start + len;
+        Some(start..self.pos)
+    }
+
+    fn skip_whitespace(&mut self) -> bool {
+        let start = self.pos;
+        while self.bytes.get(self.pos).is_some_and(|&b| is_whitespace(b)) {
+            self.pos += 1;
+        }
+        self.pos > start
+    }
+
+    fn skip_past(&mut self, byte: u8) {
+        self.pos = self
+            .find_byte(byte)
+            .map_or(self.bytes.len(), |i| i + 1);
+    }
+
+    fn skip_past_str(&mut self, needle: &str) {
+        self.pos = self.source[self.pos..]
+            .find(needle)
+            .map_or(self.bytes.len(), |i| self.pos + i + needle.len());
+    }
+
+    fn find_byte(&self, byte: u8) -> Option<usize> {
+        self.bytes[self.pos..]
+            .iter()
+            .position(|&b| b == byte)
+            .map(|i| self.pos + i)
+    }
+
+    fn starts_with(&self, prefix: &str) -> bool {
+        self.bytes[self.pos..].starts_with(prefix.as_bytes())
+    }
+
+    /// Record an error at the character starting at `offset`
+    fn error(&mut self, code: &'static str, offset: usize, message: impl Into<String>) {
+        let len = self.source[offset..].chars().next().map_or(0, char::len_utf8);
+        self.error_span(code, offset..offset + len, message);
+    }
+
+    fn error_span(&mut self, code: &'static str, span: Range<usize>, message: impl Into<String>) {
+        self.problems.push(Problem {
+            severity: Severity::Error,
+            code,
+            span,
+            message: message.into(),
+        });
+    }
+}
+
+const PREDEFINED_ENTITIES: [&str; 5] = ["lt", "gt", "amp", "quot", "apos"];
+
+fn is_whitespace(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+/// XML 1.0 `Char` production
+fn is_xml_char(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\r' | '\u{20}'..='\u{D7FF}' | '\u{E000}'..='\u{FFFD}' | '\u{10000}'..='\u{10FFFF}')
+}
+
+/// XML 1.0 `NameStartChar` production
+pub(crate) fn is_name_start_char(c: char) -> bool {
+    matches!(c,
+        ':' | 'A'..='Z' | '_' | 'a'..='z'
+        | '\u{C0}'..='\u{D6}' | '\u{D8}'..='\u{F6}' | '\u{F8}'..='\u{2FF}'
+        | '\u{370}'..='\u{37D}' | '\u{37F}'..='\u{1FFF}' | '\u{200C}'..='\u{200D}'
+        | '\u{2070}'..='\u{218F}' | '\u{2C00}'..='\u{2FEF}' | '\u{3001}'..='\u{D7FF}'
+        | '\u{F900}'..='\u{FDCF}' | '\u{FDF0}'..='\u{FFFD}' | '\u{10000}'..='\u{EFFFF}')
+}
+
+/// XML 1.0 `NameChar` production
+pub(crate) fn is_name_char(c: char) -> bool {
+    is_name_start_char(c)
+        || matches!(c,
+            '-' | '.' | '0'..='9' | '\u{B7}'
+            | '\u{300}'..='\u{36F}' | '\u{203F}'..='\u{2040}')
+}
+
+/// Check that `s` is a complete XML `Name`
+pub(crate) fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(is_name_start_char) && chars.all(is_name_char)
+}
@@ -1,17 +1,26 @@
 //! Query execution engine for CSS selectors and XPath-like expressions
 
-use crate::error::QueryError;
-use crate::types::{Document, NodeRef};
-use tl::ParserOptions;
+pub(crate) mod xpath;
+
+use crate::error::{ParseError, QueryError};
+use crate::parser::stream::{Event, XmlReader};
+use crate::parser::xml::{is_name_char, is_name_start_char};
+use crate::types::{Document, NodeRef, NodeType};
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use tl::queryselector::Selector;
 
 /// Query specification (type-safe)
 #[derive(Debug, Clone)]
 pub enum Query<'q> {
-    /// XPath-like expression (multiple results)
-    /// Currently supports simple patterns like //tag, //tag[@attr='value'], /root/child
+    /// XPath 1.0 expression (multiple results)
+    ///
+    /// Prefixes declared on the root element can be used, and unprefixed
+    /// element names are in its default namespace. Streaming supports only
+    /// `//tag`, `//tag[@attr='value']`, `//parent/child` and `/root/child`.
     XPath(&'q str),
 
-    /// XPath-like expression (single result only)
+    /// XPath 1.0 expression (single result only)
     Extract(&'q str),
 
     /// CSS selector (uses tl's query selector)
@@ -48,98 +57,190 @@ pub fn execute_query<'doc, 'input>(
     _options: &QueryOptions,
 ) -> Result<QueryIter<'doc, 'input>, QueryError> {
     match query {
-        Query::XPath(expr) | Query::Extract(expr) => execute_xpath_like(doc, expr),
+        Query::XPath(expr) | Query::Extract(expr) => match evaluate(doc, expr, false)? {
+            XPathResult::Nodes(nodes) => Ok(nodes),
+            XPathResult::Values(_) => Err(QueryError::ExecutionError(format!(
+                "{} does not select nodes",
+                expr
+            ))),
+        },
         Query::CssSelector(selector) => execute_css_selector(doc, selector),
     }
 }
 
+/// Result of an XPath expression
+pub enum XPathResult<'doc, 'input> {
+    /// Selected nodes, in document order; an attribute among other nodes
+    /// comes as its element, as do all attributes for [`execute_query`]
+    Nodes(QueryIter<'doc, 'input>),
+    /// The values of selected attributes, or the string value of an
+    /// expression that does not select nodes, like `count(//item)`
+    Values(Vec<String>),
+}
+
+/// Evaluate an XPath 1.0 expression against a document
+pub fn evaluate_xpath<'doc, 'input>(
+    doc: &'doc Document<'input>,
+    expr: &str,
+) -> Result<XPathResult<'doc, 'input>, QueryError> {
+    evaluate(doc, expr, true)
+}
+
+/// Evaluate an expression; without `attribute_values`, selected attributes
+/// always come as their elements
+fn evaluate<'doc, 'input>(
+    doc: &'doc Document<'input>,
+    expr: &str,
+    attribute_values: bool,
+) -> Result<XPathResult<'doc, 'input>, QueryError> {
+    let xpath = xpath::XPath::compile(expr, &root_namespaces(doc))?;
+    let ctx = xpath::Context::new(doc);
+    let items = match xpath.evaluate(&ctx, xpath::Item::Root)? {
+        xpath::Value::Nodes(items) => items,
+        value => return Ok(XPathResult::Values(vec![value.string(&ctx)])),
+    };
+    let attributes = !items.is_empty()
+        && items.iter().all(|item| matches!(item, xpath::Item::Attribute(..)));
+    if attribute_values && attributes {
+        let values = items.into_iter().map(|item| ctx.string_value(item)).collect();
+        return Ok(XPathResult::Values(values));
+    }
+
+    // The document node stands for its root element
+    let mut nodes: Vec<NodeRef<'doc, 'input>> = Vec::new();
+    for item in items {
+        let node = item.node().unwrap_or_else(|| doc.root());
+        if nodes.last().and_then(|n| n.id()) != node.id() {
+            nodes.push(node);
+        }
+    }
+    Ok(XPathResult::Nodes(QueryIter {
+        inner: Box::new(nodes.into_iter()),
+    }))
+}
+
+/// Namespaces for queries: the prefixes declared on the root element, and
+/// its default namespace for unprefixed element names
+fn root_namespaces(doc: &Document<'_>) -> HashMap<String, String> {
+    let mut namespaces = HashMap::new();
+    for (name, value) in doc.root().attributes() {
+        if name == "xmlns" {
+            namespaces.insert(String::new(), value.into_owned());
+        } else if let Some(prefix) = name.strip_prefix("xmlns:") {
+            namespaces.insert(prefix.to_string(), value.into_owned());
+        }
+    }
+    namespaces
+}
+
 /// Execute CSS selector query
 fn execute_css_selector<'doc, 'input>(
     doc: &'doc Document<'input>,
     selector: &str,
 ) -> Result<QueryIter<'doc, 'input>, QueryError> {
-    // Use tl's query selector
-    let vdom = doc.vdom();
-    let _parser_options = ParserOptions::default();
-
-    // Query all matching nodes
-    let results: Vec<NodeRef<'doc, 'input>> = vdom
-        .query_selector(selector)
-        .ok_or_else(|| QueryError::InvalidSelector(selector.to_string()))?
-        .map(|handle| NodeRef::new(vdom, Some(handle.clone())))
-        .collect();
+    // Use tl's selector parser and match against our own tree
+    let selector = tl::parse_query_selector(selector)
+        .ok_or_else(|| QueryError::InvalidSelector(selector.to_string()))?;
+
+    // Query all matching nodes in document order
+    let mut results: Vec<NodeRef<'doc, 'input>> = Vec::new();
+    for node in doc.children() {
+        find_by_selector_impl(node, &selector, &mut results);
+    }
 
     Ok(QueryIter {
         inner: Box::new(results.into_iter()),
     })
 }
 
-/// Execute XPath-like query
-/// This is a simplified implementation that supports common patterns
-fn execute_xpath_like<'doc, 'input>(
-    doc: &'doc Document<'input>,
-    expr: &str,
-) -> Result<QueryIter<'doc, 'input>, QueryError> {
-    let vdom = doc.vdom();
+fn find_by_selector_impl<'a, 'input>(
+    node: NodeRef<'a, 'input>,
+    selector: &Selector<'_>,
+    results: &mut Vec<NodeRef<'a, 'input>>,
+) {
+    if node.node_type() != NodeType::Element {
+        return;
+    }
 
-    // Parse the expression
-    let pattern = parse_xpath_pattern(expr)?;
+    if selector_matches(selector, node) {
+        results.push(node);
+    }
+
+    for child in node.children() {
+        find_by_selector_impl(child, selector, results);
+    }
+}
+
+/// Check whether an element matches a parsed CSS selector
+fn selector_matches(selector: &Selector<'_>, node: NodeRef<'_, '_>) -> bool {
+    let bytes_eq = |value: Option<std::borrow::Cow<'_, str>>, expected: &[u8]| {
+        value.is_some_and(|v| v.as_bytes() == expected)
+    };
+    let attr_check = |attr: &[u8], value: &[u8], check: fn(&str, &str) -> bool| {
+        let (Ok(attr), Ok(value)) = (std::str::from_utf8(attr), std::str::from_utf8(value)) else {
+            return false;
+        };
+        node.attr(attr).is_some_and(|actual| check(&actual, value))
+    };
 
-    // Execute based on pattern type
-    let results: Vec<NodeRef<'doc, 'input>> = match pattern {
-        XPathPattern::DescendantTag(tag) => {
-            // //tag pattern - find all descendants with this tag
-            find_descendants_by_tag(vdom, doc.root(), &tag)
+    match selector {
+        Selector::Tag(tag) => bytes_eq(node.tag_name(), tag),
+        Selector::Id(id) => bytes_eq(node.attr("id"), id),
+        Selector::Class(class) => node.attr("class").is_some_and(|classes| {
+            classes
+                .split_whitespace()
+                .any(|c| c.as_bytes() == *class)
+        }),
+        Selector::All => true,
+        Selector::And(a, b) => selector_matches(a, node) && selector_matches(b, node),
+        Selector::Or(a, b) => selector_matches(a, node) || selector_matches(b, node),
+        Selector::Descendant(ancestor, inner) => {
+            selector_matches(inner, node)
+                && std::iter::successors(node.parent(), |p| p.parent())
+                    .any(|p| p.node_type() == NodeType::Element && selector_matches(ancestor, p))
         }
-        XPathPattern::DescendantAttr(tag, attr, value) => {
-            // //tag[@attr='value'] pattern
-            find_descendants_by_attr(vdom, doc.root(), &tag, &attr, &value)
+        Selector::Parent(parent, inner) => {
+            selector_matches(inner, node)
+                && node.parent().is_some_and(|p| {
+                    p.node_type() == NodeType::Element && selector_matches(parent, p)
+                })
         }
-        XPathPattern::AttributeValue(path, attr) => {
-            // /path/to/tag/@attr pattern
-            let root = doc.root();
-            if !path.is_empty() && root.tag_name().as_deref() == Some(path[0].as_str()) {
-                find_attribute_value(vdom, root, &path[1..], &attr)
-            } else {
-                vec![]
-            }
+        Selector::Attribute(attr) => std::str::from_utf8(attr).is_ok_and(|attr| node.has_attr(attr)),
+        Selector::AttributeValue(attr, value) => attr_check(attr, value, |a, v| a == v),
+        Selector::AttributeValueWhitespacedContains(attr, value) => {
+            attr_check(attr, value, |a, v| a.split_whitespace().any(|x| x == v))
         }
-        XPathPattern::AbsolutePath(path) => {
-            // /root/child/grandchild pattern
-            let root = doc.root();
-            if !path.is_empty() && root.tag_name().as_deref() == Some(path[0].as_str()) {
-                find_by_absolute_path(vdom, root, &path[1..])
-            } else {
-                vec![]
-            }
+        Selector::AttributeValueStartsWith(attr, value) => {
+            attr_check(attr, value, |a, v| a.starts_with(v))
         }
-    };
-
-    Ok(QueryIter {
-        inner: Box::new(results.into_iter()),
-    })
+        Selector::AttributeValueEndsWith(attr, value) => {
+            attr_check(attr, value, |a, v| a.ends_with(v))
+        }
+        Selector::AttributeValueSubstring(attr, value) => {
+            attr_check(attr, value, |a, v| a.contains(v))
+        }
+    }
 }
 
-/// Simplified XPath patterns we support
+/// The XPath patterns a stream can be queried with
 #[derive(Debug)]
 enum XPathPattern {
     /// //tag
     DescendantTag(String),
+    /// //parent/child: the last step at any depth, below the ones before
+    DescendantPath(Vec<String>),
     /// //tag[@attr='value']
     DescendantAttr(String, String, String),
-    /// /path/@attr
-    AttributeValue(Vec<String>, String),
     /// /root/child/grandchild
     AbsolutePath(Vec<String>),
 }
 
-/// Parse XPath expression into a pattern
-fn parse_xpath_pattern(expr: &str) -> Result<XPathPattern, QueryError> {
+/// Parse XPath expression into a pattern, if it is one
+fn parse_xpath_pattern(expr: &str) -> Option<XPathPattern> {
     let expr = expr.trim();
 
-    if expr.starts_with("//") {
+    if let Some(rest) = expr.strip_prefix("//") {
         // Descendant pattern
-        let rest = &expr[2..];
 
         if let Some(bracket_pos) = rest.find('[') {
             // Has predicate: //tag[@attr='value']
@@ -149,34 +250,39 @@ fn parse_xpath_pattern(expr: &str) -> Result<XPathPattern, QueryError> {
             if let Some(end) = predicate.find(']') {
                 let pred_inner = &predicate[..end];
                 if let Some((attr, value)) = parse_attribute_predicate(pred_inner) {
-                    return Ok(XPathPattern::DescendantAttr(tag, attr, value));
+                    return Some(XPathPattern::DescendantAttr(tag, attr, value));
                 }
             }
 
-            Err(QueryError::InvalidXPath(expr.to_string()))
+            None
+        } else if rest.contains('/') {
+            // Path below any element: //parent/child
+            let steps: Vec<String> = rest.split('/').map(String::from).collect();
+            steps
+                .iter()
+                .all(|step| is_name_step(step))
+                .then_some(XPathPattern::DescendantPath(steps))
         } else {
             // Simple tag: //tag
-            Ok(XPathPattern::DescendantTag(rest.to_string()))
-        }
-    } else if expr.starts_with('/') && expr.contains("/@") {
-        // Attribute extraction: /path/@attr
-        let parts: Vec<&str> = expr.split("/@").collect();
-        if parts.len() == 2 {
-            let path = parts[0][1..].split('/').map(String::from).collect();
-            let attr = parts[1].to_string();
-            Ok(XPathPattern::AttributeValue(path, attr))
-        } else {
-            Err(QueryError::InvalidXPath(expr.to_string()))
+            is_name_step(rest).then(|| XPathPattern::DescendantTag(rest.to_string()))
         }
-    } else if expr.starts_with('/') {
+    } else if let Some(rest) = expr.strip_prefix('/') {
         // Absolute path: /root/child
-        let path: Vec<String> = expr[1..].split('/').map(String::from).collect();
-        Ok(XPathPattern::AbsolutePath(path))
+        let path: Vec<String> = rest.split('/').map(String::from).collect();
+        path.iter()
+            .all(|step| is_name_step(step))
+            .then_some(XPathPattern::AbsolutePath(path))
     } else {
-        Err(QueryError::InvalidXPath(expr.to_string()))
+        None
     }
 }
 
+/// Whether a path step is an element name, matched literally
+fn is_name_step(step: &str) -> bool {
+    let mut chars = step.chars();
+    chars.next().is_some_and(is_name_start_char) && chars.all(is_name_char)
+}
+
 /// Parse attribute predicate like @attr='value' or @attr="value"
 fn parse_attribute_predicate(pred: &str) -> Option<(String, String)> {
     if !pred.starts_with('@') {
@@ -201,127 +307,205 @@ fn parse_attribute_predicate(pred: &str) -> Option<(String, String)> {
     None
 }
 
-/// Find all descendants with a given tag name
-fn find_descendants_by_tag<'a, 'input>(
-    vdom: &'a tl::VDom<'input>,
-    node: NodeRef<'a, 'input>,
-    tag: &str,
-) -> Vec<NodeRef<'a, 'input>> {
-    let mut results = Vec::new();
-    find_descendants_by_tag_impl(vdom, node, tag, &mut results);
-    results
+/// A query result taken from a stream
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StreamMatch {
+    /// Text content, as [`NodeRef::text`] would return it
+    pub text: String,
+    /// Element markup, as [`NodeRef::outer_html`] would return it
+    pub markup: String,
 }
 
-fn find_descendants_by_tag_impl<'a, 'input>(
-    vdom: &'a tl::VDom<'input>,
-    node: NodeRef<'a, 'input>,
-    tag: &str,
-    results: &mut Vec<NodeRef<'a, 'input>>,
-) {
-    // node.tag_name() returns Option<Cow>, use as_deref to compare with &str
-    if node.tag_name().as_deref() == Some(tag) {
-        results.push(node);
-    }
-
-    for child in node.children() {
-        find_descendants_by_tag_impl(vdom, child, tag, results);
-    }
+/// Query evaluated over a stream of parser events
+///
+/// Supports the XPath patterns that can be decided when a start tag is seen:
+/// `//tag`, `//tag[@attr='value']`, `//parent/child` and `/root/child`. Only
+/// the elements being matched are held in memory.
+pub struct StreamQuery<R: Read> {
+    reader: XmlReader<R>,
+    pattern: XPathPattern,
+    /// Names of the open elements
+    path: Vec<String>,
+    /// Matches still being collected, innermost last
+    captures: Vec<Capture>,
+    /// Completed matches nested in a match that is still open
+    finished: Vec<(usize, StreamMatch)>,
+    ready: VecDeque<StreamMatch>,
+    matched: usize,
+    done: bool,
 }
 
-/// Find all descendants with a tag and specific attribute value
-fn find_descendants_by_attr<'a, 'input>(
-    vdom: &'a tl::VDom<'input>,
-    node: NodeRef<'a, 'input>,
-    tag: &str,
-    attr: &str,
-    value: &str,
-) -> Vec<NodeRef<'a, 'input>> {
-    let mut results = Vec::new();
-    find_descendants_by_attr_impl(vdom, node, tag, attr, value, &mut results);
-    results
+struct Capture {
+    /// Match number, to restore document order for nested matches
+    index: usize,
+    depth: usize,
+    result: StreamMatch,
+    /// The last start tag still needs its closing `>` or `/>`
+    open: bool,
 }
 
-fn find_descendants_by_attr_impl<'a, 'input>(
-    vdom: &'a tl::VDom<'input>,
-    node: NodeRef<'a, 'input>,
-    tag: &str,
-    attr: &str,
-    value: &str,
-    results: &mut Vec<NodeRef<'a, 'input>>,
-) {
-    if node.tag_name().as_deref() == Some(tag) && node.attr(attr).as_deref() == Some(value) {
-        results.push(node);
+impl<R: Read> StreamQuery<R> {
+    /// Prepare a query; fails for patterns that need the whole document
+    pub fn new(reader: XmlReader<R>, query: Query<'_>) -> Result<Self, QueryError> {
+        let expr = match query {
+            Query::XPath(expr) | Query::Extract(expr) => expr,
+            Query::CssSelector(selector) => {
+                return Err(QueryError::UnsupportedStreaming(selector.to_string()))
+            }
+        };
+        let pattern = parse_xpath_pattern(expr)
+            .ok_or_else(|| QueryError::UnsupportedStreaming(expr.to_string()))?;
+
+        Ok(Self {
+            reader,
+            pattern,
+            path: Vec::new(),
+            captures: Vec::new(),
+            finished: Vec::new(),
+            ready: VecDeque::new(),
+            matched: 0,
+            done: false,
+        })
     }
 
-    for child in node.children() {
-        find_descendants_by_attr_impl(vdom, child, tag, attr, value, results);
-    }
-}
+    /// Process one event; returns `false` at the end of the input
+    fn step(&mut self) -> Result<bool, ParseError> {
+        let Some(event) = self.reader.next_event()? else {
+            return Ok(false);
+        };
+
+        match event {
+            Event::StartElement {
+                name, attributes, ..
+            } => {
+                self.path.push(name.to_string());
+                let matched = match &self.pattern {
+                    XPathPattern::DescendantTag(tag) => name == tag,
+                    XPathPattern::DescendantAttr(tag, attr, value) => {
+                        name == tag && attributes.iter().any(|(a, v)| a == attr && v == value)
+                    }
+                    XPathPattern::DescendantPath(steps) => self.path.ends_with(steps),
+                    XPathPattern::AbsolutePath(steps) => self.path == *steps,
+                };
+                if matched {
+                    self.captures.push(Capture {
+                        index: self.matched,
+                        depth: self.path.len(),
+                        result: StreamMatch::default(),
+                        open: false,
+                    });
+                    self.matched += 1;
+                }
 
-/// Find nodes by absolute path and extract attribute values
-fn find_attribute_value<'a, 'input>(
-    vdom: &'a tl::VDom<'input>,
-    node: NodeRef<'a, 'input>,
-    path: &[String],
-    attr: &str,
-) -> Vec<NodeRef<'a, 'input>> {
-    let mut results = Vec::new();
-    find_attribute_value_impl(vdom, node, path, attr, &mut results);
-    results
-}
+                for capture in &mut self.captures {
+                    let markup = capture.begin_content();
+                    markup.push('<');
+                    markup.push_str(name);
+                    for (attr, value) in &attributes {
+                        let quote = if value.contains('"') { '\'' } else { '"' };
+                        markup.push(' ');
+                        markup.push_str(attr);
+                        markup.push('=');
+                        markup.push(quote);
+                        markup.push_str(value);
+                        markup.push(quote);
+                    }
+                    capture.open = true;
+                }
+            }
+            Event::EndElement { name } => {
+                for capture in &mut self.captures {
+                    if std::mem::take(&mut capture.open) {
+                        capture.result.markup.push_str("/>");
+                    } else {
+                        capture.result.markup.push_str("</");
+                        capture.result.markup.push_str(name);
+                        capture.result.markup.push('>');
+                    }
+                }
 
-fn find_attribute_value_impl<'a, 'input>(
-    vdom: &'a tl::VDom<'input>,
-    node: NodeRef<'a, 'input>,
-    path: &[String],
-    attr: &str,
-    results: &mut Vec<NodeRef<'a, 'input>>,
-) {
-    if path.is_empty() {
-        // We're at the target node, create a pseudo-node with the attribute value
-        if node.attr(attr).is_some() {
-            // For attribute values, we return the node itself
-            // The caller will extract the attribute value
-            results.push(node);
+                if self
+                    .captures
+                    .last()
+                    .is_some_and(|capture| capture.depth == self.path.len())
+                {
+                    let capture = self.captures.pop().unwrap();
+                    self.finished.push((capture.index, capture.result));
+                    if self.captures.is_empty() {
+                        self.finished.sort_by_key(|(index, _)| *index);
+                        self.ready
+                            .extend(self.finished.drain(..).map(|(_, result)| result));
+                    }
+                }
+                self.path.pop();
+            }
+            Event::Text(text) => {
+                for capture in &mut self.captures {
+                    capture.begin_content().push_str(text);
+                    capture.result.text.push_str(text);
+                }
+            }
+            Event::CData(text) => {
+                for capture in &mut self.captures {
+                    let markup = capture.begin_content();
+                    markup.push_str("<![CDATA[");
+                    markup.push_str(text);
+                    markup.push_str("]]>");
+                    capture.result.text.push_str(text);
+                }
+            }
+            Event::Comment(comment) => {
+                for capture in &mut self.captures {
+                    capture.begin_content().push_str(comment);
+                }
+            }
+            Event::ProcessingInstruction { target, data } => {
+                for capture in &mut self.captures {
+                    let markup = capture.begin_content();
+                    markup.push_str("<?");
+                    markup.push_str(target);
+                    if !data.is_empty() {
+                        markup.push(' ');
+                        markup.push_str(data);
+                    }
+                    markup.push_str("?>");
+                }
+            }
+            Event::Doctype(_) => {}
         }
-        return;
+        Ok(true)
     }
+}
 
-    // Match the first path component
-    let target = &path[0];
-    for child in node.children() {
-        if child.tag_name().as_deref() == Some(target.as_str()) {
-            find_attribute_value_impl(vdom, child, &path[1..], attr, results);
+impl Capture {
+    /// Markup buffer, with a pending start tag closed
+    fn begin_content(&mut self) -> &mut String {
+        if std::mem::take(&mut self.open) {
+            self.result.markup.push('>');
         }
+        &mut self.result.markup
     }
 }
 
-/// Find nodes by absolute path
-fn find_by_absolute_path<'a, 'input>(
-    vdom: &'a tl::VDom<'input>,
-    node: NodeRef<'a, 'input>,
-    path: &[String],
-) -> Vec<NodeRef<'a, 'input>> {
-    let mut results = Vec::new();
-    find_by_absolute_path_impl(vdom, node, path, &mut results);
-    results
-}
+impl<R: Read> Iterator for StreamQuery<R> {
+    type Item = Result<StreamMatch, ParseError>;
 
-fn find_by_absolute_path_impl<'a, 'input>(
-    vdom: &'a tl::VDom<'input>,
-    node: NodeRef<'a, 'input>,
-    path: &[String],
-    results: &mut Vec<NodeRef<'a, 'input>>,
-) {
-    if path.is_empty() {
-        results.push(node);
-        return;
-    }
-
-    let target = &path[0];
-    for child in node.children() {
-        if child.tag_name().as_deref() == Some(target.as_str()) {
-            find_by_absolute_path_impl(vdom, child, &path[1..], results);
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(result) = self.ready.pop_front() {
+                return Some(Ok(result));
+            }
+            if self.done {
+                return None;
+            }
+            match self.step() {
+                Ok(true) => {}
+                Ok(false) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
         }
     }
 }
@@ -399,7 +583,70 @@ mod tests {
         let pattern = parse_xpath_pattern("//tag[@attr='value']").unwrap();
         assert!(matches!(pattern, XPathPattern::DescendantAttr(_, _, _)));
 
-        let pattern = parse_xpath_pattern("/root/child/@attr").unwrap();
-        assert!(matches!(pattern, XPathPattern::AttributeValue(_, _)));
+        assert!(parse_xpath_pattern("/root/child/@attr").is_none());
+        assert!(parse_xpath_pattern("count(//tag)").is_none());
+
+        let pattern = parse_xpath_pattern("//parent/child").unwrap();
+        assert!(
+            matches!(pattern, XPathPattern::DescendantPath(steps) if steps == ["parent", "child"])
+        );
+        assert!(parse_xpath_pattern("//parent//child").is_none());
+    }
+
+    #[test]
+    fn test_stream_query_matches_tree_query() {
+        let xml = r#"<root><item id="1"><name>A</name></item><group><item id="2"><item>nested</item></item></group><item/></root>"#;
+        let doc = Document::parse(xml, DocumentType::Xml).unwrap();
+
+        for expr in ["//item", "/root/item", "//item[@id='2']", "//group/item", "//item/item"] {
+            let expected: Vec<_> = execute_query(&doc, Query::XPath(expr), &QueryOptions::default())
+                .unwrap()
+                .map(|node| (node.text().unwrap(), node.outer_html()))
+                .collect();
+            let streamed: Vec<_> = StreamQuery::new(XmlReader::new(xml.as_bytes()), Query::XPath(expr))
+                .unwrap()
+                .map(|result| {
+                    let result = result.unwrap();
+                    (result.text, result.markup)
+                })
+                .collect();
+            assert_eq!(streamed, expected, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn test_stream_query_unsupported() {
+        let reader = || XmlReader::new("<a/>".as_bytes());
+        assert!(matches!(
+            StreamQuery::new(reader(), Query::CssSelector("a")),
+            Err(QueryError::UnsupportedStreaming(_))
+        ));
+        assert!(matches!(
+            StreamQuery::new(reader(), Query::XPath("/a/@b")),
+            Err(QueryError::UnsupportedStreaming(_))
+        ));
+    }
+
+    #[test]
+    fn test_xpath_expressions() {
+        let xml = r#"<root xmlns="urn:r" xmlns:x="urn:x"><item id="a">1</item><x:item id="b">2</x:item></root>"#;
+        let doc = Document::parse(xml, DocumentType::Xml).unwrap();
+        let values = |expr| match evaluate_xpath(&doc, expr).unwrap() {
+            XPathResult::Values(values) => values,
+            XPathResult::Nodes(nodes) => nodes.filter_map(|node| node.text()).collect(),
+        };
+
+        assert_eq!(values("count(//item)"), ["1"]);
+        assert_eq!(values("//item | //x:item"), ["1", "2"]);
+        assert_eq!(values("//*[@id = 'b']/@id"), ["b"]);
+        assert_eq!(values("/root/item[1] = 1"), ["true"]);
+        assert!(matches!(
+            execute_query(&doc, Query::XPath("count(//item)"), &QueryOptions::default()),
+            Err(QueryError::ExecutionError(_))
+        ));
+        assert!(matches!(
+            evaluate_xpath(&doc, "//y:item"),
+            Err(QueryError::InvalidXPath(_))
+        ));
     }
 }
@@ -0,0 +1,1584 @@
+//! XPath 1.0 expression evaluation
+//!
+//! Evaluates complete XPath 1.0 expressions: every axis except `namespace`,
+//! node tests, predicates, variables, arithmetic, comparisons and the core
+//! function library. [`Query::XPath`](super::Query) queries and Schematron
+//! rules are evaluated with it; streaming queries use the pattern matcher
+//! of the parent module instead, which decides a match at the start tag.
+
+use crate::error::QueryError;
+use crate::parser::dtd::AttributeType;
+use crate::parser::xml::{is_name_char, is_name_start_char};
+use crate::tree::NodeId;
+use crate::types::{Document, NodeRef, NodeType};
+use crate::validate::{is_namespace_declaration, lookup_namespace, split_qname, unescape};
+use std::collections::HashMap;
+
+/// A node as seen by XPath
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Item<'a, 'i> {
+    /// The document node above the root element
+    Root,
+    Node(NodeRef<'a, 'i>),
+    /// Attribute `index` of an element, counted as in [`NodeRef::attributes`]
+    Attribute(NodeRef<'a, 'i>, usize),
+}
+
+impl<'a, 'i> Item<'a, 'i> {
+    /// The element an attribute belongs to, or the node itself
+    pub(crate) fn node(&self) -> Option<NodeRef<'a, 'i>> {
+        match *self {
+            Item::Root => None,
+            Item::Node(node) | Item::Attribute(node, _) => Some(node),
+        }
+    }
+}
+
+/// Result of evaluating an expression
+#[derive(Debug, Clone)]
+pub(crate) enum Value<'a, 'i> {
+    /// Nodes in document order, without duplicates
+    Nodes(Vec<Item<'a, 'i>>),
+    Boolean(bool),
+    Number(f64),
+    String(String),
+}
+
+impl<'a, 'i> Value<'a, 'i> {
+    pub(crate) fn boolean(&self) -> bool {
+        match self {
+            Value::Nodes(nodes) => !nodes.is_empty(),
+            Value::Boolean(b) => *b,
+            Value::Number(n) => *n != 0.0 && !n.is_nan(),
+            Value::String(s) => !s.is_empty(),
+        }
+    }
+
+    pub(crate) fn string(&self, ctx: &Context<'a, 'i>) -> String {
+        match self {
+            Value::Nodes(nodes) => nodes.first().map(|&n| ctx.string_value(n)).unwrap_or_default(),
+            Value::Boolean(b) => b.to_string(),
+            Value::Number(n) => format_number(*n),
+            Value::String(s) => s.clone(),
+        }
+    }
+
+    pub(crate) fn number(&self, ctx: &Context<'a, 'i>) -> f64 {
+        match self {
+            Value::Number(n) => *n,
+            Value::Boolean(b) => f64::from(u8::from(*b)),
+            Value::String(s) => parse_number(s),
+            Value::Nodes(_) => parse_number(&self.string(ctx)),
+        }
+    }
+
+    pub(crate) fn into_nodes(self) -> Result<Vec<Item<'a, 'i>>, QueryError> {
+        match self {
+            Value::Nodes(nodes) => Ok(nodes),
+            other => Err(QueryError::ExecutionError(format!(
+                "expected a node-set, found {}",
+                other.type_name()
+            ))),
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nodes(_) => "a node-set",
+            Value::Boolean(_) => "a boolean",
+            Value::Number(_) => "a number",
+            Value::String(_) => "a string",
+        }
+    }
+}
+
+/// Document and variable bindings expressions are evaluated against
+pub(crate) struct Context<'a, 'i> {
+    doc: &'a Document<'i>,
+    /// Preorder index of every node, for sorting into document order
+    order: HashMap<NodeId, usize>,
+    variables: HashMap<String, Value<'a, 'i>>,
+}
+
+impl<'a, 'i> Context<'a, 'i> {
+    pub(crate) fn new(doc: &'a Document<'i>) -> Self {
+        let mut ctx = Self {
+            doc,
+            order: HashMap::new(),
+            variables: HashMap::new(),
+        };
+        let mut pending: Vec<_> = ctx.children(Item::Root).into_iter().rev().collect();
+        while let Some(item) = pending.pop() {
+            if let Some(id) = item.node().and_then(|n| n.id()) {
+                let next = ctx.order.len() + 1;
+                ctx.order.insert(id, next);
+            }
+            pending.extend(ctx.children(item).into_iter().rev());
+        }
+        ctx
+    }
+
+    pub(crate) fn set_variable(&mut self, name: &str, value: Value<'a, 'i>) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    pub(crate) fn remove_variable(&mut self, name: &str) {
+        self.variables.remove(name);
+    }
+
+    /// Sort key in document order; attributes follow their element
+    pub(crate) fn key(&self, item: Item<'_, '_>) -> (usize, usize) {
+        let order = |node: NodeRef<'_, '_>| node.id().and_then(|id| self.order.get(&id)).copied();
+        match item {
+            Item::Root => (0, 0),
+            Item::Node(node) => (order(node).unwrap_or(usize::MAX), 0),
+            Item::Attribute(node, index) => (order(node).unwrap_or(usize::MAX), index + 1),
+        }
+    }
+
+    fn sort(&self, items: &mut Vec<Item<'a, 'i>>) {
+        items.sort_by_key(|&item| self.key(item));
+        items.dedup_by_key(|item| self.key(*item));
+    }
+
+    /// The string-value of a node
+    pub(crate) fn string_value(&self, item: Item<'a, 'i>) -> String {
+        match item {
+            Item::Attribute(node, index) => node
+                .attributes()
+                .nth(index)
+                .map(|(_, value)| unescape(&value).into_owned())
+                .unwrap_or_default(),
+            Item::Node(node) => match node.node_type() {
+                NodeType::Text => unescape(&node.text().unwrap_or_default()).into_owned(),
+                NodeType::CData => node.text().unwrap_or_default(),
+                NodeType::Comment => {
+                    let markup = node.comment().unwrap_or_default();
+                    let content = markup.strip_prefix("<!--").unwrap_or(&markup);
+                    content.strip_suffix("-->").unwrap_or(content).to_string()
+                }
+                NodeType::ProcessingInstruction => node
+                    .processing_instruction()
+                    .map(|(_, data)| data.into_owned())
+                    .unwrap_or_default(),
+                _ => self.text_descendants(item),
+            },
+            Item::Root => self.text_descendants(item),
+        }
+    }
+
+    fn text_descendants(&self, item: Item<'a, 'i>) -> String {
+        let mut text = String::new();
+        for child in self.children(item) {
+            match child.node().map(|n| n.node_type()) {
+                Some(NodeType::Text | NodeType::CData) => text.push_str(&self.string_value(child)),
+                Some(NodeType::Element) => text.push_str(&self.text_descendants(child)),
+                _ => {}
+            }
+        }
+        text
+    }
+
+    /// Qualified name as written (`name()`)
+    pub(crate) fn name(&self, item: Item<'_, '_>) -> String {
+        match item {
+            Item::Root => String::new(),
+            Item::Attribute(node, index) => node
+                .attributes()
+                .nth(index)
+                .map(|(name, _)| name.into_owned())
+                .unwrap_or_default(),
+            Item::Node(node) => match node.node_type() {
+                NodeType::Element => node.tag_name().unwrap_or_default().into_owned(),
+                NodeType::ProcessingInstruction => node
+                    .processing_instruction()
+                    .map(|(target, _)| target.into_owned())
+                    .unwrap_or_default(),
+                _ => String::new(),
+            },
+        }
+    }
+
+    fn namespace_uri(&self, item: Item<'_, '_>) -> String {
+        match item {
+            Item::Node(node) if node.node_type() == NodeType::Element => {
+                let name = self.name(item);
+                lookup_namespace(node, split_qname(&name).0).unwrap_or_default()
+            }
+            Item::Attribute(node, _) => {
+                let name = self.name(item);
+                match name.split_once(':') {
+                    Some((prefix, _)) => lookup_namespace(node, prefix).unwrap_or_default(),
+                    None => String::new(),
+                }
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn children(&self, item: Item<'a, 'i>) -> Vec<Item<'a, 'i>> {
+        let is_child = |node: &NodeRef<'_, '_>| {
+            !matches!(node.node_type(), NodeType::Doctype | NodeType::Raw)
+        };
+        match item {
+            // The lenient parser can wrap the document in a nameless element
+            Item::Root => self
+                .doc
+                .children()
+                .flat_map(|node| match node.tag_name() {
+                    Some(name) if name.is_empty() => node.children().collect(),
+                    _ => vec![node],
+                })
+                .filter(is_child)
+                .map(Item::Node)
+                .collect(),
+            Item::Node(node) if node.node_type() == NodeType::Element => {
+                node.children().filter(is_child).map(Item::Node).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn parent(&self, item: Item<'a, 'i>) -> Option<Item<'a, 'i>> {
+        match item {
+            Item::Root => None,
+            Item::Node(node) => Some(node.parent().map_or(Item::Root, Item::Node)),
+            Item::Attribute(node, _) => Some(Item::Node(node)),
+        }
+    }
+
+    fn attributes(&self, item: Item<'a, 'i>) -> Vec<Item<'a, 'i>> {
+        match item {
+            Item::Node(node) => node
+                .attributes()
+                .enumerate()
+                .filter(|(_, (name, _))| !is_namespace_declaration(name))
+                .map(|(index, _)| Item::Attribute(node, index))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn descendants(&self, item: Item<'a, 'i>, out: &mut Vec<Item<'a, 'i>>) {
+        for child in self.children(item) {
+            out.push(child);
+            self.descendants(child, out);
+        }
+    }
+
+    /// Siblings after (`following`) or before (`!following`, nearest first)
+    fn siblings(&self, item: Item<'a, 'i>, following: bool) -> Vec<Item<'a, 'i>> {
+        let (Item::Node(_), Some(parent)) = (item, self.parent(item)) else {
+            return Vec::new();
+        };
+        let key = self.key(item);
+        let siblings = self.children(parent);
+        let index = siblings.iter().position(|&s| self.key(s) == key).unwrap_or(0);
+        if following {
+            siblings[index + 1..].to_vec()
+        } else {
+            siblings[..index].iter().rev().copied().collect()
+        }
+    }
+
+    /// Nodes of an axis, in proximity order
+    fn axis(&self, axis: Axis, item: Item<'a, 'i>) -> Vec<Item<'a, 'i>> {
+        let mut out = Vec::new();
+        match axis {
+            Axis::Child => out = self.children(item),
+            Axis::Descendant => self.descendants(item, &mut out),
+            Axis::DescendantOrSelf => {
+                out.push(item);
+                self.descendants(item, &mut out);
+            }
+            Axis::Parent => out.extend(self.parent(item)),
+            Axis::Ancestor | Axis::AncestorOrSelf => {
+                if axis == Axis::AncestorOrSelf {
+                    out.push(item);
+                }
+                let mut current = item;
+                while let Some(parent) = self.parent(current) {
+                    out.push(parent);
+                    current = parent;
+                }
+            }
+            Axis::FollowingSibling => out = self.siblings(item, true),
+            Axis::PrecedingSibling => out = self.siblings(item, false),
+            Axis::Following => {
+                let mut current = item;
+                if let Item::Attribute(node, _) = item {
+                    current = Item::Node(node);
+                    self.descendants(current, &mut out);
+                }
+                loop {
+                    for sibling in self.siblings(current, true) {
+                        out.push(sibling);
+                        self.descendants(sibling, &mut out);
+                    }
+                    match self.parent(current) {
+                        Some(parent) => current = parent,
+                        None => break,
+                    }
+                }
+            }
+            Axis::Preceding => {
+                let mut current = item.node().map_or(item, Item::Node);
+                loop {
+                    for sibling in self.siblings(current, false) {
+                        let mut nested = Vec::new();
+                        self.descendants(sibling, &mut nested);
+                        out.extend(nested.into_iter().rev());
+                        out.push(sibling);
+                    }
+                    match self.parent(current) {
+                        Some(parent) => current = parent,
+                        None => break,
+                    }
+                }
+            }
+            Axis::Attribute => out = self.attributes(item),
+            Axis::SelfNode => out.push(item),
+        }
+        out
+    }
+
+    fn matches(&self, test: &NodeTest, item: Item<'_, '_>, axis: Axis) -> bool {
+        // The principal node type of the attribute axis is attribute
+        let principal = match item {
+            Item::Attribute(..) => axis == Axis::Attribute,
+            Item::Node(node) => axis != Axis::Attribute && node.node_type() == NodeType::Element,
+            Item::Root => false,
+        };
+        let node_type = item.node().filter(|_| matches!(item, Item::Node(_))).map(|n| n.node_type());
+        match test {
+            NodeTest::Node => true,
+            NodeTest::Text => matches!(node_type, Some(NodeType::Text | NodeType::CData)),
+            NodeTest::Comment => node_type == Some(NodeType::Comment),
+            NodeTest::ProcessingInstruction(target) => {
+                node_type == Some(NodeType::ProcessingInstruction)
+                    && target.as_ref().is_none_or(|t| *t == self.name(item))
+            }
+            NodeTest::Any => principal,
+            NodeTest::Namespace(ns) => principal && self.namespace_uri(item) == *ns,
+            NodeTest::Name(ns, local) => {
+                principal
+                    && split_qname(&self.name(item)).1 == local
+                    && self.namespace_uri(item) == *ns
+            }
+        }
+    }
+}
+
+/// A compiled XPath expression
+#[derive(Debug, Clone)]
+pub(crate) struct XPath {
+    expr: Expr,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Or(Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Compare(CompareOp, Box<Expr>, Box<Expr>),
+    Arithmetic(ArithmeticOp, Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Union(Box<Expr>, Box<Expr>),
+    Literal(String),
+    Number(f64),
+    Variable(String),
+    Function(&'static str, Vec<Expr>),
+    Path { absolute: bool, steps: Vec<Step> },
+    /// A primary expression with predicates, optionally followed by a path
+    Filter {
+        primary: Box<Expr>,
+        predicates: Vec<Expr>,
+        steps: Vec<Step>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ArithmeticOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+}
+
+#[derive(Debug, Clone)]
+struct Step {
+    axis: Axis,
+    test: NodeTest,
+    predicates: Vec<Expr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Axis {
+    Child,
+    Descendant,
+    DescendantOrSelf,
+    Parent,
+    Ancestor,
+    AncestorOrSelf,
+    FollowingSibling,
+    PrecedingSibling,
+    Following,
+    Preceding,
+    Attribute,
+    SelfNode,
+}
+
+impl Axis {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "child" => Axis::Child,
+            "descendant" => Axis::Descendant,
+            "descendant-or-self" => Axis::DescendantOrSelf,
+            "parent" => Axis::Parent,
+            "ancestor" => Axis::Ancestor,
+            "ancestor-or-self" => Axis::AncestorOrSelf,
+            "following-sibling" => Axis::FollowingSibling,
+            "preceding-sibling" => Axis::PrecedingSibling,
+            "following" => Axis::Following,
+            "preceding" => Axis::Preceding,
+            "attribute" => Axis::Attribute,
+            "self" => Axis::SelfNode,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+enum NodeTest {
+    /// `*`
+    Any,
+    /// `prefix:*`, holding the namespace URI
+    Namespace(String),
+    /// Expanded name: namespace URI and local name
+    Name(String, String),
+    Text,
+    Comment,
+    ProcessingInstruction(Option<String>),
+    Node,
+}
+
+/// Core library functions with their minimum and maximum argument counts
+const FUNCTIONS: &[(&str, usize, usize)] = &[
+    ("last", 0, 0),
+    ("position", 0, 0),
+    ("count", 1, 1),
+    ("id", 1, 1),
+    ("local-name", 0, 1),
+    ("namespace-uri", 0, 1),
+    ("name", 0, 1),
+    ("string", 0, 1),
+    ("concat", 2, usize::MAX),
+    ("starts-with", 2, 2),
+    ("contains", 2, 2),
+    ("substring-before", 2, 2),
+    ("substring-after", 2, 2),
+    ("substring", 2, 3),
+    ("string-length", 0, 1),
+    ("normalize-space", 0, 1),
+    ("translate", 3, 3),
+    ("boolean", 1, 1),
+    ("not", 1, 1),
+    ("true", 0, 0),
+    ("false", 0, 0),
+    ("lang", 1, 1),
+    ("number", 0, 1),
+    ("sum", 1, 1),
+    ("floor", 1, 1),
+    ("ceiling", 1, 1),
+    ("round", 1, 1),
+    // From XSLT: the node the evaluation started at
+    ("current", 0, 0),
+];
+
+/// Evaluation focus: context item, its position and the context size
+struct Focus<'a, 'i> {
+    item: Item<'a, 'i>,
+    position: usize,
+    size: usize,
+    current: Item<'a, 'i>,
+}
+
+impl XPath {
+    /// Compile an expression; `namespaces` binds the prefixes it may use,
+    /// and the empty prefix, if bound, is the namespace of unprefixed
+    /// element names
+    pub(crate) fn compile(
+        source: &str,
+        namespaces: &HashMap<String, String>,
+    ) -> Result<Self, QueryError> {
+        let invalid = |message: String| QueryError::InvalidXPath(format!("{}: {}", source, message));
+        let tokens = tokenize(source).map_err(invalid)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            namespaces,
+        };
+        let expr = parser.expr().map_err(invalid)?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(invalid(format!("unexpected {}", token.describe())));
+        }
+        Ok(Self { expr })
+    }
+
+    /// Treat relative paths as matching at any depth, like XSLT patterns
+    pub(crate) fn into_pattern(self) -> Self {
+        fn anywhere(expr: Expr) -> Expr {
+            match expr {
+                Expr::Union(a, b) => Expr::Union(Box::new(anywhere(*a)), Box::new(anywhere(*b))),
+                Expr::Path {
+                    absolute: false,
+                    mut steps,
+                } => {
+                    steps.insert(0, descendant_or_self());
+                    Expr::Path {
+                        absolute: true,
+                        steps,
+                    }
+                }
+                other => other,
+            }
+        }
+        Self {
+            expr: anywhere(self.expr),
+        }
+    }
+
+    pub(crate) fn evaluate<'a, 'i>(
+        &self,
+        ctx: &Context<'a, 'i>,
+        item: Item<'a, 'i>,
+    ) -> Result<Value<'a, 'i>, QueryError> {
+        let focus = Focus {
+            item,
+            position: 1,
+            size: 1,
+            current: item,
+        };
+        eval(ctx, &self.expr, &focus)
+    }
+}
+
+fn descendant_or_self() -> Step {
+    Step {
+        axis: Axis::DescendantOrSelf,
+        test: NodeTest::Node,
+        predicates: Vec::new(),
+    }
+}
+
+fn eval<'a, 'i>(
+    ctx: &Context<'a, 'i>,
+    expr: &Expr,
+    focus: &Focus<'a, 'i>,
+) -> Result<Value<'a, 'i>, QueryError> {
+    Ok(match expr {
+        Expr::Or(a, b) => {
+            Value::Boolean(eval(ctx, a, focus)?.boolean() || eval(ctx, b, focus)?.boolean())
+        }
+        Expr::And(a, b) => {
+            Value::Boolean(eval(ctx, a, focus)?.boolean() && eval(ctx, b, focus)?.boolean())
+        }
+        Expr::Compare(op, a, b) => {
+            let (a, b) = (eval(ctx, a, focus)?, eval(ctx, b, focus)?);
+            Value::Boolean(compare(ctx, *op, &a, &b))
+        }
+        Expr::Arithmetic(op, a, b) => {
+            let a = eval(ctx, a, focus)?.number(ctx);
+            let b = eval(ctx, b, focus)?.number(ctx);
+            Value::Number(match op {
+                ArithmeticOp::Add => a + b,
+                ArithmeticOp::Subtract => a - b,
+                ArithmeticOp::Multiply => a * b,
+                ArithmeticOp::Divide => a / b,
+                ArithmeticOp::Modulo => a % b,
+            })
+        }
+        Expr::Negate(a) => Value::Number(-eval(ctx, a, focus)?.number(ctx)),
+        Expr::Union(a, b) => {
+            let mut nodes = eval(ctx, a, focus)?.into_nodes()?;
+            nodes.extend(eval(ctx, b, focus)?.into_nodes()?);
+            ctx.sort(&mut nodes);
+            Value::Nodes(nodes)
+        }
+        Expr::Literal(s) => Value::String(s.clone()),
+        Expr::Number(n) => Value::Number(*n),
+        Expr::Variable(name) => ctx.variables.get(name).cloned().ok_or_else(|| {
+            QueryError::ExecutionError(format!("variable ${} is not defined", name))
+        })?,
+        Expr::Function(name, args) => call(ctx, name, args, focus)?,
+        Expr::Path { absolute, steps } => {
+            let start = if *absolute { Item::Root } else { focus.item };
+            Value::Nodes(select(ctx, vec![start], steps, focus.current)?)
+        }
+        Expr::Filter {
+            primary,
+            predicates,
+            steps,
+        } => {
+            let value = eval(ctx, primary, focus)?;
+            if predicates.is_empty() && steps.is_empty() {
+                return Ok(value);
+            }
+            let mut nodes = value.into_nodes()?;
+            for predicate in predicates {
+                nodes = filter(ctx, nodes, predicate, focus.current)?;
+            }
+            Value::Nodes(select(ctx, nodes, steps, focus.current)?)
+        }
+    })
+}
+
+fn select<'a, 'i>(
+    ctx: &Context<'a, 'i>,
+    mut nodes: Vec<Item<'a, 'i>>,
+    steps: &[Step],
+    current: Item<'a, 'i>,
+) -> Result<Vec<Item<'a, 'i>>, QueryError> {
+    for step in steps {
+        let mut next = Vec::new();
+        for &item in &nodes {
+            let mut candidates: Vec<_> = ctx
+                .axis(step.axis, item)
+                .into_iter()
+                .filter(|&candidate| ctx.matches(&step.test, candidate, step.axis))
+                .collect();
+            for predicate in &step.predicates {
+                candidates = filter(ctx, candidates, predicate, current)?;
+            }
+            next.extend(candidates);
+        }
+        ctx.sort(&mut next);
+        nodes = next;
+    }
+    Ok(nodes)
+}
+
+fn filter<'a, 'i>(
+    ctx: &Context<'a, 'i>,
+    items: Vec<Item<'a, 'i>>,
+    predicate: &Expr,
+    current: Item<'a, 'i>,
+) -> Result<Vec<Item<'a, 'i>>, QueryError> {
+    let size = items.len();
+    let mut kept = Vec::new();
+    for (index, item) in items.into_iter().enumerate() {
+        let focus = Focus {
+            item,
+            position: index + 1,
+            size,
+            current,
+        };
+        let keep = match eval(ctx, predicate, &focus)? {
+            Value::Number(n) => n == (index + 1) as f64,
+            value => value.boolean(),
+        };
+        if keep {
+            kept.push(item);
+        }
+    }
+    Ok(kept)
+}
+
+fn compare(ctx: &Context<'_, '_>, op: CompareOp, a: &Value<'_, '_>, b: &Value<'_, '_>) -> bool {
+    match (a, b) {
+        (Value::Nodes(x), Value::Nodes(y)) => {
+            let ys: Vec<_> = y.iter().map(|&n| Value::String(ctx.string_value(n))).collect();
+            x.iter().any(|&n| {
+                let s = Value::String(ctx.string_value(n));
+                ys.iter().any(|t| compare_atoms(ctx, op, &s, t))
+            })
+        }
+        (Value::Nodes(x), Value::Boolean(_)) => {
+            compare_atoms(ctx, op, &Value::Boolean(!x.is_empty()), b)
+        }
+        (Value::Boolean(_), Value::Nodes(y)) => {
+            compare_atoms(ctx, op, a, &Value::Boolean(!y.is_empty()))
+        }
+        (Value::Nodes(x), other) => x
+            .iter()
+            .any(|&n| compare_atoms(ctx, op, &Value::String(ctx.string_value(n)), other)),
+        (other, Value::Nodes(y)) => y
+            .iter()
+            .any(|&n| compare_atoms(ctx, op, other, &Value::String(ctx.string_value(n)))),
+        _ => compare_atoms(ctx, op, a, b),
+    }
+}
+
+fn compare_atoms(ctx: &Context<'_, '_>, op: CompareOp, a: &Value<'_, '_>, b: &Value<'_, '_>) -> bool {
+    if matches!(op, CompareOp::Eq | CompareOp::Ne) {
+        let equal = match (a, b) {
+            (Value::Boolean(_), _) | (_, Value::Boolean(_)) => a.boolean() == b.boolean(),
+            (Value::Number(_), _) | (_, Value::Number(_)) => a.number(ctx) == b.number(ctx),
+            _ => a.string(ctx) == b.string(ctx),
+        };
+        return equal == (op == CompareOp::Eq);
+    }
+    let (a, b) = (a.number(ctx), b.number(ctx));
+    match op {
+        CompareOp::Lt => a < b,
+        CompareOp::Le => a <= b,
+        CompareOp::Gt => a > b,
+        _ => a >= b,
+    }
+}
+
+fn call<'a, 'i>(
+    ctx: &Context<'a, 'i>,
+    name: &str,
+    args: &[Expr],
+    focus: &Focus<'a, 'i>,
+) -> Result<Value<'a, 'i>, QueryError> {
+    let arg = |index: usize| eval(ctx, &args[index], focus);
+    let string_arg = |index: usize| -> Result<String, QueryError> {
+        match args.get(index) {
+            Some(expr) => Ok(eval(ctx, expr, focus)?.string(ctx)),
+            None => Ok(ctx.string_value(focus.item)),
+        }
+    };
+    // First node of an optional node-set argument
+    let node_arg = || -> Result<Option<Item<'a, 'i>>, QueryError> {
+        match args.first() {
+            Some(expr) => Ok(eval(ctx, expr, focus)?.into_nodes()?.first().copied()),
+            None => Ok(Some(focus.item)),
+        }
+    };
+
+    Ok(match name {
+        "last" => Value::Number(focus.size as f64),
+        "position" => Value::Number(focus.position as f64),
+        "count" => Value::Number(arg(0)?.into_nodes()?.len() as f64),
+        "id" => Value::Nodes(find_ids(ctx, &arg(0)?)),
+        "local-name" => Value::String(
+            node_arg()?
+                .map(|n| split_qname(&ctx.name(n)).1.to_string())
+                .unwrap_or_default(),
+        ),
+        "namespace-uri" => {
+            Value::String(node_arg()?.map(|n| ctx.namespace_uri(n)).unwrap_or_default())
+        }
+        "name" => Value::String(node_arg()?.map(|n| ctx.name(n)).unwrap_or_default()),
+        "string" => Value::String(string_arg(0)?),
+        "concat" => {
+            let mut out = String::new();
+            for index in 0..args.len() {
+                out.push_str(&string_arg(index)?);
+            }
+            Value::String(out)
+        }
+        "starts-with" => Value::Boolean(string_arg(0)?.starts_with(&string_arg(1)?)),
+        "contains" => Value::Boolean(string_arg(0)?.contains(&string_arg(1)?)),
+        "substring-before" => {
+            let (s, pattern) = (string_arg(0)?, string_arg(1)?);
+            Value::String(s.find(&pattern).map(|i| s[..i].to_string()).unwrap_or_default())
+        }
+        "substring-after" => {
+            let (s, pattern) = (string_arg(0)?, string_arg(1)?);
+            Value::String(
+                s.find(&pattern)
+                    .map(|i| s[i + pattern.len()..].to_string())
+                    .unwrap_or_default(),
+            )
+        }
+        "substring" => {
+            let s = string_arg(0)?;
+            let start = round(arg(1)?.number(ctx));
+            let end = match args.get(2) {
+                Some(_) => start + round(arg(2)?.number(ctx)),
+                None => f64::INFINITY,
+            };
+            Value::String(
+                s.chars()
+                    .enumerate()
+                    .filter(|&(i, _)| {
+                        let position = (i + 1) as f64;
+                        position >= start && position < end
+                    })
+                    .map(|(_, c)| c)
+                    .collect(),
+            )
+        }
+        "string-length" => Value::Number(string_arg(0)?.chars().count() as f64),
+        "normalize-space" => Value::String(
+            string_arg(0)?
+                .split_ascii_whitespace()
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        "translate" => {
+            let (s, from, to) = (string_arg(0)?, string_arg(1)?, string_arg(2)?);
+            let to: Vec<char> = to.chars().collect();
+            Value::String(
+                s.chars()
+                    .filter_map(|c| match from.chars().position(|f| f == c) {
+                        Some(index) => to.get(index).copied(),
+                        None => Some(c),
+                    })
+                    .collect(),
+            )
+        }
+        "boolean" => Value::Boolean(arg(0)?.boolean()),
+        "not" => Value::Boolean(!arg(0)?.boolean()),
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        "lang" => {
+            let wanted = string_arg(0)?.to_ascii_lowercase();
+            let mut current = Some(focus.item);
+            let mut lang = None;
+            while let Some(item) = current {
+                if let Item::Node(node) = item {
+                    if let Some(value) = node.attr("xml:lang") {
+                        lang = Some(value.to_ascii_lowercase());
+                        break;
+                    }
+                }
+                current = ctx.parent(item);
+            }
+            Value::Boolean(lang.is_some_and(|lang| {
+                lang == wanted || lang.strip_prefix(&wanted).is_some_and(|r| r.starts_with('-'))
+            }))
+        }
+        "number" => Value::Number(match args.first() {
+            Some(_) => arg(0)?.number(ctx),
+            None => parse_number(&ctx.string_value(focus.item)),
+        }),
+        "sum" => Value::Number(
+            arg(0)?
+                .into_nodes()?
+                .into_iter()
+                .map(|n| parse_number(&ctx.string_value(n)))
+                .sum(),
+        ),
+        "floor" => Value::Number(arg(0)?.number(ctx).floor()),
+        "ceiling" => Value::Number(arg(0)?.number(ctx).ceil()),
+        "round" => Value::Number(round(arg(0)?.number(ctx))),
+        "current" => Value::Nodes(vec![focus.current]),
+        _ => unreachable!("function {} is checked when compiling", name),
+    })
+}
+
+/// Elements whose ID (`xml:id` or a DTD-declared ID attribute) is listed in `value`
+fn find_ids<'a, 'i>(ctx: &Context<'a, 'i>, value: &Value<'a, 'i>) -> Vec<Item<'a, 'i>> {
+    let wanted: Vec<String> = match value {
+        Value::Nodes(nodes) => nodes.iter().map(|&n| ctx.string_value(n)).collect(),
+        other => vec![other.string(ctx)],
+    };
+    let wanted: Vec<&str> = wanted.iter().flat_map(|s| s.split_ascii_whitespace()).collect();
+
+    let is_id = |element: &str, attribute: &str| {
+        attribute == "xml:id"
+            || ctx.doc.dtd().is_some_and(|dtd| {
+                dtd.attributes.get(element).is_some_and(|decls| {
+                    decls
+                        .iter()
+                        .any(|d| d.name == attribute && matches!(d.kind, AttributeType::Id))
+                })
+            })
+    };
+    let mut all = Vec::new();
+    ctx.descendants(Item::Root, &mut all);
+    all.into_iter()
+        .filter(|&item| {
+            let Item::Node(node) = item else {
+                return false;
+            };
+            let element = node.tag_name().unwrap_or_default();
+            let found = node
+                .attributes()
+                .any(|(name, value)| is_id(&element, &name) && wanted.contains(&value.trim()));
+            found
+        })
+        .collect()
+}
+
+/// XPath `round()`: halves round towards positive infinity
+fn round(n: f64) -> f64 {
+    if n.is_nan() || n.is_infinite() {
+        n
+    } else {
+        (n + 0.5).floor()
+    }
+}
+
+/// Convert a string to a number following XPath rules (NaN if not numeric)
+fn parse_number(s: &str) -> f64 {
+    let s = s.trim_matches(|c| matches!(c, ' ' | '\t' | '\n' | '\r'));
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let numeric = digits.chars().any(|c| c.is_ascii_digit())
+        && digits.chars().all(|c| c.is_ascii_digit() || c == '.')
+        && digits.matches('.').count() <= 1;
+    if numeric {
+        s.parse().unwrap_or(f64::NAN)
+    } else {
+        f64::NAN
+    }
+}
+
+fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        "NaN".to_string()
+    } else if n.is_infinite() {
+        if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string()
+    } else if n == 0.0 {
+        "0".to_string()
+    } else {
+        n.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Slash,
+    DoubleSlash,
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    At,
+    Comma,
+    ColonColon,
+    Dot,
+    DotDot,
+    Pipe,
+    Plus,
+    Minus,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Multiply,
+    And,
+    Or,
+    Div,
+    Mod,
+    /// Name test `*`
+    Star,
+    /// Name test `prefix:*`
+    PrefixStar(String),
+    /// Name test `name` or `prefix:name`
+    Name(String),
+    Function(String),
+    NodeType(String),
+    Axis(String),
+    Literal(String),
+    Number(f64),
+    Variable(String),
+}
+
+impl Token {
+    fn is_operator(&self) -> bool {
+        matches!(
+            self,
+            Token::Slash
+                | Token::DoubleSlash
+                | Token::Pipe
+                | Token::Plus
+                | Token::Minus
+                | Token::Eq
+                | Token::Ne
+                | Token::Lt
+                | Token::Le
+                | Token::Gt
+                | Token::Ge
+                | Token::Multiply
+                | Token::And
+                | Token::Or
+                | Token::Div
+                | Token::Mod
+        )
+    }
+
+    fn describe(&self) -> String {
+        let symbol = match self {
+            Token::Slash => "/",
+            Token::DoubleSlash => "//",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::At => "@",
+            Token::Comma => ",",
+            Token::ColonColon => "::",
+            Token::Dot => ".",
+            Token::DotDot => "..",
+            Token::Pipe => "|",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Eq => "=",
+            Token::Ne => "!=",
+            Token::Lt => "<",
+            Token::Le => "<=",
+            Token::Gt => ">",
+            Token::Ge => ">=",
+            Token::Multiply | Token::Star => "*",
+            Token::And => "and",
+            Token::Or => "or",
+            Token::Div => "div",
+            Token::Mod => "mod",
+            Token::PrefixStar(prefix) => return format!("'{}:*'", prefix),
+            Token::Name(name) | Token::Function(name) | Token::NodeType(name) | Token::Axis(name) => {
+                return format!("'{}'", name)
+            }
+            Token::Literal(s) => return format!("literal \"{}\"", s),
+            Token::Number(n) => return format!("number {}", format_number(*n)),
+            Token::Variable(name) => return format!("'${}'", name),
+        };
+        format!("'{}'", symbol)
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut i = 0;
+    let is_ncname_start = |c: char| c != ':' && is_name_start_char(c);
+    let is_ncname_char = |c: char| c != ':' && is_name_char(c);
+    let ncname = |start: usize| -> usize {
+        let mut end = start;
+        while end < chars.len() && is_ncname_char(chars[end]) {
+            end += 1;
+        }
+        end
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        let next = chars.get(i + 1).copied();
+        // Names and '*' are operators unless an operand is expected
+        let operand_expected = match tokens.last() {
+            None => true,
+            Some(token) => {
+                token.is_operator()
+                    || matches!(
+                        token,
+                        Token::At | Token::ColonColon | Token::LParen | Token::LBracket | Token::Comma
+                    )
+            }
+        };
+
+        let (token, len) = match c {
+            '/' if next == Some('/') => (Token::DoubleSlash, 2),
+            '/' => (Token::Slash, 1),
+            '(' => (Token::LParen, 1),
+            ')' => (Token::RParen, 1),
+            '[' => (Token::LBracket, 1),
+            ']' => (Token::RBracket, 1),
+            '@' => (Token::At, 1),
+            ',' => (Token::Comma, 1),
+            '|' => (Token::Pipe, 1),
+            '+' => (Token::Plus, 1),
+            '-' => (Token::Minus, 1),
+            '=' => (Token::Eq, 1),
+            '!' if next == Some('=') => (Token::Ne, 2),
+            '<' if next == Some('=') => (Token::Le, 2),
+            '<' => (Token::Lt, 1),
+            '>' if next == Some('=') => (Token::Ge, 2),
+            '>' => (Token::Gt, 1),
+            ':' if next == Some(':') => (Token::ColonColon, 2),
+            '.' if next == Some('.') => (Token::DotDot, 2),
+            '.' if !next.is_some_and(|n| n.is_ascii_digit()) => (Token::Dot, 1),
+            '*' if operand_expected => (Token::Star, 1),
+            '*' => (Token::Multiply, 1),
+            '"' | '\'' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&q| q == c)
+                    .ok_or("unterminated string literal")?;
+                let literal: String = chars[i + 1..i + 1 + end].iter().collect();
+                (Token::Literal(literal), end + 2)
+            }
+            '$' => {
+                let mut end = ncname(i + 1);
+                if chars.get(end) == Some(&':') && chars.get(end + 1).is_some_and(|&c| is_ncname_start(c)) {
+                    end = ncname(end + 1);
+                }
+                if end == i + 1 {
+                    return Err("expected a variable name after '$'".to_string());
+                }
+                (Token::Variable(chars[i + 1..end].iter().collect()), end - i)
+            }
+            c if c.is_ascii_digit() || c == '.' => {
+                let mut end = i;
+                while end < chars.len() && (chars[end].is_ascii_digit() || chars[end] == '.') {
+                    end += 1;
+                }
+                let text: String = chars[i..end].iter().collect();
+                let number = text
+                    .parse()
+                    .map_err(|_| format!("invalid number '{}'", text))?;
+                (Token::Number(number), end - i)
+            }
+            c if is_ncname_start(c) => {
+                let mut end = ncname(i);
+                let prefix: String = chars[i..end].iter().collect();
+                if !operand_expected {
+                    let token = match prefix.as_str() {
+                        "and" => Token::And,
+                        "or" => Token::Or,
+                        "div" => Token::Div,
+                        "mod" => Token::Mod,
+                        _ => return Err(format!("expected an operator, found '{}'", prefix)),
+                    };
+                    (token, end - i)
+                } else if chars.get(end) == Some(&':') && chars.get(end + 1) == Some(&'*') {
+                    (Token::PrefixStar(prefix), end + 2 - i)
+                } else {
+                    if chars.get(end) == Some(&':') && chars.get(end + 1).is_some_and(|&c| is_ncname_start(c)) {
+                        end = ncname(end + 1);
+                    }
+                    let name: String = chars[i..end].iter().collect();
+                    let mut after = end;
+                    while after < chars.len() && chars[after].is_ascii_whitespace() {
+                        after += 1;
+                    }
+                    let token = match (chars.get(after), chars.get(after + 1)) {
+                        (Some('('), _) => match name.as_str() {
+                            "comment" | "text" | "processing-instruction" | "node" => {
+                                Token::NodeType(name)
+                            }
+                            _ => Token::Function(name),
+                        },
+                        (Some(':'), Some(':')) => Token::Axis(name),
+                        _ => Token::Name(name),
+                    };
+                    (token, end - i)
+                }
+            }
+            other => return Err(format!("unexpected character '{}'", other)),
+        };
+        tokens.push(token);
+        i += len;
+    }
+    Ok(tokens)
+}
+
+struct Parser<'n> {
+    tokens: Vec<Token>,
+    pos: usize,
+    namespaces: &'n HashMap<String, String>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), String> {
+        if self.eat(&token) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&format!("expected {}", token.describe())))
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> String {
+        match self.peek() {
+            Some(token) => format!("{}, found {}", expected, token.describe()),
+            None => format!("{} at end of expression", expected),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.eat(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.equality()?;
+        while self.eat(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.equality()?));
+        }
+        Ok(expr)
+    }
+
+    fn equality(&mut self) -> Result<Expr, String> {
+        let mut expr = self.relational()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Eq) => CompareOp::Eq,
+                Some(Token::Ne) => CompareOp::Ne,
+                _ => return Ok(expr),
+            };
+            self.pos += 1;
+            expr = Expr::Compare(op, Box::new(expr), Box::new(self.relational()?));
+        }
+    }
+
+    fn relational(&mut self) -> Result<Expr, String> {
+        let mut expr = self.additive()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Lt) => CompareOp::Lt,
+                Some(Token::Le) => CompareOp::Le,
+                Some(Token::Gt) => CompareOp::Gt,
+                Some(Token::Ge) => CompareOp::Ge,
+                _ => return Ok(expr),
+            };
+            self.pos += 1;
+            expr = Expr::Compare(op, Box::new(expr), Box::new(self.additive()?));
+        }
+    }
+
+    fn additive(&mut self) -> Result<Expr, String> {
+        let mut expr = self.multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Plus) => ArithmeticOp::Add,
+                Some(Token::Minus) => ArithmeticOp::Subtract,
+                _ => return Ok(expr),
+            };
+            self.pos += 1;
+            expr = Expr::Arithmetic(op, Box::new(expr), Box::new(self.multiplicative()?));
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Multiply) => ArithmeticOp::Multiply,
+                Some(Token::Div) => ArithmeticOp::Divide,
+                Some(Token::Mod) => ArithmeticOp::Modulo,
+                _ => return Ok(expr),
+            };
+            self.pos += 1;
+            expr = Expr::Arithmetic(op, Box::new(expr), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat(&Token::Minus) {
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+        let mut expr = self.path()?;
+        while self.eat(&Token::Pipe) {
+            expr = Expr::Union(Box::new(expr), Box::new(self.path()?));
+        }
+        Ok(expr)
+    }
+
+    fn path(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some(
+                Token::Variable(_)
+                | Token::LParen
+                | Token::Literal(_)
+                | Token::Number(_)
+                | Token::Function(_),
+            ) => {
+                let primary = self.primary()?;
+                let predicates = self.predicates()?;
+                let mut steps = Vec::new();
+                if self.eat(&Token::Slash) {
+                    self.relative_path(&mut steps)?;
+                } else if self.eat(&Token::DoubleSlash) {
+                    steps.push(descendant_or_self());
+                    self.relative_path(&mut steps)?;
+                }
+                if predicates.is_empty() && steps.is_empty() {
+                    return Ok(primary);
+                }
+                Ok(Expr::Filter {
+                    primary: Box::new(primary),
+                    predicates,
+                    steps,
+                })
+            }
+            Some(Token::Slash) => {
+                self.pos += 1;
+                let mut steps = Vec::new();
+                if self.starts_step() {
+                    self.relative_path(&mut steps)?;
+                }
+                Ok(Expr::Path {
+                    absolute: true,
+                    steps,
+                })
+            }
+            Some(Token::DoubleSlash) => {
+                self.pos += 1;
+                let mut steps = vec![descendant_or_self()];
+                self.relative_path(&mut steps)?;
+                Ok(Expr::Path {
+                    absolute: true,
+                    steps,
+                })
+            }
+            _ => {
+                let mut steps = Vec::new();
+                self.relative_path(&mut steps)?;
+                Ok(Expr::Path {
+                    absolute: false,
+                    steps,
+                })
+            }
+        }
+    }
+
+    fn starts_step(&self) -> bool {
+        matches!(
+            self.peek(),
+            Some(
+                Token::Dot
+                    | Token::DotDot
+                    | Token::At
+                    | Token::Axis(_)
+                    | Token::Star
+                    | Token::PrefixStar(_)
+                    | Token::Name(_)
+                    | Token::NodeType(_)
+            )
+        )
+    }
+
+    fn relative_path(&mut self, steps: &mut Vec<Step>) -> Result<(), String> {
+        loop {
+            steps.push(self.step()?);
+            if self.eat(&Token::DoubleSlash) {
+                steps.push(descendant_or_self());
+            } else if !self.eat(&Token::Slash) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn step(&mut self) -> Result<Step, String> {
+        let abbreviated = |axis| Step {
+            axis,
+            test: NodeTest::Node,
+            predicates: Vec::new(),
+        };
+        if self.eat(&Token::Dot) {
+            return Ok(abbreviated(Axis::SelfNode));
+        }
+        if self.eat(&Token::DotDot) {
+            return Ok(abbreviated(Axis::Parent));
+        }
+
+        let axis = if self.eat(&Token::At) {
+            Axis::Attribute
+        } else if let Some(Token::Axis(name)) = self.peek() {
+            let axis = match name.as_str() {
+                "namespace" => return Err("the namespace axis is not supported".to_string()),
+                name => Axis::from_name(name).ok_or_else(|| format!("unknown axis '{}'", name))?,
+            };
+            self.pos += 1;
+            self.expect(Token::ColonColon)?;
+            axis
+        } else {
+            Axis::Child
+        };
+
+        let test = match self.peek().cloned() {
+            Some(Token::Star) => NodeTest::Any,
+            Some(Token::PrefixStar(prefix)) => NodeTest::Namespace(self.resolve(&prefix)?),
+            Some(Token::Name(name)) => {
+                let (prefix, local) = split_qname(&name);
+                let ns = if !prefix.is_empty() {
+                    self.resolve(prefix)?
+                } else if axis == Axis::Attribute {
+                    String::new()
+                } else {
+                    self.namespaces.get("").cloned().unwrap_or_default()
+                };
+                NodeTest::Name(ns, local.to_string())
+            }
+            Some(Token::NodeType(kind)) => {
+                self.pos += 1;
+                self.expect(Token::LParen)?;
+                let test = match kind.as_str() {
+                    "comment" => NodeTest::Comment,
+                    "text" => NodeTest::Text,
+                    "node" => NodeTest::Node,
+                    _ => match self.peek().cloned() {
+                        Some(Token::Literal(target)) => {
+                            self.pos += 1;
+                            NodeTest::ProcessingInstruction(Some(target))
+                        }
+                        _ => NodeTest::ProcessingInstruction(None),
+                    },
+                };
+                self.expect(Token::RParen)?;
+                let predicates = self.predicates()?;
+                return Ok(Step {
+                    axis,
+                    test,
+                    predicates,
+                });
+            }
+            _ => return Err(self.unexpected("expected a location step")),
+        };
+        self.pos += 1;
+        let predicates = self.predicates()?;
+        Ok(Step {
+            axis,
+            test,
+            predicates,
+        })
+    }
+
+    fn predicates(&mut self) -> Result<Vec<Expr>, String> {
+        let mut predicates = Vec::new();
+        while self.eat(&Token::LBracket) {
+            predicates.push(self.expr()?);
+            self.expect(Token::RBracket)?;
+        }
+        Ok(predicates)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        Ok(match token {
+            Some(Token::Variable(name)) => Expr::Variable(name),
+            Some(Token::Literal(s)) => Expr::Literal(s),
+            Some(Token::Number(n)) => Expr::Number(n),
+            Some(Token::LParen) => {
+                let expr = self.expr()?;
+                self.expect(Token::RParen)?;
+                expr
+            }
+            Some(Token::Function(name)) => {
+                let &(name, min, max) = FUNCTIONS
+                    .iter()
+                    .find(|(f, _, _)| *f == name)
+                    .ok_or_else(|| format!("unknown function {}()", name))?;
+                self.expect(Token::LParen)?;
+                let mut args = Vec::new();
+                if !self.eat(&Token::RParen) {
+                    loop {
+                        args.push(self.expr()?);
+                        if self.eat(&Token::RParen) {
+                            break;
+                        }
+                        if !self.eat(&Token::Comma) {
+                            return Err(self.unexpected("expected ',' or ')'"));
+                        }
+                    }
+                }
+                if args.len() < min || args.len() > max {
+                    return Err(format!("wrong number of arguments to {}()", name));
+                }
+                Expr::Function(name, args)
+            }
+            _ => unreachable!("primary expressions are checked by the caller"),
+        })
+    }
+
+    fn resolve(&self, prefix: &str) -> Result<String, String> {
+        if prefix == "xml" {
+            return Ok(crate::validate::XML_NAMESPACE.to_string());
+        }
+        self.namespaces
+            .get(prefix)
+            .cloned()
+            .ok_or_else(|| format!("namespace prefix '{}' is not declared", prefix))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_with_options, ParseOptions};
+    use crate::types::DocumentType;
+
+    const XML: &str = r#"<?xml version="1.0"?>
+<inventory xmlns:x="urn:x">
+  <!-- stock -->
+  <item id="a" price="2.50" xml:lang="en-GB">Apple &amp; pear</item>
+  <item id="b" price="10">Banana</item>
+  <x:item id="c" price="4">Cherry</x:item>
+  <note><![CDATA[1 < 2]]></note>
+</inventory>"#;
+
+    fn eval_str(expr: &str) -> String {
+        let options = ParseOptions {
+            strict: true,
+            ..ParseOptions::default()
+        };
+        let doc = parse_with_options(XML, DocumentType::Xml, &options).unwrap();
+        let ctx = Context::new(&doc);
+        let namespaces = HashMap::from([("y".to_string(), "urn:x".to_string())]);
+        let xpath = XPath::compile(expr, &namespaces).unwrap();
+        match xpath.evaluate(&ctx, Item::Root).unwrap() {
+            Value::Nodes(nodes) => nodes
+                .iter()
+                .map(|&n| ctx.string_value(n))
+                .collect::<Vec<_>>()
+                .join("|"),
+            value => value.string(&ctx),
+        }
+    }
+
+    #[test]
+    fn test_paths_and_predicates() {
+        assert_eq!(eval_str("/inventory/item"), "Apple & pear|Banana");
+        assert_eq!(eval_str("//item[@price > 5]/@id"), "b");
+        assert_eq!(eval_str("//item[last()]"), "Banana");
+        assert_eq!(eval_str("//y:item | //item[1]"), "Apple & pear|Cherry");
+        assert_eq!(eval_str("//*[local-name() = 'item'][2]/following-sibling::*"), "Cherry|1 < 2");
+        assert_eq!(eval_str("//note/preceding::item[1]/@id"), "b");
+        assert_eq!(eval_str("//note/preceding::*[1]/@id"), "c");
+        assert_eq!(eval_str("name(//y:item/ancestor::*)"), "inventory");
+        assert_eq!(eval_str("//comment()"), " stock ");
+        assert_eq!(eval_str("count(//@*)"), "7");
+        assert_eq!(eval_str("count(/inventory/node())"), "11");
+    }
+
+    #[test]
+    fn test_operators_and_functions() {
+        assert_eq!(eval_str("sum(//@price) div 2"), "8.25");
+        assert_eq!(eval_str("//item[@id = 'a']/@price * 2 = 5"), "true");
+        assert_eq!(eval_str("count(//item) mod 2 - -1"), "1");
+        assert_eq!(eval_str("concat(substring('12345', 1.5, 2.6), '-', translate('abc', 'abc', 'AB'))"), "234-AB");
+        assert_eq!(eval_str("normalize-space('  a \n b  ')"), "a b");
+        assert_eq!(eval_str("substring-after(//item, '&')"), " pear");
+        assert_eq!(eval_str("string(round(-2.5)) = '-2' and 1 div 0 > 10"), "true");
+        assert_eq!(eval_str("number('x') != number('x')"), "true");
+        assert_eq!(eval_str("boolean(//item[lang('en')]) and not(//item[lang('de')])"), "true");
+        assert_eq!(eval_str("//item = 'Banana' and //@price < 3"), "true");
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        let namespaces = HashMap::new();
+        let error = |expr: &str| XPath::compile(expr, &namespaces).unwrap_err().to_string();
+        assert_eq!(error("count(//a"), "invalid XPath expression: count(//a: expected ',' or ')' at end of expression");
+        assert_eq!(error("foo(1)"), "invalid XPath expression: foo(1): unknown function foo()");
+        assert_eq!(error("p:a"), "invalid XPath expression: p:a: namespace prefix 'p' is not declared");
+        assert_eq!(error("a b"), "invalid XPath expression: a b: expected an operator, found 'b'");
+        assert_eq!(error("not()"), "invalid XPath expression: not(): wrong number of arguments to not()");
+    }
+}
@@ -0,0 +1,226 @@
+//! Arena-backed document tree shared by all parser backends
+//!
+//! Nodes never hold references into the source buffer. Every string is either
+//! a byte span into the source or an owned string for content a parser had to
+//! synthesize, so the tree itself carries no lifetime and the zero-copy
+//! guarantee is kept by resolving spans against the source on access.
+
+use std::ops::Range;
+use tl::{NodeHandle, VDom};
+
+/// Index of a node inside a [`Tree`]
+pub(crate) type NodeId = usize;
+
+/// A string stored in the tree
+#[derive(Debug, Clone)]
+pub(crate) enum Str {
+    /// Byte range into the document source
+    Span(Range<usize>),
+    /// String known at compile time (e.g. implied tag names)
+    Static(&'static str),
+    /// String that does not exist verbatim in the source
+    Owned(Box<str>),
+}
+
+impl Str {
+    /// Resolve the string against the document source
+    pub(crate) fn resolve<'a>(&'a self, source: &'a str) -> &'a str {
+        match self {
+            Str::Span(range) => &source[range.clone()],
+            Str::Static(s) => s,
+            Str::Owned(s) => s,
+        }
+    }
+}
+
+/// A single attribute; HTML allows attributes without a value
+#[derive(Debug, Clone)]
+pub(crate) struct Attribute {
+    pub(crate) name: Str,
+    pub(crate) value: Option<Str>,
+}
+
+/// Node payload
+#[derive(Debug, Clone)]
+pub(crate) enum NodeKind {
+    Element { name: Str, attributes: Vec<Attribute> },
+    /// Character data, entities are kept as written
+    Text(Str),
+    /// Content of a `<![CDATA[...]]>` section, without delimiters
+    CData(Str),
+    /// Complete comment markup including `<!--` and `-->`
+    Comment(Str),
+    ProcessingInstruction { target: Str, data: Str },
+    /// Complete `<!DOCTYPE ...>` markup
+    Doctype(Str),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct NodeData {
+    pub(crate) kind: NodeKind,
+    pub(crate) parent: Option<NodeId>,
+    pub(crate) children: Vec<NodeId>,
+}
+
+/// Flat node storage with parent and child links
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Tree {
+    nodes: Vec<NodeData>,
+    roots: Vec<NodeId>,
+}
+
+impl Tree {
+    /// Append a node as the last child of `parent` (or as a top-level node)
+    pub(crate) fn append(&mut self, parent: Option<NodeId>, kind: NodeKind) -> NodeId {
+        let id = self.create(kind);
+        self.append_child(parent, id);
+        id
+    }
+
+    /// Create a node that is not attached to the tree yet
+    pub(crate) fn create(&mut self, kind: NodeKind) -> NodeId {
+        self.nodes.push(NodeData {
+            kind,
+            parent: None,
+            children: Vec::new(),
+        });
+        self.nodes.len() - 1
+    }
+
+    /// Attach a detached node as the last child of `parent`
+    pub(crate) fn append_child(&mut self, parent: Option<NodeId>, id: NodeId) {
+        self.nodes[id].parent = parent;
+        match parent {
+            Some(p) => self.nodes[p].children.push(id),
+            None => self.roots.push(id),
+        }
+    }
+
+    /// Attach a detached node immediately before `sibling`
+    pub(crate) fn insert_before(&mut self, sibling: NodeId, id: NodeId) {
+        let parent = self.nodes[sibling].parent;
+        self.nodes[id].parent = parent;
+        let siblings = match parent {
+            Some(p) => &mut self.nodes[p].children,
+            None => &mut self.roots,
+        };
+        let index = siblings.iter().position(|&s| s == sibling).unwrap_or(siblings.len());
+        siblings.insert(index, id);
+    }
+
+    /// Remove a node (and its subtree) from its parent
+    pub(crate) fn detach(&mut self, id: NodeId) {
+        let parent = self.nodes[id].parent.take();
+        let siblings = match parent {
+            Some(p) => &mut self.nodes[p].children,
+            None => &mut self.roots,
+        };
+        siblings.retain(|&s| s != id);
+    }
+
+    pub(crate) fn get_mut(&mut self, id: NodeId) -> &mut NodeData {
+        &mut self.nodes[id]
+    }
+
+    pub(crate) fn get(&self, id: NodeId) -> &NodeData {
+        &self.nodes[id]
+    }
+
+    /// Number of nodes created, attached or not
+    pub(crate) fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Top-level nodes in document order
+    pub(crate) fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    /// Convert a `tl` DOM into a tree, keeping borrowed data as source spans.
+    pub(crate) fn from_vdom(vdom: &VDom<'_>, source: &str) -> Self {
+        let mut tree = Tree::default();
+        for handle in vdom.children() {
+            tree.convert_handle(vdom, source, *handle, None);
+        }
+        tree
+    }
+
+    fn convert_handle(
+        &mut self,
+        vdom: &VDom<'_>,
+        source: &str,
+        handle: NodeHandle,
+        parent: Option<NodeId>,
+    ) {
+        let Some(node) = handle.get(vdom.parser()) else {
+            return;
+        };
+
+        match node {
+            tl::Node::Tag(tag) => {
+                let attrs = tag.attributes();
+                let mut attributes: Vec<Attribute> = attrs
+                    .unstable_raw()
+                    .iter()
+                    .map(|(k, v)| Attribute {
+                        name: str_from_bytes(k, source),
+                        value: v.as_ref().map(|v| str_from_bytes(v, source)),
+                    })
+                    .collect();
+                // tl keeps id and class outside the raw map and reports them last
+                for (name, value) in [("id", attrs.id()), ("class", attrs.class())] {
+                    if let Some(value) = value {
+                        attributes.push(Attribute {
+                            name: Str::Static(name),
+                            value: Some(str_from_bytes(value, source)),
+                        });
+                    }
+                }
+                // tl stores attributes in a hash map; restore the source order
+                attributes.sort_by_key(|attr| {
+                    [Some(&attr.name), attr.value.as_ref()]
+                        .into_iter()
+                        .flatten()
+                        .find_map(|s| match s {
+                            Str::Span(range) => Some(range.start),
+                            _ => None,
+                        })
+                        .unwrap_or(usize::MAX)
+                });
+
+                let id = self.append(
+                    parent,
+                    NodeKind::Element {
+                        name: str_from_bytes(tag.name(), source),
+                        attributes,
+                    },
+                );
+                for child in tag.children().top().iter() {
+                    self.convert_handle(vdom, source, *child, Some(id));
+                }
+            }
+            tl::Node::Raw(bytes) => {
+                self.append(parent, NodeKind::Text(str_from_bytes(bytes, source)));
+            }
+            tl::Node::Comment(bytes) => {
+                self.append(parent, NodeKind::Comment(str_from_bytes(bytes, source)));
+            }
+        }
+    }
+}
+
+/// Map `tl` bytes back to a span of the source when they are borrowed from it
+fn str_from_bytes(bytes: &tl::Bytes<'_>, source: &str) -> Str {
+    if let Some(borrowed) = bytes.as_bytes_borrowed() {
+        let start = (borrowed.as_ptr() as usize).wrapping_sub(source.as_ptr() as usize);
+        let end = start.wrapping_add(borrowed.len());
+        if start <= end
+            && end <= source.len()
+            && source.is_char_boundary(start)
+            && source.is_char_boundary(end)
+        {
+            return Str::Span(start..end);
+        }
+    }
+    Str::Owned(bytes.as_utf8_str().into())
+}
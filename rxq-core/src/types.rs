@@ -1,8 +1,13 @@
 //! Core type definitions for zero-copy document representation
 
-use crate::error::ParseError;
+use crate::error::{Diagnostic, ParseError, Position};
+use crate::parser::dtd::Dtd;
+use crate::tree::{NodeData, NodeId, NodeKind, Str, Tree};
 use std::borrow::Cow;
-use tl::{HTMLTag, NodeHandle, ParserOptions, VDom};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::{Arc, OnceLock};
+use tl::ParserOptions;
 
 /// Document type enumeration
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -18,28 +23,75 @@ pub enum NodeType {
     Element,
     Text,
     Comment,
+    /// `<![CDATA[...]]>` section (its content is part of `text()`)
+    CData,
+    /// Processing instruction such as `<?xml-stylesheet ...?>`
+    ProcessingInstruction,
+    /// `<!DOCTYPE ...>` declaration
+    Doctype,
     Raw,
 }
 
 /// A parsed document with lifetime bound to source buffer.
 /// All string data is borrowed from the original input.
 pub struct Document<'input> {
-    source: &'input str,
-    vdom: VDom<'input>,
+    source: Source<'input>,
+    tree: Tree,
     doc_type: DocumentType,
+    dtd: Option<Dtd>,
+    /// Problems met while parsing; filled in on first use if the parser
+    /// did not record them
+    diagnostics: OnceLock<Vec<Diagnostic>>,
 }
 
 impl<'input> Document<'input> {
     /// Parse a document from borrowed input (zero-copy).
+    ///
+    /// This uses the lenient parsers, which accept malformed markup. XML
+    /// keeps declarations, processing instructions and CDATA sections as
+    /// written, and entities declared in the DOCTYPE are expanded. HTML is
+    /// built with the HTML5 tree construction algorithm, so implied elements
+    /// and misnested tags come out the way a browser would build them. See
+    /// [`crate::parser::parse_with_options`] for strict XML parsing.
     pub fn parse(source: &'input str, doc_type: DocumentType) -> Result<Self, ParseError> {
+        if doc_type == DocumentType::Html {
+            let parsed = crate::parser::html::parse(source);
+            return Ok(
+                Self::from_tree(source, parsed.tree, doc_type).with_diagnostics(parsed.diagnostics)
+            );
+        }
+        if doc_type == DocumentType::Xml {
+            return crate::parser::parse_with_options(source, doc_type, &Default::default());
+        }
+
         let vdom = tl::parse(source, ParserOptions::default())
             .map_err(|e| ParseError::SyntaxError(format!("{:?}", e)))?;
+        let tree = Tree::from_vdom(&vdom, source);
 
-        Ok(Self {
-            source,
-            vdom,
+        Ok(Self::from_tree(source, tree, doc_type))
+    }
+
+    /// Wrap a tree built by one of the parser backends
+    pub(crate) fn from_tree(source: &'input str, tree: Tree, doc_type: DocumentType) -> Self {
+        Self {
+            source: Source::Borrowed(source),
+            tree,
             doc_type,
-        })
+            dtd: None,
+            diagnostics: OnceLock::new(),
+        }
+    }
+
+    /// Attach the declarations read from the document's DOCTYPE
+    pub(crate) fn with_dtd(mut self, dtd: Option<Dtd>) -> Self {
+        self.dtd = dtd;
+        self
+    }
+
+    /// Attach the problems the parser recovered from
+    pub(crate) fn with_diagnostics(self, diagnostics: Vec<Diagnostic>) -> Self {
+        let _ = self.diagnostics.set(diagnostics);
+        self
     }
 
     /// Auto-detect document type from content
@@ -57,22 +109,17 @@ impl<'input> Document<'input> {
 
     /// Get the root node of the document
     pub fn root<'a>(&'a self) -> NodeRef<'a, 'input> {
-        let parser = self.vdom.parser();
-        let handle = self
-            .vdom
-            .children()
+        let roots = self.tree.roots();
+        let id = roots
             .iter()
-            .find(|h| h.get(parser).and_then(|node| node.as_tag()).is_some())
-            .copied()
-            .or_else(|| self.vdom.children().first().copied());
+            .find(|&&id| matches!(self.tree.get(id).kind, NodeKind::Element { .. }))
+            .or_else(|| roots.first())
+            .copied();
 
-        let node = NodeRef {
-            vdom: &self.vdom,
-            handle,
-        };
+        let node = NodeRef::new(self.source(), &self.tree, id);
 
-        // If root has empty tag name (e.g. <?xml ... ?> parsed by tl as container),
-        // try to find the actual root element inside it.
+        // `tl`, used for JSON documents, can produce an element without a name
+        // around the content; the root is then the first element inside it
         if let Some(name) = node.tag_name() {
             if name.is_empty() {
                 if let Some(child) = node.children().find(|n| n.node_type() == NodeType::Element) {
@@ -84,182 +131,412 @@ impl<'input> Document<'input> {
         node
     }
 
+    /// Iterate over the top-level nodes (prolog, root element, trailing comments)
+    pub fn children<'a>(&'a self) -> impl Iterator<Item = NodeRef<'a, 'input>> + 'a {
+        self.tree
+            .roots()
+            .iter()
+            .map(move |&id| NodeRef::new(self.source(), &self.tree, Some(id)))
+    }
+
     /// Get the document type
     pub fn doc_type(&self) -> DocumentType {
         self.doc_type
     }
 
-    /// Get reference to underlying VDom
-    pub(crate) fn vdom(&self) -> &VDom<'input> {
-        &self.vdom
+    /// Declarations from the DOCTYPE, for XML documents that have one
+    pub fn dtd(&self) -> Option<&Dtd> {
+        self.dtd.as_ref()
+    }
+
+    /// Problems found in the source that lenient parsing recovered from
+    ///
+    /// For XML: unclosed and stray tags, bad attributes, undefined entities
+    /// and every other well-formedness violation, plus warnings. For HTML:
+    /// stray end tags, elements left open, duplicate attributes and unknown
+    /// character references. Empty for well-formed documents.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        self.diagnostics.get_or_init(|| match self.doc_type {
+            DocumentType::Xml => {
+                crate::parser::xml::parse(self.source(), &Default::default()).diagnostics
+            }
+            DocumentType::Html => crate::parser::html::parse(self.source()).diagnostics,
+            _ => Vec::new(),
+        })
     }
 
     /// Get the original source string
-    pub fn source(&self) -> &'input str {
-        self.source
+    pub fn source(&self) -> &str {
+        match &self.source {
+            Source::Borrowed(source) => source,
+            Source::Shared(source) => source,
+        }
+    }
+}
+
+/// Where a document's source text lives
+enum Source<'input> {
+    Borrowed(&'input str),
+    Shared(Arc<str>),
+}
+
+/// A document that owns its source text
+///
+/// Unlike [`Document`], which borrows its input, this can be stored in
+/// long-lived caches and sent between threads (it is `Send + Sync`). It
+/// dereferences to a [`Document`], so nodes, queries and formatters work
+/// the same way on both.
+pub struct OwnedDocument(Document<'static>);
+
+impl OwnedDocument {
+    /// Parse a document, taking ownership of the source text
+    ///
+    /// See [`Document::parse`].
+    pub fn parse(source: impl Into<Arc<str>>, doc_type: DocumentType) -> Result<Self, ParseError> {
+        let source = source.into();
+        let doc = Document::parse(&source, doc_type)?;
+        Ok(Self::from_document(doc, source.clone()))
+    }
+
+    /// Parse with options, taking ownership of the source text
+    ///
+    /// See [`crate::parser::parse_with_options`].
+    pub fn parse_with_options(
+        source: impl Into<Arc<str>>,
+        doc_type: DocumentType,
+        options: &crate::parser::ParseOptions,
+    ) -> Result<Self, ParseError> {
+        let source = source.into();
+        let doc = crate::parser::parse_with_options(&source, doc_type, options)?;
+        Ok(Self::from_document(doc, source.clone()))
+    }
+
+    /// Move a document parsed from `source` onto the shared buffer
+    ///
+    /// The tree only holds byte ranges into the source, so it stays valid
+    /// for any buffer with the same text.
+    fn from_document(doc: Document<'_>, source: Arc<str>) -> Self {
+        Self(Document {
+            source: Source::Shared(source),
+            tree: doc.tree,
+            doc_type: doc.doc_type,
+            dtd: doc.dtd,
+            diagnostics: doc.diagnostics,
+        })
+    }
+
+    /// The shared source text
+    pub fn shared_source(&self) -> Arc<str> {
+        match &self.0.source {
+            Source::Shared(source) => source.clone(),
+            Source::Borrowed(source) => Arc::from(*source),
+        }
+    }
+}
+
+impl Deref for OwnedDocument {
+    type Target = Document<'static>;
+
+    fn deref(&self) -> &Document<'static> {
+        &self.0
     }
 }
 
 /// A reference to a node in the document tree.
 #[derive(Clone, Copy)]
 pub struct NodeRef<'a, 'input> {
-    vdom: &'a VDom<'input>,
-    handle: Option<NodeHandle>,
+    source: &'a str,
+    tree: &'a Tree,
+    id: Option<NodeId>,
+    _input: PhantomData<&'input str>,
 }
 
 impl<'a, 'input> NodeRef<'a, 'input> {
     /// Create a new NodeRef
-    pub(crate) fn new(vdom: &'a VDom<'input>, handle: Option<NodeHandle>) -> Self {
-        Self { vdom, handle }
+    pub(crate) fn new(source: &'a str, tree: &'a Tree, id: Option<NodeId>) -> Self {
+        Self {
+            source,
+            tree,
+            id,
+            _input: PhantomData,
+        }
+    }
+
+    fn data(&self) -> Option<&'a NodeData> {
+        let tree = self.tree;
+        self.id.map(|id| tree.get(id))
     }
 
-    // Note: handle() getter removed as it was unused and caused warnings.
-    // If needed in future, uncomment:
-    // pub(crate) fn handle(&self) -> Option<NodeHandle> { self.handle }
+    /// Identity of the node within its document
+    pub(crate) fn id(&self) -> Option<NodeId> {
+        self.id
+    }
+
+    fn with_id(&self, id: NodeId) -> Self {
+        Self::new(self.source, self.tree, Some(id))
+    }
 
     /// Get node type
     pub fn node_type(&self) -> NodeType {
-        match self.handle.and_then(|h| h.get(self.vdom.parser())) {
-            Some(node) if node.as_tag().is_some() => NodeType::Element,
-            Some(node) if node.as_comment().is_some() => NodeType::Comment,
-            Some(node) if node.as_raw().is_some() => NodeType::Text,
-            _ => NodeType::Raw,
+        match self.data().map(|d| &d.kind) {
+            Some(NodeKind::Element { .. }) => NodeType::Element,
+            Some(NodeKind::Text(_)) => NodeType::Text,
+            Some(NodeKind::CData(_)) => NodeType::CData,
+            Some(NodeKind::Comment(_)) => NodeType::Comment,
+            Some(NodeKind::ProcessingInstruction { .. }) => NodeType::ProcessingInstruction,
+            Some(NodeKind::Doctype(_)) => NodeType::Doctype,
+            None => NodeType::Raw,
         }
     }
 
     /// Get the tag name (if this is an element node)
     pub fn tag_name(&self) -> Option<Cow<'a, str>> {
-        self.handle
-            .and_then(|h| h.get(self.vdom.parser()))
-            .and_then(|node| node.as_tag())
-            .map(|tag| tag.name().as_utf8_str())
+        match self.data().map(|d| &d.kind) {
+            Some(NodeKind::Element { name, .. }) => Some(Cow::Borrowed(name.resolve(self.source))),
+            _ => None,
+        }
     }
 
     /// Get inner HTML as String
     pub fn inner_html(&self) -> String {
-        self.handle
-            .and_then(|h| h.get(self.vdom.parser()))
-            .and_then(|node| node.as_tag())
-            .map(|tag| tag.inner_html(self.vdom.parser()).to_string())
-            .unwrap_or_default()
+        let mut out = String::new();
+        if self.node_type() == NodeType::Element {
+            for child in self.children() {
+                child.write_markup(&mut out);
+            }
+        }
+        out
     }
 
     /// Get outer HTML as String
     pub fn outer_html(&self) -> String {
-        self.handle
-            .and_then(|h| h.get(self.vdom.parser()))
-            .and_then(|node| node.as_tag())
-            .map(|tag| tag.outer_html(self.vdom.parser()).to_string())
-            .unwrap_or_default()
+        let mut out = String::new();
+        if self.node_type() == NodeType::Element {
+            self.write_markup(&mut out);
+        }
+        out
     }
 
-    /// Get text content (recursively collects all text nodes)
-    pub fn text(&self) -> Option<String> {
-        self.handle
-            .and_then(|h| h.get(self.vdom.parser()))
-            .map(|node| {
-                if let Some(tag) = node.as_tag() {
-                    self.collect_text_recursive(tag)
-                } else if let Some(raw) = node.as_raw() {
-                    raw.as_utf8_str().to_string()
+    /// Serialize this node and its descendants as markup
+    fn write_markup(&self, out: &mut String) {
+        let Some(data) = self.data() else {
+            return;
+        };
+        let source = self.source;
+
+        match &data.kind {
+            NodeKind::Element { name, attributes } => {
+                let name = name.resolve(source);
+                out.push('<');
+                out.push_str(name);
+                for attr in attributes {
+                    out.push(' ');
+                    out.push_str(attr.name.resolve(source));
+                    if let Some(value) = &attr.value {
+                        let value = value.resolve(source);
+                        let quote = if value.contains('"') { '\'' } else { '"' };
+                        out.push('=');
+                        out.push(quote);
+                        out.push_str(value);
+                        out.push(quote);
+                    }
+                }
+                if data.children.is_empty() {
+                    out.push_str("/>");
                 } else {
-                    String::new()
+                    out.push('>');
+                    for &child in &data.children {
+                        self.with_id(child).write_markup(out);
+                    }
+                    out.push_str("</");
+                    out.push_str(name);
+                    out.push('>');
                 }
-            })
+            }
+            NodeKind::Text(text) => out.push_str(text.resolve(source)),
+            NodeKind::CData(text) => {
+                out.push_str("<![CDATA[");
+                out.push_str(text.resolve(source));
+                out.push_str("]]>");
+            }
+            NodeKind::Comment(markup) | NodeKind::Doctype(markup) => {
+                out.push_str(markup.resolve(source))
+            }
+            NodeKind::ProcessingInstruction { target, data } => {
+                let data = data.resolve(source);
+                out.push_str("<?");
+                out.push_str(target.resolve(source));
+                if !data.is_empty() {
+                    out.push(' ');
+                    out.push_str(data);
+                }
+                out.push_str("?>");
+            }
+        }
+    }
+
+    /// Get text content (recursively collects all text nodes)
+    pub fn text(&self) -> Option<String> {
+        self.data().map(|data| match &data.kind {
+            NodeKind::Element { .. } => {
+                let mut result = String::new();
+                self.collect_text_recursive(data, &mut result);
+                result
+            }
+            NodeKind::Text(text) | NodeKind::CData(text) => text.resolve(self.source).to_string(),
+            _ => String::new(),
+        })
     }
 
     /// Recursively collect text content
-    fn collect_text_recursive(&self, tag: &HTMLTag) -> String {
-        let mut result = String::new();
-        let children = tag.children();
-        for child_handle in children.top().iter() {
-            if let Some(child) = child_handle.get(self.vdom.parser()) {
-                if let Some(child_tag) = child.as_tag() {
-                    result.push_str(&self.collect_text_recursive(child_tag));
-                } else if let Some(raw) = child.as_raw() {
-                    let text_cow = raw.as_utf8_str();
-                    let text = text_cow.as_ref();
-                    if !text.starts_with("<!--") {
-                        result.push_str(text);
-                    }
+    fn collect_text_recursive(&self, data: &NodeData, result: &mut String) {
+        for &child_id in &data.children {
+            let child = self.tree.get(child_id);
+            match &child.kind {
+                NodeKind::Element { .. } => self.collect_text_recursive(child, result),
+                NodeKind::Text(text) | NodeKind::CData(text) => {
+                    result.push_str(text.resolve(self.source))
                 }
+                _ => {}
             }
         }
-        result
     }
 
     /// Get an attribute value by name (zero-copy)
-    pub fn attr(&self, name: &'a str) -> Option<Cow<'a, str>> {
-        self.handle
-            .and_then(|h| h.get(self.vdom.parser()))
-            .and_then(|node| node.as_tag())
-            .and_then(|tag| {
-                tag.attributes()
-                    .get(name)
-                    .flatten()
-                    .map(|bytes| bytes.as_utf8_str())
-            })
+    pub fn attr(&self, name: &str) -> Option<Cow<'a, str>> {
+        let source = self.source;
+        match self.data().map(|d| &d.kind) {
+            Some(NodeKind::Element { attributes, .. }) => attributes
+                .iter()
+                .find(|attr| attr.name.resolve(source) == name)
+                .and_then(|attr| attr.value.as_ref())
+                .map(|value| Cow::Borrowed(value.resolve(source))),
+            _ => None,
+        }
+    }
+
+    /// Check whether an attribute is present, including attributes without a value
+    pub fn has_attr(&self, name: &str) -> bool {
+        let source = self.source;
+        match self.data().map(|d| &d.kind) {
+            Some(NodeKind::Element { attributes, .. }) => attributes
+                .iter()
+                .any(|attr| attr.name.resolve(source) == name),
+            _ => false,
+        }
     }
 
     /// Get comment content
+    ///
+    /// A comment left open at the end of the input gets its `-->`, so the
+    /// markup can be written out and parsed again.
     pub fn comment(&self) -> Option<Cow<'a, str>> {
-        self.handle
-            .and_then(|h| h.get(self.vdom.parser()))
-            .and_then(|node| node.as_comment())
-            .map(|comment| comment.as_utf8_str())
+        match self.data().map(|d| &d.kind) {
+            Some(NodeKind::Comment(markup)) => {
+                let markup = markup.resolve(self.source);
+                Some(if markup.starts_with("<!--") && !markup.ends_with("-->") {
+                    Cow::Owned(format!("{}-->", markup))
+                } else {
+                    Cow::Borrowed(markup)
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Get the target and data of a processing instruction
+    pub fn processing_instruction(&self) -> Option<(Cow<'a, str>, Cow<'a, str>)> {
+        match self.data().map(|d| &d.kind) {
+            Some(NodeKind::ProcessingInstruction { target, data }) => Some((
+                Cow::Borrowed(target.resolve(self.source)),
+                Cow::Borrowed(data.resolve(self.source)),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Get the complete `<!DOCTYPE ...>` markup
+    pub fn doctype(&self) -> Option<Cow<'a, str>> {
+        match self.data().map(|d| &d.kind) {
+            Some(NodeKind::Doctype(markup)) => Some(Cow::Borrowed(markup.resolve(self.source))),
+            _ => None,
+        }
     }
 
     /// Get all attributes as an iterator
     pub fn attributes(&self) -> impl Iterator<Item = (Cow<'a, str>, Cow<'a, str>)> + '_ {
-        self.handle
-            .and_then(|h| h.get(self.vdom.parser()))
-            .and_then(|node| node.as_tag())
-            .into_iter()
-            .flat_map(|tag| {
-                tag.attributes().iter().filter_map(|(k, v)| {
-                    v.as_ref().map(|val| {
-                        (
-                            k.clone(),   // k is Cow, so clone to return
-                            val.clone(), // val is &Cow, so clone to return
-                        )
-                    })
-                })
+        let source = self.source;
+        let attributes = match self.data().map(|d| &d.kind) {
+            Some(NodeKind::Element { attributes, .. }) => attributes.as_slice(),
+            _ => &[],
+        };
+        attributes.iter().filter_map(move |attr| {
+            attr.value.as_ref().map(|value| {
+                (
+                    Cow::Borrowed(attr.name.resolve(source)),
+                    Cow::Borrowed(value.resolve(source)),
+                )
             })
+        })
+    }
+
+    /// Get all attributes, including those without a value (`<input disabled>`)
+    /// that [`NodeRef::attributes`] skips
+    pub fn all_attributes(
+        &self,
+    ) -> impl Iterator<Item = (Cow<'a, str>, Option<Cow<'a, str>>)> + '_ {
+        let source = self.source;
+        let attributes = match self.data().map(|d| &d.kind) {
+            Some(NodeKind::Element { attributes, .. }) => attributes.as_slice(),
+            _ => &[],
+        };
+        attributes.iter().map(move |attr| {
+            (
+                Cow::Borrowed(attr.name.resolve(source)),
+                attr.value.as_ref().map(|value| Cow::Borrowed(value.resolve(source))),
+            )
+        })
     }
 
     /// Iterate over child nodes
     pub fn children(&self) -> impl Iterator<Item = NodeRef<'a, 'input>> + '_ {
-        self.handle
-            .and_then(|h| h.get(self.vdom.parser()))
-            .and_then(|node| node.as_tag())
-            .into_iter()
-            .flat_map(move |tag| {
-                // Collect handles
-                tag.children()
-                    .top()
-                    .iter()
-                    .cloned()
-                    .collect::<Vec<_>>()
-                    .into_iter()
-                    .map(move |child_handle| NodeRef {
-                        vdom: self.vdom,
-                        handle: Some(child_handle),
-                    })
-            })
+        let children = self.data().map(|d| d.children.as_slice()).unwrap_or(&[]);
+        children.iter().map(move |&id| self.with_id(id))
     }
 
     /// Check if this node has children
     pub fn has_children(&self) -> bool {
-        self.handle
-            .and_then(|h| h.get(self.vdom.parser()))
-            .and_then(|node| node.as_tag())
-            .map(|tag| tag.children().top().iter().next().is_some())
-            .unwrap_or(false)
+        self.data().is_some_and(|d| !d.children.is_empty())
     }
 
     /// Get parent node if available
     pub fn parent(&self) -> Option<NodeRef<'a, 'input>> {
-        None
+        self.data()
+            .and_then(|d| d.parent)
+            .map(|id| self.with_id(id))
+    }
+
+    /// Byte offset where the node's markup starts in the source
+    ///
+    /// `None` for nodes that do not appear verbatim in the source, such as
+    /// implied HTML elements or content expanded from an entity.
+    pub fn source_offset(&self) -> Option<usize> {
+        let (span, markup_before) = match self.data().map(|d| &d.kind)? {
+            NodeKind::Element { name, .. } => (name, "<".len()),
+            NodeKind::CData(content) => (content, "<![CDATA[".len()),
+            NodeKind::ProcessingInstruction { target, .. } => (target, "<?".len()),
+            NodeKind::Text(span) | NodeKind::Comment(span) | NodeKind::Doctype(span) => (span, 0),
+        };
+        match span {
+            Str::Span(range) => range.start.checked_sub(markup_before),
+            _ => None,
+        }
+    }
+
+    /// Line and column where the node starts, see [`Self::source_offset`]
+    pub fn position(&self) -> Option<Position> {
+        self.source_offset()
+            .map(|offset| Position::from_offset(self.source, offset))
     }
 }
 
@@ -284,6 +561,24 @@ mod tests {
         assert_eq!(doc.source(), xml);
     }
 
+    #[test]
+    fn test_owned_document() {
+        fn assert_send_sync<T: Send + Sync + 'static>(_: &T) {}
+
+        let doc = OwnedDocument::parse(String::from("<root><a id=\"1\">x</a></root>"), DocumentType::Xml).unwrap();
+        assert_send_sync(&doc);
+        let doc = Arc::new(doc);
+
+        let shared = Arc::clone(&doc);
+        let handle = std::thread::spawn(move || {
+            let a = shared.root().children().next().unwrap();
+            (a.attr("id").map(Cow::into_owned), a.text())
+        });
+        assert_eq!(handle.join().unwrap(), (Some("1".to_string()), Some("x".to_string())));
+        assert_eq!(doc.root().tag_name().as_deref(), Some("root"));
+        assert_eq!(&*doc.shared_source(), doc.source());
+    }
+
     #[test]
     fn test_auto_detect_html() {
         let html = "<!DOCTYPE html><html><body>test</body></html>";
@@ -352,9 +647,12 @@ mod tests {
         // Use standard tags to avoid ambiguity in default parser mode
         let xml = "<div><p>1</p><p>2</p></div>";
         let doc = Document::parse(xml, DocumentType::Html).unwrap();
-        let root = doc.root();
+        // The HTML tree builder wraps the fragment in implied html/head/body
+        let body = doc.root().children().nth(1).unwrap();
+        assert_eq!(body.tag_name().as_deref(), Some("body"));
+        let div = body.children().next().unwrap();
 
-        let children: Vec<_> = root
+        let children: Vec<_> = div
             .children()
             .filter(|n| n.node_type() == NodeType::Element)
             .collect();
@@ -0,0 +1,230 @@
+//! Schema validation
+//!
+//! Validators check a parsed document against a schema and report every
+//! violation with the position of the offending node, instead of stopping at
+//! the first one. Supported schema languages:
+//! - XML Schema 1.0 ([`xsd::XsdSchema`])
+//! - RELAX NG, XML and compact syntax ([`relaxng::RelaxNg`])
+//! - ISO Schematron ([`schematron::Schematron`])
+//! - DTDs, from a file or the document's own DOCTYPE ([`dtd`])
+
+pub(crate) mod datatypes;
+pub mod dtd;
+pub mod relaxng;
+pub mod schematron;
+pub mod xsd;
+
+use crate::error::{Position, SchemaError};
+use crate::parser::encoding::decode;
+use crate::parser::{parse_with_options, ParseOptions};
+use crate::types::{Document, DocumentType, NodeRef};
+use std::borrow::Cow;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Namespace bound to the `xml` prefix
+pub(crate) const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// A schema that documents can be validated against
+pub trait Validator {
+    /// Validate a document, returning every violation (empty if it is valid)
+    fn validate(&self, doc: &Document<'_>) -> Vec<ValidationError>;
+}
+
+/// One schema violation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// Start of the offending node, when it was parsed from the source
+    pub position: Option<Position>,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub(crate) fn at(node: NodeRef<'_, '_>, message: impl Into<String>) -> Self {
+        Self {
+            position: node.position(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some(position) => write!(f, "{}: {}", position, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// Load a schema file, choosing the language by extension (`.xsd`, `.rng`,
+/// `.rnc`, `.sch`) or else by the namespace of its root element
+pub fn load_schema(path: impl AsRef<Path>) -> Result<Box<dyn Validator>, SchemaError> {
+    let path = path.as_ref();
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+    match extension.to_ascii_lowercase().as_str() {
+        "xsd" => return Ok(Box::new(xsd::XsdSchema::from_file(path)?)),
+        "rng" | "rnc" => return Ok(Box::new(relaxng::RelaxNg::from_file(path)?)),
+        "sch" => return Ok(Box::new(schematron::Schematron::from_file(path)?)),
+        "dtd" => return Ok(Box::new(dtd::load_dtd(path)?)),
+        _ => {}
+    }
+
+    let label = path.display().to_string();
+    let source = read_schema(path)?;
+    let doc = parse_schema(&source, &label)?;
+    let root = doc.root();
+    let tag = root.tag_name().unwrap_or_default();
+    let namespace = lookup_namespace(root, split_qname(&tag).0).unwrap_or_default();
+    match namespace.as_str() {
+        "http://www.w3.org/2001/XMLSchema" => Ok(Box::new(xsd::XsdSchema::from_file(path)?)),
+        "http://relaxng.org/ns/structure/1.0" => Ok(Box::new(relaxng::RelaxNg::from_file(path)?)),
+        "http://purl.oclc.org/dsdl/schematron" | "http://www.ascc.net/xml/schematron" => {
+            Ok(Box::new(schematron::Schematron::from_file(path)?))
+        }
+        _ => Err(SchemaError::Invalid {
+            path: label,
+            message: "unknown schema language; expected XML Schema, RELAX NG or Schematron".to_string(),
+        }),
+    }
+}
+
+/// Read a schema file, detecting its encoding
+pub(crate) fn read_schema(path: &Path) -> Result<String, SchemaError> {
+    let label = path.display().to_string();
+    let bytes = std::fs::read(path).map_err(|source| SchemaError::Io {
+        path: label.clone(),
+        source,
+    })?;
+    let decoded = decode(&bytes, None).map_err(|source| SchemaError::Parse { path: label, source })?;
+    Ok(decoded.text.into_owned())
+}
+
+/// Parse a schema document, which must be well-formed XML
+pub(crate) fn parse_schema<'s>(source: &'s str, label: &str) -> Result<Document<'s>, SchemaError> {
+    let options = ParseOptions {
+        strict: true,
+        ..ParseOptions::default()
+    };
+    parse_with_options(source, DocumentType::Xml, &options).map_err(|source| SchemaError::Parse {
+        path: label.to_string(),
+        source,
+    })
+}
+
+/// Resolve an include or import against the including schema's directory
+///
+/// Only local files are read; URLs are rejected.
+pub(crate) fn resolve_location(base_dir: Option<&Path>, location: &str) -> Result<PathBuf, String> {
+    if location.contains("://") {
+        return Err(format!(
+            "cannot load '{}': only local schema files are supported",
+            location
+        ));
+    }
+    match base_dir {
+        Some(base_dir) => Ok(base_dir.join(location)),
+        None => Err(format!("cannot resolve '{}' without a base directory", location)),
+    }
+}
+
+/// Namespace URI bound to `prefix` (`""` for the default namespace) at `node`
+///
+/// Returns `None` for an undeclared prefix; the default namespace is `""`
+/// when not declared.
+pub(crate) fn lookup_namespace(node: NodeRef<'_, '_>, prefix: &str) -> Option<String> {
+    if prefix == "xml" {
+        return Some(XML_NAMESPACE.to_string());
+    }
+    let mut current = Some(node);
+    while let Some(element) = current {
+        for (name, value) in element.attributes() {
+            let bound = match name.strip_prefix("xmlns") {
+                Some("") => prefix.is_empty(),
+                Some(rest) => rest.strip_prefix(':') == Some(prefix),
+                None => false,
+            };
+            if bound {
+                return Some(value.into_owned());
+            }
+        }
+        current = element.parent();
+    }
+    prefix.is_empty().then(String::new)
+}
+
+/// Split a qualified name into prefix and local part
+pub(crate) fn split_qname(name: &str) -> (&str, &str) {
+    name.split_once(':').unwrap_or(("", name))
+}
+
+/// Whether an attribute declares a namespace
+pub(crate) fn is_namespace_declaration(name: &str) -> bool {
+    name == "xmlns" || name.starts_with("xmlns:")
+}
+
+/// Replace the predefined entities and character references in raw text
+pub(crate) fn unescape(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let reference = &rest[1..end];
+        let decoded = match reference {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => reference.strip_prefix('#').and_then(|number| {
+                match number.strip_prefix('x') {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => number.parse().ok(),
+                }
+                .and_then(char::from_u32)
+            }),
+        };
+        match decoded {
+            Some(c) => {
+                out.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    Cow::Owned(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::DocumentType;
+
+    #[test]
+    fn test_lookup_namespace() {
+        let xml = r#"<a xmlns="urn:default" xmlns:p="urn:p"><b xmlns:p="urn:inner"><c/></b></a>"#;
+        let doc = Document::parse(xml, DocumentType::Xml).unwrap();
+        let b = doc.root().children().next().unwrap();
+        let c = b.children().next().unwrap();
+        assert_eq!(lookup_namespace(c, "p").as_deref(), Some("urn:inner"));
+        assert_eq!(lookup_namespace(c, "").as_deref(), Some("urn:default"));
+        assert_eq!(lookup_namespace(doc.root(), "q"), None);
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("a &lt;b&gt; &#65;&#x42; &amp;c; & d"), "a <b> AB &c; & d");
+        assert!(matches!(unescape("plain"), Cow::Borrowed(_)));
+    }
+}
@@ -0,0 +1,712 @@
+//! XML Schema built-in datatypes and facets
+//!
+//! Lexical checks for the XSD 1.0 built-in simple types and the constraining
+//! facets that restrict them. Values are compared in their value space where
+//! it matters (numbers, dates), so `1.0` equals `1` for a decimal enumeration.
+
+use crate::parser::xml::{is_name, is_name_char};
+use regex::Regex;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::sync::OnceLock;
+
+/// Whitespace handling of a simple type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum WhiteSpace {
+    Preserve,
+    /// Tabs and newlines become spaces
+    Replace,
+    /// Like `Replace`, then runs of spaces collapse and the ends are trimmed
+    Collapse,
+}
+
+impl WhiteSpace {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "preserve" => Some(Self::Preserve),
+            "replace" => Some(Self::Replace),
+            "collapse" => Some(Self::Collapse),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn apply<'v>(self, value: &'v str) -> Cow<'v, str> {
+        match self {
+            Self::Preserve => Cow::Borrowed(value),
+            Self::Replace if !value.contains(['\t', '\n', '\r']) => Cow::Borrowed(value),
+            Self::Replace => Cow::Owned(value.replace(['\t', '\n', '\r'], " ")),
+            Self::Collapse => {
+                let collapsed = value.split_ascii_whitespace().collect::<Vec<_>>().join(" ");
+                if collapsed == value {
+                    Cow::Borrowed(value)
+                } else {
+                    Cow::Owned(collapsed)
+                }
+            }
+        }
+    }
+}
+
+macro_rules! builtins {
+    ($($variant:ident => $name:literal,)*) => {
+        /// XSD 1.0 built-in simple types
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub(crate) enum Builtin {
+            $($variant,)*
+        }
+
+        impl Builtin {
+            pub(crate) fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $($name => Some(Self::$variant),)*
+                    _ => None,
+                }
+            }
+
+            pub(crate) fn name(self) -> &'static str {
+                match self {
+                    $(Self::$variant => $name,)*
+                }
+            }
+        }
+    };
+}
+
+builtins! {
+    AnySimpleType => "anySimpleType",
+    String => "string",
+    NormalizedString => "normalizedString",
+    Token => "token",
+    Language => "language",
+    Name => "Name",
+    NcName => "NCName",
+    Id => "ID",
+    IdRef => "IDREF",
+    IdRefs => "IDREFS",
+    Entity => "ENTITY",
+    Entities => "ENTITIES",
+    NmToken => "NMTOKEN",
+    NmTokens => "NMTOKENS",
+    Boolean => "boolean",
+    Decimal => "decimal",
+    Integer => "integer",
+    NonPositiveInteger => "nonPositiveInteger",
+    NegativeInteger => "negativeInteger",
+    Long => "long",
+    Int => "int",
+    Short => "short",
+    Byte => "byte",
+    NonNegativeInteger => "nonNegativeInteger",
+    UnsignedLong => "unsignedLong",
+    UnsignedInt => "unsignedInt",
+    UnsignedShort => "unsignedShort",
+    UnsignedByte => "unsignedByte",
+    PositiveInteger => "positiveInteger",
+    Float => "float",
+    Double => "double",
+    Duration => "duration",
+    DateTime => "dateTime",
+    Date => "date",
+    Time => "time",
+    GYear => "gYear",
+    GYearMonth => "gYearMonth",
+    GMonth => "gMonth",
+    GMonthDay => "gMonthDay",
+    GDay => "gDay",
+    HexBinary => "hexBinary",
+    Base64Binary => "base64Binary",
+    AnyUri => "anyURI",
+    QName => "QName",
+    Notation => "NOTATION",
+}
+
+impl Builtin {
+    pub(crate) fn whitespace(self) -> WhiteSpace {
+        match self {
+            Self::String | Self::AnySimpleType => WhiteSpace::Preserve,
+            Self::NormalizedString => WhiteSpace::Replace,
+            _ => WhiteSpace::Collapse,
+        }
+    }
+
+    /// Built-in list types (`IDREFS`, `ENTITIES`, `NMTOKENS`)
+    pub(crate) fn item_type(self) -> Option<Builtin> {
+        match self {
+            Self::IdRefs => Some(Self::IdRef),
+            Self::Entities => Some(Self::Entity),
+            Self::NmTokens => Some(Self::NmToken),
+            _ => None,
+        }
+    }
+
+    fn is_integer(self) -> bool {
+        self.integer_range().is_some()
+    }
+
+    /// Bounds of the integer types; `None` for non-integers
+    fn integer_range(self) -> Option<(Option<i128>, Option<i128>)> {
+        let range = match self {
+            Self::Integer => (None, None),
+            Self::NonPositiveInteger => (None, Some(0)),
+            Self::NegativeInteger => (None, Some(-1)),
+            Self::Long => (Some(i64::MIN as i128), Some(i64::MAX as i128)),
+            Self::Int => (Some(i32::MIN as i128), Some(i32::MAX as i128)),
+            Self::Short => (Some(i16::MIN as i128), Some(i16::MAX as i128)),
+            Self::Byte => (Some(i8::MIN as i128), Some(i8::MAX as i128)),
+            Self::NonNegativeInteger => (Some(0), None),
+            Self::UnsignedLong => (Some(0), Some(u64::MAX as i128)),
+            Self::UnsignedInt => (Some(0), Some(u32::MAX as i128)),
+            Self::UnsignedShort => (Some(0), Some(u16::MAX as i128)),
+            Self::UnsignedByte => (Some(0), Some(u8::MAX as i128)),
+            Self::PositiveInteger => (Some(1), None),
+            _ => return None,
+        };
+        Some(range)
+    }
+
+    /// Check a whitespace-normalized value against the lexical space
+    pub(crate) fn check(self, value: &str) -> bool {
+        match self {
+            Self::AnySimpleType | Self::String | Self::NormalizedString | Self::Token => true,
+            Self::AnyUri => !value.contains(['<', '>', '"', '{', '}', '|', '\\', '^', '`']),
+            Self::Language => matches(&LANGUAGE, r"^[a-zA-Z]{1,8}(-[a-zA-Z0-9]{1,8})*$", value),
+            Self::Name => is_name(value),
+            Self::NcName | Self::Id | Self::IdRef | Self::Entity => is_ncname(value),
+            Self::NmToken => !value.is_empty() && value.chars().all(is_name_char),
+            Self::IdRefs | Self::Entities | Self::NmTokens => {
+                let item = self.item_type().unwrap_or(Self::NmToken);
+                !value.is_empty() && value.split(' ').all(|v| item.check(v))
+            }
+            Self::QName | Self::Notation => match value.split_once(':') {
+                Some((prefix, local)) => is_ncname(prefix) && is_ncname(local),
+                None => is_ncname(value),
+            },
+            Self::Boolean => matches!(value, "true" | "false" | "1" | "0"),
+            Self::Decimal => parse_decimal(value).is_some(),
+            Self::Float | Self::Double => parse_float(value).is_some(),
+            Self::Duration => check_duration(value),
+            Self::DateTime | Self::Date | Self::Time | Self::GYear | Self::GYearMonth
+            | Self::GMonth | Self::GMonthDay | Self::GDay => parse_date(self, value).is_some(),
+            Self::HexBinary => value.len().is_multiple_of(2) && value.bytes().all(|b| b.is_ascii_hexdigit()),
+            Self::Base64Binary => base64_len(value).is_some(),
+            integer => {
+                let Some(decimal) = parse_decimal(value).filter(|d| d.fraction.is_empty()) else {
+                    return false;
+                };
+                if value.contains('.') {
+                    return false;
+                }
+                let (min, max) = integer.integer_range().unwrap_or((None, None));
+                match decimal.to_i128() {
+                    Some(n) => min.is_none_or(|min| n >= min) && max.is_none_or(|max| n <= max),
+                    // Too large for i128: only the unbounded directions are fine
+                    None => {
+                        if decimal.negative {
+                            min.is_none()
+                        } else {
+                            max.is_none()
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Compare two valid values in the value space; `None` when unordered
+    pub(crate) fn compare(self, a: &str, b: &str) -> Option<Ordering> {
+        match self {
+            Self::Decimal => Some(parse_decimal(a)?.cmp(&parse_decimal(b)?)),
+            _ if self.is_integer() => Some(parse_decimal(a)?.cmp(&parse_decimal(b)?)),
+            Self::Float | Self::Double => parse_float(a)?.partial_cmp(&parse_float(b)?),
+            Self::DateTime | Self::Date | Self::Time | Self::GYear | Self::GYearMonth
+            | Self::GMonth | Self::GMonthDay | Self::GDay => {
+                parse_date(self, a)?.partial_cmp(&parse_date(self, b)?)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether two valid values are equal in the value space
+    pub(crate) fn equal(self, a: &str, b: &str) -> bool {
+        match self.compare(a, b) {
+            Some(ordering) => ordering == Ordering::Equal,
+            None if self == Self::Boolean => {
+                matches!(a, "true" | "1") == matches!(b, "true" | "1")
+            }
+            None => a == b,
+        }
+    }
+
+    /// Length for the `length` facets: characters, octets or list items
+    pub(crate) fn length(self, value: &str) -> usize {
+        match self {
+            Self::HexBinary => value.len() / 2,
+            Self::Base64Binary => base64_len(value).unwrap_or(0),
+            Self::IdRefs | Self::Entities | Self::NmTokens => value.split(' ').count(),
+            _ => value.chars().count(),
+        }
+    }
+
+    /// Total and fraction digit counts of a decimal value
+    pub(crate) fn digits(self, value: &str) -> Option<(usize, usize)> {
+        let decimal = parse_decimal(value)?;
+        let integer = decimal.integer.len();
+        Some((integer + decimal.fraction.len(), decimal.fraction.len()))
+    }
+}
+
+/// A constraining facet on a simple type
+#[derive(Debug, Clone)]
+pub(crate) enum Facet {
+    Length(usize),
+    MinLength(usize),
+    MaxLength(usize),
+    /// Patterns given in one derivation step; a value must match one of them
+    Pattern(Vec<(String, Regex)>),
+    Enumeration(Vec<String>),
+    WhiteSpace(WhiteSpace),
+    MinInclusive(String),
+    MaxInclusive(String),
+    MinExclusive(String),
+    MaxExclusive(String),
+    TotalDigits(usize),
+    FractionDigits(usize),
+}
+
+impl Facet {
+    /// Facet named `kind` with the given value; `None` for names that are
+    /// not a single-valued facet (`pattern` and `enumeration` group values)
+    pub(crate) fn parse(kind: &str, value: &str) -> Result<Option<Self>, String> {
+        let count = || {
+            value
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| format!("invalid {} facet '{}'", kind, value))
+        };
+        let facet = match kind {
+            "length" => Facet::Length(count()?),
+            "minLength" => Facet::MinLength(count()?),
+            "maxLength" => Facet::MaxLength(count()?),
+            "totalDigits" => Facet::TotalDigits(count()?),
+            "fractionDigits" => Facet::FractionDigits(count()?),
+            "minInclusive" => Facet::MinInclusive(value.trim().to_string()),
+            "maxInclusive" => Facet::MaxInclusive(value.trim().to_string()),
+            "minExclusive" => Facet::MinExclusive(value.trim().to_string()),
+            "maxExclusive" => Facet::MaxExclusive(value.trim().to_string()),
+            "whiteSpace" => Facet::WhiteSpace(
+                WhiteSpace::from_name(value.trim())
+                    .ok_or_else(|| format!("invalid whiteSpace facet '{}'", value))?,
+            ),
+            _ => return Ok(None),
+        };
+        Ok(Some(facet))
+    }
+
+    /// Check a value of `base` type; returns the reason it is rejected
+    ///
+    /// `length` is the value's length for the length facets, which for list
+    /// types is the number of items.
+    pub(crate) fn check(&self, base: Builtin, value: &str, length: usize) -> Result<(), String> {
+        let ok = match self {
+            Facet::Length(n) => length == *n,
+            Facet::MinLength(n) => length >= *n,
+            Facet::MaxLength(n) => length <= *n,
+            Facet::Pattern(patterns) => patterns.iter().any(|(_, re)| re.is_match(value)),
+            Facet::Enumeration(values) => values.iter().any(|v| base.equal(v, value)),
+            Facet::WhiteSpace(_) => true,
+            Facet::MinInclusive(bound) => base.compare(value, bound).is_some_and(Ordering::is_ge),
+            Facet::MaxInclusive(bound) => base.compare(value, bound).is_some_and(Ordering::is_le),
+            Facet::MinExclusive(bound) => base.compare(value, bound).is_some_and(Ordering::is_gt),
+            Facet::MaxExclusive(bound) => base.compare(value, bound).is_some_and(Ordering::is_lt),
+            Facet::TotalDigits(n) => base.digits(value).is_some_and(|(total, _)| total <= *n),
+            Facet::FractionDigits(n) => base.digits(value).is_some_and(|(_, fraction)| fraction <= *n),
+        };
+        if ok {
+            return Ok(());
+        }
+
+        Err(match self {
+            Facet::Length(n) => format!("must have length {}", n),
+            Facet::MinLength(n) => format!("must have length at least {}", n),
+            Facet::MaxLength(n) => format!("must have length at most {}", n),
+            Facet::Pattern(patterns) => {
+                let list: Vec<_> = patterns.iter().map(|(p, _)| format!("'{}'", p)).collect();
+                format!("does not match pattern {}", list.join(" or "))
+            }
+            Facet::Enumeration(values) => {
+                let list: Vec<_> = values.iter().map(|v| format!("'{}'", v)).collect();
+                format!("must be one of {}", list.join(", "))
+            }
+            Facet::MinInclusive(bound) => format!("must be at least {}", bound),
+            Facet::MaxInclusive(bound) => format!("must be at most {}", bound),
+            Facet::MinExclusive(bound) => format!("must be greater than {}", bound),
+            Facet::MaxExclusive(bound) => format!("must be less than {}", bound),
+            Facet::TotalDigits(n) => format!("must have at most {} digits", n),
+            Facet::FractionDigits(n) => format!("must have at most {} fraction digits", n),
+            Facet::WhiteSpace(_) => unreachable!("whiteSpace never rejects a value"),
+        })
+    }
+}
+
+/// Translate an XML Schema regular expression to an anchored [`Regex`]
+///
+/// XSD patterns match the whole value, treat `^` and `$` literally, and add
+/// the `\i`/`\c` name classes and `[a-z-[aeiou]]` class subtraction.
+pub(crate) fn compile_pattern(pattern: &str) -> Result<Regex, String> {
+    const NAME_START: &str = r"\p{L}\p{Nl}_:";
+    const NAME: &str = r"\p{L}\p{Nl}\p{Nd}\p{Mn}\p{Mc}._:\-\x{B7}";
+    const WORD_EXCLUDED: &str = r"\p{P}\p{Z}\p{C}";
+
+    let mut out = String::from("^(?:");
+    let mut chars = pattern.chars().peekable();
+    // Nesting of character classes; subtractions open a nested class
+    let mut class_depth = 0usize;
+    while let Some(c) = chars.next() {
+        let in_class = class_depth > 0;
+        match c {
+            '\\' => {
+                let escaped = chars
+                    .next()
+                    .ok_or_else(|| format!("pattern '{}' ends with a backslash", pattern))?;
+                let translated = match (escaped, in_class) {
+                    ('i', true) => NAME_START.to_string(),
+                    ('c', true) => NAME.to_string(),
+                    ('i', false) => format!("[{}]", NAME_START),
+                    ('I', false) => format!("[^{}]", NAME_START),
+                    ('c', false) => format!("[{}]", NAME),
+                    ('C', false) => format!("[^{}]", NAME),
+                    ('w', false) => format!("[^{}]", WORD_EXCLUDED),
+                    ('W', false) => format!("[{}]", WORD_EXCLUDED),
+                    ('s', _) => if in_class { r" \t\n\r" } else { r"[ \t\n\r]" }.to_string(),
+                    ('S', false) => r"[^ \t\n\r]".to_string(),
+                    ('d' | 'D' | 'n' | 'r' | 't' | '\\' | '|' | '.' | '-' | '^' | '?' | '*' | '+'
+                    | '{' | '}' | '(' | ')' | '[' | ']', _) => format!("\\{}", escaped),
+                    ('p' | 'P', _) => {
+                        let mut property = String::new();
+                        for c in chars.by_ref() {
+                            property.push(c);
+                            if c == '}' {
+                                break;
+                            }
+                        }
+                        if property.starts_with("{Is") {
+                            return Err(format!("unsupported block escape \\{}{}", escaped, property));
+                        }
+                        format!("\\{}{}", escaped, property)
+                    }
+                    _ => return Err(format!("unsupported escape '\\{}' in pattern '{}'", escaped, pattern)),
+                };
+                out.push_str(&translated);
+            }
+            '[' if !in_class => {
+                class_depth = 1;
+                out.push('[');
+                if chars.peek() == Some(&'^') {
+                    out.push(chars.next().unwrap_or('^'));
+                }
+            }
+            '-' if in_class && chars.peek() == Some(&'[') => {
+                // Class subtraction: [a-z-[aeiou]] becomes [a-z&&[^aeiou]]
+                chars.next();
+                out.push_str("&&[^");
+                class_depth += 1;
+            }
+            ']' if in_class => {
+                class_depth -= 1;
+                out.push(']');
+            }
+            '[' | '&' | '~' if in_class => {
+                out.push('\\');
+                out.push(c);
+            }
+            '.' if !in_class => out.push_str(r"[^\n\r]"),
+            '^' | '$' if !in_class => {
+                out.push('\\');
+                out.push(c);
+            }
+            '^' if in_class => out.push_str(r"\^"),
+            _ => out.push(c),
+        }
+    }
+    out.push_str(")$");
+    Regex::new(&out).map_err(|e| format!("invalid pattern '{}': {}", pattern, e))
+}
+
+static LANGUAGE: OnceLock<Regex> = OnceLock::new();
+static DURATION: OnceLock<Regex> = OnceLock::new();
+static DATE: OnceLock<Regex> = OnceLock::new();
+
+fn matches(cell: &'static OnceLock<Regex>, pattern: &str, value: &str) -> bool {
+    cell.get_or_init(|| Regex::new(pattern).expect("valid built-in pattern"))
+        .is_match(value)
+}
+
+fn is_ncname(value: &str) -> bool {
+    is_name(value) && !value.contains(':')
+}
+
+/// A decimal number split into its digits, for exact comparison
+#[derive(Debug, PartialEq, Eq)]
+struct Decimal<'v> {
+    negative: bool,
+    /// Integer digits without leading zeros
+    integer: &'v str,
+    /// Fraction digits without trailing zeros
+    fraction: &'v str,
+}
+
+impl Decimal<'_> {
+    fn to_i128(&self) -> Option<i128> {
+        if self.integer.is_empty() {
+            return Some(0);
+        }
+        let n: i128 = self.integer.parse().ok()?;
+        Some(if self.negative { -n } else { n })
+    }
+
+    fn is_zero(&self) -> bool {
+        self.integer.is_empty() && self.fraction.is_empty()
+    }
+}
+
+impl Ord for Decimal<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let negative = self.negative && !self.is_zero();
+        let other_negative = other.negative && !other.is_zero();
+        if negative != other_negative {
+            return if negative { Ordering::Less } else { Ordering::Greater };
+        }
+        let magnitude = self
+            .integer
+            .len()
+            .cmp(&other.integer.len())
+            .then_with(|| self.integer.cmp(other.integer))
+            .then_with(|| self.fraction.cmp(other.fraction));
+        if negative {
+            magnitude.reverse()
+        } else {
+            magnitude
+        }
+    }
+}
+
+impl PartialOrd for Decimal<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+fn parse_decimal(value: &str) -> Option<Decimal<'_>> {
+    let (negative, digits) = match value.as_bytes().first() {
+        Some(b'-') => (true, &value[1..]),
+        Some(b'+') => (false, &value[1..]),
+        _ => (false, value),
+    };
+    let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if integer.is_empty() && fraction.is_empty()
+        || !integer.bytes().all(|b| b.is_ascii_digit())
+        || !fraction.bytes().all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    Some(Decimal {
+        negative,
+        integer: integer.trim_start_matches('0'),
+        fraction: fraction.trim_end_matches('0'),
+    })
+}
+
+fn parse_float(value: &str) -> Option<f64> {
+    match value {
+        "INF" => return Some(f64::INFINITY),
+        "-INF" => return Some(f64::NEG_INFINITY),
+        "NaN" => return Some(f64::NAN),
+        _ => {}
+    }
+    let (mantissa, exponent) = match value.find(['e', 'E']) {
+        Some(i) => (&value[..i], Some(&value[i + 1..])),
+        None => (value, None),
+    };
+    parse_decimal(mantissa)?;
+    if let Some(exponent) = exponent {
+        let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+    }
+    value.parse().ok()
+}
+
+fn check_duration(value: &str) -> bool {
+    matches(
+        &DURATION,
+        r"^-?P(?:\d+Y)?(?:\d+M)?(?:\d+D)?(?:T(?:\d+H)?(?:\d+M)?(?:\d+(?:\.\d+)?S)?)?$",
+        value,
+    ) && !value.ends_with('P')
+        && !value.ends_with('T')
+}
+
+/// Parse the date and time types into comparable components
+///
+/// Returns `[year, month, day, hour, minute, second]` in UTC when a timezone
+/// is given; missing components are zero.
+fn parse_date(kind: Builtin, value: &str) -> Option<[f64; 6]> {
+    let re = DATE.get_or_init(|| {
+        Regex::new(
+            r"^(?:(?P<year>-?\d{4,})(?:-(?P<month>\d{2})(?:-(?P<day>\d{2}))?)?|--(?P<gmonth>\d{2})(?:-(?P<gmday>\d{2}))?|---(?P<gday>\d{2}))?(?:T?(?P<time>(?P<hour>\d{2}):(?P<minute>\d{2}):(?P<second>\d{2}(?:\.\d+)?)))?(?P<tz>Z|[+-]\d{2}:\d{2})?$",
+        )
+        .expect("valid date pattern")
+    });
+    let caps = re.captures(value)?;
+    let has = |name: &str| caps.name(name).is_some();
+    let num = |name: &str| caps.name(name).map_or(Some(0.0), |m| m.as_str().parse().ok());
+
+    // Which components each type requires
+    let shape_ok = match kind {
+        Builtin::DateTime => has("day") && has("time") && value.contains('T'),
+        Builtin::Date => has("day") && !has("time"),
+        Builtin::Time => !has("year") && !has("gmonth") && !has("gday") && has("time") && !value.contains('T'),
+        Builtin::GYear => has("year") && !has("month") && !has("time"),
+        Builtin::GYearMonth => has("month") && !has("day") && !has("time"),
+        Builtin::GMonth => has("gmonth") && !has("gmday") && !has("time"),
+        Builtin::GMonthDay => has("gmday") && !has("time"),
+        Builtin::GDay => has("gday") && !has("time"),
+        _ => false,
+    };
+    if !shape_ok {
+        return None;
+    }
+
+    let year = num("year")?;
+    let month = if has("gmonth") { num("gmonth")? } else { num("month")? };
+    let day = if has("gmday") {
+        num("gmday")?
+    } else if has("gday") {
+        num("gday")?
+    } else {
+        num("day")?
+    };
+    let (hour, minute, second) = (num("hour")?, num("minute")?, num("second")?);
+
+    let month_ok = month == 0.0 && !has("month") && !has("gmonth") || (1.0..=12.0).contains(&month);
+    let day_ok = day == 0.0 && !has("day") && !has("gmday") && !has("gday")
+        || day >= 1.0 && day <= days_in_month(year, month);
+    let time_ok = hour < 24.0 && minute < 60.0 && second < 60.0
+        || hour == 24.0 && minute == 0.0 && second == 0.0;
+    if caps.name("year").is_some_and(|y| y.as_str().trim_start_matches('-') == "0000")
+        || !month_ok
+        || !day_ok
+        || !time_ok
+    {
+        return None;
+    }
+
+    let mut offset = 0.0;
+    if let Some(tz) = caps.name("tz").map(|m| m.as_str()).filter(|&tz| tz != "Z") {
+        let hours: f64 = tz[1..3].parse().ok()?;
+        let minutes: f64 = tz[4..6].parse().ok()?;
+        if minutes >= 60.0 || hours * 60.0 + minutes > 14.0 * 60.0 {
+            return None;
+        }
+        offset = (hours * 60.0 + minutes) * if tz.starts_with('-') { -1.0 } else { 1.0 };
+    }
+
+    // Normalizing to UTC only shifts the time of day; dates crossing
+    // midnight compare slightly off, which is fine for range facets
+    let minutes = hour * 60.0 + minute - offset;
+    Some([year, month, day, (minutes / 60.0).floor(), minutes.rem_euclid(60.0), second])
+}
+
+fn days_in_month(year: f64, month: f64) -> f64 {
+    match month as u32 {
+        4 | 6 | 9 | 11 => 30.0,
+        2 => {
+            let y = year as i64;
+            // Without a year (gMonthDay) February 29 is allowed
+            if y == 0 || y % 4 == 0 && (y % 100 != 0 || y % 400 == 0) {
+                29.0
+            } else {
+                28.0
+            }
+        }
+        _ => 31.0,
+    }
+}
+
+/// Number of octets encoded by a base64 value
+fn base64_len(value: &str) -> Option<usize> {
+    let data: Vec<u8> = value.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !data.len().is_multiple_of(4) {
+        return None;
+    }
+    let padding = data.iter().rev().take_while(|&&b| b == b'=').count();
+    if padding > 2
+        || !data[..data.len() - padding]
+            .iter()
+            .all(|&b| b.is_ascii_alphanumeric() || b == b'+' || b == b'/')
+    {
+        return None;
+    }
+    Some(data.len() / 4 * 3 - padding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lexical_spaces() {
+        assert!(Builtin::Int.check("-2147483648"));
+        assert!(!Builtin::Int.check("2147483648"));
+        assert!(!Builtin::PositiveInteger.check("0"));
+        assert!(Builtin::Integer.check("123456789012345678901234567890123456789012"));
+        assert!(!Builtin::Integer.check("1.0"));
+        assert!(Builtin::Decimal.check("-.5"));
+        assert!(Builtin::Double.check("1.5E-3") && Builtin::Float.check("INF"));
+        assert!(!Builtin::Double.check("1e"));
+        assert!(Builtin::Date.check("2024-02-29") && !Builtin::Date.check("2023-02-29"));
+        assert!(Builtin::DateTime.check("2024-01-31T23:59:59.5+01:00"));
+        assert!(!Builtin::DateTime.check("2024-01-31"));
+        assert!(Builtin::Time.check("24:00:00") && !Builtin::Time.check("24:00:01"));
+        assert!(Builtin::GMonthDay.check("--02-29") && Builtin::GDay.check("---31"));
+        assert!(Builtin::Duration.check("P1Y2MT3.5S") && !Builtin::Duration.check("P1YT"));
+        assert!(Builtin::Base64Binary.check("aGVsbG8=") && !Builtin::Base64Binary.check("aGVsbG8"));
+        assert!(Builtin::Language.check("en-GB") && !Builtin::Language.check("en_GB"));
+        assert!(Builtin::NmTokens.check("a b-c") && !Builtin::NcName.check("a:b"));
+    }
+
+    #[test]
+    fn test_facets_compare_values() {
+        let enumeration = Facet::Enumeration(vec!["1.50".to_string(), "3".to_string()]);
+        assert!(enumeration.check(Builtin::Decimal, "1.5", 3).is_ok());
+        assert!(enumeration.check(Builtin::String, "1.5", 3).is_err());
+
+        let max = Facet::MaxExclusive("-1.5".to_string());
+        assert!(max.check(Builtin::Decimal, "-2", 2).is_ok());
+        assert_eq!(
+            max.check(Builtin::Decimal, "-1.50", 5),
+            Err("must be less than -1.5".to_string())
+        );
+        assert!(Facet::MinInclusive("2024-01-01".into())
+            .check(Builtin::Date, "2023-12-31", 10)
+            .is_err());
+        assert!(Facet::TotalDigits(3).check(Builtin::Decimal, "12.30", 5).is_ok());
+        assert!(Facet::FractionDigits(1).check(Builtin::Decimal, "1.25", 4).is_err());
+    }
+
+    #[test]
+    fn test_compile_pattern() {
+        let re = compile_pattern(r"[A-Z]{2}\d{3}").unwrap();
+        assert!(re.is_match("AB123") && !re.is_match("xAB123"));
+
+        let vowels_removed = compile_pattern("[a-z-[aeiou]]+").unwrap();
+        assert!(vowels_removed.is_match("xyz") && !vowels_removed.is_match("abc"));
+
+        // '^' and '$' are ordinary characters
+        let anchors = compile_pattern(r"$\d+|^x").unwrap();
+        assert!(anchors.is_match("$10") && anchors.is_match("^x") && !anchors.is_match("x"));
+        assert!(compile_pattern("a$").unwrap().is_match("a$"));
+        assert!(compile_pattern(r"\i\c*").unwrap().is_match("_name-1"));
+        assert!(compile_pattern(r"\p{IsBasicLatin}").is_err());
+    }
+}
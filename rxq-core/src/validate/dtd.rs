@@ -0,0 +1,518 @@
+//! DTD validation
+//!
+//! Checks a document against the declarations of a [`Dtd`], either its own
+//! DOCTYPE or one loaded from a file:
+//! - the root element matches the DOCTYPE name
+//! - every element is declared and its content matches the content model
+//! - attributes are declared, required ones are present, fixed ones have
+//!   their value, and values fit their type
+//! - IDs are unique and every IDREF names an ID
+//!
+//! DTDs are not namespace aware, so names are compared as written and
+//! namespace declarations (`xmlns`, `xmlns:*`) need no declaration.
+
+use super::{is_namespace_declaration, read_schema, unescape, ValidationError, Validator};
+use crate::error::{Position, SchemaError};
+use crate::parser::dtd::{
+    AttributeDefault, AttributeType, ContentParticle, ContentSpec, Dtd, Entity, Occurrence,
+    ParticleKind,
+};
+use crate::parser::xml::{is_name, is_name_char};
+use crate::types::{Document, NodeRef, NodeType};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Load a DTD file, such as one named by a DOCTYPE's system identifier
+///
+/// External parameter entities are read relative to the file.
+pub fn load_dtd(path: impl AsRef<Path>) -> Result<Dtd, SchemaError> {
+    let path = path.as_ref();
+    let text = read_schema(path)?;
+    let mut dtd = Dtd::default();
+    let mut errors = Vec::new();
+    dtd.parse_file(&text, path.parent(), &mut errors);
+    match errors.into_iter().next() {
+        Some((offset, message)) => Err(SchemaError::Invalid {
+            path: path.display().to_string(),
+            message: format!("{}: {}", Position::from_offset(&text, offset), message),
+        }),
+        None => Ok(dtd),
+    }
+}
+
+/// Validate a document against its own DOCTYPE
+pub fn validate_doctype(doc: &Document<'_>) -> Vec<ValidationError> {
+    match doc.dtd() {
+        Some(dtd) => dtd.validate(doc),
+        None => vec![ValidationError {
+            position: None,
+            message: "document has no DOCTYPE".to_string(),
+        }],
+    }
+}
+
+impl Validator for Dtd {
+    fn validate(&self, doc: &Document<'_>) -> Vec<ValidationError> {
+        let mut run = Run {
+            dtd: self,
+            models: HashMap::new(),
+            errors: Vec::new(),
+            ids: HashSet::new(),
+            idrefs: Vec::new(),
+        };
+        if self.incomplete {
+            run.errors.push(ValidationError {
+                position: None,
+                message: "DTD is incomplete: an external subset or parameter entity was not loaded"
+                    .to_string(),
+            });
+        }
+
+        let root = doc.root();
+        if root.node_type() != NodeType::Element {
+            run.errors.push(ValidationError::at(root, "document has no root element"));
+            return run.errors;
+        }
+        let name = root.tag_name().unwrap_or_default();
+        if !self.name.is_empty() && name != self.name {
+            run.error(
+                root,
+                format!("root element <{}> does not match the DOCTYPE name '{}'", name, self.name),
+            );
+        }
+        run.element(root);
+
+        for (node, id) in std::mem::take(&mut run.idrefs) {
+            if !run.ids.contains(&id) {
+                run.error(node, format!("IDREF '{}' has no matching ID", id));
+            }
+        }
+        run.errors
+    }
+}
+
+/// State of one validation run
+struct Run<'d, 'a, 'i> {
+    dtd: &'d Dtd,
+    /// Compiled element content models, by element name
+    models: HashMap<&'d str, Nfa<'d>>,
+    errors: Vec<ValidationError>,
+    ids: HashSet<String>,
+    idrefs: Vec<(NodeRef<'a, 'i>, String)>,
+}
+
+impl<'d, 'a, 'i> Run<'d, 'a, 'i> {
+    fn error(&mut self, node: NodeRef<'_, '_>, message: impl Into<String>) {
+        self.errors.push(ValidationError::at(node, message));
+    }
+
+    fn element(&mut self, node: NodeRef<'a, 'i>) {
+        let name = node.tag_name().unwrap_or_default();
+        self.attributes(node, &name);
+
+        match self.dtd.elements.get_key_value(name.as_ref()) {
+            Some((key, spec)) => self.content(node, key, spec),
+            // Without every declaration, a missing one may just not be loaded
+            None if self.dtd.incomplete => {}
+            None => self.error(node, format!("no declaration for element <{}>", name)),
+        }
+
+        for child in node.children() {
+            if child.node_type() == NodeType::Element {
+                self.element(child);
+            }
+        }
+    }
+
+    fn content(&mut self, node: NodeRef<'a, 'i>, name: &'d str, spec: &'d ContentSpec) {
+        match spec {
+            ContentSpec::Any => {}
+            ContentSpec::Empty => {
+                let content = node.children().any(|c| {
+                    !matches!(c.node_type(), NodeType::Comment | NodeType::ProcessingInstruction)
+                });
+                if content {
+                    self.error(node, format!("element <{}> must be empty", name));
+                }
+            }
+            ContentSpec::Mixed(allowed) => {
+                for child in node.children().filter(|c| c.node_type() == NodeType::Element) {
+                    let child_name = child.tag_name().unwrap_or_default();
+                    if !allowed.iter().any(|a| *a == child_name) {
+                        let expected = expected_list(allowed.iter().map(String::as_str));
+                        self.error(
+                            child,
+                            format!("element <{}> is not expected here{}", child_name, expected),
+                        );
+                    }
+                }
+            }
+            ContentSpec::Children(particle) => {
+                let text = node.children().find(|c| match c.node_type() {
+                    NodeType::CData => true,
+                    NodeType::Text => !is_whitespace(&unescape(&c.text().unwrap_or_default())),
+                    _ => false,
+                });
+                if let Some(text) = text {
+                    self.error(text, format!("element <{}> cannot contain text", name));
+                }
+                self.children(node, name, particle);
+            }
+        }
+    }
+
+    fn children(&mut self, node: NodeRef<'a, 'i>, name: &'d str, particle: &'d ContentParticle) {
+        let nfa = self.models.remove(name).unwrap_or_else(|| Nfa::new(particle));
+        let mut states = nfa.closure(vec![nfa.start]);
+        for child in node.children().filter(|c| c.node_type() == NodeType::Element) {
+            let child_name = child.tag_name().unwrap_or_default();
+            let next = nfa.step(&states, &child_name);
+            if next.is_empty() {
+                // Skip the unexpected element and keep matching the rest
+                let expected = expected_list(nfa.expected(&states));
+                self.error(child, format!("element <{}> is not expected here{}", child_name, expected));
+            } else {
+                states = next;
+            }
+        }
+        if !states.contains(&nfa.accept) {
+            let expected = expected_list(nfa.expected(&states));
+            self.error(node, format!("element <{}> is incomplete{}", name, expected));
+        }
+        self.models.insert(name, nfa);
+    }
+
+    fn attributes(&mut self, node: NodeRef<'a, 'i>, element: &str) {
+        let decls = self.dtd.attributes.get(element).map(Vec::as_slice).unwrap_or_default();
+        let mut present = Vec::new();
+        for (name, value) in node.attributes() {
+            if is_namespace_declaration(&name) {
+                continue;
+            }
+            let Some(decl) = decls.iter().find(|d| d.name == name) else {
+                if !self.dtd.incomplete {
+                    self.error(node, format!("attribute '{}' is not allowed on <{}>", name, element));
+                }
+                continue;
+            };
+            present.push(decl.name.as_str());
+
+            let raw = unescape(&value);
+            // Attributes of any type but CDATA have their whitespace collapsed
+            let value = match decl.kind {
+                AttributeType::CData => raw.into_owned(),
+                _ => raw.split_ascii_whitespace().collect::<Vec<_>>().join(" "),
+            };
+            if let Err(reason) = self.check_value(node, &decl.kind, &value) {
+                self.error(
+                    node,
+                    format!("value '{}' of attribute '{}' on <{}> {}", value, name, element, reason),
+                );
+            }
+            if let AttributeDefault::Fixed(fixed) = &decl.default {
+                if value != *fixed {
+                    self.error(
+                        node,
+                        format!("attribute '{}' on <{}> must have the fixed value '{}'", name, element, fixed),
+                    );
+                }
+            }
+        }
+
+        for decl in decls {
+            if decl.default == AttributeDefault::Required && !present.contains(&decl.name.as_str()) {
+                self.error(
+                    node,
+                    format!("missing required attribute '{}' on <{}>", decl.name, element),
+                );
+            }
+        }
+    }
+
+    /// Check a normalized value against its declared type
+    fn check_value(&mut self, node: NodeRef<'a, 'i>, kind: &AttributeType, value: &str) -> Result<(), String> {
+        match kind {
+            AttributeType::CData => {}
+            AttributeType::Id => {
+                if !is_name(value) {
+                    return Err("is not a valid ID".to_string());
+                }
+                if !self.ids.insert(value.to_string()) {
+                    return Err("is a duplicate ID".to_string());
+                }
+            }
+            AttributeType::IdRef | AttributeType::IdRefs => {
+                let list = *kind == AttributeType::IdRefs;
+                if !is_names(value, list, is_name) {
+                    return Err(format!("is not a valid {}", if list { "IDREFS" } else { "IDREF" }));
+                }
+                for id in value.split(' ') {
+                    self.idrefs.push((node, id.to_string()));
+                }
+            }
+            AttributeType::Entity | AttributeType::Entities => {
+                let list = *kind == AttributeType::Entities;
+                if !is_names(value, list, is_name) {
+                    return Err(format!("is not a valid {}", if list { "ENTITIES" } else { "ENTITY" }));
+                }
+                for name in value.split(' ') {
+                    let unparsed = matches!(
+                        self.dtd.entities.get(name),
+                        Some(Entity::External { notation: Some(_), .. })
+                    );
+                    if !unparsed && !self.dtd.incomplete {
+                        return Err(format!("names '{}', which is not an unparsed entity", name));
+                    }
+                }
+            }
+            AttributeType::NmToken | AttributeType::NmTokens => {
+                let list = *kind == AttributeType::NmTokens;
+                if !is_names(value, list, is_nmtoken) {
+                    return Err(format!("is not a valid {}", if list { "NMTOKENS" } else { "NMTOKEN" }));
+                }
+            }
+            AttributeType::Notation(values) | AttributeType::Enumeration(values) => {
+                if !values.iter().any(|v| v == value) {
+                    let list: Vec<_> = values.iter().map(|v| format!("'{}'", v)).collect();
+                    return Err(format!("must be one of {}", list.join(", ")));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// "; expected <a>, <b>" for a list of element names
+fn expected_list<'n>(names: impl IntoIterator<Item = &'n str>) -> String {
+    let list: Vec<_> = names.into_iter().map(|n| format!("<{}>", n)).collect();
+    if list.is_empty() {
+        String::new()
+    } else {
+        format!("; expected {}", list.join(", "))
+    }
+}
+
+/// One value, or a space-separated list of values when `list` is set
+fn is_names(value: &str, list: bool, check: fn(&str) -> bool) -> bool {
+    if list {
+        !value.is_empty() && value.split(' ').all(check)
+    } else {
+        check(value)
+    }
+}
+
+fn is_nmtoken(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(is_name_char)
+}
+
+fn is_whitespace(text: &str) -> bool {
+    text.bytes().all(|b| matches!(b, b' ' | b'\t' | b'\n' | b'\r'))
+}
+
+/// An element content model as a nondeterministic automaton
+///
+/// Each state has edges labelled with an element name, or unlabelled edges
+/// that are followed without consuming an element.
+struct Nfa<'d> {
+    edges: Vec<Vec<(Option<&'d str>, usize)>>,
+    start: usize,
+    accept: usize,
+}
+
+impl<'d> Nfa<'d> {
+    fn new(particle: &'d ContentParticle) -> Self {
+        let mut nfa = Nfa {
+            edges: Vec::new(),
+            start: 0,
+            accept: 0,
+        };
+        let (start, accept) = nfa.particle(particle);
+        nfa.start = start;
+        nfa.accept = accept;
+        nfa
+    }
+
+    fn state(&mut self) -> usize {
+        self.edges.push(Vec::new());
+        self.edges.len() - 1
+    }
+
+    fn edge(&mut self, from: usize, label: Option<&'d str>, to: usize) {
+        self.edges[from].push((label, to));
+    }
+
+    /// Add a particle; returns its entry and exit states
+    fn particle(&mut self, particle: &'d ContentParticle) -> (usize, usize) {
+        let (first, last) = match &particle.kind {
+            ParticleKind::Element(name) => {
+                let (start, end) = (self.state(), self.state());
+                self.edge(start, Some(name), end);
+                (start, end)
+            }
+            ParticleKind::Sequence(items) => {
+                let start = self.state();
+                let mut end = start;
+                for item in items {
+                    let (first, last) = self.particle(item);
+                    self.edge(end, None, first);
+                    end = last;
+                }
+                (start, end)
+            }
+            ParticleKind::Choice(items) => {
+                let (start, end) = (self.state(), self.state());
+                for item in items {
+                    let (first, last) = self.particle(item);
+                    self.edge(start, None, first);
+                    self.edge(last, None, end);
+                }
+                (start, end)
+            }
+        };
+
+        if particle.occurrence == Occurrence::Once {
+            return (first, last);
+        }
+        let (start, end) = (self.state(), self.state());
+        self.edge(start, None, first);
+        self.edge(last, None, end);
+        if matches!(particle.occurrence, Occurrence::Optional | Occurrence::ZeroOrMore) {
+            self.edge(start, None, end);
+        }
+        if matches!(particle.occurrence, Occurrence::ZeroOrMore | Occurrence::OneOrMore) {
+            self.edge(last, None, first);
+        }
+        (start, end)
+    }
+
+    /// States reachable through unlabelled edges, sorted
+    fn closure(&self, mut pending: Vec<usize>) -> Vec<usize> {
+        let mut seen = vec![false; self.edges.len()];
+        let mut states = Vec::new();
+        while let Some(state) = pending.pop() {
+            if std::mem::replace(&mut seen[state], true) {
+                continue;
+            }
+            states.push(state);
+            for &(label, to) in &self.edges[state] {
+                if label.is_none() {
+                    pending.push(to);
+                }
+            }
+        }
+        states.sort_unstable();
+        states
+    }
+
+    fn step(&self, states: &[usize], name: &str) -> Vec<usize> {
+        let next = states
+            .iter()
+            .flat_map(|&s| &self.edges[s])
+            .filter(|(label, _)| *label == Some(name))
+            .map(|&(_, to)| to)
+            .collect();
+        self.closure(next)
+    }
+
+    /// Element names that can follow, in model order
+    fn expected(&self, states: &[usize]) -> Vec<&'d str> {
+        let mut names = Vec::new();
+        for &state in states {
+            for &(label, _) in &self.edges[state] {
+                if let Some(name) = label {
+                    if !names.contains(&name) {
+                        names.push(name);
+                    }
+                }
+            }
+        }
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_with_options, ParseOptions};
+    use crate::types::DocumentType;
+
+    fn errors(xml: &str) -> Vec<String> {
+        let options = ParseOptions {
+            strict: true,
+            ..ParseOptions::default()
+        };
+        let doc = parse_with_options(xml, DocumentType::Xml, &options).unwrap();
+        validate_doctype(&doc).iter().map(|e| e.to_string()).collect()
+    }
+
+    const MEMO: &str = r#"<!DOCTYPE memo [
+  <!ELEMENT memo (to+, from, (body | attachment*), signed?)>
+  <!ELEMENT to (#PCDATA)>
+  <!ELEMENT from (#PCDATA | b)*>
+  <!ELEMENT b (#PCDATA)>
+  <!ELEMENT body ANY>
+  <!ELEMENT attachment EMPTY>
+  <!ELEMENT signed EMPTY>
+  <!ATTLIST memo id ID #REQUIRED
+                 reply IDREFS #IMPLIED
+                 priority (low | high) "low"
+                 version CDATA #FIXED "1">
+  <!ATTLIST attachment name NMTOKEN #REQUIRED>
+]>"#;
+
+    #[test]
+    fn test_valid_document() {
+        let xml = format!(
+            r#"{}<memo id="m1" reply=" m1 " priority="high"><to>A</to><to>B</to><from>me <b>!</b></from><attachment name="a.txt"/><attachment name="b"/></memo>"#,
+            MEMO
+        );
+        assert!(errors(&xml).is_empty());
+    }
+
+    #[test]
+    fn test_content_models() {
+        let xml = format!(
+            "{}\n<memo id=\"m1\">text<from/><to/><body/><signed>x</signed><note/></memo>",
+            MEMO
+        );
+        assert_eq!(
+            errors(&xml),
+            [
+                "15:15: element <memo> cannot contain text",
+                "15:19: element <from> is not expected here; expected <to>",
+                "15:31: element <body> is not expected here; expected <to>, <from>",
+                "15:38: element <signed> is not expected here; expected <to>, <from>",
+                "15:56: element <note> is not expected here; expected <to>, <from>",
+                "15:1: element <memo> is incomplete; expected <to>, <from>",
+                "15:38: element <signed> must be empty",
+                "15:56: no declaration for element <note>",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_attributes_and_ids() {
+        let xml = format!(
+            r#"{}<memo id="1" reply="m2" priority="urgent" version="2" lang="en"><to>A</to><from/><attachment/></memo>"#,
+            MEMO
+        );
+        assert_eq!(
+            errors(&xml),
+            [
+                "14:3: value '1' of attribute 'id' on <memo> is not a valid ID",
+                "14:3: value 'urgent' of attribute 'priority' on <memo> must be one of 'low', 'high'",
+                "14:3: attribute 'version' on <memo> must have the fixed value '1'",
+                "14:3: attribute 'lang' is not allowed on <memo>",
+                "14:84: missing required attribute 'name' on <attachment>",
+                "14:3: IDREF 'm2' has no matching ID",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_root_name_and_missing_doctype() {
+        let xml = "<!DOCTYPE a [<!ELEMENT a EMPTY><!ELEMENT b EMPTY>]><b/>";
+        assert_eq!(errors(xml), ["1:52: root element <b> does not match the DOCTYPE name 'a'"]);
+        assert_eq!(errors("<a/>"), ["document has no DOCTYPE"]);
+    }
+}
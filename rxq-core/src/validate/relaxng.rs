@@ -0,0 +1,1581 @@
+//! RELAX NG validation
+//!
+//! Schemas in the XML syntax (`.rng`) and the compact syntax (`.rnc`) are read
+//! into one syntax tree, then compiled into patterns that are matched with
+//! derivatives, following James Clark's algorithm. Supported:
+//! - grammars with `start`, `define` (including `combine`), `ref`,
+//!   `parentRef`, `div`, and `include` and `externalRef` of local files
+//! - every pattern and name class of RELAX NG 1.0
+//! - the built-in `string` and `token` datatypes and the XML Schema datatype
+//!   library, with facets as `param`s
+//!
+//! After an error, validation recovers by skipping the unexpected element,
+//! attribute or text, so one run reports every violation.
+
+mod compact;
+
+use super::datatypes::{compile_pattern, Builtin, Facet};
+use super::{
+    is_namespace_declaration, lookup_namespace, parse_schema, read_schema, resolve_location,
+    split_qname, unescape, ValidationError, Validator,
+};
+use crate::error::SchemaError;
+use crate::types::{Document, NodeRef, NodeType};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+const RNG: &str = "http://relaxng.org/ns/structure/1.0";
+const XSD_DATATYPES: &str = "http://www.w3.org/2001/XMLSchema-datatypes";
+
+/// A pattern as written in either syntax, before references are resolved
+#[derive(Debug, Clone)]
+enum Syntax {
+    Element(NameClass, Box<Syntax>),
+    Attribute(NameClass, Box<Syntax>),
+    Group(Vec<Syntax>),
+    Interleave(Vec<Syntax>),
+    Choice(Vec<Syntax>),
+    Optional(Box<Syntax>),
+    ZeroOrMore(Box<Syntax>),
+    OneOrMore(Box<Syntax>),
+    List(Box<Syntax>),
+    Mixed(Box<Syntax>),
+    Empty,
+    Text,
+    NotAllowed,
+    Ref(String),
+    ParentRef(String),
+    /// `externalRef`, with the namespace it inherits
+    ExternalRef(PathBuf, String),
+    Data {
+        library: String,
+        name: String,
+        params: Vec<(String, String)>,
+        except: Option<Box<Syntax>>,
+    },
+    Value {
+        library: String,
+        name: String,
+        value: String,
+    },
+    Grammar(Vec<Component>),
+}
+
+/// Content of a `grammar`
+#[derive(Debug, Clone)]
+enum Component {
+    Start(Option<Combine>, Syntax),
+    Define(String, Option<Combine>, Syntax),
+    Div(Vec<Component>),
+    /// `include`, with the namespace it inherits and the overriding components
+    Include(PathBuf, String, Vec<Component>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combine {
+    Choice,
+    Interleave,
+}
+
+impl Combine {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "choice" => Some(Self::Choice),
+            "interleave" => Some(Self::Interleave),
+            _ => None,
+        }
+    }
+}
+
+/// A set of expanded names
+#[derive(Debug, Clone)]
+enum NameClass {
+    Name(String, String),
+    AnyName(Option<Box<NameClass>>),
+    NsName(String, Option<Box<NameClass>>),
+    Choice(Box<NameClass>, Box<NameClass>),
+}
+
+impl NameClass {
+    fn contains(&self, ns: &str, local: &str) -> bool {
+        match self {
+            NameClass::Name(n, l) => n == ns && l == local,
+            NameClass::AnyName(except) => except.as_ref().is_none_or(|e| !e.contains(ns, local)),
+            NameClass::NsName(n, except) => {
+                n == ns && except.as_ref().is_none_or(|e| !e.contains(ns, local))
+            }
+            NameClass::Choice(a, b) => a.contains(ns, local) || b.contains(ns, local),
+        }
+    }
+
+    /// Names for messages: `<name>` for elements, `'name'` for attributes
+    fn describe(&self, element: bool, out: &mut Vec<String>) {
+        let text = match self {
+            NameClass::Name(_, local) if element => format!("<{}>", local),
+            NameClass::Name(_, local) => format!("'{}'", local),
+            NameClass::AnyName(_) | NameClass::NsName(..) if element => "any element".to_string(),
+            NameClass::AnyName(_) | NameClass::NsName(..) => "any attribute".to_string(),
+            NameClass::Choice(a, b) => {
+                a.describe(element, out);
+                b.describe(element, out);
+                return;
+            }
+        };
+        if !out.contains(&text) {
+            out.push(text);
+        }
+    }
+}
+
+/// A datatype with its facets
+#[derive(Debug)]
+struct Datatype {
+    builtin: Builtin,
+    facets: Vec<Facet>,
+}
+
+impl Datatype {
+    fn new(library: &str, name: &str, params: &[(String, String)]) -> Result<Self, String> {
+        let builtin = match library {
+            "" => {
+                let builtin = match name {
+                    "string" => Builtin::String,
+                    "token" => Builtin::Token,
+                    _ => return Err(format!("unknown built-in datatype '{}'", name)),
+                };
+                if !params.is_empty() {
+                    return Err(format!("datatype '{}' takes no parameters", name));
+                }
+                builtin
+            }
+            XSD_DATATYPES => {
+                Builtin::from_name(name).ok_or_else(|| format!("unknown XML Schema datatype '{}'", name))?
+            }
+            _ => return Err(format!("datatype library '{}' is not supported", library)),
+        };
+
+        let mut facets = Vec::new();
+        for (kind, value) in params {
+            // Each param restricts the type further, so patterns are not alternatives
+            let facet = match kind.as_str() {
+                "pattern" => Facet::Pattern(vec![(value.clone(), compile_pattern(value)?)]),
+                _ => Facet::parse(kind, value)?
+                    .ok_or_else(|| format!("unknown parameter '{}' for datatype '{}'", kind, name))?,
+            };
+            facets.push(facet);
+        }
+        Ok(Self { builtin, facets })
+    }
+
+    /// Check a value; returns the reason it is rejected
+    fn check(&self, value: &str) -> Result<(), String> {
+        let normalized = self.builtin.whitespace().apply(value);
+        if !self.builtin.check(&normalized) {
+            return Err(format!("is not a valid {}", self.builtin.name()));
+        }
+        let length = self.builtin.length(&normalized);
+        for facet in &self.facets {
+            facet.check(self.builtin, &normalized, length)?;
+        }
+        Ok(())
+    }
+
+    fn equal(&self, a: &str, b: &str) -> bool {
+        let ws = self.builtin.whitespace();
+        let (a, b) = (ws.apply(a), ws.apply(b));
+        self.builtin.check(&b) && self.builtin.equal(&a, &b)
+    }
+}
+
+/// Index of a pattern in an [`Arena`]
+type P = u32;
+
+const EMPTY: P = 0;
+const NOT_ALLOWED: P = 1;
+const TEXT: P = 2;
+
+/// A compiled pattern; indexes refer to the schema's tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Pat {
+    Empty,
+    NotAllowed,
+    Text,
+    Choice(P, P),
+    Interleave(P, P),
+    Group(P, P),
+    OneOrMore(P),
+    List(P),
+    /// Datatype and the `except` pattern (`NOT_ALLOWED` for none)
+    Data(u32, P),
+    Value(u32),
+    Attribute(u32, P),
+    Element(u32, P),
+    /// Content still to match in the current element, then what follows it
+    After(P, P),
+    /// A `define`, resolved through [`Arena::defs`]
+    Ref(u32),
+}
+
+/// Hash-consed patterns, so equal patterns share one index
+#[derive(Debug, Clone)]
+struct Arena {
+    pats: Vec<Pat>,
+    index: HashMap<Pat, P>,
+    defs: Vec<P>,
+}
+
+impl Arena {
+    fn new() -> Self {
+        let mut arena = Self {
+            pats: Vec::new(),
+            index: HashMap::new(),
+            defs: Vec::new(),
+        };
+        for pat in [Pat::Empty, Pat::NotAllowed, Pat::Text] {
+            arena.mk(pat);
+        }
+        arena
+    }
+
+    fn mk(&mut self, pat: Pat) -> P {
+        if let Some(&p) = self.index.get(&pat) {
+            return p;
+        }
+        let p = self.pats.len() as P;
+        self.pats.push(pat);
+        self.index.insert(pat, p);
+        p
+    }
+
+    /// The pattern at `p`, following references
+    fn get(&self, mut p: P) -> Pat {
+        loop {
+            match self.pats[p as usize] {
+                Pat::Ref(def) => p = self.defs[def as usize],
+                pat => return pat,
+            }
+        }
+    }
+
+    fn choice(&mut self, a: P, b: P) -> P {
+        match (a, b) {
+            (NOT_ALLOWED, _) => b,
+            (_, NOT_ALLOWED) => a,
+            _ if a == b => a,
+            _ => self.mk(Pat::Choice(a, b)),
+        }
+    }
+
+    fn group(&mut self, a: P, b: P) -> P {
+        match (a, b) {
+            (NOT_ALLOWED, _) | (_, NOT_ALLOWED) => NOT_ALLOWED,
+            (EMPTY, _) => b,
+            (_, EMPTY) => a,
+            _ => self.mk(Pat::Group(a, b)),
+        }
+    }
+
+    fn interleave(&mut self, a: P, b: P) -> P {
+        match (a, b) {
+            (NOT_ALLOWED, _) | (_, NOT_ALLOWED) => NOT_ALLOWED,
+            (EMPTY, _) => b,
+            (_, EMPTY) => a,
+            _ => self.mk(Pat::Interleave(a, b)),
+        }
+    }
+
+    fn after(&mut self, a: P, b: P) -> P {
+        match (a, b) {
+            (NOT_ALLOWED, _) | (_, NOT_ALLOWED) => NOT_ALLOWED,
+            _ => self.mk(Pat::After(a, b)),
+        }
+    }
+
+    fn one_or_more(&mut self, p: P) -> P {
+        match p {
+            NOT_ALLOWED | EMPTY => p,
+            _ => self.mk(Pat::OneOrMore(p)),
+        }
+    }
+}
+
+/// A compiled RELAX NG schema
+#[derive(Debug)]
+pub struct RelaxNg {
+    arena: Arena,
+    start: P,
+    names: Vec<NameClass>,
+    datatypes: Vec<Datatype>,
+    /// `value` patterns: datatype index and value
+    values: Vec<(u32, String)>,
+}
+
+impl RelaxNg {
+    /// Load a schema file; `.rnc` files are read as compact syntax
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, SchemaError> {
+        let path = path.as_ref();
+        let label = path.display().to_string();
+        let mut compiler = Compiler::new(label);
+        let syntax = compiler.load(path, "")?;
+        compiler.compile(&syntax)
+    }
+
+    /// Compile a schema in the XML syntax
+    pub fn from_source(source: &str, base_dir: Option<&Path>) -> Result<Self, SchemaError> {
+        let label = "<schema>".to_string();
+        let syntax = XmlReader::read(source, &label, base_dir, "")?;
+        Compiler::new(label).compile(&syntax)
+    }
+
+    /// Compile a schema in the compact syntax
+    pub fn from_compact(source: &str, base_dir: Option<&Path>) -> Result<Self, SchemaError> {
+        let label = "<schema>".to_string();
+        let syntax = compact::parse(source, &label, base_dir, "")?;
+        Compiler::new(label).compile(&syntax)
+    }
+}
+
+/// Definitions of one name (or of `start`) with their `combine` methods
+type Parts = Vec<(Option<Combine>, Syntax)>;
+
+/// Turns syntax trees into patterns, loading included files
+struct Compiler {
+    path: String,
+    arena: Arena,
+    names: Vec<NameClass>,
+    datatypes: Vec<Datatype>,
+    values: Vec<(u32, String)>,
+    /// Defines of each enclosing grammar, innermost last
+    scopes: Vec<HashMap<String, u32>>,
+    /// Name of each define, by index
+    def_names: Vec<String>,
+    /// Files being loaded, to reject circular includes
+    loading: Vec<PathBuf>,
+}
+
+impl Compiler {
+    fn new(path: String) -> Self {
+        Self {
+            path,
+            arena: Arena::new(),
+            names: Vec::new(),
+            datatypes: Vec::new(),
+            values: Vec::new(),
+            scopes: Vec::new(),
+            def_names: Vec::new(),
+            loading: Vec::new(),
+        }
+    }
+
+    fn invalid(&self, message: impl Into<String>) -> SchemaError {
+        SchemaError::Invalid {
+            path: self.path.clone(),
+            message: message.into(),
+        }
+    }
+
+    /// Read a schema file in either syntax; unqualified names default to `ns`
+    fn load(&mut self, path: &Path, ns: &str) -> Result<Syntax, SchemaError> {
+        let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if self.loading.contains(&key) {
+            return Err(self.invalid(format!("'{}' includes itself", path.display())));
+        }
+        let source = read_schema(path)?;
+        let label = path.display().to_string();
+        let syntax = if path.extension().is_some_and(|e| e == "rnc") {
+            compact::parse(&source, &label, path.parent(), ns)?
+        } else {
+            XmlReader::read(&source, &label, path.parent(), ns)?
+        };
+        self.loading.push(key);
+        Ok(syntax)
+    }
+
+    fn compile(mut self, syntax: &Syntax) -> Result<RelaxNg, SchemaError> {
+        let start = self.pattern(syntax)?;
+        self.check_recursion()?;
+        Ok(RelaxNg {
+            arena: self.arena,
+            start,
+            names: self.names,
+            datatypes: self.datatypes,
+            values: self.values,
+        })
+    }
+
+    fn pattern(&mut self, syntax: &Syntax) -> Result<P, SchemaError> {
+        Ok(match syntax {
+            Syntax::Element(name, content) | Syntax::Attribute(name, content) => {
+                let content = self.pattern(content)?;
+                let index = self.names.len() as u32;
+                self.names.push(name.clone());
+                match syntax {
+                    Syntax::Element(..) => self.arena.mk(Pat::Element(index, content)),
+                    _ => self.arena.mk(Pat::Attribute(index, content)),
+                }
+            }
+            Syntax::Group(items) => self.fold(items, Arena::group)?,
+            Syntax::Interleave(items) => self.fold(items, Arena::interleave)?,
+            Syntax::Choice(items) => self.fold(items, Arena::choice)?,
+            Syntax::Optional(p) => {
+                let p = self.pattern(p)?;
+                self.arena.choice(p, EMPTY)
+            }
+            Syntax::ZeroOrMore(p) => {
+                let p = self.pattern(p)?;
+                let more = self.arena.one_or_more(p);
+                self.arena.choice(more, EMPTY)
+            }
+            Syntax::OneOrMore(p) => {
+                let p = self.pattern(p)?;
+                self.arena.one_or_more(p)
+            }
+            Syntax::List(p) => {
+                let p = self.pattern(p)?;
+                self.arena.mk(Pat::List(p))
+            }
+            Syntax::Mixed(p) => {
+                let p = self.pattern(p)?;
+                self.arena.interleave(TEXT, p)
+            }
+            Syntax::Empty => EMPTY,
+            Syntax::Text => TEXT,
+            Syntax::NotAllowed => NOT_ALLOWED,
+            Syntax::Ref(name) => self.reference(name, 1)?,
+            Syntax::ParentRef(name) => self.reference(name, 2)?,
+            Syntax::ExternalRef(path, ns) => {
+                let syntax = self.load(path, ns)?;
+                let p = self.pattern(&syntax)?;
+                self.loading.pop();
+                p
+            }
+            Syntax::Data {
+                library,
+                name,
+                params,
+                except,
+            } => {
+                let datatype = Datatype::new(library, name, params).map_err(|m| self.invalid(m))?;
+                let except = match except {
+                    Some(except) => self.pattern(except)?,
+                    None => NOT_ALLOWED,
+                };
+                self.datatypes.push(datatype);
+                self.arena.mk(Pat::Data(self.datatypes.len() as u32 - 1, except))
+            }
+            Syntax::Value { library, name, value } => {
+                let datatype = Datatype::new(library, name, &[]).map_err(|m| self.invalid(m))?;
+                if let Err(reason) = datatype.check(value) {
+                    return Err(self.invalid(format!("value '{}' {}", value, reason)));
+                }
+                self.datatypes.push(datatype);
+                self.values.push((self.datatypes.len() as u32 - 1, value.clone()));
+                self.arena.mk(Pat::Value(self.values.len() as u32 - 1))
+            }
+            Syntax::Grammar(components) => self.grammar(components)?,
+        })
+    }
+
+    fn fold(&mut self, items: &[Syntax], combine: fn(&mut Arena, P, P) -> P) -> Result<P, SchemaError> {
+        let mut result = None;
+        for item in items {
+            let p = self.pattern(item)?;
+            result = Some(match result {
+                Some(acc) => combine(&mut self.arena, acc, p),
+                None => p,
+            });
+        }
+        result.ok_or_else(|| self.invalid("empty group, interleave or choice"))
+    }
+
+    /// A `ref` (`up` = 1) or `parentRef` (`up` = 2)
+    fn reference(&mut self, name: &str, up: usize) -> Result<P, SchemaError> {
+        let def = self
+            .scopes
+            .len()
+            .checked_sub(up)
+            .and_then(|i| self.scopes[i].get(name))
+            .copied()
+            .ok_or_else(|| self.invalid(format!("reference to undefined pattern '{}'", name)))?;
+        Ok(self.arena.mk(Pat::Ref(def)))
+    }
+
+    fn grammar(&mut self, components: &[Component]) -> Result<P, SchemaError> {
+        let mut starts = Vec::new();
+        let mut defines: Vec<(String, Parts)> = Vec::new();
+        self.collect(components, &mut starts, &mut defines)?;
+        if starts.is_empty() {
+            return Err(self.invalid("grammar has no start"));
+        }
+
+        let mut scope = HashMap::new();
+        for (name, _) in &defines {
+            scope.insert(name.clone(), self.arena.defs.len() as u32);
+            self.arena.defs.push(NOT_ALLOWED);
+            self.def_names.push(name.clone());
+        }
+        self.scopes.push(scope.clone());
+        let result = (|| {
+            for (name, parts) in &defines {
+                let p = self.combine(name, parts)?;
+                self.arena.defs[scope[name] as usize] = p;
+            }
+            self.combine("start", &starts)
+        })();
+        self.scopes.pop();
+        result
+    }
+
+    /// Merge the definitions of one name according to their `combine`
+    fn combine(&mut self, name: &str, parts: &Parts) -> Result<P, SchemaError> {
+        let mut combine = None;
+        let mut plain = 0;
+        for (method, _) in parts {
+            match method {
+                None => plain += 1,
+                Some(method) if combine.is_some_and(|c| c != *method) => {
+                    return Err(self.invalid(format!("conflicting combine methods for '{}'", name)));
+                }
+                Some(method) => combine = Some(*method),
+            }
+        }
+        if plain > 1 || (parts.len() > 1 && combine.is_none()) {
+            return Err(self.invalid(format!("'{}' is defined more than once without combine", name)));
+        }
+
+        let mut result = None;
+        for (_, syntax) in parts {
+            let p = self.pattern(syntax)?;
+            result = Some(match (result, combine) {
+                (None, _) => p,
+                (Some(acc), Some(Combine::Interleave)) => self.arena.interleave(acc, p),
+                (Some(acc), _) => self.arena.choice(acc, p),
+            });
+        }
+        Ok(result.unwrap_or(NOT_ALLOWED))
+    }
+
+    fn collect(
+        &mut self,
+        components: &[Component],
+        starts: &mut Parts,
+        defines: &mut Vec<(String, Parts)>,
+    ) -> Result<(), SchemaError> {
+        for component in components {
+            match component {
+                Component::Start(combine, syntax) => starts.push((*combine, syntax.clone())),
+                Component::Define(name, combine, syntax) => {
+                    match defines.iter_mut().find(|(n, _)| n == name) {
+                        Some((_, parts)) => parts.push((*combine, syntax.clone())),
+                        None => defines.push((name.clone(), vec![(*combine, syntax.clone())])),
+                    }
+                }
+                Component::Div(components) => self.collect(components, starts, defines)?,
+                Component::Include(path, ns, overrides) => {
+                    let Syntax::Grammar(included) = self.load(path, ns)? else {
+                        return Err(self.invalid(format!("included '{}' is not a grammar", path.display())));
+                    };
+                    let mut replaced = HashSet::new();
+                    overridden(overrides, &mut replaced);
+                    let included = without(&included, &replaced);
+                    let result = self.collect(&included, starts, defines);
+                    self.loading.pop();
+                    result?;
+                    self.collect(overrides, starts, defines)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reject references that recurse without passing through an element
+    fn check_recursion(&self) -> Result<(), SchemaError> {
+        fn visit(arena: &Arena, p: P, stack: &mut Vec<u32>, done: &mut HashSet<u32>) -> Result<(), u32> {
+            match arena.pats[p as usize] {
+                Pat::Ref(def) if stack.contains(&def) => Err(def),
+                Pat::Ref(def) if done.contains(&def) => Ok(()),
+                Pat::Ref(def) => {
+                    stack.push(def);
+                    visit(arena, arena.defs[def as usize], stack, done)?;
+                    stack.pop();
+                    done.insert(def);
+                    Ok(())
+                }
+                Pat::Choice(a, b) | Pat::Interleave(a, b) | Pat::Group(a, b) | Pat::After(a, b) => {
+                    visit(arena, a, stack, done)?;
+                    visit(arena, b, stack, done)
+                }
+                Pat::OneOrMore(a) | Pat::List(a) | Pat::Data(_, a) | Pat::Attribute(_, a) => {
+                    visit(arena, a, stack, done)
+                }
+                _ => Ok(()),
+            }
+        }
+
+        let mut done = HashSet::new();
+        for def in 0..self.arena.defs.len() as u32 {
+            if let Err(def) = visit(&self.arena, self.arena.defs[def as usize], &mut vec![def], &mut done) {
+                let name = &self.def_names[def as usize];
+                return Err(self.invalid(format!("'{}' refers to itself outside an element", name)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Names that `include` overrides; `start` stands for the start component
+fn overridden(components: &[Component], names: &mut HashSet<String>) {
+    for component in components {
+        match component {
+            Component::Start(..) => {
+                names.insert("start".to_string());
+            }
+            Component::Define(name, ..) => {
+                names.insert(name.clone());
+            }
+            Component::Div(inner) => overridden(inner, names),
+            Component::Include(..) => {}
+        }
+    }
+}
+
+/// Components of an included grammar, minus the overridden ones
+fn without(components: &[Component], names: &HashSet<String>) -> Vec<Component> {
+    components
+        .iter()
+        .filter_map(|component| match component {
+            Component::Start(..) if names.contains("start") => None,
+            Component::Define(name, ..) if names.contains(name) => None,
+            Component::Div(inner) => Some(Component::Div(without(inner, names))),
+            other => Some(other.clone()),
+        })
+        .collect()
+}
+
+/// Reads the XML syntax
+struct XmlReader<'p> {
+    base_dir: Option<&'p Path>,
+}
+
+impl XmlReader<'_> {
+    fn read(source: &str, path: &str, base_dir: Option<&Path>, ns: &str) -> Result<Syntax, SchemaError> {
+        let doc = parse_schema(source, path)?;
+        let reader = XmlReader { base_dir };
+        reader.pattern(doc.root(), ns, "").map_err(|message| SchemaError::Invalid {
+            path: path.to_string(),
+            message,
+        })
+    }
+
+    fn pattern(&self, node: NodeRef<'_, '_>, ns: &str, library: &str) -> Result<Syntax, String> {
+        let kind = rng_name(node).ok_or_else(|| {
+            format!("<{}> is not a RELAX NG pattern", node.tag_name().unwrap_or_default())
+        })?;
+        let own_ns = node.attr("ns").map(|v| unescape(&v).into_owned());
+        let ns = own_ns.as_deref().unwrap_or(ns);
+        let own_library = node.attr("datatypeLibrary").map(|v| unescape(&v).into_owned());
+        let library = own_library.as_deref().unwrap_or(library);
+        let children = rng_children(node);
+
+        Ok(match kind {
+            "element" | "attribute" => {
+                let mut rest = children.as_slice();
+                let name = match node.attr("name") {
+                    Some(name) => {
+                        // An unprefixed attribute name has no namespace unless `ns` is given
+                        let ns = if kind == "attribute" { own_ns.as_deref().unwrap_or("") } else { ns };
+                        qname(node, name.trim(), ns)?
+                    }
+                    None => {
+                        let (first, others) = rest
+                            .split_first()
+                            .ok_or_else(|| format!("{} needs a name", kind))?;
+                        rest = others;
+                        self.name_class(*first, ns)?
+                    }
+                };
+                let content = match rest {
+                    [] if kind == "attribute" => Syntax::Text,
+                    [] => return Err("element needs a content pattern".to_string()),
+                    _ => self.group(rest, ns, library)?,
+                };
+                if kind == "element" {
+                    Syntax::Element(name, Box::new(content))
+                } else {
+                    Syntax::Attribute(name, Box::new(content))
+                }
+            }
+            "group" => Syntax::Group(self.patterns(&children, ns, library)?),
+            "interleave" => Syntax::Interleave(self.patterns(&children, ns, library)?),
+            "choice" => Syntax::Choice(self.patterns(&children, ns, library)?),
+            "optional" => Syntax::Optional(Box::new(self.group(&children, ns, library)?)),
+            "zeroOrMore" => Syntax::ZeroOrMore(Box::new(self.group(&children, ns, library)?)),
+            "oneOrMore" => Syntax::OneOrMore(Box::new(self.group(&children, ns, library)?)),
+            "list" => Syntax::List(Box::new(self.group(&children, ns, library)?)),
+            "mixed" => Syntax::Mixed(Box::new(self.group(&children, ns, library)?)),
+            "empty" => Syntax::Empty,
+            "text" => Syntax::Text,
+            "notAllowed" => Syntax::NotAllowed,
+            "ref" | "parentRef" => {
+                let name = node
+                    .attr("name")
+                    .ok_or_else(|| format!("{} needs a name", kind))?
+                    .trim()
+                    .to_string();
+                if kind == "ref" {
+                    Syntax::Ref(name)
+                } else {
+                    Syntax::ParentRef(name)
+                }
+            }
+            "externalRef" => Syntax::ExternalRef(self.href(node)?, ns.to_string()),
+            "data" => {
+                let name = node.attr("type").ok_or("data needs a type")?.trim().to_string();
+                let mut params = Vec::new();
+                let mut except = None;
+                for child in children {
+                    match rng_name(child) {
+                        Some("param") => {
+                            let param = child.attr("name").ok_or("param needs a name")?;
+                            params.push((param.trim().to_string(), text(child)));
+                        }
+                        Some("except") => {
+                            let patterns = self.patterns(&rng_children(child), ns, library)?;
+                            except = Some(Box::new(Syntax::Choice(patterns)));
+                        }
+                        _ => return Err("data may only contain param and except".to_string()),
+                    }
+                }
+                Syntax::Data {
+                    library: library.to_string(),
+                    name,
+                    params,
+                    except,
+                }
+            }
+            "value" => {
+                let (library, name) = match node.attr("type") {
+                    Some(name) => (library.to_string(), name.trim().to_string()),
+                    None => (String::new(), "token".to_string()),
+                };
+                Syntax::Value {
+                    library,
+                    name,
+                    value: text(node),
+                }
+            }
+            "grammar" => Syntax::Grammar(self.components(&children, ns, library)?),
+            _ => return Err(format!("<{}> is not a RELAX NG pattern", kind)),
+        })
+    }
+
+    fn patterns(&self, nodes: &[NodeRef<'_, '_>], ns: &str, library: &str) -> Result<Vec<Syntax>, String> {
+        if nodes.is_empty() {
+            return Err("expected a pattern".to_string());
+        }
+        nodes.iter().map(|&n| self.pattern(n, ns, library)).collect()
+    }
+
+    /// Several patterns as one; more than one form a group
+    fn group(&self, nodes: &[NodeRef<'_, '_>], ns: &str, library: &str) -> Result<Syntax, String> {
+        let mut patterns = self.patterns(nodes, ns, library)?;
+        Ok(if patterns.len() == 1 {
+            patterns.remove(0)
+        } else {
+            Syntax::Group(patterns)
+        })
+    }
+
+    fn components(&self, nodes: &[NodeRef<'_, '_>], ns: &str, library: &str) -> Result<Vec<Component>, String> {
+        let mut components = Vec::new();
+        for &node in nodes {
+            let ns_attr = node.attr("ns").map(|v| unescape(&v).into_owned());
+            let ns = ns_attr.as_deref().unwrap_or(ns);
+            let library_attr = node.attr("datatypeLibrary").map(|v| unescape(&v).into_owned());
+            let library = library_attr.as_deref().unwrap_or(library);
+            let combine = match node.attr("combine") {
+                Some(c) => Some(Combine::from_name(c.trim()).ok_or_else(|| format!("invalid combine '{}'", c))?),
+                None => None,
+            };
+            let children = rng_children(node);
+            let component = match rng_name(node) {
+                Some("start") => Component::Start(combine, self.group(&children, ns, library)?),
+                Some("define") => {
+                    let name = node.attr("name").ok_or("define needs a name")?;
+                    Component::Define(name.trim().to_string(), combine, self.group(&children, ns, library)?)
+                }
+                Some("div") => Component::Div(self.components(&children, ns, library)?),
+                Some("include") => Component::Include(
+                    self.href(node)?,
+                    ns.to_string(),
+                    self.components(&children, ns, library)?,
+                ),
+                _ => {
+                    let tag = node.tag_name().unwrap_or_default();
+                    return Err(format!("<{}> is not allowed in a grammar", tag));
+                }
+            };
+            components.push(component);
+        }
+        Ok(components)
+    }
+
+    fn name_class(&self, node: NodeRef<'_, '_>, ns: &str) -> Result<NameClass, String> {
+        let own_ns = node.attr("ns").map(|v| unescape(&v).into_owned());
+        let ns = own_ns.as_deref().unwrap_or(ns);
+        let except = |reader: &Self| -> Result<Option<Box<NameClass>>, String> {
+            match rng_children(node).first() {
+                Some(&except) if rng_name(except) == Some("except") => {
+                    Ok(Some(Box::new(reader.name_class_choice(&rng_children(except), ns)?)))
+                }
+                Some(_) => Err("name class may only contain except".to_string()),
+                None => Ok(None),
+            }
+        };
+        match rng_name(node) {
+            Some("name") => qname(node, text(node).trim(), ns),
+            Some("anyName") => Ok(NameClass::AnyName(except(self)?)),
+            Some("nsName") => Ok(NameClass::NsName(ns.to_string(), except(self)?)),
+            Some("choice") => self.name_class_choice(&rng_children(node), ns),
+            _ => Err(format!(
+                "<{}> is not a name class",
+                node.tag_name().unwrap_or_default()
+            )),
+        }
+    }
+
+    fn name_class_choice(&self, nodes: &[NodeRef<'_, '_>], ns: &str) -> Result<NameClass, String> {
+        let mut result: Option<NameClass> = None;
+        for &node in nodes {
+            let class = self.name_class(node, ns)?;
+            result = Some(match result {
+                Some(acc) => NameClass::Choice(Box::new(acc), Box::new(class)),
+                None => class,
+            });
+        }
+        result.ok_or_else(|| "expected a name class".to_string())
+    }
+
+    fn href(&self, node: NodeRef<'_, '_>) -> Result<PathBuf, String> {
+        let href = node.attr("href").ok_or("missing href")?;
+        resolve_location(self.base_dir, unescape(href.trim()).as_ref())
+    }
+}
+
+/// Resolve a QName from the schema; unprefixed names take `ns`
+fn qname(node: NodeRef<'_, '_>, name: &str, ns: &str) -> Result<NameClass, String> {
+    let (prefix, local) = split_qname(name);
+    let ns = if prefix.is_empty() {
+        ns.to_string()
+    } else {
+        lookup_namespace(node, prefix).ok_or_else(|| format!("namespace prefix '{}' is not declared", prefix))?
+    };
+    Ok(NameClass::Name(ns, local.to_string()))
+}
+
+/// Text content of a schema element
+fn text(node: NodeRef<'_, '_>) -> String {
+    let mut text = String::new();
+    for child in node.children() {
+        match child.node_type() {
+            NodeType::Text => text.push_str(&unescape(&child.text().unwrap_or_default())),
+            NodeType::CData => text.push_str(&child.text().unwrap_or_default()),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Local name of an element in the RELAX NG namespace
+fn rng_name(node: NodeRef<'_, '_>) -> Option<&'static str> {
+    let tag = node.tag_name()?;
+    let (prefix, local) = split_qname(&tag);
+    if lookup_namespace(node, prefix).as_deref() != Some(RNG) {
+        return None;
+    }
+    // The reader matches on static names
+    const NAMES: [&str; 28] = [
+        "element", "attribute", "group", "interleave", "choice", "optional", "zeroOrMore",
+        "oneOrMore", "list", "mixed", "ref", "parentRef", "empty", "text", "value", "data",
+        "notAllowed", "externalRef", "grammar", "param", "except", "div", "include", "start",
+        "define", "name", "anyName", "nsName",
+    ];
+    NAMES.into_iter().find(|&n| n == local)
+}
+
+/// Child elements in the RELAX NG namespace; foreign elements are annotations
+fn rng_children<'a, 'i>(node: NodeRef<'a, 'i>) -> Vec<NodeRef<'a, 'i>> {
+    node.children().filter(|&child| rng_name(child).is_some()).collect()
+}
+
+impl Validator for RelaxNg {
+    fn validate(&self, doc: &Document<'_>) -> Vec<ValidationError> {
+        let mut run = Run {
+            schema: self,
+            arena: self.arena.clone(),
+            errors: Vec::new(),
+        };
+        let root = doc.root();
+        if root.node_type() != NodeType::Element {
+            run.errors.push(ValidationError::at(root, "document has no root element"));
+            return run.errors;
+        }
+        run.element(self.start, root);
+        run.errors
+    }
+}
+
+/// What to wrap around the continuation of an `After`
+#[derive(Clone, Copy)]
+enum Then {
+    /// `x & p`
+    InterleaveRight(P),
+    /// `p & x`
+    InterleaveLeft(P),
+    /// `x , p`
+    Group(P),
+    /// `After(x, p)`
+    After(P),
+}
+
+/// State of one validation run
+struct Run<'s> {
+    schema: &'s RelaxNg,
+    /// The schema's patterns plus the derivatives built while matching
+    arena: Arena,
+    errors: Vec<ValidationError>,
+}
+
+impl Run<'_> {
+    fn error(&mut self, node: NodeRef<'_, '_>, message: impl Into<String>) {
+        self.errors.push(ValidationError::at(node, message));
+    }
+
+    /// Match one element against `p`; returns the pattern for what follows it
+    fn element(&mut self, p: P, node: NodeRef<'_, '_>) -> P {
+        let tag = node.tag_name().unwrap_or_default();
+        let (prefix, local) = split_qname(&tag);
+        let ns = lookup_namespace(node, prefix).unwrap_or_default();
+
+        let opened = self.start_tag_open(p, &ns, local);
+        if opened == NOT_ALLOWED {
+            let expected = self.expected_elements(p);
+            self.error(node, format!("element <{}> is not expected here{}", local, expected));
+            return p;
+        }
+
+        let mut state = opened;
+        for (name, value) in node.attributes() {
+            if is_namespace_declaration(&name) {
+                continue;
+            }
+            let value = unescape(&value);
+            let (attr_ns, attr_local) = match name.split_once(':') {
+                Some((prefix, local)) => (lookup_namespace(node, prefix).unwrap_or_default(), local),
+                None => (String::new(), name.as_ref()),
+            };
+            let next = self.attribute(state, &attr_ns, attr_local, Some(&value));
+            if next != NOT_ALLOWED {
+                state = next;
+                continue;
+            }
+            let message = match self.attribute_content(state, &attr_ns, attr_local) {
+                Some(content) => {
+                    let reason = self.value_error(content, &value);
+                    state = self.attribute(state, &attr_ns, attr_local, None);
+                    format!("value '{}' of attribute '{}' on <{}> {}", value, name, local, reason)
+                }
+                None => format!("attribute '{}' is not allowed on <{}>", name, local),
+            };
+            self.error(node, message);
+        }
+
+        let mut closed = self.start_tag_close(state, false);
+        if closed == NOT_ALLOWED {
+            let missing = self.required_attributes(state);
+            if missing.is_empty() {
+                self.error(node, format!("missing required attribute on <{}>", local));
+            }
+            for name in missing {
+                self.error(node, format!("missing required attribute {} on <{}>", name, local));
+            }
+            closed = self.start_tag_close(state, true);
+        }
+
+        let Some(content) = self.children(closed, node, local) else {
+            return self.skip_content(closed);
+        };
+        let ended = self.end_tag(content);
+        if ended != NOT_ALLOWED {
+            return ended;
+        }
+        let expected = self.expected_elements(content);
+        let message = if expected.is_empty() && self.allows_text(content) {
+            self.text_error(content, "", local)
+        } else {
+            format!("element <{}> is incomplete{}", local, expected)
+        };
+        self.error(node, message);
+        self.skip_content(content)
+    }
+
+    /// Match the children of an element; `None` after an invalid value
+    fn children(&mut self, mut p: P, node: NodeRef<'_, '_>, local: &str) -> Option<P> {
+        // Adjacent text and CDATA sections form one text child
+        let mut children: Vec<(NodeRef<'_, '_>, Option<String>)> = Vec::new();
+        for child in node.children() {
+            let text = match child.node_type() {
+                NodeType::Element => {
+                    children.push((child, None));
+                    continue;
+                }
+                NodeType::Text => unescape(&child.text().unwrap_or_default()).into_owned(),
+                NodeType::CData => child.text().unwrap_or_default(),
+                _ => continue,
+            };
+            match children.last_mut() {
+                Some((_, Some(previous))) => previous.push_str(&text),
+                _ => children.push((child, Some(text))),
+            }
+        }
+
+        // Text alone is matched as a value, even when empty or whitespace
+        if children.iter().all(|(_, text)| text.is_some()) {
+            let text = children.first().and_then(|(_, t)| t.clone()).unwrap_or_default();
+            let next = self.text(p, &text);
+            if next != NOT_ALLOWED {
+                return Some(if is_whitespace(&text) { self.arena.choice(p, next) } else { next });
+            }
+            // Missing content is reported when the element ends
+            if is_whitespace(&text) {
+                return Some(p);
+            }
+            let message = self.text_error(p, &text, local);
+            self.error(node, message);
+            return None;
+        }
+
+        for (child, text) in children {
+            match text {
+                None => p = self.element(p, child),
+                Some(text) if is_whitespace(&text) => {}
+                Some(text) => {
+                    let next = self.text(p, &text);
+                    if next == NOT_ALLOWED {
+                        let message = self.text_error(p, &text, local);
+                        self.error(child, message);
+                    } else {
+                        p = next;
+                    }
+                }
+            }
+        }
+        Some(p)
+    }
+
+    /// Whether a datatype, value or list could match text at `p`
+    fn allows_text(&self, p: P) -> bool {
+        let mut found = Found::default();
+        self.text_patterns(p, &mut found, 0);
+        !found.values.is_empty() || !found.data.is_empty() || found.list
+    }
+
+    fn text_error(&self, p: P, text: &str, local: &str) -> String {
+        if !self.allows_text(p) {
+            return format!("element <{}> cannot contain text", local);
+        }
+        format!("value '{}' of element <{}> {}", text.trim(), local, self.value_error(p, text))
+    }
+
+    /// Why `text` does not match the datatypes and values allowed at `p`
+    fn value_error(&self, p: P, text: &str) -> String {
+        let mut found = Found::default();
+        self.text_patterns(p, &mut found, 0);
+        for &dt in &found.data {
+            if let Err(reason) = self.schema.datatypes[dt as usize].check(text) {
+                return reason;
+            }
+        }
+        if !found.values.is_empty() && found.data.is_empty() {
+            let list: Vec<_> = found.values.iter().map(|v| format!("'{}'", v)).collect();
+            return format!("must be one of {}", list.join(", "));
+        }
+        "is not allowed".to_string()
+    }
+
+    /// Datatypes and values that could match text at `p`
+    fn text_patterns(&self, p: P, found: &mut Found, depth: usize) {
+        if depth > MAX_DEPTH {
+            return;
+        }
+        match self.arena.get(p) {
+            Pat::Choice(a, b) | Pat::Interleave(a, b) => {
+                self.text_patterns(a, found, depth + 1);
+                self.text_patterns(b, found, depth + 1);
+            }
+            Pat::Group(a, b) => {
+                self.text_patterns(a, found, depth + 1);
+                if self.nullable(a) {
+                    self.text_patterns(b, found, depth + 1);
+                }
+            }
+            Pat::OneOrMore(a) | Pat::After(a, _) | Pat::Attribute(_, a) => self.text_patterns(a, found, depth + 1),
+            Pat::Data(dt, _) => found.data.push(dt),
+            Pat::Value(v) => {
+                let value = self.schema.values[v as usize].1.clone();
+                if !found.values.contains(&value) {
+                    found.values.push(value);
+                }
+            }
+            Pat::List(_) => found.list = true,
+            _ => {}
+        }
+    }
+
+    /// "; expected <a>, <b>" for the elements allowed at `p`
+    fn expected_elements(&self, p: P) -> String {
+        let mut names = Vec::new();
+        self.first_elements(p, &mut names, 0);
+        if names.is_empty() {
+            String::new()
+        } else {
+            format!("; expected {}", names.join(", "))
+        }
+    }
+
+    fn first_elements(&self, p: P, out: &mut Vec<String>, depth: usize) {
+        if depth > MAX_DEPTH {
+            return;
+        }
+        match self.arena.get(p) {
+            Pat::Choice(a, b) | Pat::Interleave(a, b) => {
+                self.first_elements(a, out, depth + 1);
+                self.first_elements(b, out, depth + 1);
+            }
+            Pat::Group(a, b) => {
+                self.first_elements(a, out, depth + 1);
+                if self.nullable(a) {
+                    self.first_elements(b, out, depth + 1);
+                }
+            }
+            Pat::OneOrMore(a) | Pat::After(a, _) => self.first_elements(a, out, depth + 1),
+            Pat::Element(name, _) => self.schema.names[name as usize].describe(true, out),
+            _ => {}
+        }
+    }
+
+    /// Attributes every alternative of `p` still requires
+    fn required_attributes(&self, p: P) -> Vec<String> {
+        let mut out = Vec::new();
+        match self.arena.get(p) {
+            Pat::Attribute(name, _) => self.schema.names[name as usize].describe(false, &mut out),
+            Pat::Group(a, b) | Pat::Interleave(a, b) => {
+                out = self.required_attributes(a);
+                for name in self.required_attributes(b) {
+                    if !out.contains(&name) {
+                        out.push(name);
+                    }
+                }
+            }
+            Pat::Choice(a, b) => {
+                let right = self.required_attributes(b);
+                out = self.required_attributes(a);
+                out.retain(|name| right.contains(name));
+            }
+            Pat::OneOrMore(a) | Pat::After(a, _) => out = self.required_attributes(a),
+            _ => {}
+        }
+        out.retain(|name| name != "'any attribute'" && name != "any attribute");
+        out
+    }
+
+    /// Content pattern of the attribute named `ns`/`local` allowed at `p`
+    fn attribute_content(&self, p: P, ns: &str, local: &str) -> Option<P> {
+        match self.arena.get(p) {
+            Pat::Attribute(name, content) if self.schema.names[name as usize].contains(ns, local) => {
+                Some(content)
+            }
+            Pat::Choice(a, b) | Pat::Group(a, b) | Pat::Interleave(a, b) => self
+                .attribute_content(a, ns, local)
+                .or_else(|| self.attribute_content(b, ns, local)),
+            Pat::OneOrMore(a) | Pat::After(a, _) => self.attribute_content(a, ns, local),
+            _ => None,
+        }
+    }
+
+    /// After an incomplete element, continue with what follows it
+    fn skip_content(&mut self, p: P) -> P {
+        match self.arena.get(p) {
+            Pat::After(_, b) => b,
+            Pat::Choice(a, b) => {
+                let a = self.skip_content(a);
+                let b = self.skip_content(b);
+                self.arena.choice(a, b)
+            }
+            _ => NOT_ALLOWED,
+        }
+    }
+
+    fn nullable(&self, p: P) -> bool {
+        match self.arena.get(p) {
+            Pat::Empty | Pat::Text => true,
+            Pat::Group(a, b) | Pat::Interleave(a, b) => self.nullable(a) && self.nullable(b),
+            Pat::Choice(a, b) => self.nullable(a) || self.nullable(b),
+            Pat::OneOrMore(a) => self.nullable(a),
+            _ => false,
+        }
+    }
+
+    fn text(&mut self, p: P, text: &str) -> P {
+        match self.arena.get(p) {
+            Pat::Choice(a, b) => {
+                let (a, b) = (self.text(a, text), self.text(b, text));
+                self.arena.choice(a, b)
+            }
+            Pat::Interleave(a, b) => {
+                let da = self.text(a, text);
+                let left = self.arena.interleave(da, b);
+                let db = self.text(b, text);
+                let right = self.arena.interleave(a, db);
+                self.arena.choice(left, right)
+            }
+            Pat::Group(a, b) => {
+                let da = self.text(a, text);
+                let group = self.arena.group(da, b);
+                if self.nullable(a) {
+                    let db = self.text(b, text);
+                    self.arena.choice(group, db)
+                } else {
+                    group
+                }
+            }
+            Pat::After(a, b) => {
+                let da = self.text(a, text);
+                self.arena.after(da, b)
+            }
+            Pat::OneOrMore(a) => {
+                let da = self.text(a, text);
+                let more = self.arena.one_or_more(a);
+                let rest = self.arena.choice(more, EMPTY);
+                self.arena.group(da, rest)
+            }
+            Pat::Text => TEXT,
+            Pat::Value(v) => {
+                let (dt, value) = &self.schema.values[v as usize];
+                if self.schema.datatypes[*dt as usize].equal(text, value) {
+                    EMPTY
+                } else {
+                    NOT_ALLOWED
+                }
+            }
+            Pat::Data(dt, except) => {
+                if self.schema.datatypes[dt as usize].check(text).is_err() {
+                    return NOT_ALLOWED;
+                }
+                if except != NOT_ALLOWED {
+                    let excluded = self.text(except, text);
+                    if self.nullable(excluded) {
+                        return NOT_ALLOWED;
+                    }
+                }
+                EMPTY
+            }
+            Pat::List(a) => {
+                let mut p = a;
+                for token in text.split_ascii_whitespace() {
+                    p = self.text(p, token);
+                }
+                if self.nullable(p) {
+                    EMPTY
+                } else {
+                    NOT_ALLOWED
+                }
+            }
+            _ => NOT_ALLOWED,
+        }
+    }
+
+    fn start_tag_open(&mut self, p: P, ns: &str, local: &str) -> P {
+        match self.arena.get(p) {
+            Pat::Choice(a, b) => {
+                let (a, b) = (self.start_tag_open(a, ns, local), self.start_tag_open(b, ns, local));
+                self.arena.choice(a, b)
+            }
+            Pat::Element(name, content) => {
+                if self.schema.names[name as usize].contains(ns, local) {
+                    self.arena.after(content, EMPTY)
+                } else {
+                    NOT_ALLOWED
+                }
+            }
+            Pat::Interleave(a, b) => {
+                let da = self.start_tag_open(a, ns, local);
+                let left = self.apply_after(da, Then::InterleaveRight(b));
+                let db = self.start_tag_open(b, ns, local);
+                let right = self.apply_after(db, Then::InterleaveLeft(a));
+                self.arena.choice(left, right)
+            }
+            Pat::OneOrMore(a) => {
+                let da = self.start_tag_open(a, ns, local);
+                let more = self.arena.one_or_more(a);
+                let rest = self.arena.choice(more, EMPTY);
+                self.apply_after(da, Then::Group(rest))
+            }
+            Pat::Group(a, b) => {
+                let da = self.start_tag_open(a, ns, local);
+                let group = self.apply_after(da, Then::Group(b));
+                if self.nullable(a) {
+                    let db = self.start_tag_open(b, ns, local);
+                    self.arena.choice(group, db)
+                } else {
+                    group
+                }
+            }
+            Pat::After(a, b) => {
+                let da = self.start_tag_open(a, ns, local);
+                self.apply_after(da, Then::After(b))
+            }
+            _ => NOT_ALLOWED,
+        }
+    }
+
+    fn apply_after(&mut self, p: P, then: Then) -> P {
+        match self.arena.get(p) {
+            Pat::After(a, b) => {
+                let b = match then {
+                    Then::InterleaveRight(q) => self.arena.interleave(b, q),
+                    Then::InterleaveLeft(q) => self.arena.interleave(q, b),
+                    Then::Group(q) => self.arena.group(b, q),
+                    Then::After(q) => self.arena.after(b, q),
+                };
+                self.arena.after(a, b)
+            }
+            Pat::Choice(a, b) => {
+                let (a, b) = (self.apply_after(a, then), self.apply_after(b, then));
+                self.arena.choice(a, b)
+            }
+            _ => NOT_ALLOWED,
+        }
+    }
+
+    /// Match an attribute; a `None` value matches anything, to recover
+    /// after an invalid value
+    fn attribute(&mut self, p: P, ns: &str, local: &str, value: Option<&str>) -> P {
+        match self.arena.get(p) {
+            Pat::After(a, b) => {
+                let da = self.attribute(a, ns, local, value);
+                self.arena.after(da, b)
+            }
+            Pat::Choice(a, b) => {
+                let (a, b) = (self.attribute(a, ns, local, value), self.attribute(b, ns, local, value));
+                self.arena.choice(a, b)
+            }
+            Pat::Group(a, b) | Pat::Interleave(a, b) => {
+                let combine = match self.arena.get(p) {
+                    Pat::Group(..) => Arena::group,
+                    _ => Arena::interleave,
+                };
+                let da = self.attribute(a, ns, local, value);
+                let left = combine(&mut self.arena, da, b);
+                let db = self.attribute(b, ns, local, value);
+                let right = combine(&mut self.arena, a, db);
+                self.arena.choice(left, right)
+            }
+            Pat::OneOrMore(a) => {
+                let da = self.attribute(a, ns, local, value);
+                let more = self.arena.one_or_more(a);
+                let rest = self.arena.choice(more, EMPTY);
+                self.arena.group(da, rest)
+            }
+            Pat::Attribute(name, content) if self.schema.names[name as usize].contains(ns, local) => {
+                let matched = match value {
+                    None => true,
+                    Some(value) => (self.nullable(content) && is_whitespace(value)) || {
+                        let d = self.text(content, value);
+                        self.nullable(d)
+                    },
+                };
+                if matched {
+                    EMPTY
+                } else {
+                    NOT_ALLOWED
+                }
+            }
+            _ => NOT_ALLOWED,
+        }
+    }
+
+    /// End of the start tag; attributes not yet matched are missing, unless
+    /// `lenient`, which drops them to continue after the error
+    fn start_tag_close(&mut self, p: P, lenient: bool) -> P {
+        match self.arena.get(p) {
+            Pat::After(a, b) => {
+                let a = self.start_tag_close(a, lenient);
+                self.arena.after(a, b)
+            }
+            Pat::Choice(a, b) => {
+                let (a, b) = (self.start_tag_close(a, lenient), self.start_tag_close(b, lenient));
+                self.arena.choice(a, b)
+            }
+            Pat::Group(a, b) => {
+                let (a, b) = (self.start_tag_close(a, lenient), self.start_tag_close(b, lenient));
+                self.arena.group(a, b)
+            }
+            Pat::Interleave(a, b) => {
+                let (a, b) = (self.start_tag_close(a, lenient), self.start_tag_close(b, lenient));
+                self.arena.interleave(a, b)
+            }
+            Pat::OneOrMore(a) => {
+                let a = self.start_tag_close(a, lenient);
+                self.arena.one_or_more(a)
+            }
+            Pat::Attribute(..) if lenient => EMPTY,
+            Pat::Attribute(..) => NOT_ALLOWED,
+            _ => p,
+        }
+    }
+
+    fn end_tag(&mut self, p: P) -> P {
+        match self.arena.get(p) {
+            Pat::Choice(a, b) => {
+                let (a, b) = (self.end_tag(a), self.end_tag(b));
+                self.arena.choice(a, b)
+            }
+            Pat::After(a, b) if self.nullable(a) => b,
+            _ => NOT_ALLOWED,
+        }
+    }
+}
+
+/// Deepest nesting followed when describing what was expected
+const MAX_DEPTH: usize = 256;
+
+/// Text patterns found by [`Run::text_patterns`]
+#[derive(Default)]
+struct Found {
+    data: Vec<u32>,
+    values: Vec<String>,
+    list: bool,
+}
+
+fn is_whitespace(text: &str) -> bool {
+    text.bytes().all(|b| matches!(b, b' ' | b'\t' | b'\n' | b'\r'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_with_options, ParseOptions};
+    use crate::types::DocumentType;
+
+    fn validate(schema: &RelaxNg, xml: &str) -> Vec<String> {
+        let options = ParseOptions {
+            strict: true,
+            ..ParseOptions::default()
+        };
+        let doc = parse_with_options(xml, DocumentType::Xml, &options).unwrap();
+        schema.validate(&doc).iter().map(|e| e.to_string()).collect()
+    }
+
+    const ADDRESS_BOOK: &str = r#"<grammar xmlns="http://relaxng.org/ns/structure/1.0"
+    datatypeLibrary="http://www.w3.org/2001/XMLSchema-datatypes">
+  <start>
+    <element name="addressBook">
+      <zeroOrMore><ref name="card"/></zeroOrMore>
+    </element>
+  </start>
+  <define name="card">
+    <element name="card">
+      <attribute name="id"><data type="ID"/></attribute>
+      <optional>
+        <attribute name="kind"><choice><value>home</value><value>work</value></choice></attribute>
+      </optional>
+      <interleave>
+        <element name="name"><text/></element>
+        <element name="email"><data type="string"><param name="pattern">[^@]+@[^@]+</param></data></element>
+      </interleave>
+      <optional><element name="age"><data type="nonNegativeInteger"/></element></optional>
+    </element>
+  </define>
+</grammar>"#;
+
+    #[test]
+    fn test_valid_document() {
+        let schema = RelaxNg::from_source(ADDRESS_BOOK, None).unwrap();
+        let xml = r#"<addressBook>
+  <card id="a" kind="home"><email>a@example.com</email><name>A</name></card>
+  <card id="b"><name>B</name><email>b@example.com</email><age>42</age></card>
+</addressBook>"#;
+        assert!(validate(&schema, xml).is_empty());
+    }
+
+    #[test]
+    fn test_errors_and_recovery() {
+        let schema = RelaxNg::from_source(ADDRESS_BOOK, None).unwrap();
+        let xml = r#"<addressBook>
+  <card kind="office"><name>A</name><email>nobody</email></card>
+  <card id="b" color="red"><name>B</name><phone/><email>b@example.com</email><age>-1</age></card>
+  <card id="c"><name>C</name></card>
+</addressBook>"#;
+        assert_eq!(
+            validate(&schema, xml),
+            [
+                "2:3: value 'office' of attribute 'kind' on <card> must be one of 'home', 'work'",
+                "2:3: missing required attribute 'id' on <card>",
+                "2:37: value 'nobody' of element <email> does not match pattern '[^@]+@[^@]+'",
+                "3:3: attribute 'color' is not allowed on <card>",
+                "3:42: element <phone> is not expected here; expected <email>",
+                "3:78: value '-1' of element <age> is not a valid nonNegativeInteger",
+                "4:3: element <card> is incomplete; expected <email>",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_namespaces_and_name_classes() {
+        let schema = r#"<element name="doc" ns="urn:doc" xmlns="http://relaxng.org/ns/structure/1.0">
+  <zeroOrMore>
+    <attribute><anyName><except><nsName ns=""/></except></anyName></attribute>
+  </zeroOrMore>
+  <mixed>
+    <zeroOrMore>
+      <element><nsName ns="urn:ext"/><empty/></element>
+    </zeroOrMore>
+  </mixed>
+</element>"#;
+        let schema = RelaxNg::from_source(schema, None).unwrap();
+        let valid = r#"<doc xmlns="urn:doc" xmlns:x="urn:ext" x:a="1">text <x:note/></doc>"#;
+        assert!(validate(&schema, valid).is_empty());
+
+        let invalid = r#"<doc xmlns="urn:doc" a="1"><note/></doc>"#;
+        assert_eq!(
+            validate(&schema, invalid),
+            [
+                "1:1: attribute 'a' is not allowed on <doc>",
+                "1:28: element <note> is not expected here; expected any element",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_combine_and_recursion() {
+        let schema = r#"<grammar xmlns="http://relaxng.org/ns/structure/1.0">
+  <start><ref name="section"/></start>
+  <define name="section">
+    <element name="section"><ref name="inline"/><zeroOrMore><ref name="section"/></zeroOrMore></element>
+  </define>
+  <define name="inline"><attribute name="title"/></define>
+  <define name="inline" combine="interleave"><optional><attribute name="id"/></optional></define>
+</grammar>"#;
+        let schema = RelaxNg::from_source(schema, None).unwrap();
+        let xml = r#"<section title="a" id="1"><section title="b"><section id="2"/></section></section>"#;
+        assert_eq!(validate(&schema, xml), ["1:46: missing required attribute 'title' on <section>"]);
+
+        let looped = r#"<grammar xmlns="http://relaxng.org/ns/structure/1.0">
+  <start><ref name="a"/></start>
+  <define name="a"><choice><empty/><group><element name="x"><empty/></element><ref name="a"/></group></choice></define>
+</grammar>"#;
+        let err = RelaxNg::from_source(looped, None).unwrap_err();
+        assert_eq!(err.to_string(), "invalid schema <schema>: 'a' refers to itself outside an element");
+    }
+}
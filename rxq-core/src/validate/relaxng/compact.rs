@@ -0,0 +1,650 @@
+//! RELAX NG compact syntax
+//!
+//! Parses `.rnc` schemas into the same [`Syntax`] tree as the XML syntax.
+//! Annotations (`[...]` and `>> name [...]`) and comments are skipped.
+
+use super::{Combine, Component, NameClass, Syntax, XSD_DATATYPES};
+use crate::error::{Position, SchemaError};
+use crate::parser::xml::{is_name_char, is_name_start_char};
+use crate::validate::{resolve_location, XML_NAMESPACE};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// Identifier or keyword; an escaped `\name` is never a keyword
+    Ident(String, bool),
+    /// `prefix:local`
+    CName(String, String),
+    /// `prefix:*`
+    NsName(String),
+    Literal(String),
+    Punct(&'static str),
+    Eof,
+}
+
+const PUNCTUATION: [&str; 15] = [
+    "|=", "&=", "{", "}", "(", ")", ",", "&", "|", "?", "*", "+", "=", "-", "~",
+];
+
+/// Parse a compact syntax schema; unprefixed element names default to `ns`
+pub(super) fn parse(
+    source: &str,
+    path: &str,
+    base_dir: Option<&Path>,
+    ns: &str,
+) -> Result<Syntax, SchemaError> {
+    let invalid = |offset: usize, message: String| SchemaError::Invalid {
+        path: path.to_string(),
+        message: format!("{}: {}", Position::from_offset(source, offset), message),
+    };
+    let tokens = tokenize(source).map_err(|(offset, message)| invalid(offset, message))?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        base_dir,
+        namespaces: HashMap::from([("xml".to_string(), XML_NAMESPACE.to_string())]),
+        datatypes: HashMap::from([("xsd".to_string(), XSD_DATATYPES.to_string())]),
+        default_ns: ns.to_string(),
+        inherited_ns: ns.to_string(),
+    };
+    parser.top_level().map_err(|message| {
+        let offset = parser.tokens[parser.pos.min(parser.tokens.len() - 1)].1;
+        invalid(offset, message)
+    })
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, (usize, String)> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        let rest = &source[start..];
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            while chars.next_if(|&(_, c)| c != '\n').is_some() {}
+        } else if c == '[' {
+            skip_annotation(source, start).map(|end| {
+                while chars.next_if(|&(i, _)| i < end).is_some() {}
+            })?;
+        } else if rest.starts_with(">>") {
+            // An annotation element: `>> name [...]`, the brackets skipped next
+            chars.next();
+            chars.next();
+            while chars.next_if(|&(_, c)| c.is_whitespace()).is_some() {}
+            while chars.next_if(|&(_, c)| is_name_char(c)).is_some() {}
+        } else if c == '"' || c == '\'' {
+            let (value, end) = literal(source, start)?;
+            tokens.push((Token::Literal(value), start));
+            while chars.next_if(|&(i, _)| i < end).is_some() {}
+        } else if c == '\\' || is_name_start_char(c) {
+            let escaped = c == '\\';
+            if escaped {
+                chars.next();
+            }
+            let name_start = chars.peek().map_or(source.len(), |&(i, _)| i);
+            let mut end = name_start;
+            while let Some((i, c)) = chars.next_if(|&(_, c)| is_name_char(c) && c != ':') {
+                end = i + c.len_utf8();
+            }
+            let name = source[name_start..end].to_string();
+            if name.is_empty() {
+                return Err((start, "expected a name after '\\'".to_string()));
+            }
+            let after = &source[end..];
+            if after.starts_with(":*") {
+                chars.next();
+                chars.next();
+                tokens.push((Token::NsName(name), start));
+            } else if after.starts_with(':') && after[1..].starts_with(is_name_start_char) {
+                chars.next();
+                let local_start = end + 1;
+                let mut local_end = local_start;
+                while let Some((i, c)) = chars.next_if(|&(_, c)| is_name_char(c) && c != ':') {
+                    local_end = i + c.len_utf8();
+                }
+                tokens.push((Token::CName(name, source[local_start..local_end].to_string()), start));
+            } else {
+                tokens.push((Token::Ident(name, escaped), start));
+            }
+        } else {
+            let Some(punct) = PUNCTUATION.into_iter().find(|p| rest.starts_with(p)) else {
+                return Err((start, format!("unexpected character '{}'", c)));
+            };
+            for _ in 0..punct.len() {
+                chars.next();
+            }
+            tokens.push((Token::Punct(punct), start));
+        }
+    }
+    tokens.push((Token::Eof, source.len()));
+    Ok(tokens)
+}
+
+/// End offset of the annotation starting at `start`, skipping nested brackets
+fn skip_annotation(source: &str, start: usize) -> Result<usize, (usize, String)> {
+    let mut depth = 0;
+    let mut i = start;
+    while i < source.len() {
+        let c = source[i..].chars().next().unwrap_or_default();
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(i + 1);
+                }
+            }
+            '"' | '\'' => {
+                i = literal(source, i)?.1;
+                continue;
+            }
+            _ => {}
+        }
+        i += c.len_utf8();
+    }
+    Err((start, "unterminated annotation".to_string()))
+}
+
+/// A quoted literal starting at `start`: its value and end offset
+fn literal(source: &str, start: usize) -> Result<(String, usize), (usize, String)> {
+    let rest = &source[start..];
+    let quote = &rest[..1];
+    let triple = quote.repeat(3);
+    let (delimiter, body) = if rest.starts_with(&triple) {
+        (triple.as_str(), &rest[3..])
+    } else {
+        (quote, &rest[1..])
+    };
+    let end = body
+        .find(delimiter)
+        .filter(|&end| delimiter.len() == 3 || !body[..end].contains('\n'))
+        .ok_or((start, "unterminated literal".to_string()))?;
+    let value = body[..end].to_string();
+    Ok((value, start + (rest.len() - body.len()) + end + delimiter.len()))
+}
+
+struct Parser<'p> {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    base_dir: Option<&'p Path>,
+    namespaces: HashMap<String, String>,
+    datatypes: HashMap<String, String>,
+    default_ns: String,
+    inherited_ns: String,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn peek_at(&self, ahead: usize) -> &Token {
+        &self.tokens[(self.pos + ahead).min(self.tokens.len() - 1)].0
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Token::Ident(name, false) if name == keyword)
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Token::Punct(p) if *p == punct) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), String> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            Err(format!("expected '{}', found {}", punct, describe(self.peek())))
+        }
+    }
+
+    fn identifier(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Token::Ident(..) => match self.next() {
+                Token::Ident(name, _) => Ok(name),
+                _ => unreachable!(),
+            },
+            other => Err(format!("expected a name, found {}", describe(other))),
+        }
+    }
+
+    /// A literal, possibly concatenated from pieces with `~`
+    fn literal(&mut self) -> Result<String, String> {
+        let mut value = match self.next() {
+            Token::Literal(value) => value,
+            other => return Err(format!("expected a literal, found {}", describe(&other))),
+        };
+        while self.eat("~") {
+            match self.next() {
+                Token::Literal(more) => value.push_str(&more),
+                other => return Err(format!("expected a literal, found {}", describe(&other))),
+            }
+        }
+        Ok(value)
+    }
+
+    /// A namespace URI literal, or `inherit`
+    fn namespace_uri(&mut self) -> Result<String, String> {
+        if self.is_keyword("inherit") {
+            self.next();
+            return Ok(self.inherited_ns.clone());
+        }
+        self.literal()
+    }
+
+    fn top_level(&mut self) -> Result<Syntax, String> {
+        loop {
+            if self.is_keyword("namespace") {
+                self.next();
+                let prefix = self.identifier()?;
+                self.expect("=")?;
+                let uri = self.namespace_uri()?;
+                self.namespaces.insert(prefix, uri);
+            } else if self.is_keyword("default") {
+                self.next();
+                if !self.is_keyword("namespace") {
+                    return Err(format!("expected 'namespace', found {}", describe(self.peek())));
+                }
+                self.next();
+                let prefix = match self.peek() {
+                    Token::Ident(..) => Some(self.identifier()?),
+                    _ => None,
+                };
+                self.expect("=")?;
+                let uri = self.namespace_uri()?;
+                if let Some(prefix) = prefix {
+                    self.namespaces.insert(prefix, uri.clone());
+                }
+                self.default_ns = uri;
+            } else if self.is_keyword("datatypes") {
+                self.next();
+                let prefix = self.identifier()?;
+                self.expect("=")?;
+                let uri = self.literal()?;
+                self.datatypes.insert(prefix, uri);
+            } else {
+                break;
+            }
+        }
+
+        if self.starts_component() || *self.peek() == Token::Eof {
+            let components = self.components()?;
+            if *self.peek() != Token::Eof {
+                return Err(format!("expected a definition, found {}", describe(self.peek())));
+            }
+            return Ok(Syntax::Grammar(components));
+        }
+        let pattern = self.pattern()?;
+        if *self.peek() != Token::Eof {
+            return Err(format!("unexpected {} after the pattern", describe(self.peek())));
+        }
+        Ok(pattern)
+    }
+
+    fn starts_component(&self) -> bool {
+        match self.peek() {
+            Token::Ident(name, false) if matches!(name.as_str(), "start" | "div" | "include") => true,
+            Token::Ident(..) => matches!(self.peek_at(1), Token::Punct("=" | "|=" | "&=")),
+            _ => false,
+        }
+    }
+
+    /// Grammar content up to a closing brace or the end of input
+    fn components(&mut self) -> Result<Vec<Component>, String> {
+        let mut components = Vec::new();
+        while !matches!(self.peek(), Token::Punct("}") | Token::Eof) {
+            let component = if self.is_keyword("start") {
+                self.next();
+                let combine = self.assign()?;
+                Component::Start(combine, self.pattern()?)
+            } else if self.is_keyword("div") {
+                self.next();
+                self.expect("{")?;
+                let inner = self.components()?;
+                self.expect("}")?;
+                Component::Div(inner)
+            } else if self.is_keyword("include") {
+                self.next();
+                let path = self.href()?;
+                let ns = self.inherit()?;
+                let mut overrides = Vec::new();
+                if self.eat("{") {
+                    overrides = self.components()?;
+                    self.expect("}")?;
+                }
+                Component::Include(path, ns, overrides)
+            } else {
+                let name = self.identifier()?;
+                let combine = self.assign()?;
+                Component::Define(name, combine, self.pattern()?)
+            };
+            components.push(component);
+        }
+        Ok(components)
+    }
+
+    fn assign(&mut self) -> Result<Option<Combine>, String> {
+        match self.next() {
+            Token::Punct("=") => Ok(None),
+            Token::Punct("|=") => Ok(Some(Combine::Choice)),
+            Token::Punct("&=") => Ok(Some(Combine::Interleave)),
+            other => Err(format!("expected '=', found {}", describe(&other))),
+        }
+    }
+
+    fn href(&mut self) -> Result<PathBuf, String> {
+        let href = self.literal()?;
+        resolve_location(self.base_dir, &href)
+    }
+
+    /// Optional `inherit = prefix` of `include` and `external`
+    fn inherit(&mut self) -> Result<String, String> {
+        if !self.is_keyword("inherit") {
+            return Ok(self.default_ns.clone());
+        }
+        self.next();
+        self.expect("=")?;
+        let prefix = self.identifier()?;
+        self.namespace(&prefix)
+    }
+
+    fn namespace(&self, prefix: &str) -> Result<String, String> {
+        self.namespaces
+            .get(prefix)
+            .cloned()
+            .ok_or_else(|| format!("namespace prefix '{}' is not declared", prefix))
+    }
+
+    /// Particles joined by one kind of operator
+    fn pattern(&mut self) -> Result<Syntax, String> {
+        let first = self.particle()?;
+        let op = match self.peek() {
+            Token::Punct(op @ ("," | "&" | "|")) => *op,
+            _ => return Ok(first),
+        };
+        let mut items = vec![first];
+        while self.eat(op) {
+            items.push(self.particle()?);
+        }
+        if matches!(self.peek(), Token::Punct("," | "&" | "|")) {
+            return Err("mixing ',', '&' and '|' needs parentheses".to_string());
+        }
+        Ok(match op {
+            "," => Syntax::Group(items),
+            "&" => Syntax::Interleave(items),
+            _ => Syntax::Choice(items),
+        })
+    }
+
+    fn particle(&mut self) -> Result<Syntax, String> {
+        let primary = self.primary()?;
+        Ok(match self.peek() {
+            Token::Punct("?") => {
+                self.next();
+                Syntax::Optional(Box::new(primary))
+            }
+            Token::Punct("*") => {
+                self.next();
+                Syntax::ZeroOrMore(Box::new(primary))
+            }
+            Token::Punct("+") => {
+                self.next();
+                Syntax::OneOrMore(Box::new(primary))
+            }
+            _ => primary,
+        })
+    }
+
+    fn braced(&mut self) -> Result<Syntax, String> {
+        self.expect("{")?;
+        let pattern = self.pattern()?;
+        self.expect("}")?;
+        Ok(pattern)
+    }
+
+    fn primary(&mut self) -> Result<Syntax, String> {
+        match self.next() {
+            Token::Ident(keyword, false) => match keyword.as_str() {
+                "element" => {
+                    let name = self.name_class(true)?;
+                    Ok(Syntax::Element(name, Box::new(self.braced()?)))
+                }
+                "attribute" => {
+                    let name = self.name_class(false)?;
+                    Ok(Syntax::Attribute(name, Box::new(self.braced()?)))
+                }
+                "list" => Ok(Syntax::List(Box::new(self.braced()?))),
+                "mixed" => Ok(Syntax::Mixed(Box::new(self.braced()?))),
+                "empty" => Ok(Syntax::Empty),
+                "text" => Ok(Syntax::Text),
+                "notAllowed" => Ok(Syntax::NotAllowed),
+                "parent" => Ok(Syntax::ParentRef(self.identifier()?)),
+                "external" => {
+                    let path = self.href()?;
+                    let ns = self.inherit()?;
+                    Ok(Syntax::ExternalRef(path, ns))
+                }
+                "grammar" => {
+                    self.expect("{")?;
+                    let components = self.components()?;
+                    self.expect("}")?;
+                    Ok(Syntax::Grammar(components))
+                }
+                "string" | "token" => self.datatype(String::new(), keyword),
+                _ => Ok(Syntax::Ref(keyword)),
+            },
+            Token::Ident(name, true) => Ok(Syntax::Ref(name)),
+            Token::CName(prefix, local) => {
+                let Some(library) = self.datatypes.get(&prefix).cloned() else {
+                    self.pos -= 1;
+                    return Err(format!("datatypes prefix '{}' is not declared", prefix));
+                };
+                self.datatype(library, local)
+            }
+            Token::Literal(first) => {
+                let mut value = first;
+                while self.eat("~") {
+                    match self.next() {
+                        Token::Literal(more) => value.push_str(&more),
+                        other => return Err(format!("expected a literal, found {}", describe(&other))),
+                    }
+                }
+                Ok(Syntax::Value {
+                    library: String::new(),
+                    name: "token".to_string(),
+                    value,
+                })
+            }
+            Token::Punct("(") => {
+                let pattern = self.pattern()?;
+                self.expect(")")?;
+                Ok(pattern)
+            }
+            other => {
+                if other != Token::Eof {
+                    self.pos -= 1;
+                }
+                Err(format!("expected a pattern, found {}", describe(&other)))
+            }
+        }
+    }
+
+    /// A value (`type "literal"`) or data pattern with params and except
+    fn datatype(&mut self, library: String, name: String) -> Result<Syntax, String> {
+        if matches!(self.peek(), Token::Literal(_)) {
+            let value = self.literal()?;
+            return Ok(Syntax::Value { library, name, value });
+        }
+        let mut params = Vec::new();
+        if self.eat("{") {
+            while !self.eat("}") {
+                let param = self.identifier()?;
+                self.expect("=")?;
+                params.push((param, self.literal()?));
+            }
+        }
+        let except = if self.eat("-") {
+            Some(Box::new(self.primary()?))
+        } else {
+            None
+        };
+        Ok(Syntax::Data {
+            library,
+            name,
+            params,
+            except,
+        })
+    }
+
+    fn name_class(&mut self, element: bool) -> Result<NameClass, String> {
+        let mut class = self.name_class_primary(element)?;
+        while self.eat("|") {
+            let other = self.name_class_primary(element)?;
+            class = NameClass::Choice(Box::new(class), Box::new(other));
+        }
+        Ok(class)
+    }
+
+    fn name_class_primary(&mut self, element: bool) -> Result<NameClass, String> {
+        // Prefixes are resolved before the name is consumed, so errors point at it
+        let ns = match self.peek() {
+            Token::NsName(prefix) | Token::CName(prefix, _) => self.namespace(prefix)?,
+            _ => String::new(),
+        };
+        match self.next() {
+            Token::Punct("*") => Ok(NameClass::AnyName(self.name_class_except(element)?)),
+            Token::NsName(_) => Ok(NameClass::NsName(ns, self.name_class_except(element)?)),
+            Token::CName(_, local) => Ok(NameClass::Name(ns, local)),
+            // Unprefixed attribute names have no namespace
+            Token::Ident(local, _) if element => Ok(NameClass::Name(self.default_ns.clone(), local)),
+            Token::Ident(local, _) => Ok(NameClass::Name(String::new(), local)),
+            Token::Punct("(") => {
+                let class = self.name_class(element)?;
+                self.expect(")")?;
+                Ok(class)
+            }
+            other => {
+                if other != Token::Eof {
+                    self.pos -= 1;
+                }
+                Err(format!("expected a name, found {}", describe(&other)))
+            }
+        }
+    }
+
+    fn name_class_except(&mut self, element: bool) -> Result<Option<Box<NameClass>>, String> {
+        if self.eat("-") {
+            Ok(Some(Box::new(self.name_class_primary(element)?)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Ident(name, _) => format!("'{}'", name),
+        Token::CName(prefix, local) => format!("'{}:{}'", prefix, local),
+        Token::NsName(prefix) => format!("'{}:*'", prefix),
+        Token::Literal(value) => format!("\"{}\"", value),
+        Token::Punct(punct) => format!("'{}'", punct),
+        Token::Eof => "end of input".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::RelaxNg;
+    use crate::parser::{parse_with_options, ParseOptions};
+    use crate::types::DocumentType;
+    use crate::validate::Validator;
+
+    fn validate(schema: &str, xml: &str) -> Vec<String> {
+        let schema = RelaxNg::from_compact(schema, None).unwrap();
+        let options = ParseOptions {
+            strict: true,
+            ..ParseOptions::default()
+        };
+        let doc = parse_with_options(xml, DocumentType::Xml, &options).unwrap();
+        schema.validate(&doc).iter().map(|e| e.to_string()).collect()
+    }
+
+    const BOOKS: &str = r#"# A library catalogue
+default namespace = "urn:books"
+namespace dc = "http://purl.org/dc/elements/1.1/"
+
+start = library
+library = element library { book* }
+[ a:documentation [ 'A book, "quoted" ]' ] ]
+book =
+  element book {
+    attribute isbn { xsd:string { pattern = "\d{3}-\d+" } },
+    attribute format { "hardcover" | "paperback" }?,
+    (element dc:title { text } & element year { xsd:gYear })
+  }
+"#;
+
+    #[test]
+    fn test_compact_schema() {
+        let valid = r#"<library xmlns="urn:books" xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <book isbn="978-1"><year>2001</year><dc:title>A</dc:title></book>
+</library>"#;
+        assert!(validate(BOOKS, valid).is_empty());
+
+        let invalid = r#"<library xmlns="urn:books" xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <book isbn="x" format="ebook"><dc:title>A</dc:title><year>soon</year></book>
+</library>"#;
+        assert_eq!(
+            validate(BOOKS, invalid),
+            [
+                r"2:3: value 'x' of attribute 'isbn' on <book> does not match pattern '\d{3}-\d+'",
+                "2:3: value 'ebook' of attribute 'format' on <book> must be one of 'hardcover', 'paperback'",
+                "2:55: value 'soon' of element <year> is not a valid gYear",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_data_except_and_lists() {
+        let schema = r#"element sizes {
+  attribute unit { token - ("px" | "pt") },
+  list { xsd:positiveInteger+ }
+}"#;
+        assert!(validate(schema, r#"<sizes unit="em">1 2 3</sizes>"#).is_empty());
+        assert_eq!(
+            validate(schema, r#"<sizes unit="px">1 0</sizes>"#),
+            [
+                "1:1: value 'px' of attribute 'unit' on <sizes> is not allowed",
+                "1:1: value '1 0' of element <sizes> is not allowed",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let err = RelaxNg::from_compact("element a { text, empty | text }", None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid schema <schema>: 1:25: mixing ',', '&' and '|' needs parentheses"
+        );
+        let err = RelaxNg::from_compact("element p:a { text }", None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid schema <schema>: 1:9: namespace prefix 'p' is not declared"
+        );
+    }
+}
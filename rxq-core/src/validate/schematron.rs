@@ -0,0 +1,715 @@
+//! ISO Schematron validation
+//!
+//! Rules use the default XPath 1.0 query binding and are evaluated with
+//! [`crate::query::xpath`]. Supported: `ns`, `let` (schema, phase, pattern
+//! and rule level), `pattern`, `rule`, `assert`, `report`, message `name`
+//! and `value-of`, abstract rules with `extends`, abstract patterns with
+//! `is-a` and `param`, `include` of local files and the `defaultPhase`.
+//!
+//! Every failed `assert` and every successful `report` is a
+//! [`ValidationError`] at the rule's context node.
+
+use super::{lookup_namespace, parse_schema, read_schema, resolve_location, split_qname};
+use super::{unescape, ValidationError, Validator};
+use crate::error::SchemaError;
+use crate::query::xpath::{Context, Item, Value, XPath};
+use crate::types::{Document, NodeRef, NodeType};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+const SCH: &str = "http://purl.oclc.org/dsdl/schematron";
+/// Namespace of Schematron 1.5, which uses the same elements
+const SCH_1_5: &str = "http://www.ascc.net/xml/schematron";
+
+/// A compiled Schematron schema
+#[derive(Debug)]
+pub struct Schematron {
+    lets: Vec<Let>,
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Debug)]
+struct Let {
+    name: String,
+    value: XPath,
+}
+
+#[derive(Debug)]
+struct Pattern {
+    lets: Vec<Let>,
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    context: XPath,
+    lets: Vec<Let>,
+    checks: Vec<Check>,
+}
+
+#[derive(Debug)]
+struct Check {
+    /// `report` fires when the test is true, `assert` when it is false
+    report: bool,
+    test: XPath,
+    test_source: String,
+    message: Vec<Segment<XPath>>,
+}
+
+/// Part of an assertion message
+#[derive(Debug, Clone)]
+enum Segment<X> {
+    Text(String),
+    /// `<name/>`: name of the context node or of the node `path` selects
+    Name(Option<X>),
+    ValueOf(X),
+}
+
+/// A pattern as read from the schema, before its expressions are compiled
+///
+/// Abstract patterns are instantiated by substituting parameters into the
+/// expression sources, so compiling waits until every file has been read.
+#[derive(Debug, Clone, Default)]
+struct RawPattern {
+    id: Option<String>,
+    is_abstract: bool,
+    is_a: Option<String>,
+    params: Vec<(String, String)>,
+    lets: Vec<(String, String)>,
+    rules: Vec<RawRule>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct RawRule {
+    id: Option<String>,
+    is_abstract: bool,
+    context: Option<String>,
+    extends: Vec<String>,
+    lets: Vec<(String, String)>,
+    checks: Vec<RawCheck>,
+}
+
+#[derive(Debug, Clone)]
+struct RawCheck {
+    report: bool,
+    test: String,
+    message: Vec<Segment<String>>,
+}
+
+impl Schematron {
+    /// Load a Schematron schema file, following `include` relative to it
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, SchemaError> {
+        let path = path.as_ref();
+        let source = read_schema(path)?;
+        let mut reader = Reader::new(path.display().to_string(), path.parent());
+        reader.read_source(&source)?;
+        reader.compile()
+    }
+
+    /// Compile a Schematron schema from source text
+    pub fn from_source(source: &str, base_dir: Option<&Path>) -> Result<Self, SchemaError> {
+        let mut reader = Reader::new("<schema>".to_string(), base_dir);
+        reader.read_source(source)?;
+        reader.compile()
+    }
+}
+
+impl Validator for Schematron {
+    fn validate(&self, doc: &Document<'_>) -> Vec<ValidationError> {
+        let mut ctx = Context::new(doc);
+        let mut errors = Vec::new();
+        set_lets(&mut ctx, &self.lets, Item::Root, &mut errors);
+
+        for pattern in &self.patterns {
+            set_lets(&mut ctx, &pattern.lets, Item::Root, &mut errors);
+            // Within a pattern, a node is checked by the first rule it matches
+            let mut fired = HashSet::new();
+            for rule in &pattern.rules {
+                let nodes = match rule.context.evaluate(&ctx, Item::Root).and_then(Value::into_nodes) {
+                    Ok(nodes) => nodes,
+                    Err(err) => {
+                        errors.push(unpositioned(format!("rule context: {}", err)));
+                        continue;
+                    }
+                };
+                for item in nodes {
+                    if !fired.insert(ctx.key(item)) {
+                        continue;
+                    }
+                    set_lets(&mut ctx, &rule.lets, item, &mut errors);
+                    for check in &rule.checks {
+                        match check.test.evaluate(&ctx, item) {
+                            Ok(value) if value.boolean() == check.report => {
+                                let message = message(&ctx, check, item);
+                                errors.push(match item.node() {
+                                    Some(node) => ValidationError::at(node, message),
+                                    None => unpositioned(message),
+                                });
+                            }
+                            Ok(_) => {}
+                            Err(err) => errors.push(unpositioned(format!(
+                                "test '{}': {}",
+                                check.test_source, err
+                            ))),
+                        }
+                    }
+                    unset_lets(&mut ctx, &rule.lets);
+                }
+            }
+            unset_lets(&mut ctx, &pattern.lets);
+        }
+        errors
+    }
+}
+
+fn unpositioned(message: String) -> ValidationError {
+    ValidationError {
+        position: None,
+        message,
+    }
+}
+
+fn set_lets<'a, 'i>(
+    ctx: &mut Context<'a, 'i>,
+    lets: &[Let],
+    item: Item<'a, 'i>,
+    errors: &mut Vec<ValidationError>,
+) {
+    for variable in lets {
+        match variable.value.evaluate(ctx, item) {
+            Ok(value) => ctx.set_variable(&variable.name, value),
+            Err(err) => errors.push(unpositioned(format!("let ${}: {}", variable.name, err))),
+        }
+    }
+}
+
+fn unset_lets(ctx: &mut Context<'_, '_>, lets: &[Let]) {
+    for variable in lets {
+        ctx.remove_variable(&variable.name);
+    }
+}
+
+/// The assertion text with `name` and `value-of` filled in
+fn message<'a, 'i>(ctx: &Context<'a, 'i>, check: &Check, item: Item<'a, 'i>) -> String {
+    let mut text = String::new();
+    for segment in &check.message {
+        match segment {
+            Segment::Text(s) => text.push_str(s),
+            Segment::Name(path) => {
+                let target = match path {
+                    Some(path) => path
+                        .evaluate(ctx, item)
+                        .and_then(Value::into_nodes)
+                        .ok()
+                        .and_then(|nodes| nodes.first().copied()),
+                    None => Some(item),
+                };
+                text.push_str(&target.map(|t| ctx.name(t)).unwrap_or_default());
+            }
+            Segment::ValueOf(select) => {
+                if let Ok(value) = select.evaluate(ctx, item) {
+                    text.push_str(&value.string(ctx));
+                }
+            }
+        }
+    }
+
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match (text.is_empty(), check.report) {
+        (false, _) => text,
+        (true, false) => format!("assertion failed: {}", check.test_source),
+        (true, true) => format!("report: {}", check.test_source),
+    }
+}
+
+/// `let` names with their expression sources
+type Lets = Vec<(String, String)>;
+
+/// Reads schema documents into raw patterns
+struct Reader {
+    path: String,
+    base_dir: Option<PathBuf>,
+    namespaces: HashMap<String, String>,
+    default_phase: Option<String>,
+    /// Active pattern ids and lets of each phase
+    phases: HashMap<String, (Vec<String>, Lets)>,
+    lets: Lets,
+    patterns: Vec<RawPattern>,
+    loaded: HashSet<PathBuf>,
+}
+
+impl Reader {
+    fn new(path: String, base_dir: Option<&Path>) -> Self {
+        Self {
+            path,
+            base_dir: base_dir.map(Path::to_path_buf),
+            namespaces: HashMap::new(),
+            default_phase: None,
+            phases: HashMap::new(),
+            lets: Vec::new(),
+            patterns: Vec::new(),
+            loaded: HashSet::new(),
+        }
+    }
+
+    fn invalid(&self, message: impl Into<String>) -> SchemaError {
+        SchemaError::Invalid {
+            path: self.path.clone(),
+            message: message.into(),
+        }
+    }
+
+    fn read_source(&mut self, source: &str) -> Result<(), SchemaError> {
+        let doc = parse_schema(source, &self.path)?;
+        let root = doc.root();
+        if sch_name(root) != Some("schema") {
+            return Err(self.invalid("root element must be sch:schema"));
+        }
+        self.default_phase = root.attr("defaultPhase").map(|p| p.into_owned());
+        self.schema_children(root)
+    }
+
+    /// Parse an included file and pass its root element to `read`
+    fn include(
+        &mut self,
+        node: NodeRef<'_, '_>,
+        read: impl FnOnce(&mut Self, NodeRef<'_, '_>) -> Result<(), SchemaError>,
+    ) -> Result<(), SchemaError> {
+        let href = node
+            .attr("href")
+            .ok_or_else(|| self.invalid("include needs an href"))?;
+        let path = resolve_location(self.base_dir.as_deref(), &href).map_err(|m| self.invalid(m))?;
+        let key = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if !self.loaded.insert(key.clone()) {
+            return Err(self.invalid(format!("'{}' includes itself", href)));
+        }
+
+        let source = read_schema(&path)?;
+        let label = path.display().to_string();
+        let doc = parse_schema(&source, &label)?;
+        let outer = (
+            std::mem::replace(&mut self.path, label),
+            std::mem::replace(&mut self.base_dir, path.parent().map(Path::to_path_buf)),
+        );
+        let result = read(self, doc.root());
+        (self.path, self.base_dir) = outer;
+        self.loaded.remove(&key);
+        result
+    }
+
+    fn schema_children(&mut self, node: NodeRef<'_, '_>) -> Result<(), SchemaError> {
+        for (child, kind) in sch_children(node) {
+            match kind {
+                "ns" => {
+                    let prefix = child.attr("prefix").ok_or_else(|| self.invalid("ns needs a prefix"))?;
+                    let uri = child.attr("uri").ok_or_else(|| self.invalid("ns needs a uri"))?;
+                    self.namespaces.insert(prefix.into_owned(), uri.into_owned());
+                }
+                "let" => {
+                    let variable = self.read_let(child)?;
+                    self.lets.push(variable);
+                }
+                "phase" => {
+                    let id = child.attr("id").ok_or_else(|| self.invalid("phase needs an id"))?;
+                    let mut active = Vec::new();
+                    let mut lets = Vec::new();
+                    for (item, kind) in sch_children(child) {
+                        match kind {
+                            "active" => active.extend(item.attr("pattern").map(|p| p.into_owned())),
+                            "let" => lets.push(self.read_let(item)?),
+                            _ => {}
+                        }
+                    }
+                    self.phases.insert(id.into_owned(), (active, lets));
+                }
+                "pattern" => {
+                    let pattern = self.read_pattern(child)?;
+                    self.patterns.push(pattern);
+                }
+                "include" => self.include(child, |reader, root| match sch_name(root) {
+                    Some("schema") => reader.schema_children(root),
+                    Some("pattern") => {
+                        let pattern = reader.read_pattern(root)?;
+                        reader.patterns.push(pattern);
+                        Ok(())
+                    }
+                    _ => reader.schema_children_of(root),
+                })?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// An included element that is not a schema or pattern, such as an `ns`
+    fn schema_children_of(&mut self, node: NodeRef<'_, '_>) -> Result<(), SchemaError> {
+        match node.parent() {
+            Some(parent) => self.schema_children(parent),
+            None => Err(self.invalid("included file must contain a schema, pattern or rule")),
+        }
+    }
+
+    fn read_let(&self, node: NodeRef<'_, '_>) -> Result<(String, String), SchemaError> {
+        let name = node.attr("name").ok_or_else(|| self.invalid("let needs a name"))?;
+        let value = node.attr("value").ok_or_else(|| {
+            self.invalid(format!("let ${} needs a value attribute", name))
+        })?;
+        Ok((name.into_owned(), unescape(&value).into_owned()))
+    }
+
+    fn read_pattern(&mut self, node: NodeRef<'_, '_>) -> Result<RawPattern, SchemaError> {
+        let mut pattern = RawPattern {
+            id: node.attr("id").map(|v| v.into_owned()),
+            is_abstract: node.attr("abstract").as_deref() == Some("true"),
+            is_a: node.attr("is-a").map(|v| v.into_owned()),
+            ..RawPattern::default()
+        };
+        self.pattern_children(node, &mut pattern)?;
+        Ok(pattern)
+    }
+
+    fn pattern_children(&mut self, node: NodeRef<'_, '_>, pattern: &mut RawPattern) -> Result<(), SchemaError> {
+        for (child, kind) in sch_children(node) {
+            match kind {
+                "let" => pattern.lets.push(self.read_let(child)?),
+                "param" => {
+                    let name = child.attr("name").ok_or_else(|| self.invalid("param needs a name"))?;
+                    let value = child.attr("value").unwrap_or_default();
+                    pattern.params.push((name.into_owned(), unescape(&value).into_owned()));
+                }
+                "rule" => pattern.rules.push(self.read_rule(child)?),
+                "include" => self.include(child, |reader, root| {
+                    let rule = reader.read_rule(root)?;
+                    pattern.rules.push(rule);
+                    Ok(())
+                })?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn read_rule(&mut self, node: NodeRef<'_, '_>) -> Result<RawRule, SchemaError> {
+        if sch_name(node) != Some("rule") {
+            return Err(self.invalid("expected a rule"));
+        }
+        let mut rule = RawRule {
+            id: node.attr("id").map(|v| v.into_owned()),
+            is_abstract: node.attr("abstract").as_deref() == Some("true"),
+            context: node.attr("context").map(|c| unescape(&c).into_owned()),
+            ..RawRule::default()
+        };
+        if !rule.is_abstract && rule.context.is_none() {
+            return Err(self.invalid("rule needs a context"));
+        }
+        for (child, kind) in sch_children(node) {
+            match kind {
+                "let" => rule.lets.push(self.read_let(child)?),
+                "extends" => {
+                    let id = child.attr("rule").ok_or_else(|| self.invalid("extends needs a rule"))?;
+                    rule.extends.push(id.into_owned());
+                }
+                "assert" | "report" => {
+                    let test = child
+                        .attr("test")
+                        .ok_or_else(|| self.invalid(format!("{} needs a test", kind)))?;
+                    rule.checks.push(RawCheck {
+                        report: kind == "report",
+                        test: unescape(&test).into_owned(),
+                        message: self.read_message(child),
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(rule)
+    }
+
+    fn read_message(&self, node: NodeRef<'_, '_>) -> Vec<Segment<String>> {
+        let mut segments = Vec::new();
+        for child in node.children() {
+            match child.node_type() {
+                NodeType::Text => {
+                    segments.push(Segment::Text(unescape(&child.text().unwrap_or_default()).into_owned()))
+                }
+                NodeType::CData => segments.push(Segment::Text(child.text().unwrap_or_default())),
+                NodeType::Element => match sch_name(child) {
+                    Some("name") => segments.push(Segment::Name(
+                        child.attr("path").map(|p| unescape(&p).into_owned()),
+                    )),
+                    Some("value-of") => {
+                        if let Some(select) = child.attr("select") {
+                            segments.push(Segment::ValueOf(unescape(&select).into_owned()));
+                        }
+                    }
+                    // emph, dir, span and foreign markup contribute their text
+                    _ => segments.extend(self.read_message(child)),
+                },
+                _ => {}
+            }
+        }
+        segments
+    }
+
+    /// Resolve phases, abstract rules and patterns, and compile expressions
+    fn compile(self) -> Result<Schematron, SchemaError> {
+        let mut lets = self.compile_lets(&self.lets, &[])?;
+        let phase = self.default_phase.as_deref().filter(|&p| p != "#ALL");
+        let active = match phase {
+            Some(phase) => {
+                let (active, phase_lets) = self
+                    .phases
+                    .get(phase)
+                    .ok_or_else(|| self.invalid(format!("phase '{}' is not defined", phase)))?;
+                lets.extend(self.compile_lets(phase_lets, &[])?);
+                Some(active)
+            }
+            None => None,
+        };
+
+        let abstract_rules: HashMap<&str, &RawRule> = self
+            .patterns
+            .iter()
+            .flat_map(|p| &p.rules)
+            .filter(|r| r.is_abstract)
+            .filter_map(|r| Some((r.id.as_deref()?, r)))
+            .collect();
+
+        let mut patterns = Vec::new();
+        for pattern in self.patterns.iter().filter(|p| !p.is_abstract) {
+            let included = active.is_none_or(|active| {
+                pattern.id.as_ref().is_some_and(|id| active.contains(id))
+            });
+            if !included {
+                continue;
+            }
+            let (template, params) = match &pattern.is_a {
+                Some(is_a) => {
+                    let template = self
+                        .patterns
+                        .iter()
+                        .find(|p| p.is_abstract && p.id.as_deref() == Some(is_a))
+                        .ok_or_else(|| self.invalid(format!("abstract pattern '{}' is not defined", is_a)))?;
+                    (template, pattern.params.as_slice())
+                }
+                None => (pattern, &[][..]),
+            };
+            patterns.push(self.compile_pattern(template, params, &abstract_rules)?);
+        }
+        Ok(Schematron { lets, patterns })
+    }
+
+    fn compile_pattern(
+        &self,
+        pattern: &RawPattern,
+        params: &[(String, String)],
+        abstract_rules: &HashMap<&str, &RawRule>,
+    ) -> Result<Pattern, SchemaError> {
+        let mut rules = Vec::new();
+        for rule in pattern.rules.iter().filter(|r| !r.is_abstract) {
+            let context = rule.context.as_deref().unwrap_or_default();
+            let mut lets = self.compile_lets(&rule.lets, params)?;
+            let mut checks = self.compile_checks(&rule.checks, params)?;
+            let mut pending: Vec<&str> = rule.extends.iter().map(String::as_str).collect();
+            let mut seen = HashSet::new();
+            while let Some(id) = pending.pop() {
+                let base = abstract_rules
+                    .get(id)
+                    .ok_or_else(|| self.invalid(format!("abstract rule '{}' is not defined", id)))?;
+                if !seen.insert(id) {
+                    continue;
+                }
+                lets.extend(self.compile_lets(&base.lets, params)?);
+                checks.extend(self.compile_checks(&base.checks, params)?);
+                pending.extend(base.extends.iter().map(String::as_str));
+            }
+            rules.push(Rule {
+                context: self.xpath(context, params)?.into_pattern(),
+                lets,
+                checks,
+            });
+        }
+        Ok(Pattern {
+            lets: self.compile_lets(&pattern.lets, params)?,
+            rules,
+        })
+    }
+
+    fn compile_lets(&self, lets: &[(String, String)], params: &[(String, String)]) -> Result<Vec<Let>, SchemaError> {
+        lets.iter()
+            .map(|(name, value)| {
+                Ok(Let {
+                    name: name.clone(),
+                    value: self.xpath(value, params)?,
+                })
+            })
+            .collect()
+    }
+
+    fn compile_checks(&self, checks: &[RawCheck], params: &[(String, String)]) -> Result<Vec<Check>, SchemaError> {
+        checks
+            .iter()
+            .map(|check| {
+                let message = check
+                    .message
+                    .iter()
+                    .map(|segment| {
+                        Ok(match segment {
+                            Segment::Text(text) => Segment::Text(text.clone()),
+                            Segment::Name(path) => Segment::Name(
+                                path.as_deref().map(|p| self.xpath(p, params)).transpose()?,
+                            ),
+                            Segment::ValueOf(select) => Segment::ValueOf(self.xpath(select, params)?),
+                        })
+                    })
+                    .collect::<Result<_, SchemaError>>()?;
+                Ok(Check {
+                    report: check.report,
+                    test: self.xpath(&check.test, params)?,
+                    test_source: substitute(&check.test, params),
+                    message,
+                })
+            })
+            .collect()
+    }
+
+    fn xpath(&self, source: &str, params: &[(String, String)]) -> Result<XPath, SchemaError> {
+        XPath::compile(&substitute(source, params), &self.namespaces)
+            .map_err(|err| self.invalid(err.to_string()))
+    }
+}
+
+/// Replace `$name` parameter references of an abstract pattern
+fn substitute(source: &str, params: &[(String, String)]) -> String {
+    let mut params: Vec<_> = params.iter().collect();
+    // Longest names first, so `$a` does not replace the start of `$ab`
+    params.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+    let mut out = source.to_string();
+    for (name, value) in params {
+        out = out.replace(&format!("${}", name), value);
+    }
+    out
+}
+
+/// Local name of an element in a Schematron namespace
+fn sch_name(node: NodeRef<'_, '_>) -> Option<&'static str> {
+    let tag = node.tag_name()?;
+    let (prefix, local) = split_qname(&tag);
+    let ns = lookup_namespace(node, prefix)?;
+    if ns != SCH && ns != SCH_1_5 {
+        return None;
+    }
+    // The reader matches on static names
+    const NAMES: [&str; 15] = [
+        "schema", "ns", "let", "phase", "active", "pattern", "param", "rule", "extends",
+        "assert", "report", "name", "value-of", "include", "title",
+    ];
+    NAMES.into_iter().find(|&n| n == local)
+}
+
+fn sch_children<'a, 'i>(node: NodeRef<'a, 'i>) -> Vec<(NodeRef<'a, 'i>, &'static str)> {
+    node.children()
+        .filter_map(|child| sch_name(child).map(|name| (child, name)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_with_options, ParseOptions};
+    use crate::types::DocumentType;
+
+    fn errors(schema: &str, xml: &str) -> Vec<String> {
+        let schema = Schematron::from_source(schema, None).unwrap();
+        let options = ParseOptions {
+            strict: true,
+            ..ParseOptions::default()
+        };
+        let doc = parse_with_options(xml, DocumentType::Xml, &options).unwrap();
+        schema.validate(&doc).iter().map(|e| e.to_string()).collect()
+    }
+
+    const ORDERS: &str = r#"<schema xmlns="http://purl.oclc.org/dsdl/schematron">
+  <ns prefix="o" uri="urn:orders"/>
+  <let name="max" value="100"/>
+  <pattern>
+    <rule context="o:order[@status = 'shipped']">
+      <assert test="o:shipped">A shipped order needs a <name/> date</assert>
+    </rule>
+    <rule context="o:order">
+      <let name="total" value="sum(o:line/@qty)"/>
+      <assert test="$total &lt;= $max">Order <value-of select="@id"/> has <value-of select="$total"/> items, more than <value-of select="$max"/></assert>
+      <report test="not(o:line)"/>
+    </rule>
+  </pattern>
+  <pattern>
+    <rule context="@qty">
+      <assert test=". &gt; 0">Quantity on <name path=".."/> must be positive</assert>
+    </rule>
+  </pattern>
+</schema>"#;
+
+    #[test]
+    fn test_asserts_and_reports() {
+        let xml = r#"<orders xmlns="urn:orders">
+  <order id="1" status="shipped"><line qty="1"/></order>
+  <order id="2"><line qty="60"/><line qty="50"/></order>
+  <order id="3">
+    <line qty="0"/>
+  </order>
+  <order id="4"/>
+</orders>"#;
+        assert_eq!(
+            errors(ORDERS, xml),
+            [
+                // Only the first matching rule of a pattern fires
+                "2:3: A shipped order needs a order date",
+                "3:3: Order 2 has 110 items, more than 100",
+                "7:3: report: not(o:line)",
+                "5:5: Quantity on line must be positive",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_abstract_rules_and_patterns() {
+        let schema = r#"<sch:schema xmlns:sch="http://purl.oclc.org/dsdl/schematron" defaultPhase="basic">
+  <sch:phase id="basic"><sch:active pattern="titled"/></sch:phase>
+  <sch:pattern abstract="true" id="has-child">
+    <sch:rule context="$parent"><sch:assert test="$child">missing child element</sch:assert></sch:rule>
+  </sch:pattern>
+  <sch:pattern id="titled" is-a="has-child">
+    <sch:param name="parent" value="chapter"/>
+    <sch:param name="child" value="title"/>
+  </sch:pattern>
+  <sch:pattern id="inactive">
+    <sch:rule context="*"><sch:assert test="false()"/></sch:rule>
+  </sch:pattern>
+  <sch:pattern id="identified">
+    <sch:rule abstract="true" id="needs-id"><sch:assert test="@id">no id</sch:assert></sch:rule>
+    <sch:rule context="section"><sch:extends rule="needs-id"/></sch:rule>
+  </sch:pattern>
+</sch:schema>"#;
+        let xml = "<book><chapter/><chapter><title/></chapter></book>";
+        assert_eq!(errors(schema, xml), ["1:7: missing child element"]);
+
+        let all = schema.replace("defaultPhase=\"basic\"", "defaultPhase=\"#ALL\"");
+        let xml = "<book><section/></book>";
+        assert_eq!(
+            errors(&all, xml),
+            ["1:1: assertion failed: false()", "1:7: assertion failed: false()", "1:7: no id"]
+        );
+    }
+
+    #[test]
+    fn test_schema_errors() {
+        let bad = r#"<schema xmlns="http://purl.oclc.org/dsdl/schematron">
+  <pattern><rule context="p:a"><assert test="1"/></rule></pattern>
+</schema>"#;
+        let err = Schematron::from_source(bad, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid schema <schema>: invalid XPath expression: p:a: namespace prefix 'p' is not declared"
+        );
+    }
+}
@@ -0,0 +1,1803 @@
+//! XML Schema 1.0 validation
+//!
+//! Loads a schema (following `xs:include` and `xs:import` of local files)
+//! into declarations for elements, attributes, and simple and complex types,
+//! then validates instance documents against it:
+//! - content models built from sequences, choices, `xs:all`, model groups,
+//!   wildcards and substitution groups, with minOccurs/maxOccurs
+//! - attribute uses, attribute groups and `xs:anyAttribute`
+//! - built-in datatypes, list and union types and all facets
+//! - type derivation by extension and restriction, `xsi:type` and `xsi:nil`
+//! - ID uniqueness and IDREF resolution
+//!
+//! Identity constraints (`xs:key`, `xs:unique`) and `xs:redefine` overrides
+//! are not supported; a redefine is read like an include.
+
+use super::datatypes::{compile_pattern, Builtin, Facet, WhiteSpace};
+use super::{
+    is_namespace_declaration, lookup_namespace, parse_schema, read_schema, resolve_location,
+    split_qname, unescape, ValidationError, Validator,
+};
+use crate::error::SchemaError;
+use crate::types::{Document, NodeRef, NodeType};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+const XS: &str = "http://www.w3.org/2001/XMLSchema";
+const XSI: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
+/// Deepest nesting of model groups followed while matching content
+const MAX_GROUP_DEPTH: usize = 256;
+
+/// An expanded name: namespace URI and local name
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct QName {
+    ns: String,
+    local: String,
+}
+
+impl QName {
+    fn new(ns: &str, local: &str) -> Self {
+        Self {
+            ns: ns.to_string(),
+            local: local.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for QName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.local)
+    }
+}
+
+/// A compiled XML Schema
+#[derive(Debug)]
+pub struct XsdSchema {
+    elements: HashMap<QName, ElementDecl>,
+    types: HashMap<QName, Type>,
+    groups: HashMap<QName, Particle>,
+    attribute_groups: HashMap<QName, AttributeGroup>,
+    attributes: HashMap<QName, AttributeDecl>,
+    /// Members of each substitution group head
+    substitutions: HashMap<QName, Vec<QName>>,
+}
+
+#[derive(Debug)]
+struct ElementDecl {
+    name: QName,
+    /// `None` takes the type of the substitution group head, or `xs:anyType`
+    ty: Option<TypeRef>,
+    substitution_head: Option<QName>,
+    nillable: bool,
+    is_abstract: bool,
+    default: Option<String>,
+    fixed: Option<String>,
+}
+
+#[derive(Debug)]
+enum TypeRef {
+    Named(QName),
+    Inline(Box<Type>),
+}
+
+#[derive(Debug)]
+enum Type {
+    Simple(SimpleType),
+    Complex(Box<ComplexType>),
+}
+
+#[derive(Debug)]
+struct SimpleType {
+    variety: Variety,
+    facets: Vec<Facet>,
+}
+
+#[derive(Debug)]
+enum Variety {
+    Builtin(Builtin),
+    Restriction(TypeRef),
+    List(TypeRef),
+    Union(Vec<TypeRef>),
+}
+
+#[derive(Debug)]
+struct ComplexType {
+    mixed: bool,
+    is_abstract: bool,
+    base: Option<(Derivation, TypeRef)>,
+    content: Content,
+    attributes: Attributes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Derivation {
+    Extension,
+    Restriction,
+}
+
+#[derive(Debug)]
+enum Content {
+    Empty,
+    /// Simple content: a restriction of the base type's value
+    Simple(SimpleType),
+    Elements(Particle),
+}
+
+/// Attribute uses, group references and wildcard of a type or group
+#[derive(Debug, Default)]
+struct Attributes {
+    uses: Vec<AttributeUse>,
+    groups: Vec<QName>,
+    wildcard: Option<Wildcard>,
+}
+
+type AttributeGroup = Attributes;
+
+#[derive(Debug)]
+struct AttributeDecl {
+    ty: Option<TypeRef>,
+    fixed: Option<String>,
+}
+
+#[derive(Debug)]
+struct AttributeUse {
+    name: QName,
+    /// Refers to a global attribute declaration
+    global: bool,
+    decl: AttributeDecl,
+    required: bool,
+    prohibited: bool,
+}
+
+#[derive(Debug)]
+struct Particle {
+    min: u32,
+    /// `None` is unbounded
+    max: Option<u32>,
+    term: Term,
+}
+
+#[derive(Debug)]
+enum Term {
+    Element(ElementDecl),
+    ElementRef(QName),
+    Sequence(Vec<Particle>),
+    Choice(Vec<Particle>),
+    All(Vec<Particle>),
+    Group(QName),
+    Any(Wildcard),
+}
+
+#[derive(Debug, Clone)]
+struct Wildcard {
+    namespaces: NamespaceConstraint,
+    process: ProcessContents,
+}
+
+#[derive(Debug, Clone)]
+enum NamespaceConstraint {
+    Any,
+    /// `##other`: any namespace except these (the target and no namespace)
+    Not(Vec<String>),
+    Only(Vec<String>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProcessContents {
+    Strict,
+    Lax,
+    Skip,
+}
+
+impl Wildcard {
+    fn allows(&self, ns: &str) -> bool {
+        match &self.namespaces {
+            NamespaceConstraint::Any => true,
+            NamespaceConstraint::Not(excluded) => !excluded.iter().any(|n| n == ns),
+            NamespaceConstraint::Only(allowed) => allowed.iter().any(|n| n == ns),
+        }
+    }
+}
+
+impl XsdSchema {
+    /// Load a schema file, following includes and imports relative to it
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, SchemaError> {
+        let mut loader = Loader::new();
+        loader.load_file(path.as_ref(), None)?;
+        loader.finish()
+    }
+
+    /// Compile a schema from source text
+    ///
+    /// Includes and imports are resolved against `base_dir`; without one
+    /// they are an error.
+    pub fn from_source(source: &str, base_dir: Option<&Path>) -> Result<Self, SchemaError> {
+        let mut loader = Loader::new();
+        loader.load_source(source, base_dir, "<schema>", None)?;
+        loader.finish()
+    }
+
+    fn lookup<'s>(&'s self, ty: &'s TypeRef) -> Option<&'s Type> {
+        match ty {
+            TypeRef::Named(name) => self.types.get(name),
+            TypeRef::Inline(ty) => Some(ty),
+        }
+    }
+
+    /// Type of an element declaration, following substitution group heads
+    fn element_type<'s>(&'s self, decl: &'s ElementDecl) -> Option<&'s Type> {
+        let mut decl = decl;
+        for _ in 0..MAX_GROUP_DEPTH {
+            if let Some(ty) = &decl.ty {
+                return self.lookup(ty);
+            }
+            match decl.substitution_head.as_ref().and_then(|h| self.elements.get(h)) {
+                Some(head) => decl = head,
+                None => break,
+            }
+        }
+        self.types.get(&QName::new(XS, "anyType"))
+    }
+
+    /// Whether a child named `name` can stand for the element `head`
+    fn substitutes(&self, head: &QName, name: &QName) -> bool {
+        let mut pending = vec![head];
+        let mut seen = HashSet::new();
+        while let Some(current) = pending.pop() {
+            if current == name {
+                return true;
+            }
+            if seen.insert(current) {
+                pending.extend(self.substitutions.get(current).into_iter().flatten());
+            }
+        }
+        false
+    }
+}
+
+impl Validator for XsdSchema {
+    fn validate(&self, doc: &Document<'_>) -> Vec<ValidationError> {
+        let mut run = Run {
+            schema: self,
+            errors: Vec::new(),
+            ids: HashSet::new(),
+            idrefs: Vec::new(),
+        };
+
+        let root = doc.root();
+        if root.node_type() != NodeType::Element {
+            run.errors.push(ValidationError::at(root, "document has no root element"));
+            return run.errors;
+        }
+        let name = element_name(root);
+        match self.elements.get(&name) {
+            Some(decl) => run.element(root, decl),
+            None => run.errors.push(ValidationError::at(
+                root,
+                format!("no declaration for root element <{}>", name),
+            )),
+        }
+
+        for (node, id) in std::mem::take(&mut run.idrefs) {
+            if !run.ids.contains(&id) {
+                run.errors
+                    .push(ValidationError::at(node, format!("IDREF '{}' has no matching ID", id)));
+            }
+        }
+        run.errors
+    }
+}
+
+/// Expanded name of an element in an instance document
+fn element_name(node: NodeRef<'_, '_>) -> QName {
+    let tag = node.tag_name().unwrap_or_default();
+    let (prefix, local) = split_qname(&tag);
+    let ns = lookup_namespace(node, prefix).unwrap_or_default();
+    QName::new(&ns, local)
+}
+
+/// Expanded name of an attribute; unprefixed attributes have no namespace
+fn attribute_name(node: NodeRef<'_, '_>, name: &str) -> QName {
+    match name.split_once(':') {
+        Some((prefix, local)) => {
+            QName::new(&lookup_namespace(node, prefix).unwrap_or_default(), local)
+        }
+        None => QName::new("", name),
+    }
+}
+
+/// State of one validation run
+struct Run<'s, 'a, 'i> {
+    schema: &'s XsdSchema,
+    errors: Vec<ValidationError>,
+    ids: HashSet<String>,
+    idrefs: Vec<(NodeRef<'a, 'i>, String)>,
+}
+
+/// Content model of a complex type after derivation
+enum Model<'s> {
+    Empty,
+    Simple,
+    Elements(Vec<&'s Particle>),
+}
+
+impl<'s, 'a, 'i> Run<'s, 'a, 'i> {
+    fn error(&mut self, node: NodeRef<'a, 'i>, message: impl Into<String>) {
+        self.errors.push(ValidationError::at(node, message));
+    }
+
+    fn element(&mut self, node: NodeRef<'a, 'i>, decl: &'s ElementDecl) {
+        let schema = self.schema;
+        if decl.is_abstract {
+            self.error(node, format!("element <{}> is abstract", decl.name));
+        }
+
+        let mut ty = schema.element_type(decl);
+        let mut nil = false;
+        for (name, value) in node.attributes() {
+            let name = attribute_name(node, &name);
+            if name.ns != XSI {
+                continue;
+            }
+            match name.local.as_str() {
+                "type" => match self.instance_type(node, &value) {
+                    Some(found) => ty = Some(found),
+                    None => self.error(node, format!("xsi:type '{}' is not defined", value)),
+                },
+                "nil" => nil = matches!(value.trim(), "true" | "1"),
+                _ => {}
+            }
+        }
+
+        if nil {
+            if !decl.nillable {
+                self.error(node, format!("element <{}> is not nillable", decl.name));
+            } else if node.children().any(|c| c.node_type() == NodeType::Element)
+                || !text_content(node).is_empty()
+            {
+                self.error(node, format!("nil element <{}> must be empty", decl.name));
+            }
+        }
+
+        match ty {
+            Some(Type::Simple(simple)) => {
+                self.check_no_attributes(node);
+                if self.check_no_children(node, &decl.name) && !nil {
+                    let text = text_content(node);
+                    self.check_element_value(node, decl, &text, |run, value| {
+                        run.check_simple(node, simple, value)
+                    });
+                }
+            }
+            Some(Type::Complex(complex)) => self.complex(node, decl, complex, nil),
+            None => {}
+        }
+    }
+
+    /// Resolve an `xsi:type` value in the instance's namespace scope
+    fn instance_type(&self, node: NodeRef<'_, '_>, value: &str) -> Option<&'s Type> {
+        let (prefix, local) = split_qname(value.trim());
+        let ns = lookup_namespace(node, prefix)?;
+        self.schema.types.get(&QName::new(&ns, local))
+    }
+
+    /// Check text against a default or fixed value and the type
+    fn check_element_value(
+        &mut self,
+        node: NodeRef<'a, 'i>,
+        decl: &ElementDecl,
+        text: &str,
+        check: impl FnOnce(&mut Self, &str) -> Result<Option<Builtin>, String>,
+    ) {
+        let value = match (&decl.default, &decl.fixed) {
+            (Some(default), _) | (None, Some(default)) if text.is_empty() => default.as_str(),
+            _ => text,
+        };
+        match check(self, value) {
+            Ok(builtin) => {
+                if let Some(fixed) = &decl.fixed {
+                    let equal = builtin.map_or(value == fixed, |b| {
+                        b.equal(&b.whitespace().apply(value), &b.whitespace().apply(fixed))
+                    });
+                    if !equal {
+                        self.error(
+                            node,
+                            format!("element <{}> must have the fixed value '{}'", decl.name, fixed),
+                        );
+                    }
+                }
+            }
+            Err(reason) => self.error(
+                node,
+                format!("value '{}' of element <{}> {}", value, decl.name, reason),
+            ),
+        }
+    }
+
+    fn check_no_attributes(&mut self, node: NodeRef<'a, 'i>) {
+        for (name, _) in node.attributes() {
+            if !is_namespace_declaration(&name) && attribute_name(node, &name).ns != XSI {
+                let message = format!(
+                    "attribute '{}' is not allowed on <{}>",
+                    name,
+                    node.tag_name().unwrap_or_default()
+                );
+                self.error(node, message);
+            }
+        }
+    }
+
+    /// Report child elements of an element with simple content
+    fn check_no_children(&mut self, node: NodeRef<'a, 'i>, name: &QName) -> bool {
+        match node.children().find(|c| c.node_type() == NodeType::Element) {
+            Some(child) => {
+                self.error(child, format!("element <{}> cannot have child elements", name));
+                false
+            }
+            None => true,
+        }
+    }
+
+    fn complex(&mut self, node: NodeRef<'a, 'i>, decl: &ElementDecl, ty: &'s ComplexType, nil: bool) {
+        if ty.is_abstract {
+            self.error(node, format!("element <{}> has an abstract type", decl.name));
+        }
+        self.check_attributes(node, ty);
+        if nil {
+            return;
+        }
+
+        match self.model(ty) {
+            Model::Empty => {
+                let has_content = node.children().any(|c| c.node_type() == NodeType::Element)
+                    || !text_content(node).trim().is_empty();
+                if has_content {
+                    self.error(node, format!("element <{}> must be empty", decl.name));
+                }
+            }
+            Model::Simple => {
+                if self.check_no_children(node, &decl.name) {
+                    let text = text_content(node);
+                    self.check_element_value(node, decl, &text, |run, value| {
+                        run.check_simple_content(node, ty, value)
+                    });
+                }
+            }
+            Model::Elements(particles) => {
+                if !ty.mixed {
+                    let text = node.children().find(|c| {
+                        matches!(c.node_type(), NodeType::Text | NodeType::CData)
+                            && !c.text().unwrap_or_default().trim().is_empty()
+                    });
+                    if let Some(text) = text {
+                        self.error(text, format!("element <{}> cannot contain text", decl.name));
+                    }
+                }
+                self.children(node, &decl.name, &particles);
+            }
+        }
+    }
+
+    fn model(&self, ty: &'s ComplexType) -> Model<'s> {
+        let mut particles = Vec::new();
+        if let Some((Derivation::Extension, base)) = &ty.base {
+            match self.schema.lookup(base) {
+                Some(Type::Complex(base)) => match self.model(base) {
+                    Model::Elements(inherited) => particles = inherited,
+                    Model::Simple => return Model::Simple,
+                    Model::Empty => {}
+                },
+                _ => return Model::Simple,
+            }
+        }
+        match &ty.content {
+            Content::Simple(_) => Model::Simple,
+            Content::Elements(particle) => {
+                particles.push(particle);
+                Model::Elements(particles)
+            }
+            Content::Empty if particles.is_empty() => Model::Empty,
+            Content::Empty => Model::Elements(particles),
+        }
+    }
+
+    /// Match child elements against the content model and validate them
+    fn children(&mut self, node: NodeRef<'a, 'i>, name: &QName, particles: &[&'s Particle]) {
+        let children: Vec<_> = node
+            .children()
+            .filter(|c| c.node_type() == NodeType::Element)
+            .collect();
+        let names: Vec<_> = children.iter().map(|&c| element_name(c)).collect();
+
+        let mut matcher = Matcher {
+            schema: self.schema,
+            names: &names,
+            furthest: 0,
+            expected: Vec::new(),
+            depth: 0,
+        };
+        let mut ends = BTreeSet::from([0]);
+        for particle in particles {
+            ends = ends
+                .iter()
+                .flat_map(|&start| matcher.particle(particle, start))
+                .collect();
+        }
+
+        if !ends.contains(&names.len()) {
+            let expected = if matcher.expected.is_empty() {
+                String::new()
+            } else {
+                let list: Vec<_> = matcher.expected.iter().map(|e| e.to_string()).collect();
+                format!("; expected {}", list.join(", "))
+            };
+            match children.get(matcher.furthest) {
+                Some(&child) => self.error(
+                    child,
+                    format!("element <{}> is not expected here{}", names[matcher.furthest], expected),
+                ),
+                None => self.error(node, format!("element <{}> is incomplete{}", name, expected)),
+            }
+        }
+
+        let mut decls = HashMap::new();
+        let mut wildcards = Vec::new();
+        for particle in particles {
+            self.collect_decls(particle, &mut decls, &mut wildcards, 0);
+        }
+        for (child, child_name) in children.into_iter().zip(&names) {
+            let decl = decls.get(child_name).copied().or_else(|| {
+                // A member of a substitution group standing in for its head
+                self.schema
+                    .elements
+                    .get(child_name)
+                    .filter(|_| decls.keys().any(|head| self.schema.substitutes(head, child_name)))
+            });
+            if let Some(decl) = decl {
+                self.element(child, decl);
+                continue;
+            }
+            // Children matching nothing were reported with the content model
+            if let Some(wildcard) = wildcards.iter().find(|w| w.allows(&child_name.ns)) {
+                self.wildcard_element(child, child_name, wildcard.process);
+            }
+        }
+    }
+
+    fn wildcard_element(&mut self, node: NodeRef<'a, 'i>, name: &QName, process: ProcessContents) {
+        if process == ProcessContents::Skip {
+            return;
+        }
+        match self.schema.elements.get(name) {
+            Some(decl) => self.element(node, decl),
+            None if process == ProcessContents::Strict => {
+                self.error(node, format!("no declaration for element <{}>", name));
+            }
+            None => {}
+        }
+    }
+
+    /// Element declarations and wildcards reachable in a content model
+    fn collect_decls(
+        &self,
+        particle: &'s Particle,
+        decls: &mut HashMap<QName, &'s ElementDecl>,
+        wildcards: &mut Vec<&'s Wildcard>,
+        depth: usize,
+    ) {
+        if depth > MAX_GROUP_DEPTH {
+            return;
+        }
+        match &particle.term {
+            Term::Element(decl) => {
+                decls.entry(decl.name.clone()).or_insert(decl);
+            }
+            Term::ElementRef(name) => {
+                if let Some(decl) = self.schema.elements.get(name) {
+                    decls.entry(name.clone()).or_insert(decl);
+                }
+            }
+            Term::Sequence(items) | Term::Choice(items) | Term::All(items) => {
+                for item in items {
+                    self.collect_decls(item, decls, wildcards, depth + 1);
+                }
+            }
+            Term::Group(name) => {
+                if let Some(group) = self.schema.groups.get(name) {
+                    self.collect_decls(group, decls, wildcards, depth + 1);
+                }
+            }
+            Term::Any(wildcard) => wildcards.push(wildcard),
+        }
+    }
+
+    fn check_attributes(&mut self, node: NodeRef<'a, 'i>, ty: &'s ComplexType) {
+        let mut uses: Vec<&'s AttributeUse> = Vec::new();
+        let mut wildcard = None;
+        self.collect_attributes(ty, &mut uses, &mut wildcard, 0);
+        let element = node.tag_name().unwrap_or_default().into_owned();
+
+        let mut seen = HashSet::new();
+        for (raw_name, value) in node.attributes() {
+            if is_namespace_declaration(&raw_name) {
+                continue;
+            }
+            let name = attribute_name(node, &raw_name);
+            if name.ns == XSI {
+                continue;
+            }
+            let value = unescape(&value);
+            match uses.iter().find(|u| u.name == name) {
+                Some(attribute) => {
+                    seen.insert(name);
+                    self.check_attribute_value(node, &raw_name, &element, attribute, &value);
+                }
+                None => match wildcard.filter(|w| w.allows(&name.ns)) {
+                    Some(wildcard) if wildcard.process == ProcessContents::Skip => {}
+                    Some(wildcard) => match self.schema.attributes.get(&name) {
+                        Some(decl) => {
+                            let ty = decl.ty.as_ref().and_then(|t| self.schema.lookup(t));
+                            self.check_value(node, ty, &value, |reason| {
+                                format!("value '{}' of attribute '{}' on <{}> {}", value, raw_name, element, reason)
+                            });
+                        }
+                        None if wildcard.process == ProcessContents::Strict => self.error(
+                            node,
+                            format!("no declaration for attribute '{}' on <{}>", raw_name, element),
+                        ),
+                        None => {}
+                    },
+                    None => self.error(
+                        node,
+                        format!("attribute '{}' is not allowed on <{}>", raw_name, element),
+                    ),
+                },
+            }
+        }
+
+        for attribute in uses {
+            if attribute.required && !seen.contains(&attribute.name) {
+                self.error(
+                    node,
+                    format!("missing required attribute '{}' on <{}>", attribute.name, element),
+                );
+            }
+        }
+    }
+
+    fn check_attribute_value(
+        &mut self,
+        node: NodeRef<'a, 'i>,
+        raw_name: &str,
+        element: &str,
+        attribute: &'s AttributeUse,
+        value: &str,
+    ) {
+        let global = attribute
+            .global
+            .then(|| self.schema.attributes.get(&attribute.name))
+            .flatten();
+        let decl_ty = attribute.decl.ty.as_ref().or(global.and_then(|g| g.ty.as_ref()));
+        let fixed = attribute
+            .decl
+            .fixed
+            .as_ref()
+            .or(global.and_then(|g| g.fixed.as_ref()));
+
+        let ty = decl_ty.and_then(|t| self.schema.lookup(t));
+        let builtin = self.check_value(node, ty, value, |reason| {
+            format!("value '{}' of attribute '{}' on <{}> {}", value, raw_name, element, reason)
+        });
+        if let (Some(fixed), Some(builtin)) = (fixed, builtin) {
+            let ws = builtin.whitespace();
+            if !builtin.equal(&ws.apply(value), &ws.apply(fixed)) {
+                self.error(
+                    node,
+                    format!("attribute '{}' on <{}> must have the fixed value '{}'", raw_name, element, fixed),
+                );
+            }
+        }
+    }
+
+    /// Check a value against an optional simple type; returns its built-in base
+    fn check_value(
+        &mut self,
+        node: NodeRef<'a, 'i>,
+        ty: Option<&'s Type>,
+        value: &str,
+        message: impl FnOnce(&str) -> String,
+    ) -> Option<Builtin> {
+        let result = match ty {
+            Some(Type::Simple(simple)) => self.check_simple(node, simple, value),
+            None => Ok(Some(Builtin::AnySimpleType)),
+            Some(Type::Complex(_)) => Ok(None),
+        };
+        match result {
+            Ok(builtin) => builtin,
+            Err(reason) => {
+                self.error(node, message(&reason));
+                None
+            }
+        }
+    }
+
+    fn collect_attributes(
+        &self,
+        ty: &'s ComplexType,
+        uses: &mut Vec<&'s AttributeUse>,
+        wildcard: &mut Option<&'s Wildcard>,
+        depth: usize,
+    ) {
+        if depth > MAX_GROUP_DEPTH {
+            return;
+        }
+        if let Some((_, base)) = &ty.base {
+            if let Some(Type::Complex(base)) = self.schema.lookup(base) {
+                self.collect_attributes(base, uses, wildcard, depth + 1);
+            }
+        }
+        self.collect_group_attributes(&ty.attributes, uses, wildcard, depth);
+    }
+
+    fn collect_group_attributes(
+        &self,
+        attributes: &'s Attributes,
+        uses: &mut Vec<&'s AttributeUse>,
+        wildcard: &mut Option<&'s Wildcard>,
+        depth: usize,
+    ) {
+        for attribute in &attributes.uses {
+            // Later (derived) declarations override inherited ones
+            uses.retain(|u| u.name != attribute.name);
+            if !attribute.prohibited {
+                uses.push(attribute);
+            }
+        }
+        for group in &attributes.groups {
+            if let Some(group) = self.schema.attribute_groups.get(group) {
+                if depth < MAX_GROUP_DEPTH {
+                    self.collect_group_attributes(group, uses, wildcard, depth + 1);
+                }
+            }
+        }
+        if attributes.wildcard.is_some() {
+            *wildcard = attributes.wildcard.as_ref();
+        }
+    }
+
+    /// Check the value of a complex type with simple content
+    fn check_simple_content(
+        &mut self,
+        node: NodeRef<'a, 'i>,
+        ty: &'s ComplexType,
+        value: &str,
+    ) -> Result<Option<Builtin>, String> {
+        match &ty.content {
+            Content::Simple(simple) => self.check_simple(node, simple, value),
+            // Extension of a type with simple content
+            _ => match ty.base.as_ref().and_then(|(_, base)| self.schema.lookup(base)) {
+                Some(Type::Simple(simple)) => self.check_simple(node, simple, value),
+                Some(Type::Complex(base)) => self.check_simple_content(node, base, value),
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Check a value against a simple type; returns the built-in it derives from
+    fn check_simple(
+        &mut self,
+        node: NodeRef<'a, 'i>,
+        ty: &'s SimpleType,
+        value: &str,
+    ) -> Result<Option<Builtin>, String> {
+        let (builtin, list) = match &ty.variety {
+            Variety::Builtin(builtin) => {
+                let normalized = builtin.whitespace().apply(value);
+                if !builtin.check(&normalized) {
+                    return Err(format!("is not a valid {}", builtin.name()));
+                }
+                self.track_ids(node, *builtin, &normalized);
+                (Some(*builtin), builtin.item_type().is_some())
+            }
+            Variety::Restriction(base) => {
+                let builtin = match self.schema.lookup(base) {
+                    Some(Type::Simple(base)) => self.check_simple(node, base, value)?,
+                    Some(Type::Complex(base)) => self.check_simple_content(node, base, value)?,
+                    None => None,
+                };
+                (builtin, self.is_list(base, 0))
+            }
+            Variety::List(item) => {
+                for token in value.split_ascii_whitespace() {
+                    if let Some(Type::Simple(item)) = self.schema.lookup(item) {
+                        if let Err(reason) = self.check_simple(node, item, token) {
+                            return Err(format!("contains '{}', which {}", token, reason));
+                        }
+                    }
+                }
+                (None, true)
+            }
+            Variety::Union(members) => {
+                let mut valid = false;
+                let mut names = Vec::new();
+                for member in members {
+                    if let Some(Type::Simple(simple)) = self.schema.lookup(member) {
+                        // Validate silently; only the union as a whole reports
+                        let errors = self.errors.len();
+                        if self.check_simple(node, simple, value).is_ok() {
+                            valid = true;
+                            break;
+                        }
+                        self.errors.truncate(errors);
+                    }
+                    if let TypeRef::Named(name) = member {
+                        names.push(name.local.clone());
+                    }
+                }
+                if !valid {
+                    return Err(if names.is_empty() {
+                        "does not match any member of the union".to_string()
+                    } else {
+                        format!("is not a valid {}", names.join(" or "))
+                    });
+                }
+                (None, false)
+            }
+        };
+
+        if ty.facets.is_empty() {
+            return Ok(builtin);
+        }
+        let ws = self.whitespace(ty, 0);
+        let normalized = ws.apply(value);
+        let base = builtin.unwrap_or(Builtin::String);
+        let length = if list {
+            normalized.split_ascii_whitespace().count()
+        } else {
+            base.length(&normalized)
+        };
+        for facet in &ty.facets {
+            facet.check(base, &normalized, length)?;
+        }
+        Ok(builtin)
+    }
+
+    fn track_ids(&mut self, node: NodeRef<'a, 'i>, builtin: Builtin, value: &str) {
+        match builtin {
+            Builtin::Id if !self.ids.insert(value.to_string()) => {
+                self.error(node, format!("duplicate ID '{}'", value));
+            }
+            Builtin::IdRef => self.idrefs.push((node, value.to_string())),
+            Builtin::IdRefs => {
+                for id in value.split(' ') {
+                    self.idrefs.push((node, id.to_string()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn is_list(&self, ty: &'s TypeRef, depth: usize) -> bool {
+        match self.schema.lookup(ty) {
+            _ if depth > MAX_GROUP_DEPTH => false,
+            Some(Type::Simple(simple)) => match &simple.variety {
+                Variety::Builtin(builtin) => builtin.item_type().is_some(),
+                Variety::List(_) => true,
+                Variety::Restriction(base) => self.is_list(base, depth + 1),
+                Variety::Union(_) => false,
+            },
+            _ => false,
+        }
+    }
+
+    fn whitespace(&self, ty: &'s SimpleType, depth: usize) -> WhiteSpace {
+        let own = ty.facets.iter().rev().find_map(|f| match f {
+            Facet::WhiteSpace(ws) => Some(*ws),
+            _ => None,
+        });
+        if let Some(ws) = own {
+            return ws;
+        }
+        match &ty.variety {
+            Variety::Builtin(builtin) => builtin.whitespace(),
+            Variety::List(_) => WhiteSpace::Collapse,
+            Variety::Union(_) => WhiteSpace::Preserve,
+            Variety::Restriction(base) => match self.schema.lookup(base) {
+                Some(Type::Simple(base)) if depth < MAX_GROUP_DEPTH => self.whitespace(base, depth + 1),
+                _ => WhiteSpace::Preserve,
+            },
+        }
+    }
+}
+
+/// Matches a sequence of child element names against particles
+///
+/// Each step maps a set of start positions to the set of positions where the
+/// particle can end, so every way of matching is explored without
+/// backtracking blowup.
+struct Matcher<'s, 'n> {
+    schema: &'s XsdSchema,
+    names: &'n [QName],
+    /// Furthest position any match attempt reached
+    furthest: usize,
+    /// Names that would have been accepted at `furthest`
+    expected: Vec<String>,
+    depth: usize,
+}
+
+impl<'s> Matcher<'s, '_> {
+    fn particle(&mut self, particle: &'s Particle, start: usize) -> BTreeSet<usize> {
+        let mut ends = BTreeSet::new();
+        if particle.min == 0 {
+            ends.insert(start);
+        }
+        let mut frontier = BTreeSet::from([start]);
+        let mut count = 0;
+        while !frontier.is_empty() && particle.max.is_none_or(|max| count < max) {
+            count += 1;
+            let next: BTreeSet<usize> = frontier
+                .iter()
+                .flat_map(|&pos| self.term(&particle.term, pos))
+                .collect();
+            if count >= particle.min {
+                // Only positions not seen before can lead anywhere new
+                frontier = next.difference(&ends).copied().collect();
+                ends.extend(next);
+            } else {
+                frontier = next;
+            }
+        }
+        ends
+    }
+
+    fn term(&mut self, term: &'s Term, start: usize) -> BTreeSet<usize> {
+        match term {
+            Term::Element(decl) => self.element(&decl.name, start),
+            Term::ElementRef(name) => self.element(name, start),
+            Term::Any(wildcard) => {
+                if self.names.get(start).is_some_and(|n| wildcard.allows(&n.ns)) {
+                    self.reached(start + 1);
+                    BTreeSet::from([start + 1])
+                } else {
+                    self.expect(start, "any element".to_string());
+                    BTreeSet::new()
+                }
+            }
+            Term::Sequence(items) => {
+                let mut positions = BTreeSet::from([start]);
+                for item in items {
+                    positions = positions
+                        .iter()
+                        .flat_map(|&pos| self.particle(item, pos))
+                        .collect();
+                    if positions.is_empty() {
+                        break;
+                    }
+                }
+                positions
+            }
+            Term::Choice(items) => items
+                .iter()
+                .flat_map(|item| self.particle(item, start))
+                .collect(),
+            Term::All(items) => self.all(items, start),
+            Term::Group(name) => {
+                let Some(group) = self.schema.groups.get(name) else {
+                    return BTreeSet::new();
+                };
+                if self.depth >= MAX_GROUP_DEPTH {
+                    return BTreeSet::new();
+                }
+                self.depth += 1;
+                let ends = self.particle(group, start);
+                self.depth -= 1;
+                ends
+            }
+        }
+    }
+
+    fn element(&mut self, name: &QName, start: usize) -> BTreeSet<usize> {
+        match self.names.get(start) {
+            Some(found) if found == name || self.schema.substitutes(name, found) => {
+                self.reached(start + 1);
+                BTreeSet::from([start + 1])
+            }
+            _ => {
+                self.expect(start, format!("<{}>", name));
+                BTreeSet::new()
+            }
+        }
+    }
+
+    /// `xs:all`: each element at most once, in any order
+    fn all(&mut self, items: &'s [Particle], start: usize) -> BTreeSet<usize> {
+        let name_of = |item: &Particle| match &item.term {
+            Term::Element(decl) => Some(decl.name.clone()),
+            Term::ElementRef(name) => Some(name.clone()),
+            _ => None,
+        };
+        let mut used = vec![false; items.len()];
+        let mut pos = start;
+        while let Some(found) = self.names.get(pos) {
+            let matched = items.iter().enumerate().position(|(i, item)| {
+                !used[i] && name_of(item).is_some_and(|n| &n == found || self.schema.substitutes(&n, found))
+            });
+            match matched {
+                Some(i) => {
+                    used[i] = true;
+                    pos += 1;
+                    self.reached(pos);
+                }
+                None => break,
+            }
+        }
+
+        let mut complete = true;
+        for (i, item) in items.iter().enumerate() {
+            if !used[i] {
+                if let Some(name) = name_of(item) {
+                    self.expect(pos, format!("<{}>", name));
+                }
+                complete &= item.min == 0;
+            }
+        }
+        if complete {
+            BTreeSet::from([pos])
+        } else {
+            BTreeSet::new()
+        }
+    }
+
+    fn reached(&mut self, pos: usize) {
+        if pos > self.furthest {
+            self.furthest = pos;
+            self.expected.clear();
+        }
+    }
+
+    fn expect(&mut self, pos: usize, what: String) {
+        self.reached(pos);
+        if pos == self.furthest && !self.expected.contains(&what) {
+            self.expected.push(what);
+        }
+    }
+}
+
+/// Concatenated character data of an element's own text children
+fn text_content(node: NodeRef<'_, '_>) -> String {
+    let mut text = String::new();
+    for child in node.children() {
+        match child.node_type() {
+            NodeType::Text => text.push_str(&unescape(&child.text().unwrap_or_default())),
+            NodeType::CData => text.push_str(&child.text().unwrap_or_default()),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Reads schema documents into an [`XsdSchema`]
+struct Loader {
+    schema: XsdSchema,
+    /// Files already read, to stop include cycles
+    loaded: HashSet<PathBuf>,
+    /// Named components referenced so far, checked once everything is loaded
+    references: Vec<(Component, QName, String)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Component {
+    Type,
+    Element,
+    Group,
+    AttributeGroup,
+    Attribute,
+}
+
+/// Settings of the schema document being read
+struct SchemaDoc {
+    path: String,
+    target: String,
+    element_qualified: bool,
+    attribute_qualified: bool,
+    /// Included without a target namespace: unqualified references mean the includer's
+    chameleon: bool,
+}
+
+impl Loader {
+    fn new() -> Self {
+        let mut types = HashMap::new();
+        const BUILTINS: [&str; 45] = [
+            "anySimpleType", "string", "normalizedString", "token", "language", "Name",
+            "NCName", "ID", "IDREF", "IDREFS", "ENTITY", "ENTITIES", "NMTOKEN", "NMTOKENS",
+            "boolean", "decimal", "integer", "nonPositiveInteger", "negativeInteger", "long",
+            "int", "short", "byte", "nonNegativeInteger", "unsignedLong", "unsignedInt",
+            "unsignedShort", "unsignedByte", "positiveInteger", "float", "double", "duration",
+            "dateTime", "date", "time", "gYear", "gYearMonth", "gMonth", "gMonthDay", "gDay",
+            "hexBinary", "base64Binary", "anyURI", "QName", "NOTATION",
+        ];
+        for name in BUILTINS {
+            if let Some(builtin) = Builtin::from_name(name) {
+                types.insert(
+                    QName::new(XS, name),
+                    Type::Simple(SimpleType {
+                        variety: Variety::Builtin(builtin),
+                        facets: Vec::new(),
+                    }),
+                );
+            }
+        }
+        let any = Wildcard {
+            namespaces: NamespaceConstraint::Any,
+            process: ProcessContents::Lax,
+        };
+        types.insert(
+            QName::new(XS, "anyType"),
+            Type::Complex(Box::new(ComplexType {
+                mixed: true,
+                is_abstract: false,
+                base: None,
+                content: Content::Elements(Particle {
+                    min: 0,
+                    max: None,
+                    term: Term::Any(any.clone()),
+                }),
+                attributes: Attributes {
+                    wildcard: Some(any),
+                    ..Attributes::default()
+                },
+            })),
+        );
+
+        Self {
+            schema: XsdSchema {
+                elements: HashMap::new(),
+                types,
+                groups: HashMap::new(),
+                attribute_groups: HashMap::new(),
+                attributes: HashMap::new(),
+                substitutions: HashMap::new(),
+            },
+            loaded: HashSet::new(),
+            references: Vec::new(),
+        }
+    }
+
+    fn finish(self) -> Result<XsdSchema, SchemaError> {
+        for (component, name, path) in &self.references {
+            let (defined, kind) = match component {
+                Component::Type => (self.schema.types.contains_key(name), "type"),
+                Component::Element => (self.schema.elements.contains_key(name), "element"),
+                Component::Group => (self.schema.groups.contains_key(name), "group"),
+                Component::AttributeGroup => {
+                    (self.schema.attribute_groups.contains_key(name), "attribute group")
+                }
+                Component::Attribute => (self.schema.attributes.contains_key(name), "attribute"),
+            };
+            if !defined {
+                let namespace = if name.ns.is_empty() {
+                    String::new()
+                } else {
+                    format!(" in namespace '{}'", name.ns)
+                };
+                return Err(SchemaError::Invalid {
+                    path: path.clone(),
+                    message: format!("{} '{}'{} is not defined", kind, name.local, namespace),
+                });
+            }
+        }
+        Ok(self.schema)
+    }
+
+    fn load_file(&mut self, path: &Path, chameleon: Option<&str>) -> Result<(), SchemaError> {
+        let key = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if !self.loaded.insert(key) {
+            return Ok(());
+        }
+        let source = read_schema(path)?;
+        self.load_source(&source, path.parent(), &path.display().to_string(), chameleon)
+    }
+
+    fn load_source(
+        &mut self,
+        source: &str,
+        base_dir: Option<&Path>,
+        path: &str,
+        chameleon: Option<&str>,
+    ) -> Result<(), SchemaError> {
+        let doc = parse_schema(source, path)?;
+
+        let root = doc.root();
+        let invalid = |message: String| SchemaError::Invalid {
+            path: path.to_string(),
+            message,
+        };
+        if xs_name(root) != Some("schema") {
+            return Err(invalid("root element must be xs:schema".to_string()));
+        }
+
+        let declared = root.attr("targetNamespace").map(|t| t.into_owned());
+        let target = match (declared, chameleon) {
+            (Some(declared), Some(includer)) if declared != includer => {
+                return Err(invalid(format!(
+                    "included schema has target namespace '{}' instead of '{}'",
+                    declared, includer
+                )));
+            }
+            (Some(declared), _) => declared,
+            (None, includer) => includer.unwrap_or_default().to_string(),
+        };
+        let ctx = SchemaDoc {
+            path: path.to_string(),
+            chameleon: chameleon.is_some() && root.attr("targetNamespace").is_none(),
+            target,
+            element_qualified: root.attr("elementFormDefault").as_deref() == Some("qualified"),
+            attribute_qualified: root.attr("attributeFormDefault").as_deref() == Some("qualified"),
+        };
+
+        for (node, kind) in xs_children(root) {
+            match kind {
+                "include" | "redefine" | "import" => {
+                    let Some(location) = node.attr("schemaLocation") else {
+                        // An import without a location must be satisfied elsewhere
+                        continue;
+                    };
+                    let location = resolve_location(base_dir, &location).map_err(invalid)?;
+                    let includer = (kind != "import").then_some(ctx.target.as_str());
+                    self.load_file(&location, includer)?;
+                }
+                "element" => {
+                    let decl = self.element(node, &ctx, true).map_err(invalid)?;
+                    if let Some(head) = &decl.substitution_head {
+                        self.schema
+                            .substitutions
+                            .entry(head.clone())
+                            .or_default()
+                            .push(decl.name.clone());
+                    }
+                    self.schema.elements.insert(decl.name.clone(), decl);
+                }
+                "complexType" => {
+                    let name = global_name(node, &ctx).map_err(invalid)?;
+                    let ty = self.complex_type(node, &ctx).map_err(invalid)?;
+                    self.schema.types.insert(name, Type::Complex(Box::new(ty)));
+                }
+                "simpleType" => {
+                    let name = global_name(node, &ctx).map_err(invalid)?;
+                    let ty = self.simple_type(node, &ctx).map_err(invalid)?;
+                    self.schema.types.insert(name, Type::Simple(ty));
+                }
+                "group" => {
+                    let name = global_name(node, &ctx).map_err(invalid)?;
+                    let particle = xs_children(node)
+                        .find(|(_, kind)| matches!(*kind, "sequence" | "choice" | "all"))
+                        .map(|(child, _)| self.particle(child, &ctx))
+                        .transpose()
+                        .map_err(invalid)?
+                        .flatten()
+                        .ok_or_else(|| invalid(format!("group '{}' has no model group", name)))?;
+                    self.schema.groups.insert(name, particle);
+                }
+                "attributeGroup" => {
+                    let name = global_name(node, &ctx).map_err(invalid)?;
+                    let attributes = self.attributes(node, &ctx).map_err(invalid)?;
+                    self.schema.attribute_groups.insert(name, attributes);
+                }
+                "attribute" => {
+                    let name = global_name(node, &ctx).map_err(invalid)?;
+                    let decl = self.attribute_decl(node, &ctx).map_err(invalid)?;
+                    self.schema.attributes.insert(name, decl);
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn reference(&mut self, component: Component, name: &QName, ctx: &SchemaDoc) {
+        self.references.push((component, name.clone(), ctx.path.clone()));
+    }
+
+    /// Resolve a QName-valued attribute of a schema element
+    fn qname(
+        &mut self,
+        node: NodeRef<'_, '_>,
+        value: &str,
+        component: Component,
+        ctx: &SchemaDoc,
+    ) -> Result<QName, String> {
+        let (prefix, local) = split_qname(value.trim());
+        let mut ns = lookup_namespace(node, prefix)
+            .ok_or_else(|| format!("prefix '{}' in '{}' is not declared", prefix, value))?;
+        if ns.is_empty() && ctx.chameleon {
+            ns = ctx.target.clone();
+        }
+        let name = QName::new(&ns, local);
+        if !(ns == XS && matches!(component, Component::Type)) {
+            self.reference(component, &name, ctx);
+        } else if !self.schema.types.contains_key(&name) {
+            return Err(format!("'{}' is not a built-in type", value));
+        }
+        Ok(name)
+    }
+
+    fn element(&mut self, node: NodeRef<'_, '_>, ctx: &SchemaDoc, global: bool) -> Result<ElementDecl, String> {
+        let local = node.attr("name").ok_or("element declaration needs a name")?;
+        let qualified = global
+            || match node.attr("form").as_deref() {
+                Some(form) => form == "qualified",
+                None => ctx.element_qualified,
+            };
+        let ns = if qualified { ctx.target.as_str() } else { "" };
+
+        let ty = match node.attr("type") {
+            Some(ty) => Some(TypeRef::Named(self.qname(node, &ty, Component::Type, ctx)?)),
+            None => self.inline_type(node, ctx)?,
+        };
+        let substitution_head = match node.attr("substitutionGroup") {
+            Some(head) => Some(self.qname(node, &head, Component::Element, ctx)?),
+            None => None,
+        };
+
+        Ok(ElementDecl {
+            name: QName::new(ns, &local),
+            ty,
+            substitution_head,
+            nillable: is_true(node.attr("nillable").as_deref()),
+            is_abstract: is_true(node.attr("abstract").as_deref()),
+            default: node.attr("default").map(|v| unescape(&v).into_owned()),
+            fixed: node.attr("fixed").map(|v| unescape(&v).into_owned()),
+        })
+    }
+
+    /// An anonymous `xs:simpleType` or `xs:complexType` child
+    fn inline_type(&mut self, node: NodeRef<'_, '_>, ctx: &SchemaDoc) -> Result<Option<TypeRef>, String> {
+        for (child, kind) in xs_children(node) {
+            let ty = match kind {
+                "simpleType" => Type::Simple(self.simple_type(child, ctx)?),
+                "complexType" => Type::Complex(Box::new(self.complex_type(child, ctx)?)),
+                _ => continue,
+            };
+            return Ok(Some(TypeRef::Inline(Box::new(ty))));
+        }
+        Ok(None)
+    }
+
+    fn particle(&mut self, node: NodeRef<'_, '_>, ctx: &SchemaDoc) -> Result<Option<Particle>, String> {
+        let term = match xs_name(node) {
+            Some("element") => match node.attr("ref") {
+                Some(name) => Term::ElementRef(self.qname(node, &name, Component::Element, ctx)?),
+                None => Term::Element(self.element(node, ctx, false)?),
+            },
+            Some(kind @ ("sequence" | "choice" | "all")) => {
+                let mut items = Vec::new();
+                for (child, _) in xs_children(node) {
+                    if let Some(item) = self.particle(child, ctx)? {
+                        items.push(item);
+                    }
+                }
+                match kind {
+                    "sequence" => Term::Sequence(items),
+                    "choice" => Term::Choice(items),
+                    _ => Term::All(items),
+                }
+            }
+            Some("group") => {
+                let name = node.attr("ref").ok_or("group reference needs a ref")?;
+                Term::Group(self.qname(node, &name, Component::Group, ctx)?)
+            }
+            Some("any") => Term::Any(wildcard(node, ctx)),
+            _ => return Ok(None),
+        };
+
+        let min = match node.attr("minOccurs") {
+            Some(min) => min.trim().parse().map_err(|_| format!("invalid minOccurs '{}'", min))?,
+            None => 1,
+        };
+        let max = match node.attr("maxOccurs").as_deref().map(str::trim) {
+            Some("unbounded") => None,
+            Some(max) => Some(max.parse().map_err(|_| format!("invalid maxOccurs '{}'", max))?),
+            None => Some(1),
+        };
+        if max.is_some_and(|max| max < min) {
+            return Err(format!("maxOccurs is less than minOccurs {}", min));
+        }
+        Ok(Some(Particle { min, max, term }))
+    }
+
+    fn complex_type(&mut self, node: NodeRef<'_, '_>, ctx: &SchemaDoc) -> Result<ComplexType, String> {
+        let mut ty = ComplexType {
+            mixed: is_true(node.attr("mixed").as_deref()),
+            is_abstract: is_true(node.attr("abstract").as_deref()),
+            base: None,
+            content: Content::Empty,
+            attributes: Attributes::default(),
+        };
+
+        let derived = xs_children(node).find(|(_, kind)| matches!(*kind, "simpleContent" | "complexContent"));
+        let Some((content, kind)) = derived else {
+            ty.content = self.content_particle(node, ctx)?;
+            ty.attributes = self.attributes(node, ctx)?;
+            return Ok(ty);
+        };
+
+        if let Some(mixed) = content.attr("mixed") {
+            ty.mixed = is_true(Some(&mixed));
+        }
+        let (derivation, method) = xs_children(content)
+            .find(|(_, k)| matches!(*k, "extension" | "restriction"))
+            .ok_or_else(|| format!("xs:{} needs an extension or restriction", kind))?;
+        let base = derivation.attr("base").ok_or("derivation needs a base type")?;
+        let base = self.qname(derivation, &base, Component::Type, ctx)?;
+        let method = if method == "extension" {
+            Derivation::Extension
+        } else {
+            Derivation::Restriction
+        };
+
+        ty.content = if kind == "simpleContent" {
+            Content::Simple(SimpleType {
+                variety: Variety::Restriction(TypeRef::Named(base.clone())),
+                facets: self.facets(derivation)?,
+            })
+        } else {
+            self.content_particle(derivation, ctx)?
+        };
+        ty.attributes = self.attributes(derivation, ctx)?;
+        ty.base = Some((method, TypeRef::Named(base)));
+        Ok(ty)
+    }
+
+    /// The model group child of a complex type or derivation, if any
+    fn content_particle(&mut self, node: NodeRef<'_, '_>, ctx: &SchemaDoc) -> Result<Content, String> {
+        for (child, kind) in xs_children(node) {
+            if matches!(kind, "sequence" | "choice" | "all" | "group") {
+                if let Some(particle) = self.particle(child, ctx)? {
+                    return Ok(Content::Elements(particle));
+                }
+            }
+        }
+        Ok(Content::Empty)
+    }
+
+    fn attributes(&mut self, node: NodeRef<'_, '_>, ctx: &SchemaDoc) -> Result<Attributes, String> {
+        let mut attributes = Attributes::default();
+        for (child, kind) in xs_children(node) {
+            match kind {
+                "attribute" => attributes.uses.push(self.attribute_use(child, ctx)?),
+                "attributeGroup" => {
+                    let name = child.attr("ref").ok_or("attribute group reference needs a ref")?;
+                    let name = self.qname(child, &name, Component::AttributeGroup, ctx)?;
+                    attributes.groups.push(name);
+                }
+                "anyAttribute" => attributes.wildcard = Some(wildcard(child, ctx)),
+                _ => {}
+            }
+        }
+        Ok(attributes)
+    }
+
+    fn attribute_use(&mut self, node: NodeRef<'_, '_>, ctx: &SchemaDoc) -> Result<AttributeUse, String> {
+        let usage = node.attr("use");
+        let (name, global) = match node.attr("ref") {
+            Some(name) => (self.qname(node, &name, Component::Attribute, ctx)?, true),
+            None => {
+                let local = node.attr("name").ok_or("attribute declaration needs a name")?;
+                let qualified = match node.attr("form").as_deref() {
+                    Some(form) => form == "qualified",
+                    None => ctx.attribute_qualified,
+                };
+                let ns = if qualified { ctx.target.as_str() } else { "" };
+                (QName::new(ns, &local), false)
+            }
+        };
+        Ok(AttributeUse {
+            name,
+            global,
+            decl: self.attribute_decl(node, ctx)?,
+            required: usage.as_deref() == Some("required"),
+            prohibited: usage.as_deref() == Some("prohibited"),
+        })
+    }
+
+    fn attribute_decl(&mut self, node: NodeRef<'_, '_>, ctx: &SchemaDoc) -> Result<AttributeDecl, String> {
+        let ty = match node.attr("type") {
+            Some(ty) => Some(TypeRef::Named(self.qname(node, &ty, Component::Type, ctx)?)),
+            None => self.inline_type(node, ctx)?,
+        };
+        Ok(AttributeDecl {
+            ty,
+            fixed: node.attr("fixed").map(|v| unescape(&v).into_owned()),
+        })
+    }
+
+    fn simple_type(&mut self, node: NodeRef<'_, '_>, ctx: &SchemaDoc) -> Result<SimpleType, String> {
+        let (child, kind) = xs_children(node)
+            .find(|(_, kind)| matches!(*kind, "restriction" | "list" | "union"))
+            .ok_or("simple type needs a restriction, list or union")?;
+
+        let variety = match kind {
+            "restriction" => {
+                let base = match child.attr("base") {
+                    Some(base) => TypeRef::Named(self.qname(child, &base, Component::Type, ctx)?),
+                    None => self.inline_type(child, ctx)?.ok_or("restriction needs a base type")?,
+                };
+                Variety::Restriction(base)
+            }
+            "list" => {
+                let item = match child.attr("itemType") {
+                    Some(item) => TypeRef::Named(self.qname(child, &item, Component::Type, ctx)?),
+                    None => self.inline_type(child, ctx)?.ok_or("list needs an item type")?,
+                };
+                Variety::List(item)
+            }
+            _ => {
+                let mut members = Vec::new();
+                for member in child.attr("memberTypes").unwrap_or_default().split_ascii_whitespace() {
+                    members.push(TypeRef::Named(self.qname(child, member, Component::Type, ctx)?));
+                }
+                for (inline, kind) in xs_children(child) {
+                    if kind == "simpleType" {
+                        members.push(TypeRef::Inline(Box::new(Type::Simple(self.simple_type(inline, ctx)?))));
+                    }
+                }
+                Variety::Union(members)
+            }
+        };
+
+        let facets = if kind == "restriction" {
+            self.facets(child)?
+        } else {
+            Vec::new()
+        };
+        Ok(SimpleType { variety, facets })
+    }
+
+    fn facets(&mut self, node: NodeRef<'_, '_>) -> Result<Vec<Facet>, String> {
+        let mut facets = Vec::new();
+        let mut patterns = Vec::new();
+        let mut enumeration = Vec::new();
+        for (child, kind) in xs_children(node) {
+            let Some(value) = child.attr("value").map(|v| unescape(&v).into_owned()) else {
+                continue;
+            };
+            match kind {
+                "pattern" => {
+                    let regex = compile_pattern(&value)?;
+                    patterns.push((value, regex));
+                }
+                "enumeration" => enumeration.push(value),
+                _ => facets.extend(Facet::parse(kind, &value)?),
+            }
+        }
+        if !patterns.is_empty() {
+            facets.push(Facet::Pattern(patterns));
+        }
+        if !enumeration.is_empty() {
+            facets.push(Facet::Enumeration(enumeration));
+        }
+        Ok(facets)
+    }
+}
+
+/// Name of a top-level component, in the target namespace
+fn global_name(node: NodeRef<'_, '_>, ctx: &SchemaDoc) -> Result<QName, String> {
+    let local = node
+        .attr("name")
+        .ok_or_else(|| format!("top-level xs:{} needs a name", xs_name(node).unwrap_or_default()))?;
+    Ok(QName::new(&ctx.target, &local))
+}
+
+fn wildcard(node: NodeRef<'_, '_>, ctx: &SchemaDoc) -> Wildcard {
+    let namespaces = match node.attr("namespace").as_deref().map(str::trim) {
+        None | Some("##any") => NamespaceConstraint::Any,
+        Some("##other") => NamespaceConstraint::Not(vec![ctx.target.clone(), String::new()]),
+        Some(list) => NamespaceConstraint::Only(
+            list.split_ascii_whitespace()
+                .map(|ns| match ns {
+                    "##targetNamespace" => ctx.target.clone(),
+                    "##local" => String::new(),
+                    ns => ns.to_string(),
+                })
+                .collect(),
+        ),
+    };
+    let process = match node.attr("processContents").as_deref() {
+        Some("lax") => ProcessContents::Lax,
+        Some("skip") => ProcessContents::Skip,
+        _ => ProcessContents::Strict,
+    };
+    Wildcard { namespaces, process }
+}
+
+/// Local name of an element in the XML Schema namespace
+fn xs_name(node: NodeRef<'_, '_>) -> Option<&'static str> {
+    let tag = node.tag_name()?;
+    let (prefix, local) = split_qname(&tag);
+    if lookup_namespace(node, prefix).as_deref() != Some(XS) {
+        return None;
+    }
+    // The loader matches on static names
+    const NAMES: [&str; 33] = [
+        "schema", "include", "import", "redefine", "element", "complexType", "simpleType",
+        "group", "attributeGroup", "attribute", "sequence", "choice", "all", "any",
+        "anyAttribute", "simpleContent", "complexContent", "extension", "restriction", "list",
+        "union", "length", "minLength", "maxLength", "pattern", "enumeration", "whiteSpace",
+        "minInclusive", "maxInclusive", "minExclusive", "maxExclusive", "totalDigits",
+        "fractionDigits",
+    ];
+    NAMES.into_iter().find(|&n| n == local)
+}
+
+/// Child elements in the XML Schema namespace with their local names
+fn xs_children<'a, 'i>(node: NodeRef<'a, 'i>) -> impl Iterator<Item = (NodeRef<'a, 'i>, &'static str)> {
+    node.children()
+        .filter_map(|child| xs_name(child).map(|name| (child, name)))
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+fn is_true(value: Option<&str>) -> bool {
+    matches!(value.map(str::trim), Some("true" | "1"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{parse_with_options, ParseOptions};
+    use crate::types::DocumentType;
+
+    const ORDER_XSD: &str = r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+  <xs:simpleType name="sku">
+    <xs:restriction base="xs:string">
+      <xs:pattern value="\d{3}-[A-Z]{2}"/>
+    </xs:restriction>
+  </xs:simpleType>
+  <xs:complexType name="party">
+    <xs:sequence>
+      <xs:element name="name" type="xs:string"/>
+      <xs:element name="street" type="xs:string" minOccurs="0"/>
+    </xs:sequence>
+    <xs:attribute name="country" type="xs:NMTOKEN" fixed="US"/>
+  </xs:complexType>
+  <xs:element name="order">
+    <xs:complexType>
+      <xs:sequence>
+        <xs:choice>
+          <xs:element name="shipTo" type="party"/>
+          <xs:element name="pickup" type="xs:boolean"/>
+        </xs:choice>
+        <xs:element name="item" maxOccurs="3">
+          <xs:complexType>
+            <xs:simpleContent>
+              <xs:extension base="xs:string">
+                <xs:attribute name="sku" type="sku" use="required"/>
+                <xs:attribute name="qty" default="1">
+                  <xs:simpleType>
+                    <xs:restriction base="xs:positiveInteger">
+                      <xs:maxExclusive value="100"/>
+                    </xs:restriction>
+                  </xs:simpleType>
+                </xs:attribute>
+              </xs:extension>
+            </xs:simpleContent>
+          </xs:complexType>
+        </xs:element>
+      </xs:sequence>
+      <xs:attribute name="date" type="xs:date" use="required"/>
+    </xs:complexType>
+  </xs:element>
+</xs:schema>"#;
+
+    fn errors(schema: &str, xml: &str) -> Vec<String> {
+        let schema = XsdSchema::from_source(schema, None).unwrap();
+        let options = ParseOptions {
+            strict: true,
+            ..ParseOptions::default()
+        };
+        let doc = parse_with_options(xml, DocumentType::Xml, &options).unwrap();
+        schema.validate(&doc).iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn test_valid_document() {
+        let xml = r#"<order date="2024-05-01">
+  <shipTo country="US"><name>Alice</name></shipTo>
+  <item sku="123-AB" qty="2">Widget</item>
+  <item sku="456-CD">Gadget</item>
+</order>"#;
+        assert!(errors(ORDER_XSD, xml).is_empty(), "{:?}", errors(ORDER_XSD, xml));
+    }
+
+    #[test]
+    fn test_reports_violations_with_positions() {
+        let xml = r#"<order date="May 1st">
+  <pickup>maybe</pickup>
+  <item sku="12-AB" qty="100" color="red">Widget</item>
+</order>"#;
+        assert_eq!(
+            errors(ORDER_XSD, xml),
+            [
+                "1:1: value 'May 1st' of attribute 'date' on <order> is not a valid date",
+                "2:3: value 'maybe' of element <pickup> is not a valid boolean",
+                "3:3: value '12-AB' of attribute 'sku' on <item> does not match pattern '\\d{3}-[A-Z]{2}'",
+                "3:3: value '100' of attribute 'qty' on <item> must be less than 100",
+                "3:3: attribute 'color' is not allowed on <item>",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_content_model_errors() {
+        let missing = r#"<order date="2024-05-01"><shipTo country="CA"><street/></shipTo></order>"#;
+        assert_eq!(
+            errors(ORDER_XSD, missing),
+            [
+                "1:1: element <order> is incomplete; expected <item>",
+                "1:26: attribute 'country' on <shipTo> must have the fixed value 'US'",
+                "1:47: element <street> is not expected here; expected <name>",
+            ]
+        );
+
+        let too_many = format!(
+            "<order date=\"2024-05-01\"><pickup>1</pickup>{}</order>",
+            "<item sku=\"123-AB\"/>".repeat(4)
+        );
+        assert_eq!(
+            errors(ORDER_XSD, &too_many),
+            ["1:104: element <item> is not expected here"]
+        );
+    }
+
+    #[test]
+    fn test_namespaces_extension_and_ids() {
+        let schema = r###"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema"
+            xmlns:t="urn:test" targetNamespace="urn:test" elementFormDefault="qualified">
+  <xs:complexType name="base">
+    <xs:sequence><xs:element name="a" type="xs:int"/></xs:sequence>
+    <xs:attribute name="id" type="xs:ID"/>
+  </xs:complexType>
+  <xs:complexType name="derived">
+    <xs:complexContent>
+      <xs:extension base="t:base">
+        <xs:sequence><xs:element name="b" type="xs:IDREF" minOccurs="0"/></xs:sequence>
+      </xs:extension>
+    </xs:complexContent>
+  </xs:complexType>
+  <xs:element name="root">
+    <xs:complexType>
+      <xs:sequence>
+        <xs:element name="entry" type="t:derived" maxOccurs="unbounded"/>
+        <xs:any namespace="##other" processContents="skip" minOccurs="0"/>
+      </xs:sequence>
+    </xs:complexType>
+  </xs:element>
+</xs:schema>"###;
+        let valid = r#"<root xmlns="urn:test"><entry id="x"><a>1</a><b>y</b></entry><entry id="y"><a>2</a></entry><x:ext xmlns:x="urn:other"/></root>"#;
+        assert!(errors(schema, valid).is_empty(), "{:?}", errors(schema, valid));
+
+        let invalid = r#"<root xmlns="urn:test"><entry id="x"><a>1</a><b>z</b></entry><entry id="x"><a>2</a></entry><ext/></root>"#;
+        assert_eq!(
+            errors(schema, invalid),
+            [
+                "1:92: element <ext> is not expected here; expected <entry>, any element",
+                "1:62: duplicate ID 'x'",
+                "1:46: IDREF 'z' has no matching ID",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_schema_errors() {
+        let undefined = r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+  <xs:element name="a" type="missing"/>
+</xs:schema>"#;
+        let err = XsdSchema::from_source(undefined, None).unwrap_err();
+        assert_eq!(err.to_string(), "invalid schema <schema>: type 'missing' is not defined");
+
+        let builtin = r#"<xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema"><xs:element name="a" type="xs:strin"/></xs:schema>"#;
+        assert!(XsdSchema::from_source(builtin, None).is_err());
+    }
+}
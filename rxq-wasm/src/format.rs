@@ -1,6 +1,6 @@
 use crate::RxqDocument;
-use rxq_core::format::XmlFormatter;
-use rxq_core::{ColorMode, DocumentType, FormatOptions, Formatter, Indent};
+use rxq_core::format::{ColorScheme, HtmlFormatter, HtmlHighlighter, Minifier, XmlFormatter};
+use rxq_core::{AttributeLayout, ColorMode, DocumentType, FormatOptions, Formatter, Indent};
 use serde::Serialize;
 use wasm_bindgen::prelude::*;
 
@@ -29,16 +29,55 @@ impl RxqDocument {
                 ColorMode::Never
             },
             compact: opts.compact.unwrap_or(false),
+            preserve_whitespace: opts.preserveWhitespace.unwrap_or_default(),
+            max_width: opts.maxWidth,
+            attribute_layout: match opts.attributeLayout {
+                Some(layout) => layout.parse().map_err(|e: String| JsValue::from_str(&e))?,
+                None => AttributeLayout::default(),
+            },
+            sort_attributes: opts.sortAttributes.unwrap_or(false),
+            theme: match opts.theme {
+                Some(theme) => theme.parse().map_err(|e: String| JsValue::from_str(&e))?,
+                None => ColorScheme::default(),
+            },
         };
 
         let mut buffer = Vec::new();
 
         // Choose formatter based on document type
         match doc.doc_type() {
-            DocumentType::Xml | DocumentType::Html => {
-                // rxq-core uses XmlFormatter for both (generic)
-                let formatter = XmlFormatter;
-                formatter
+            DocumentType::Xml | DocumentType::Html if opts.minify.unwrap_or(false) => {
+                let minifier = Minifier {
+                    strip_comments: opts.stripComments.unwrap_or(false),
+                    strip_declarations: opts.stripDeclarations.unwrap_or(false),
+                };
+                minifier
+                    .format(doc, &mut buffer, &format_opts)
+                    .map_err(|e| JsValue::from_str(&e.to_string()))?;
+            }
+            DocumentType::Xml if opts.highlight.unwrap_or(false) => {
+                HtmlHighlighter {
+                    formatter: XmlFormatter,
+                    inline_styles: opts.inlineStyles.unwrap_or(false),
+                }
+                .format(doc, &mut buffer, &format_opts)
+                .map_err(|e| JsValue::from_str(&e.to_string()))?;
+            }
+            DocumentType::Html if opts.highlight.unwrap_or(false) => {
+                HtmlHighlighter {
+                    formatter: HtmlFormatter,
+                    inline_styles: opts.inlineStyles.unwrap_or(false),
+                }
+                .format(doc, &mut buffer, &format_opts)
+                .map_err(|e| JsValue::from_str(&e.to_string()))?;
+            }
+            DocumentType::Xml => {
+                XmlFormatter
+                    .format(doc, &mut buffer, &format_opts)
+                    .map_err(|e| JsValue::from_str(&e.to_string()))?;
+            }
+            DocumentType::Html => {
+                HtmlFormatter
                     .format(doc, &mut buffer, &format_opts)
                     .map_err(|e| JsValue::from_str(&e.to_string()))?;
             }
@@ -72,6 +111,16 @@ impl RxqDocument {
     }
 }
 
+/// CSS for the classes of highlighted output, in the colors of a theme
+#[wasm_bindgen]
+pub fn stylesheet(theme: Option<String>) -> Result<String, JsValue> {
+    let theme = match theme {
+        Some(theme) => theme.parse().map_err(|e: String| JsValue::from_str(&e))?,
+        None => ColorScheme::default(),
+    };
+    Ok(rxq_core::format::stylesheet(&theme))
+}
+
 #[derive(serde::Deserialize, Default)]
 #[allow(non_snake_case)]
 struct FormatConfig {
@@ -79,4 +128,14 @@ struct FormatConfig {
     useTabs: Option<bool>,
     color: Option<bool>,
     compact: Option<bool>,
+    preserveWhitespace: Option<Vec<String>>,
+    maxWidth: Option<usize>,
+    attributeLayout: Option<String>,
+    sortAttributes: Option<bool>,
+    theme: Option<String>,
+    highlight: Option<bool>,
+    inlineStyles: Option<bool>,
+    minify: Option<bool>,
+    stripComments: Option<bool>,
+    stripDeclarations: Option<bool>,
 }